@@ -0,0 +1,575 @@
+#![allow(clippy::missing_errors_doc)]
+//! Finding the files rustc generated among the rest of the build: next to the artifacts cargo
+//! reports, where an earlier build left them for `--no-build`, in pieces when there are several
+//! codegen units. `--keep` copies them under names that say where they came from.
+use crate::asm;
+use crate::exit::Failure;
+use crate::opts;
+use anyhow::Context;
+use cargo_metadata::{
+    camino::{Utf8Path, Utf8PathBuf},
+    Artifact,
+};
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+pub fn locate_asm_path_via_artifact(
+    artifact: &Artifact,
+    expect_ext: &str,
+) -> anyhow::Result<PathBuf> {
+    // For lib, test, bench, lib-type example, `filenames` hint the file stem of the asm file.
+    // We could locate asm files precisely.
+    //
+    // `filenames`:
+    // [..]/target/debug/deps/libfoo-01234567.rmeta         # lib by-product
+    // [..]/target/debug/deps/foo-01234567                  # test & bench
+    // [..]/target/debug/deps/example/libfoo-01234567.rmeta # lib-type example by-product
+    // Asm files:
+    // [..]/target/debug/deps/foo-01234567.s
+    // [..]/target/debug/deps/example/foo-01234567.s
+    if let Some(path) = artifact
+        .filenames
+        .iter()
+        .filter(|path| {
+            matches!(
+                path.parent().unwrap().file_name(),
+                Some("deps" | "examples")
+            )
+        })
+        .find_map(|path| {
+            let path = path.with_extension(expect_ext);
+            if asm_file_exists(path.as_std_path()) {
+                return Some(path);
+            }
+            let path = path.with_file_name(path.file_name()?.strip_prefix("lib")?);
+            if asm_file_exists(path.as_std_path()) {
+                return Some(path);
+            }
+            None
+        })
+    {
+        return Ok(path.into_std_path_buf());
+    }
+
+    // For bin or bin-type example artifacts, `filenames` provide hard-linked paths
+    // without extra-filename.
+    // We scans all possible original artifacts by checking hard links,
+    // in order to retrieve the correct extra-filename, and then locate asm files.
+    //
+    // `filenames`, also `executable`:
+    // [..]/target/debug/foobin                    <+
+    // [..]/target/debug/examples/fooexample        | <+ Hard linked.
+    // Origins:                                     |  |
+    // [..]/target/debug/deps/foobin-01234567      <+  |
+    // [..]/target/debug/examples/fooexample-01234567 <+
+    // Asm files:
+    // [..]/target/debug/deps/foobin-01234567.s
+    // [..]/target/debug/examples/fooexample-01234567.s
+    //
+    // Build scripts are hard linked inside of their own directory.
+    //
+    // `filenames`:
+    // [..]/target/debug/build/foo-01234567/build-script-build  <+ Hard linked.
+    // Origin:                                                   |
+    // [..]/target/debug/build/foo-01234567/build_script_build-01234567
+    // Asm file:
+    // [..]/target/debug/build/foo-01234567/build_script_build-01234567.s
+    let build_scripts = artifact.filenames.iter().filter(|path| {
+        artifact.target.kind == ["custom-build"]
+            && path
+                .parent()
+                .and_then(Utf8Path::parent)
+                .map(Utf8Path::file_name)
+                == Some(Some("build"))
+    });
+    //
+    // Libraries other than rlib are uplifted the same way, `cdylib` doesn't get extra-filename.
+    //
+    // `filenames`:
+    // [..]/target/debug/libfoo.so                   <+
+    // [..]/target/debug/libfoo.a                     | <+ Hard linked.
+    // Origins:                                       |  |
+    // [..]/target/debug/deps/libfoo.so              <+  |
+    // [..]/target/debug/deps/libfoo-01234567.a         <+
+    // Asm files:
+    // [..]/target/debug/deps/foo.s
+    // [..]/target/debug/deps/foo-01234567.s
+    let uplifted_libs = artifact.filenames.iter().filter(|path| {
+        artifact.executable.is_none()
+            && !matches!(
+                path.parent().and_then(Utf8Path::file_name),
+                Some("deps" | "examples")
+            )
+            && path.extension().is_some_and(|ext| ext != "rlib")
+    });
+    for exe_path in artifact
+        .executable
+        .iter()
+        .chain(build_scripts)
+        .chain(uplifted_libs)
+    {
+        let parent = exe_path.parent().unwrap();
+        let deps_dir = if matches!(parent.file_name(), Some("examples"))
+            || artifact.target.kind == ["custom-build"]
+        {
+            parent.to_owned()
+        } else {
+            exe_path.with_file_name("deps")
+        };
+
+        for entry in deps_dir.read_dir()? {
+            let maybe_origin = entry?.path();
+            if maybe_origin != exe_path.as_std_path()
+                && same_file::is_same_file(exe_path, &maybe_origin)?
+            {
+                let asm_file = maybe_origin.with_extension(expect_ext);
+                if asm_file_exists(&asm_file) {
+                    return Ok(asm_file);
+                }
+                if let Some(name) = asm_file
+                    .file_name()
+                    .and_then(|f| f.to_str()?.strip_prefix("lib"))
+                {
+                    let asm_file = asm_file.with_file_name(name);
+                    if asm_file_exists(&asm_file) {
+                        return Ok(asm_file);
+                    }
+                }
+            }
+        }
+    }
+
+    anyhow::bail!("Cannot locate the path to the asm file");
+}
+
+/// `cross` runs cargo inside of a container with workspace mounted as `/project` and target
+/// directory mounted as `/target` so paths it reports need to be mapped back to the host
+pub fn remap_container_paths(
+    artifact: &mut Artifact,
+    metadata: &cargo_metadata::Metadata,
+    target_dir: Option<&Path>,
+) {
+    let target_dir = target_dir
+        .and_then(|dir| Utf8Path::from_path(dir))
+        .unwrap_or(&metadata.target_directory);
+    let remap = |path: &mut Utf8PathBuf| {
+        if path.exists() {
+            return;
+        }
+        if let Ok(rest) = path.strip_prefix("/target") {
+            *path = target_dir.join(rest);
+        } else if let Ok(rest) = path.strip_prefix("/project") {
+            *path = metadata.workspace_root.join(rest);
+        }
+    };
+    remap(&mut artifact.manifest_path);
+    artifact.filenames.iter_mut().for_each(remap);
+    artifact.executable.iter_mut().for_each(remap);
+}
+
+/// Files rustc leaves next to `path` when codegen units can't be put into a single one:
+/// `foo-hash.foo.abc123-cgu.0.rcgu.s` for `foo-hash.s`
+fn codegen_units(path: &Path) -> Vec<PathBuf> {
+    let (Some(dir), Some(stem), Some(ext)) = (path.parent(), path.file_stem(), path.extension())
+    else {
+        return Vec::new();
+    };
+    let prefix = format!("{}.", stem.to_string_lossy());
+    let suffix = format!(".rcgu.{}", ext.to_string_lossy());
+    let Ok(entries) = dir.read_dir() else {
+        return Vec::new();
+    };
+    let mut parts = entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|part| {
+            part.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(&prefix) && name.ends_with(&suffix))
+        })
+        .collect::<Vec<_>>();
+    // cgu.2 before cgu.10
+    parts.sort_by_key(|part| {
+        let name = part.to_string_lossy();
+        let number = name
+            .trim_end_matches(suffix.as_str())
+            .rsplit('.')
+            .next()
+            .and_then(|n| n.parse::<usize>().ok());
+        (number, part.clone())
+    });
+    parts
+}
+
+/// Check if the asm, llvm-ir or MIR file rustc was asked for is at `path`, merging codegen
+/// units into it first if the build produced several of them
+fn asm_file_exists(path: &Path) -> bool {
+    let parts = codegen_units(path);
+    if !parts.is_empty() {
+        let modified = |path: &Path| path.metadata().and_then(|meta| meta.modified()).ok();
+        let merged = modified(path);
+        let stale = parts.iter().any(|part| modified(part) >= merged);
+        if stale {
+            if let Err(err) = merge_codegen_units(path, &parts) {
+                eprintln!(
+                    "Failed to merge codegen units into {}: {err}",
+                    path.display()
+                );
+            }
+        }
+    }
+    path.exists()
+}
+
+fn merge_codegen_units(path: &Path, parts: &[PathBuf]) -> anyhow::Result<()> {
+    let mut out = std::io::BufWriter::new(File::create(path)?);
+    if path.extension().is_some_and(|ext| ext == "s") {
+        asm::merge_codegen_units(parts, &mut out)?;
+    } else {
+        // functions in llvm-ir don't refer to anything outside of their own file
+        for part in parts {
+            out.write_all(&std::fs::read(part)?)?;
+            writeln!(out)?;
+        }
+    }
+    Ok(out.flush()?)
+}
+
+/// Name cargo knows `target` by, custom target specs are named after the file
+#[must_use]
+pub fn target_name(target: &str) -> &str {
+    let spec = Path::new(target);
+    match spec.file_stem().and_then(OsStr::to_str) {
+        Some(stem) if spec.extension().is_some_and(|ext| ext == "json") => stem,
+        _ => target,
+    }
+}
+
+/// Directory cargo puts the files of `profile` in, custom target specs get a directory named
+/// after the file
+pub fn profile_dir(target_dir: &Path, target: Option<&str>, profile: &str) -> PathBuf {
+    let profile = match profile {
+        "dev" | "test" => "debug",
+        "bench" => "release",
+        profile => profile,
+    };
+    let mut base = target_dir.to_owned();
+    if let Some(target) = target {
+        base.push(target_name(target));
+    }
+    base.push(profile);
+    base
+}
+
+/// Newest file of the kind `opts` asks for that cargo made for `focus` of `package` in
+/// `target_dir`, for `--no-build`
+pub fn find_built(
+    opts: &opts::Options,
+    target_dir: &Path,
+    target: Option<&str>,
+    package: &cargo_metadata::Package,
+    focus: &opts::Focus,
+) -> anyhow::Result<PathBuf> {
+    if matches!(focus, opts::Focus::Doctest(_)) {
+        anyhow::bail!("--no-build doesn't work with doctests, rustdoc keeps them elsewhere");
+    }
+    let name = package
+        .targets
+        .iter()
+        .find(|t| opts::Focus::try_from(*t).is_ok_and(|f| f.as_parts() == focus.as_parts()))
+        .map_or_else(|| package.name.clone(), |t| t.name.replace('-', "_"));
+    let base = profile_dir(target_dir, target, opts.compile_mode.profile_name());
+    let dirs = match focus {
+        opts::Focus::Example(_) => vec![base.join("examples")],
+        opts::Focus::BuildScript => std::fs::read_dir(base.join("build"))
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|dir| {
+                dir.file_name()
+                    .and_then(OsStr::to_str)
+                    .is_some_and(|dir| dir.starts_with(&format!("{}-", package.name)))
+            })
+            .collect(),
+        _ => vec![base.join("deps")],
+    };
+    let stem = match focus {
+        opts::Focus::BuildScript => "build_script_build",
+        _ => &name,
+    };
+    let ext = opts.syntax().ext();
+    // `name-0123456789abcdef.s`, files of codegen units have more dots in them
+    let matches = |file: &str| {
+        file.strip_suffix(&format!(".{ext}"))
+            .and_then(|file| file.strip_prefix(stem))
+            .is_some_and(|rest| {
+                rest.is_empty()
+                    || rest
+                        .strip_prefix('-')
+                        .is_some_and(|hash| hash.chars().all(|c| c.is_ascii_hexdigit()))
+            })
+    };
+    let newest = dirs
+        .iter()
+        .flat_map(std::fs::read_dir)
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.file_name().to_str().is_some_and(matches))
+        .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+        .filter(|(_, path)| asm_file_exists(path))
+        .max();
+    match newest {
+        Some((_, path)) => Ok(path),
+        None => anyhow::bail!(Failure::not_found(format!(
+            "No .{ext} file for {} {} in {}, build it first or drop --no-build",
+            package.name,
+            focus.as_cargo_args().collect::<Vec<_>>().join(" "),
+            base.display()
+        ))),
+    }
+}
+
+/// Make cargo build the target of `package` that made `stale` again, leaving the rest of the
+/// build and the sources alone. Cargo keeps a fingerprint for each thing it builds in
+/// `.fingerprint/{package}-{hash}`, the hash is the same one as in the name of the generated
+/// file. Without the file there's no hash and all of the package fingerprints go
+pub fn invalidate(
+    profile_dir: &Path,
+    package: &str,
+    stale: Option<&PathBuf>,
+) -> anyhow::Result<()> {
+    if let Some(stale) = stale {
+        let _ = std::fs::remove_file(stale);
+    }
+    let hash = stale
+        .and_then(|path| path.file_stem()?.to_str()?.rsplit_once('-'))
+        .map(|(_, hash)| hash);
+    let fingerprints = profile_dir.join(".fingerprint");
+    for entry in std::fs::read_dir(&fingerprints)
+        .into_iter()
+        .flatten()
+        .flatten()
+    {
+        let name = entry.file_name();
+        let Some(rest) = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(package)?.strip_prefix('-'))
+        else {
+            continue;
+        };
+        // `{package}-{other}-{hash}` belongs to some other package
+        if !rest.contains('-') && hash.is_none_or(|hash| hash == rest) {
+            std::fs::remove_dir_all(entry.path())
+                .with_context(|| format!("Failed to remove {}", entry.path().display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Copy the files made for `focus` in `package` to `dir` for `--keep`, named after the package,
+/// the target, the profile and the hash of the flags
+pub fn keep(
+    opts: &opts::Options,
+    dir: &Path,
+    package: &cargo_metadata::Package,
+    focus: &opts::Focus,
+    flags: u64,
+    asm_path: &Path,
+) -> anyhow::Result<()> {
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+    let (kind, name) = focus.as_parts();
+    let mut base = format!("{}-{kind}", package.name);
+    if let Some(name) = name {
+        base.push('-');
+        base.push_str(name);
+    }
+    base = sanitize(&format!(
+        "{base}-{}-{flags:016x}",
+        opts.compile_mode.profile_name()
+    ));
+    // `--tui`, `--serve` and `--lsp` use all the kinds of output
+    let exts = if opts.emit_all() {
+        vec!["s", "ll", "mir"]
+    } else {
+        vec![opts.syntax().ext()]
+    };
+    for ext in exts {
+        let from = asm_path.with_extension(ext);
+        if !from.exists() {
+            continue;
+        }
+        let to = dir.join(format!("{base}.{ext}"));
+        std::fs::copy(&from, &to)
+            .with_context(|| format!("Failed to copy {} to {}", from.display(), to.display()))?;
+        if !opts.porcelain {
+            eprintln!("Note: kept a copy at {}", to.display());
+        }
+    }
+    Ok(())
+}
+
+/// File name for `name` that is safe to use on any platform
+pub fn sanitize(name: &str) -> String {
+    let mut res = String::new();
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+            res.push(c);
+        } else if !res.ends_with('_') {
+            res.push('_');
+        }
+    }
+    // leave some room for the suffix and the extension
+    res.truncate(200);
+    let res = res.trim_matches(['_', '.']);
+    // windows reserves device names regardless of the extension
+    let stem = res
+        .split('.')
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let device = matches!(stem.as_str(), "con" | "prn" | "aux" | "nul")
+        || (stem.len() == 4
+            && (stem.starts_with("com") || stem.starts_with("lpt"))
+            && stem.ends_with(|c: char| c.is_ascii_digit()));
+    match res {
+        "" => "_".to_owned(),
+        res if device => format!("{}_{}", &res[..stem.len()], &res[stem.len()..]),
+        res => res.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{asm_file_exists, codegen_units, invalidate, profile_dir, sanitize};
+    use crate::cache;
+    use std::path::Path;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn profile_dirs() {
+        let target = Path::new("/w/target");
+        assert_eq!(
+            profile_dir(target, None, "dev"),
+            Path::new("/w/target/debug")
+        );
+        assert_eq!(
+            profile_dir(target, Some("thumbv7em-none-eabihf"), "bench"),
+            Path::new("/w/target/thumbv7em-none-eabihf/release")
+        );
+        assert_eq!(
+            profile_dir(target, Some("/specs/my-board.json"), "release"),
+            Path::new("/w/target/my-board/release")
+        );
+        assert_eq!(
+            profile_dir(target, Some("specs/my-board.json"), "fast"),
+            Path::new("/w/target/my-board/fast")
+        );
+    }
+
+    #[test]
+    fn rebuild_stale() {
+        let root =
+            std::env::temp_dir().join(format!("cargo-show-asm-stale-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let profile = root.join("target/release");
+        let fingerprints = profile.join(".fingerprint");
+        for dir in ["mt-1111", "mt-2222", "mt-extra-1111", "other-1111"] {
+            std::fs::create_dir_all(fingerprints.join(dir)).unwrap();
+        }
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::create_dir_all(profile.join("deps")).unwrap();
+        let asm = profile.join("deps/mt-1111.s");
+        std::fs::write(&asm, "").unwrap();
+        std::fs::write(asm.with_extension("d"), "mt-1111.s: src/lib.rs\n").unwrap();
+        let source = root.join("src/lib.rs");
+        std::fs::write(&source, "").unwrap();
+        let set_modified = |time| {
+            std::fs::File::options()
+                .write(true)
+                .open(&asm)
+                .unwrap()
+                .set_modified(time)
+                .unwrap();
+        };
+        set_modified(SystemTime::now() + Duration::from_secs(60));
+        assert!(!cache::older_than_sources(&asm, &root));
+        let old = SystemTime::now() - Duration::from_secs(60);
+        set_modified(old);
+        assert!(cache::older_than_sources(&asm, &root));
+
+        invalidate(&profile, "mt", Some(&asm)).unwrap();
+        assert!(!asm.exists());
+        let left = |dir: &str| fingerprints.join(dir).exists();
+        assert!(!left("mt-1111"));
+        assert!(left("mt-2222") && left("mt-extra-1111") && left("other-1111"));
+        // sources are left alone
+        let modified = std::fs::metadata(&source).unwrap().modified().unwrap();
+        assert!(modified > old);
+
+        // without the generated file all of the package fingerprints go
+        invalidate(&profile, "mt", None).unwrap();
+        assert!(!left("mt-2222"));
+        assert!(left("mt-extra-1111") && left("other-1111"));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn codegen_unit_files() {
+        let dir = std::env::temp_dir().join(format!("cargo-show-asm-units-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("mt-1111.s");
+        for n in [10, 2, 0] {
+            let part = dir.join(format!("mt-1111.mt.abc-cgu.{n}.rcgu.s"));
+            std::fs::write(part, format!("\t.text\nunit{n}:\n\tret\n")).unwrap();
+        }
+        std::fs::write(dir.join("mt-2222.mt.abc-cgu.1.rcgu.s"), "").unwrap();
+        std::fs::write(dir.join("mt-1111.mt.abc-cgu.1.rcgu.ll"), "").unwrap();
+        let names = codegen_units(&path)
+            .iter()
+            .map(|part| part.file_name().unwrap().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "mt-1111.mt.abc-cgu.0.rcgu.s",
+                "mt-1111.mt.abc-cgu.2.rcgu.s",
+                "mt-1111.mt.abc-cgu.10.rcgu.s"
+            ]
+        );
+
+        assert!(asm_file_exists(&path));
+        let merged = std::fs::read_to_string(&path).unwrap();
+        let labels = merged
+            .lines()
+            .filter(|line| line.starts_with("unit"))
+            .collect::<Vec<_>>();
+        assert_eq!(labels, ["unit0:", "unit2:", "unit10:"]);
+        assert!(!asm_file_exists(&dir.join("mt-3333.s")));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn file_names() {
+        assert_eq!(sanitize("sample::main"), "sample_main");
+        assert_eq!(
+            sanitize("<T as core::fmt::Debug>::fmt"),
+            "T_as_core_fmt_Debug_fmt"
+        );
+        assert_eq!(sanitize("sample::gen::h1234abcd"), "sample_gen_h1234abcd");
+        assert_eq!(sanitize("mt-bin-b"), "mt-bin-b");
+        assert_eq!(sanitize("../../etc/passwd"), "etc_passwd");
+        assert_eq!(sanitize("{{closure}}"), "closure");
+        assert_eq!(sanitize("caf\u{e9}::\u{1f980}"), "caf");
+        assert_eq!(sanitize("<_>"), "_");
+        assert_eq!(sanitize(""), "_");
+        assert_eq!(sanitize("con"), "con_");
+        assert_eq!(sanitize("::Nul"), "Nul_");
+        assert_eq!(sanitize("lpt1.rs"), "lpt1_.rs");
+        assert_eq!(sanitize("console"), "console");
+        assert_eq!(sanitize(&"x".repeat(500)).len(), 200);
+    }
+}
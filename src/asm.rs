@@ -5,15 +5,27 @@ use crate::{color, demangle};
 // TODO, use https://sourceware.org/binutils/docs/as/index.html
 use crate::opts::Format;
 
+mod calls;
+mod coroutine;
+mod global_asm;
+mod index;
+mod profile;
+mod relocation;
 mod statements;
+mod tls;
+mod unwind;
 
+use global_asm::GlobalAsm;
+use index::Index;
 use owo_colors::OwoColorize;
-use statements::{parse_statement, Directive, Loc, Statement};
+use serde::{Deserialize, Serialize};
+use statements::{parse_statement, Directive, GenericDirective, Loc, Statement};
 use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
 use std::ops::Range;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-fn parse_file(input: &str) -> anyhow::Result<Vec<Statement>> {
+fn parse_file(input: &str) -> anyhow::Result<Vec<Statement<'_>>> {
     // eat all statements until the eof, so we can report the proper errors on failed parse
     match nom::multi::many0(parse_statement)(input) {
         Ok(("", stmts)) => Ok(stmts),
@@ -31,51 +43,438 @@ fn parse_file(input: &str) -> anyhow::Result<Vec<Statement>> {
     }
 }
 
-#[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq)]
+#[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Item {
     /// demangled name
     pub name: String,
     /// demangled name with hash
     pub hashed: String,
-    /// sequential number of demangled name
+    /// sequential number of demangled name, items are ordered by name and then by hash
     pub index: usize,
     /// number of lines
     pub len: usize,
+    /// only known for assembly
+    pub linkage: Linkage,
 }
 
-fn find_items(lines: &[Statement]) -> BTreeMap<Item, Range<usize>> {
-    let mut res = BTreeMap::new();
-
-    let mut sec_start = 0;
-    let mut item: Option<Item> = None;
-    let mut names = BTreeMap::new();
-
-    for (ix, line) in lines.iter().enumerate() {
-        if line.is_section_start() {
-            sec_start = ix;
-        } else if line.is_end_of_fn() {
-            let sec_end = ix;
-            let range = sec_start..sec_end;
-            if let Some(mut item) = item.take() {
-                item.len = ix - item.len;
-                res.insert(item, range);
-            }
-        } else if let Statement::Label(label) = line {
-            if let Some(dem) = demangle::demangled(label.id) {
-                let hashed = format!("{dem:?}");
-                let name = format!("{dem:#?}");
-                let name_entry = names.entry(name.clone()).or_insert(0);
-                item = Some(Item {
-                    name,
-                    hashed,
-                    index: *name_entry,
-                    len: ix,
-                });
-                *name_entry += 1;
+/// Symbol binding as the assembler sees it
+#[derive(Debug, Clone, Copy, Default, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Binding {
+    #[default]
+    Unknown,
+    /// not visible outside of the object file
+    Local,
+    Global,
+    /// the linker can pick a definition from some other object instead
+    Weak,
+}
+
+/// How the linker treats the symbol of an item
+#[derive(Debug, Clone, Default, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Linkage {
+    pub binding: Binding,
+    /// the code is in a COMDAT group, the linker keeps only one copy of each group
+    pub comdat: bool,
+    /// section the code is in without the name of the function, `.text.unlikely` for
+    /// `.text.unlikely.foo`, empty when not known
+    pub section: String,
+}
+
+impl Linkage {
+    /// Words listings put after the item, nothing for plain global functions
+    #[must_use]
+    pub fn tags(&self) -> Vec<&'static str> {
+        let mut tags = Vec::new();
+        match self.binding {
+            Binding::Local => tags.push("local"),
+            Binding::Weak => tags.push("weak"),
+            Binding::Unknown | Binding::Global => {}
+        }
+        if self.comdat {
+            tags.push("comdat");
+        }
+        // where LLVM puts `#[cold]` functions and the ones a profile says are rarely called
+        if self.section.starts_with(".text.unlikely") || self.section.starts_with(".text.split") {
+            tags.push("cold");
+        } else if self.section.starts_with(".text.hot") {
+            tags.push("hot");
+        }
+        tags
+    }
+
+    /// Section for listings, nothing for the usual one
+    #[must_use]
+    pub fn section(&self) -> Option<&str> {
+        Some(self.section.as_str())
+            .filter(|section| !["", ".text", "__TEXT,__text"].contains(section))
+    }
+}
+
+/// Section of the code of `symbol` without the name of the function, with function sections
+/// every function gets its own: `.text.unlikely.foo` is `.text.unlikely` and `.text.foo` is
+/// `.text`. Mach-O section names are made of the segment and the section
+fn section_name(section: &str, symbol: &str) -> String {
+    let mut parts = section.split(',');
+    let name = parts.next().unwrap_or_default().trim().trim_matches('"');
+    if name.starts_with("__") {
+        return match parts.next() {
+            Some(section) => format!("{name},{}", section.trim()),
+            None => name.to_owned(),
+        };
+    }
+    name.strip_suffix(symbol)
+        .and_then(|name| name.strip_suffix('.'))
+        .filter(|name| !name.is_empty())
+        .unwrap_or(name)
+        .to_owned()
+}
+
+impl Item {
+    /// Check if item is the one `goal` refers to, either by name and index or by hashed name
+    #[must_use]
+    pub fn matches(&self, goal: (&str, usize)) -> bool {
+        (self.name.as_ref(), self.index) == goal || self.hashed == goal.0
+    }
+}
+
+/// Start of a line in the file, both as a line number and as a byte offset
+#[derive(Debug, Clone, Copy, Default)]
+struct Pos {
+    line: usize,
+    byte: usize,
+}
+
+/// What finding items needs to know about a statement, doesn't depend on the statements
+/// around it so those can be worked out in parallel
+#[derive(Debug)]
+enum Event {
+    /// start of a section, Mach-O one and one in a COMDAT group
+    Section {
+        name: String,
+        macho: bool,
+        comdat: bool,
+    },
+    /// `.linkonce` makes the current COFF section a COMDAT one
+    LinkOnce,
+    /// `.cfi_endproc` and friends
+    EndOfFn,
+    /// `.Lfunc_end`
+    EndLabel,
+    /// label of a rust or C++ function: symbol, demangled name with and without the hash
+    Function(String, String, String),
+    /// any other label, a function if it's also global
+    Label(String),
+    Global(String),
+    Weak(String),
+    /// `.subsections_via_symbols`, Mach-O files end with it
+    MachO,
+    /// `.file` with the name of the codegen unit, `global_asm!` code comes right after it
+    UnitFile,
+    Blank,
+}
+
+impl Event {
+    fn new(line: &Statement) -> Option<Self> {
+        Some(match line {
+            Statement::Directive(Directive::SectionStart(sec)) => Self::Section {
+                name: (*sec).to_owned(),
+                macho: sec.starts_with("__TEXT,"),
+                comdat: is_comdat(sec),
+            },
+            _ if line.is_end_of_fn() => Self::EndOfFn,
+            _ if line.is_end_label() => Self::EndLabel,
+            Statement::Directive(Directive::SubsectionsViaSym) => Self::MachO,
+            Statement::Directive(Directive::Generic(GenericDirective(dir))) => {
+                let (dir, arg) = dir.split_once(char::is_whitespace).unwrap_or((dir, ""));
+                let arg = arg.trim().to_owned();
+                match dir {
+                    "globl" | "global" => Self::Global(arg),
+                    // Mach-O ones, `.weak_reference` is about symbols defined elsewhere
+                    "weak" | "weak_definition" | "weak_def_can_be_hidden" => Self::Weak(arg),
+                    "linkonce" => Self::LinkOnce,
+                    "file" => Self::UnitFile,
+                    _ => return None,
+                }
             }
+            Statement::Label(label) => {
+                if let Some(dem) = demangle::demangled(label.id) {
+                    Self::Function(label.id.to_owned(), format!("{dem:#?}"), format!("{dem:?}"))
+                } else if let Some(name) = demangle::foreign(label.id) {
+                    Self::Function(label.id.to_owned(), name, label.id.to_owned())
+                } else if label.local {
+                    return None;
+                } else {
+                    Self::Label(label.id.to_owned())
+                }
+            }
+            Statement::Nothing => Self::Blank,
+            _ => return None,
+        })
+    }
+}
+
+/// ELF sections in a group have `G` in the flags and end with `,comdat`, COFF ones pick one of
+/// the ways to deal with duplicates after the flags
+fn is_comdat(section: &str) -> bool {
+    section.ends_with(",comdat")
+        || section.split(',').skip(2).any(|attr| {
+            matches!(
+                attr.trim(),
+                "discard"
+                    | "one_only"
+                    | "same_size"
+                    | "same_contents"
+                    | "associative"
+                    | "largest"
+                    | "newest"
+            )
+        })
+}
+
+/// Events for `lines`, split between all the available cores
+fn events<T: Sync>(lines: &[T], event: impl Fn(&T) -> Option<Event> + Sync) -> Vec<(usize, Event)> {
+    let threads = std::thread::available_parallelism().map_or(1, usize::from);
+    let chunk = lines.len().div_ceil(threads).max(1024);
+    std::thread::scope(|scope| {
+        let workers = lines
+            .chunks(chunk)
+            .enumerate()
+            .map(|(n, lines)| {
+                let event = &event;
+                scope.spawn(move || {
+                    lines
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(ix, line)| Some((n * chunk + ix, event(line)?)))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("Event worker panicked"))
+            .collect()
+    })
+}
+
+/// Functions found so far with one way of telling where they end
+struct Scan {
+    /// `.Lfunc_end` labels instead of `.cfi_endproc` and friends
+    end_labels: bool,
+    sec_start: Pos,
+    /// current section is in a COMDAT group
+    comdat: bool,
+    /// the whole `.section` directive of the current section
+    section: String,
+    /// item, its symbol, unmangled global and the line with its label
+    item: Option<(Item, String, bool, usize)>,
+    found: Vec<(Item, String, bool, Range<Pos>)>,
+}
+
+impl Scan {
+    fn new(end_labels: bool) -> Self {
+        Self {
+            end_labels,
+            sec_start: Pos::default(),
+            comdat: false,
+            section: String::new(),
+            item: None,
+            found: Vec::new(),
         }
     }
-    res
+
+    fn push(&mut self, here: Pos, next: Pos, event: &Event, globals: &BTreeSet<String>) {
+        let (symbol, name, hashed, global) = match event {
+            Event::Section { name, comdat, .. } => {
+                self.sec_start = here;
+                self.comdat = *comdat;
+                self.section.clone_from(name);
+                return;
+            }
+            Event::LinkOnce => {
+                self.comdat = true;
+                return;
+            }
+            Event::EndLabel if !self.end_labels => return,
+            Event::EndOfFn | Event::EndLabel => {
+                if let Some((mut item, symbol, global, start)) = self.item.take() {
+                    item.len = here.line - start;
+                    self.found
+                        .push((item, symbol, global, self.sec_start..here));
+                }
+                // Mach-O keeps all the functions in one section, the next one starts right here
+                self.sec_start = next;
+                return;
+            }
+            Event::Function(symbol, name, hashed) => (symbol, name, hashed, false),
+            Event::Label(id) if globals.contains(id) => (id, id, id, true),
+            _ => return,
+        };
+        let item = Item {
+            name: name.clone(),
+            hashed: hashed.clone(),
+            index: 0,
+            len: 0,
+            linkage: Linkage {
+                binding: Binding::Unknown,
+                comdat: self.comdat,
+                section: section_name(&self.section, symbol),
+            },
+        };
+        self.item = Some((item, symbol.clone(), global, here.line));
+    }
+}
+
+/// Collects items from events fed one at a time, whole file is needed to decide how
+/// functions end and how unmangled names look, so both ways are tracked until then
+struct ItemFinder {
+    // exported functions, the ones with unmangled names are `#[no_mangle] extern "C"`
+    globals: BTreeSet<String>,
+    weak: BTreeSet<String>,
+    // Mach-O puts `_` in front of every symbol, `#[no_mangle] fn foo` is `_foo` there
+    macho: bool,
+    // `.Lfunc_end` comes before `.cfi_endproc`, only use it when there's nothing else
+    end_of_fn: bool,
+    by_end_of_fn: Scan,
+    by_end_label: Scan,
+    global_asm: GlobalAsm,
+}
+
+impl ItemFinder {
+    fn new() -> Self {
+        Self {
+            globals: BTreeSet::new(),
+            weak: BTreeSet::new(),
+            macho: false,
+            end_of_fn: false,
+            by_end_of_fn: Scan::new(false),
+            by_end_label: Scan::new(true),
+            global_asm: GlobalAsm::default(),
+        }
+    }
+
+    /// Event for the statement starting at `here`, the next one starts at `next`
+    fn push(&mut self, here: Pos, next: Pos, event: Event) {
+        match &event {
+            Event::Global(global) => {
+                self.globals.insert(global.clone());
+            }
+            // weak symbols are exported too
+            Event::Weak(weak) => {
+                self.globals.insert(weak.clone());
+                self.weak.insert(weak.clone());
+            }
+            Event::MachO | Event::Section { macho: true, .. } => self.macho = true,
+            Event::EndOfFn => self.end_of_fn = true,
+            _ => {}
+        }
+        self.by_end_of_fn.push(here, next, &event, &self.globals);
+        self.by_end_label.push(here, next, &event, &self.globals);
+        self.global_asm.push(here, next, &event);
+    }
+
+    /// Items found in the file at `path`, it is needed to name `global_asm!` blocks
+    fn finish(self, path: &Path) -> BTreeMap<Item, Range<Pos>> {
+        let mut found = if self.end_of_fn {
+            self.by_end_of_fn.found
+        } else {
+            self.by_end_label.found
+        };
+        // naked functions are functions, the rest of the blocks are named after their module
+        let (naked, blocks): (Vec<_>, Vec<_>) = self
+            .global_asm
+            .found
+            .into_iter()
+            .partition(|block| block.naked);
+        for block in naked {
+            let (Some(symbol), Some((name, hashed))) = (block.label, block.function) else {
+                continue;
+            };
+            // without `.cfi_endproc` the end label makes it look like a regular function
+            found.retain(|(_, other, _, _)| *other != symbol);
+            let item = Item {
+                name,
+                hashed,
+                index: 0,
+                len: block.range.end.line - block.range.start.line,
+                linkage: Linkage::default(),
+            };
+            found.push((item, symbol, false, block.range));
+        }
+        let mut names = BTreeMap::new();
+        let mut res = found
+            .into_iter()
+            .map(|(mut item, symbol, global, range)| {
+                item.linkage.binding = if self.weak.contains(&symbol) {
+                    Binding::Weak
+                } else if self.globals.contains(&symbol) {
+                    Binding::Global
+                } else {
+                    Binding::Local
+                };
+                if global && self.macho {
+                    if let Some(name) = item.name.strip_prefix('_') {
+                        item.name = name.to_owned();
+                        item.hashed = item.name.clone();
+                    }
+                }
+                let name_entry = names.entry(item.name.clone()).or_insert(0);
+                item.index = *name_entry;
+                *name_entry += 1;
+                (item, range)
+            })
+            .collect::<Vec<_>>();
+        if !blocks.is_empty() {
+            let labels = blocks
+                .iter()
+                .map(|block| block.label.clone())
+                .collect::<Vec<_>>();
+            let names = global_asm::names(path, &labels);
+            res.extend(names.into_iter().zip(blocks).map(|(name, block)| {
+                let item = Item {
+                    hashed: name.clone(),
+                    name,
+                    index: 0,
+                    len: block.range.end.line - block.range.start.line,
+                    linkage: Linkage::default(),
+                };
+                (item, block.range)
+            }));
+        }
+
+        // order in the file depends on how LLVM happens to lay out the code, number items with
+        // the same name by their hash instead so the same function gets the same index between
+        // builds
+        let mut prev_name = None::<String>;
+        let mut index = 0;
+        res.into_iter()
+            .collect::<BTreeMap<_, _>>()
+            .into_iter()
+            .map(|(mut item, range)| {
+                if prev_name.as_ref() == Some(&item.name) {
+                    index += 1;
+                } else {
+                    prev_name = Some(item.name.clone());
+                    index = 0;
+                }
+                item.index = index;
+                (item, range)
+            })
+            .collect()
+    }
+}
+
+fn find_items(path: &Path, lines: &[Statement]) -> BTreeMap<Item, Range<usize>> {
+    let mut finder = ItemFinder::new();
+    for (ix, event) in events(lines, Event::new) {
+        let pos = |line| Pos { line, byte: 0 };
+        finder.push(pos(ix), pos(ix + 1), event);
+    }
+    finder
+        .finish(path)
+        .into_iter()
+        .map(|(item, range)| (item, range.start.line..range.end.line))
+        .collect()
 }
 
 fn used_labels<'a>(stmts: &'_ [Statement<'a>]) -> BTreeSet<&'a str> {
@@ -99,23 +498,335 @@ fn used_labels<'a>(stmts: &'_ [Statement<'a>]) -> BTreeSet<&'a str> {
         .collect::<BTreeSet<_>>()
 }
 
+/// Wrap `text` into OSC 8 escape sequence so terminals can open `path` when clicked
+pub fn hyperlink(fmt: &Format, path: &Path, line: u64, text: &str) -> String {
+    if !path.is_absolute() {
+        return text.to_owned();
+    }
+    let path = path.display().to_string();
+    let url = match &fmt.link_template {
+        Some(template) => template
+            .replace("{path}", &path)
+            .replace("{line}", &line.to_string()),
+        None => format!("file://{path}"),
+    };
+    format!("\x1b]8;;{url}\x1b\\{text}\x1b]8;;\x1b\\")
+}
+
+/// File and line from the `// path : line` comment [`dump_range`] prints with `--rust`
+#[must_use]
+pub fn location_comment(line: &str) -> Option<(&str, u64)> {
+    let (file, line) = line.trim_start().strip_prefix("// ")?.rsplit_once(" : ")?;
+    Some((file, line.parse().ok()?))
+}
+
+/// Functions that only ever run on the way to a panic
+fn is_panic(name: &str) -> bool {
+    name.starts_with("core::panicking::")
+        || name.starts_with("std::panicking::")
+        || (name.starts_with("core::slice::index::") && name.ends_with("_fail"))
+        || [
+            "core::option::unwrap_failed",
+            "core::option::expect_failed",
+            "core::result::unwrap_failed",
+            "alloc::raw_vec::capacity_overflow",
+            "alloc::raw_vec::handle_error",
+            "alloc::alloc::handle_alloc_error",
+        ]
+        .contains(&name)
+}
+
+/// Standard library and registry sources, a location in the crate itself is more useful
+fn is_foreign(path: &Path) -> bool {
+    path.starts_with("/rustc/")
+        || path.to_str().is_some_and(|path| {
+            let path = path.replace('\\', "/");
+            path.contains("/registry/src/") || path.contains("/lib/rustlib/src/rust/")
+        })
+}
+
+/// Print every call on the way to a panic as `file:line:col: message` so editors can load them
+/// into a quickfix list, locations inlined from the standard library or dependencies are
+/// attributed to the closest preceding location in the crate itself
+fn dump_quickfix(files: &Sources, stmts: &[Statement], out: &mut dyn Write) -> anyhow::Result<()> {
+    let location = |line: &Statement| match line {
+        Statement::Directive(Directive::Loc(loc)) if loc.line > 0 => {
+            let (path, _) = files.get(&loc.file)?;
+            Some((path, loc.line, loc.column.max(1)))
+        }
+        _ => None,
+    };
+    // code before the first location in the crate gets that location
+    let mut own = stmts
+        .iter()
+        .filter_map(location)
+        .find(|(path, _, _)| !is_foreign(path));
+    let mut last = None;
+    let mut prev = String::new();
+    for line in stmts {
+        if let Some(here) = location(line) {
+            if !is_foreign(here.0) {
+                own = Some(here);
+            }
+            last = Some(here);
+        }
+        let Statement::Instruction(instr) = line else {
+            continue;
+        };
+        let Some(((path, line, column), args)) = own.or(last).zip(instr.args) else {
+            continue;
+        };
+        for name in demangle::symbols(args).filter(|name| is_panic(name)) {
+            let finding = format!(
+                "{}:{line}:{column}: panic path: {} {name}",
+                path.display(),
+                instr.op
+            );
+            if finding != prev {
+                writeln!(out, "{finding}")?;
+                prev = finding;
+            }
+        }
+    }
+    Ok(())
+}
+
 pub fn dump_range(
-    files: &BTreeMap<u64, (std::borrow::Cow<Path>, CachedLines)>,
+    files: &Sources,
     fmt: &Format,
     stmts: &[Statement],
+    out: &mut dyn Write,
 ) -> anyhow::Result<()> {
-    let mut prev_loc = Loc::default();
-
+    if fmt.quickfix {
+        return dump_quickfix(files, stmts, out);
+    }
     let used = if fmt.keep_labels {
         BTreeSet::new()
     } else {
         used_labels(stmts)
     };
+    let used = |label: &str| used.contains(label);
+    dump_statements(files, fmt, stmts, &used, &mut Progress::default(), out)
+}
+
+/// Naked functions end with `.Lfunc_end_{symbol}`, LLVM numbers the end labels it makes
+fn is_naked(stmts: &[Statement]) -> bool {
+    stmts.iter().any(
+        |stmt| matches!(stmt, Statement::Label(Label { local: true, id }) if id.starts_with(".Lfunc_end_")),
+    )
+}
+
+/// Index of the source file of a naked function in [`Sources`], there's no `.file` for it
+const NAKED_FILE: u64 = u64::MAX;
+
+/// The naked function `name` from `path` with the line of the `naked_asm!` template each
+/// statement comes from, `None` if the template can't be found
+fn naked_lines<'a>(
+    path: &Path,
+    name: &str,
+    stmts: &[Statement<'a>],
+) -> Option<(Sources<'static>, Vec<Statement<'a>>)> {
+    let (file, pieces) = global_asm::naked_template(path, name)?;
+    let source = std::fs::read_to_string(&file).ok()?;
+    // rustc adds the label and the directives around the template, the body is what the
+    // template turned into, one statement for each of its pieces
+    let start = stmts
+        .iter()
+        .position(|stmt| matches!(stmt, Statement::Label(Label { local: false, .. })))
+        .map_or(0, |ix| ix + 1);
+    let body = stmts
+        .iter()
+        .enumerate()
+        .skip(start)
+        .take_while(|(_, stmt)| !matches!(stmt, Statement::Label(Label { id, .. }) if id.starts_with(".Lfunc_end_")))
+        .filter(|(_, stmt)| {
+            !matches!(
+                stmt,
+                Statement::Nothing | Statement::Directive(Directive::Loc(_) | Directive::File(_))
+            )
+        })
+        .map(|(ix, _)| ix)
+        .collect::<Vec<_>>();
+    let loc = |line| {
+        Statement::Directive(Directive::Loc(Loc {
+            file: NAKED_FILE,
+            line,
+            column: 0,
+            extra: None,
+        }))
+    };
+    // when LLVM didn't keep them one to one the whole template goes above the body
+    let mut lines = if body.len() == pieces.len() {
+        body.iter()
+            .copied()
+            .zip(pieces.iter().map(|(line, _)| *line))
+            .collect::<Vec<_>>()
+    } else {
+        let mut lines = pieces.iter().map(|(line, _)| *line).collect::<Vec<_>>();
+        lines.dedup();
+        lines.into_iter().map(|line| (start, line)).collect()
+    }
+    .into_iter()
+    .peekable();
+    let mut annotated = Vec::with_capacity(stmts.len() + pieces.len());
+    for (ix, stmt) in stmts.iter().enumerate() {
+        while let Some((_, line)) = lines.next_if(|(at, _)| *at == ix) {
+            annotated.push(loc(line));
+        }
+        annotated.push(stmt.clone());
+    }
+    let files = BTreeMap::from([(
+        NAKED_FILE,
+        (file.into(), CachedLines::without_ending(source)),
+    )]);
+    Some((files, annotated))
+}
+
+/// `stmts` with each of `notes` put above the statement at its index, notes are sorted by
+/// the index and the ones past the end go below the last statement
+fn interleave<'a>(stmts: &[Statement<'a>], notes: &'a [(usize, String)]) -> Vec<Statement<'a>> {
+    let mut notes = notes.iter().peekable();
+    let mut annotated = Vec::with_capacity(stmts.len() + notes.len());
+    for (ix, stmt) in stmts.iter().enumerate() {
+        while let Some((_, note)) = notes.next_if(|(at, _)| *at == ix) {
+            annotated.push(Statement::Dunno(note));
+        }
+        annotated.push(stmt.clone());
+    }
+    annotated.extend(notes.map(|(_, note)| Statement::Dunno(note)));
+    annotated
+}
+
+/// Print the function `name` from `path` along with the notes of all the modes that ask for
+/// them: what the code of a naked function and the parts of an async state machine are, the
+/// unwind info and the instruction at `--addr`. With a `profile` the samples go in a gutter
+#[allow(clippy::too_many_arguments)]
+fn dump_code(
+    path: &Path,
+    name: &str,
+    files: &Sources,
+    sources: &[Statement],
+    fmt: &Format,
+    stmts: &[Statement],
+    profile: Option<&profile::Profile>,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    if fmt.quickfix {
+        return dump_range(files, fmt, stmts, out);
+    }
+    let naked;
+    let no_files = BTreeMap::new();
+    let (files, stmts) = if is_naked(stmts) {
+        let note = "naked function, the code is its naked_asm! block as written";
+        writeln!(out, "\t\t// {}", color!(note, comment))?;
+        naked = fmt.rust.then(|| naked_lines(path, name, stmts)).flatten();
+        match &naked {
+            Some((files, stmts)) => (files, stmts.as_slice()),
+            None => (&no_files, stmts),
+        }
+    } else {
+        (files, stmts)
+    };
+
+    let mut notes = Vec::new();
+    if fmt.async_fn && name.ends_with(coroutine::CLOSURE) {
+        let (file, points) = coroutine::await_points(path, name).unwrap_or_default();
+        let table = coroutine::jump_table(path, stmts)?;
+        notes.extend(coroutine::notes(stmts, sources, &file, &points, &table));
+    }
+    if fmt.unwind_info {
+        notes.extend(unwind::notes(path, stmts)?);
+    }
+    if let Some(addr) = fmt.addr {
+        notes.push(addr_note(path, stmts, addr)?);
+    }
+    notes.sort_by_key(|(ix, _)| *ix);
+    let annotated = interleave(stmts, &notes);
+    match profile {
+        Some(profile) => profile::dump(path, files, fmt, &annotated, profile, out),
+        None => dump_range(files, fmt, &annotated, out),
+    }
+}
+
+/// Note for the instruction of the function in `stmts` from `path` at `addr` of the binary
+/// rustc made along with the assembly, it goes right below that instruction
+fn addr_note(path: &Path, stmts: &[Statement], addr: u64) -> anyhow::Result<(usize, String)> {
+    let symbol = profile::symbol(stmts).unwrap_or_default();
+    let binary = crate::elf::object_near(path)
+        .ok_or_else(|| anyhow::anyhow!("No binary next to the assembly to look up {addr:#x} in"))?;
+    let Some((_, start)) = crate::elf::function_at(&binary, addr)? else {
+        anyhow::bail!("{addr:#x} is not in the code of {symbol}");
+    };
+    let offsets = profile::offsets(stmts, &profile::disassemble(&binary, symbol)?);
+    // the instruction with the address in it starts at or before it
+    let target = offsets
+        .iter()
+        .enumerate()
+        .filter_map(|(ix, offset)| Some((ix, (*offset)?)))
+        .filter(|(_, offset)| start + offset <= addr)
+        .max_by_key(|(_, offset)| *offset);
+    let note = match target {
+        Some(_) => format!(
+            "\t\t// ^ {addr:#x}, {:#x} bytes into the function",
+            addr - start
+        ),
+        None => format!(
+            "\t\t// {addr:#x} is {:#x} bytes into the function",
+            addr - start
+        ),
+    };
+    Ok(match target {
+        Some((ix, _)) => (ix + 1, note),
+        None => (0, note),
+    })
+}
+
+/// Symbol and demangled name with the hash of the function with code at `addr` in the binary
+/// rustc made along with `path`
+pub fn function_at(path: &Path, addr: u64) -> anyhow::Result<String> {
+    let binary = crate::elf::object_near(path).ok_or_else(|| {
+        anyhow::anyhow!(
+            "No binary next to the assembly, --addr works with --bin, --example or --test"
+        )
+    })?;
+    let Some((symbol, _)) = crate::elf::function_at(&binary, addr)? else {
+        anyhow::bail!(crate::exit::Failure::not_found(format!(
+            "No function in {} has code at {addr:#x}",
+            binary.display()
+        )));
+    };
+    if let Some(kind) = crate::elf::stub_kind(&symbol) {
+        anyhow::bail!(
+            "{addr:#x} is in {symbol}, {kind} made by the linker or the compiler with no source"
+        );
+    }
+    Ok(demangle::demangled(&symbol).map_or(symbol.clone(), |dem| format!("{dem:?}")))
+}
 
-    let mut empty_line = false;
-    for line in stmts.iter() {
+/// What printing statements carries over from one line to the next
+#[derive(Default)]
+struct Progress {
+    /// only file and line are compared, the rest is dropped so it can outlive the statements
+    prev_loc: Loc<'static>,
+    empty_line: bool,
+}
+
+fn dump_statements(
+    files: &Sources,
+    fmt: &Format,
+    stmts: &[Statement],
+    used: &dyn Fn(&str) -> bool,
+    progress: &mut Progress,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    let Progress {
+        prev_loc,
+        empty_line,
+    } = progress;
+    let tls = tls::notes(stmts);
+    for (ix, line) in stmts.iter().enumerate() {
         if fmt.verbosity > 2 {
-            println!("{line:?}");
+            eprintln!("{line:?}");
         }
         if let Statement::Directive(Directive::File(_)) = &line {
         } else if let Statement::Directive(Directive::Loc(loc)) = &line {
@@ -125,59 +836,74 @@ pub fn dump_range(
             if loc.line == 0 {
                 continue;
             }
-            if loc == &prev_loc {
+            let loc = &Loc {
+                extra: None,
+                ..*loc
+            };
+            if loc == prev_loc {
                 continue;
             }
-            prev_loc = *loc;
+            *prev_loc = *loc;
             if let Some((fname, file)) = files.get(&loc.file) {
                 let rust_line = &file[loc.line as usize - 1];
-                let pos = format!("\t\t// {} : {}", fname.display(), loc.line);
-                println!("{}", color!(pos, OwoColorize::cyan));
-                println!(
-                    "\t\t{}",
-                    color!(rust_line.trim_start(), OwoColorize::bright_red)
-                );
-            }
-            empty_line = false;
+                let pos = format!("{} : {}", fname.display(), loc.line);
+                let pos = pos.if_supports_color(owo_colors::Stream::Stdout, |pos| {
+                    hyperlink(fmt, fname, loc.line, pos)
+                });
+                writeln!(out, "\t\t// {}", color!(pos, location))?;
+                writeln!(out, "\t\t{}", color!(rust_line.trim_start(), source))?;
+            }
+            *empty_line = false;
         } else if let Statement::Label(Label { local: true, id }) = line {
-            if fmt.keep_labels || used.contains(id) {
-                println!("{line}");
-            } else if !empty_line {
-                println!();
-                empty_line = true;
+            if fmt.keep_labels || used(id) {
+                writeln!(out, "{line}")?;
+            } else if !*empty_line {
+                writeln!(out)?;
+                *empty_line = true;
             }
+        } else if fmt.simplify && matches!(line, Statement::Directive(_)) {
         } else {
-            empty_line = false;
+            *empty_line = false;
             #[allow(clippy::match_bool)]
             match fmt.full_name {
-                true => println!("{line:#}"),
-                false => println!("{line}"),
+                true => writeln!(out, "{line:#}")?,
+                false => writeln!(out, "{line}")?,
+            }
+            if let Statement::Instruction(instr) = line {
+                if let Some(note) = fmt
+                    .relocations
+                    .then(|| relocation::describe(instr))
+                    .flatten()
+                {
+                    let note = demangle::contents(&note, fmt.full_name);
+                    writeln!(out, "\t\t// {}", color!(note, comment))?;
+                }
+            }
+            if let Some(note) = tls.get(&ix) {
+                let note = demangle::contents(note, fmt.full_name);
+                writeln!(out, "\t\t// {}", color!(note, comment))?;
             }
         }
     }
     Ok(())
 }
 
-/// try to print `goal` from `path`, collect available items otherwise
-pub fn dump_function(
-    goal: Option<(&str, usize)>,
-    path: &Path,
-    sysroot: &Path,
-    fmt: &Format,
-    items: &mut Vec<Item>,
-) -> anyhow::Result<bool> {
-    let contents = std::fs::read_to_string(path)?;
-    let file = parse_file(&contents)?;
-    let functions = find_items(&file);
+type Sources<'a> = BTreeMap<u64, (std::borrow::Cow<'a, Path>, CachedLines)>;
 
+/// Load rust sources referenced by `.file` directives, only needed for `--rust`, `--quickfix`
+/// needs just the paths
+fn load_sources<'a>(file: &'a [Statement<'a>], sysroot: &Path, fmt: &Format) -> Sources<'a> {
     let mut files = BTreeMap::new();
-    if fmt.rust {
-        for line in &file {
+    if fmt.rust || fmt.quickfix {
+        for line in file {
             if let Statement::Directive(Directive::File(f)) = line {
                 files.entry(f.index).or_insert_with(|| {
                 let path = f.path.as_full_path();
+                if !fmt.rust {
+                    return (path, CachedLines::without_ending(String::new()));
+                }
                 if fmt.verbosity > 1 {
-                    println!("Reading file #{} {:?}", f.index, path);
+                    eprintln!("Reading file #{} {:?}", f.index, path);
                 }
                 if let Ok(payload) = std::fs::read_to_string(&path) {
                     return (path, CachedLines::without_ending(payload));
@@ -220,7 +946,7 @@ pub fn dump_function(
                        return (path, CachedLines::without_ending(payload));
                     }
                 } else if fmt.verbosity > 0 {
-                    println!("File not found {:?}", path);
+                    eprintln!("File not found {:?}", path);
                 }
                 // if file is not found - ust create a dummy
                 (path, CachedLines::without_ending(String::new()))
@@ -228,24 +954,524 @@ pub fn dump_function(
             }
         }
     }
+    files
+}
 
+/// Print what `goal` from `path` calls, collect all available items if it's not there
+fn dump_call_tree(
+    goal: Option<(&str, usize)>,
+    path: &Path,
+    fmt: &Format,
+    out: &mut dyn Write,
+    items: &mut Vec<Item>,
+) -> anyhow::Result<bool> {
+    // calls go anywhere in the file so all of it is parsed
+    let contents = std::fs::read_to_string(path)?;
+    let file = parse_file(&contents)?;
+    let functions = find_items(path, &file);
+    let graph = functions
+        .iter()
+        .filter_map(|(item, range)| {
+            let stmts = &file[range.clone()];
+            Some((profile::symbol(stmts)?, (item, calls::callees(stmts))))
+        })
+        .collect::<calls::Graph>();
+    let root = goal.and_then(|goal| {
+        graph
+            .iter()
+            .find(|(_, (item, _))| item.matches(goal))
+            .map(|(symbol, _)| *symbol)
+    });
+    let Some(root) = root else {
+        *items = functions.keys().cloned().collect();
+        return Ok(false);
+    };
+    let sizes = crate::elf::sizes_near(path);
+    let depth = fmt.depth.unwrap_or(usize::MAX);
+    calls::dump(&graph, root, &sizes, depth, fmt.full_name, out)?;
+    Ok(true)
+}
+
+/// try to print `goal` from `path`, collect all available items otherwise. `comment` starts
+/// the lines added when printing the whole file so it stays valid asm
+pub fn dump_function(
+    goal: Option<(&str, usize)>,
+    path: &Path,
+    sysroot: &Path,
+    comment: &str,
+    fmt: &Format,
+    out: &mut dyn Write,
+    items: &mut Vec<Item>,
+) -> anyhow::Result<bool> {
+    if fmt.call_tree {
+        return dump_call_tree(goal, path, fmt, out, items);
+    }
     if let Some(goal) = goal {
-        for (item, range) in &functions {
-            if (item.name.as_ref(), item.index) == goal || item.hashed == goal.0 {
-                dump_range(&files, fmt, &file[range.clone()])?;
+        // files from LTO builds can be huge, only the code of the goal is parsed
+        let index = Index::new(path)?;
+        for (item, range) in &index.items {
+            if item.matches(goal) {
+                let sources = parse_file(&index.files)?;
+                let files = load_sources(&sources, sysroot, fmt);
+                let code = index::read_range(path, range)?;
+                let stmts = parse_file(&code)?;
+                let profile = profile::Profile::load(fmt)?;
+                dump_code(
+                    path,
+                    &item.name,
+                    &files,
+                    &sources,
+                    fmt,
+                    &stmts,
+                    profile.as_ref(),
+                    out,
+                )?;
                 return Ok(true);
             }
         }
 
-        *items = functions
-            .keys()
-            .filter(|i| i.name.contains(goal.0))
-            .cloned()
-            .collect::<Vec<_>>();
+        *items = index.items.into_keys().collect::<Vec<_>>();
 
         Ok(false)
+    } else if fmt.quickfix {
+        let contents = std::fs::read_to_string(path)?;
+        let file = parse_file(&contents)?;
+        let files = load_sources(&file, sysroot, fmt);
+        dump_range(&files, fmt, &file, out)?;
+        Ok(true)
     } else {
-        dump_range(&files, fmt, &file)?;
+        dump_all(path, sysroot, comment, fmt, out)?;
         Ok(true)
     }
 }
+
+/// Print the whole file a block at a time so it doesn't have to fit into memory, labels used
+/// anywhere in the file and the sources are collected in the first pass over it
+fn dump_all(
+    path: &Path,
+    sysroot: &Path,
+    comment: &str,
+    fmt: &Format,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    let mut sources = String::new();
+    let mut used = BTreeSet::new();
+    for_each_block(path, &mut |block| {
+        for line in block.lines().filter(|line| line.starts_with("\t.file\t")) {
+            sources.push_str(line);
+            sources.push('\n');
+        }
+        let stmts = parse_file(block)?;
+        if !fmt.keep_labels {
+            used.extend(used_labels(&stmts).into_iter().map(str::to_owned));
+        }
+        Ok(())
+    })?;
+    let sources = parse_file(&sources)?;
+    let files = load_sources(&sources, sysroot, fmt);
+    let used = |label: &str| used.contains(label);
+
+    let mut starts = Index::new(path)?
+        .items
+        .into_iter()
+        .map(|(item, range)| {
+            let name = if fmt.full_name {
+                item.hashed
+            } else {
+                item.name
+            };
+            (range.start, name)
+        })
+        .collect::<Vec<_>>();
+    starts.sort();
+    // the first pass only counts lines to know where each function ends up in the output
+    let lines = dump_sections(
+        &files,
+        fmt,
+        path,
+        comment,
+        &starts,
+        &used,
+        &mut std::io::sink(),
+    )?;
+    let contents = format!(
+        "{comment} {} functions, by line in this output:",
+        starts.len()
+    );
+    writeln!(out, "{}", color!(contents, comment))?;
+    // contents take a line for each function, the heading and a blank line
+    let shift = starts.len() + 2;
+    for ((_, name), line) in starts.iter().zip(lines) {
+        let line = format!("{comment}{:>8}", line + shift + 1);
+        writeln!(out, "{}  {}", color!(line, comment), color!(name, symbol))?;
+    }
+    writeln!(out)?;
+    dump_sections(&files, fmt, path, comment, &starts, &used, out)?;
+    Ok(())
+}
+
+/// Print the file at `path` a block at a time with a separator before each function that
+/// starts at one of the byte offsets in `starts`, gives the number of lines printed before
+/// each separator
+fn dump_sections(
+    files: &Sources,
+    fmt: &Format,
+    path: &Path,
+    comment: &str,
+    starts: &[(usize, String)],
+    used: &dyn Fn(&str) -> bool,
+    out: &mut dyn Write,
+) -> anyhow::Result<Vec<usize>> {
+    let mut out = LineTracker {
+        inner: out,
+        lines: 0,
+    };
+    let mut progress = Progress::default();
+    let mut offset = 0;
+    let mut next = starts.iter().peekable();
+    let mut lines = Vec::with_capacity(starts.len());
+    for_each_block(path, &mut |block| {
+        let end = offset + block.len();
+        let mut rest = block;
+        let mut at = offset;
+        while let Some((start, name)) = next.next_if(|(start, _)| *start < end) {
+            // `rest` is split on line boundaries, items start with a line
+            let (before, after) = rest.split_at(start.saturating_sub(at).min(rest.len()));
+            dump_statements(
+                files,
+                fmt,
+                &parse_file(before)?,
+                used,
+                &mut progress,
+                &mut out,
+            )?;
+            lines.push(out.lines);
+            let separator = format!("{comment} ======== {name}");
+            writeln!(out, "{}", color!(separator, header))?;
+            at += before.len();
+            rest = after;
+        }
+        dump_statements(
+            files,
+            fmt,
+            &parse_file(rest)?,
+            used,
+            &mut progress,
+            &mut out,
+        )?;
+        offset = end;
+        Ok(())
+    })?;
+    Ok(lines)
+}
+
+/// Passes everything through to `inner` and counts the lines
+struct LineTracker<'a> {
+    inner: &'a mut dyn Write,
+    lines: usize,
+}
+
+impl Write for LineTracker<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.lines += buf[..len].iter().filter(|b| **b == b'\n').count();
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Call `f` on consecutive blocks of whole lines from `path`
+fn for_each_block(
+    path: &Path,
+    f: &mut dyn FnMut(&str) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let mut reader = std::io::BufReader::new(std::fs::File::open(path)?);
+    let mut block = String::new();
+    while std::io::BufRead::read_line(&mut reader, &mut block)? > 0 {
+        if block.len() > index::BLOCK {
+            f(&block)?;
+            block.clear();
+        }
+    }
+    if !block.is_empty() {
+        f(&block)?;
+    }
+    Ok(())
+}
+
+/// Marker [`merge_codegen_units`] puts before the code of each unit
+const CGU_MARKER: &str = "cgu ";
+
+/// Combine assembly rustc produced for several codegen units, `.file` and `.loc` directives
+/// are renumbered so each unit keeps its own sources and a `.cgu NAME` line is put before
+/// each one
+pub fn merge_codegen_units(parts: &[PathBuf], out: &mut dyn Write) -> anyhow::Result<()> {
+    let mut base = 0;
+    for part in parts {
+        let contents = std::fs::read_to_string(part)?;
+        let name = part.file_stem().unwrap_or_default().to_string_lossy();
+        // `{stem}.{crate}.{hash}-cgu.N.rcgu`, stem is the same for all of them
+        let name = name.trim_end_matches(".rcgu");
+        let name = name.split_once('.').map_or(name, |(_, name)| name);
+        writeln!(out, "\t.{CGU_MARKER}{name}")?;
+        let mut last = base;
+        for line in contents.lines() {
+            let renumbered = ["\t.file\t", "\t.loc\t"].iter().find_map(|prefix| {
+                let rest = line.strip_prefix(prefix)?;
+                let end = rest.find(|c: char| !c.is_ascii_digit())?;
+                let index = rest[..end].parse::<u64>().ok()? + base;
+                last = last.max(index + 1);
+                Some(format!("{prefix}{index}{}", &rest[end..]))
+            });
+            writeln!(out, "{}", renumbered.as_deref().unwrap_or(line))?;
+        }
+        base = last;
+    }
+    Ok(())
+}
+
+/// Codegen unit `goal` comes from in a file [`merge_codegen_units`] made, `None` for other
+/// files
+pub fn codegen_unit(path: &Path, goal: (&str, usize)) -> anyhow::Result<Option<String>> {
+    let index = Index::new(path)?;
+    Ok(index
+        .items
+        .iter()
+        .find(|(item, _)| item.matches(goal))
+        .and_then(|(_, range)| index.unit(range))
+        .map(str::to_owned))
+}
+
+/// Source lines each of the items has code for
+pub type Locations = Vec<(Item, Vec<(PathBuf, u64)>)>;
+
+/// Collect source lines each item in `path` has code for, paths are the same as in `.file`
+/// directives
+pub fn locations(path: &Path) -> anyhow::Result<Locations> {
+    let contents = std::fs::read_to_string(path)?;
+    let file = parse_file(&contents)?;
+    let mut files = BTreeMap::new();
+    for line in &file {
+        if let Statement::Directive(Directive::File(f)) = line {
+            files
+                .entry(f.index)
+                .or_insert_with(|| f.path.as_full_path().into_owned());
+        }
+    }
+    Ok(find_items(path, &file)
+        .into_iter()
+        .map(|(item, range)| {
+            let lines = file[range]
+                .iter()
+                .filter_map(|line| match line {
+                    Statement::Directive(Directive::Loc(loc)) if loc.line > 0 => {
+                        Some((files.get(&loc.file)?.clone(), loc.line))
+                    }
+                    _ => None,
+                })
+                .collect();
+            (item, lines)
+        })
+        .collect())
+}
+
+/// Up to `n` of `items` the profile from `--perf` or `--callgrind` has the most samples in,
+/// along with how many
+pub fn hottest(fmt: &Format, items: &[Item], n: usize) -> anyhow::Result<Vec<(Item, u64)>> {
+    let Some(profile) = profile::Profile::load(fmt)? else {
+        anyhow::bail!("--hottest needs a profile, pass one with --perf or --callgrind");
+    };
+    Ok(profile::hottest(&profile, items, n)
+        .into_iter()
+        .map(|(item, count)| (item.clone(), count))
+        .collect())
+}
+
+/// print every item from `path` selected by `filter`, `open` gives a writer for each one
+pub fn dump_each(
+    path: &Path,
+    sysroot: &Path,
+    fmt: &Format,
+    filter: &dyn Fn(&Item) -> bool,
+    open: &mut dyn FnMut(&Item) -> anyhow::Result<Box<dyn Write>>,
+) -> anyhow::Result<()> {
+    let contents = std::fs::read_to_string(path)?;
+    let file = parse_file(&contents)?;
+    let functions = find_items(path, &file);
+    let files = load_sources(&file, sysroot, fmt);
+    // loaded for the first function that needs it
+    let mut profile = None;
+
+    for (item, range) in &functions {
+        if filter(item) {
+            let mut out = open(item)?;
+            let stmts = &file[range.clone()];
+            if profile.is_none() && (fmt.perf.is_some() || fmt.callgrind.is_some()) {
+                profile = profile::Profile::load(fmt)?;
+            }
+            let (name, profile) = (&item.name, profile.as_ref());
+            dump_code(path, name, &files, &file, fmt, stmts, profile, &mut out)?;
+            out.flush()?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::index::{read_range, Index};
+    use super::{codegen_unit, events, find_items, merge_codegen_units, parse_file, Event};
+    use std::path::Path;
+
+    #[test]
+    fn events_in_order() {
+        // more lines than one chunk takes so offsets need to account for the chunk too
+        let lines = (0..5000).collect::<Vec<usize>>();
+        let found = events(&lines, |n| {
+            (n % 7 == 0).then(|| Event::Label(n.to_string()))
+        })
+        .into_iter()
+        .map(|(ix, event)| match event {
+            Event::Label(label) => (ix, label),
+            _ => unreachable!(),
+        })
+        .collect::<Vec<_>>();
+        let expected = (0..5000)
+            .step_by(7)
+            .map(|n| (n, n.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(found, expected);
+    }
+
+    /// Assembly of functions `symbols` in that order, each one with its own code
+    fn functions(symbols: &[(&str, usize)]) -> String {
+        symbols
+            .iter()
+            .map(|(sym, n)| {
+                format!(
+                    "\t.section\t.text.{sym},\"ax\",@progbits\n\t.globl\t{sym}\n{sym}:\n\
+                     \t.cfi_startproc\n\tmov eax, {n}\n\tret\n.Lfunc_end{n}:\n\t.cfi_endproc\n"
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn stable_order() {
+        let first = ("_ZN6sample3gen17h1111111111111111E", 1);
+        let second = ("_ZN6sample3gen17h2222222222222222E", 2);
+        let other = ("_ZN6sample1f17h0123456789abcdefE", 3);
+        let listing = |asm: &str| {
+            let file = parse_file(asm).unwrap();
+            find_items(Path::new(""), &file)
+                .into_iter()
+                .map(|(item, range)| {
+                    (
+                        item.name,
+                        item.hashed,
+                        item.index,
+                        format!("{:?}", &file[range]),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let index = |asm: &str, tag: &str| {
+            let path = std::env::temp_dir().join(format!(
+                "cargo-show-asm-order-{}-{tag}.s",
+                std::process::id()
+            ));
+            std::fs::write(&path, asm).unwrap();
+            let index = Index::new(&path).unwrap();
+            let res = index
+                .items
+                .iter()
+                .map(|(item, range)| {
+                    let code = read_range(&path, range).unwrap();
+                    (item.name.clone(), item.hashed.clone(), item.index, code)
+                })
+                .collect::<Vec<_>>();
+            std::fs::remove_file(&path).unwrap();
+            res
+        };
+
+        let asm = functions(&[first, second, other]);
+        let reordered = functions(&[other, second, first]);
+        let items = listing(&asm);
+        assert_eq!(
+            items
+                .iter()
+                .map(|(name, hashed, index, _)| (name.as_str(), hashed.as_str(), *index))
+                .collect::<Vec<_>>(),
+            [
+                ("sample::f", "sample::f::h0123456789abcdef", 0),
+                ("sample::gen", "sample::gen::h1111111111111111", 0),
+                ("sample::gen", "sample::gen::h2222222222222222", 1),
+            ]
+        );
+        assert!(items[2].3.contains(r#"args: Some("eax, 2")"#));
+        assert_eq!(items, listing(&reordered));
+
+        let streamed = index(&asm, "first");
+        assert!(streamed[2].3.contains("\tmov eax, 2\n"));
+        assert_eq!(streamed, index(&reordered, "reordered"));
+    }
+
+    #[test]
+    fn merged_codegen_units() {
+        let dir = std::env::temp_dir().join(format!("cargo-show-asm-cgu-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let units = [
+            ("_ZN6sample1f17h0123456789abcdefE", "/src/lib.rs", 1),
+            ("_ZN6sample1g17h0123456789abcdefE", "/src/other.rs", 2),
+        ]
+        .iter()
+        .enumerate()
+        .map(|(n, (sym, source, code))| {
+            let part = dir.join(format!("sample-1234.sample.abc-cgu.{n}.rcgu.s"));
+            let asm = format!(
+                "\t.file\t1 \"{source}\"\n{}",
+                functions(&[(sym, *code)]).replace("\tmov", "\t.loc\t1 3 5\n\tmov")
+            );
+            std::fs::write(&part, asm).unwrap();
+            part
+        })
+        .collect::<Vec<_>>();
+        let mut out = Vec::new();
+        merge_codegen_units(&units, &mut out).unwrap();
+        let merged = String::from_utf8(out).unwrap();
+        let directives = merged
+            .lines()
+            .filter(|line| {
+                line.starts_with("\t.cgu")
+                    || line.starts_with("\t.file")
+                    || line.starts_with("\t.loc")
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            directives,
+            [
+                "\t.cgu sample.abc-cgu.0",
+                "\t.file\t1 \"/src/lib.rs\"",
+                "\t.loc\t1 3 5",
+                "\t.cgu sample.abc-cgu.1",
+                // DWARF 5 numbers files from 0, the next unit skips 2 to stay clear of it
+                "\t.file\t3 \"/src/other.rs\"",
+                "\t.loc\t3 3 5",
+            ]
+        );
+
+        let path = dir.join("sample-1234.s");
+        std::fs::write(&path, &merged).unwrap();
+        let unit = |name| codegen_unit(&path, (name, 0)).unwrap();
+        assert_eq!(unit("sample::f").as_deref(), Some("sample.abc-cgu.0"));
+        assert_eq!(unit("sample::g").as_deref(), Some("sample.abc-cgu.1"));
+        assert_eq!(unit("sample::h"), None);
+        // files made by rustc directly have no units
+        std::fs::write(&path, functions(&[("_ZN6sample1f17h0123456789abcdefE", 1)])).unwrap();
+        assert_eq!(unit("sample::f"), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,193 @@
+//! Calls from one function to others for `--call-tree`: direct calls, calls through the PLT or
+//! the GOT and tail calls, indirect calls through registers have no target to follow
+use super::relocation;
+use super::statements::{Instruction, Statement};
+use super::Item;
+use crate::{color, demangle};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+
+/// Symbols `stmts` calls or jumps to, in the order of the first call to each
+pub(super) fn callees<'a>(stmts: &[Statement<'a>]) -> Vec<&'a str> {
+    let mut res = Vec::new();
+    for stmt in stmts {
+        let Statement::Instruction(Instruction {
+            op,
+            args: Some(args),
+        }) = stmt
+        else {
+            continue;
+        };
+        if !(relocation::is_call(op) || relocation::is_jump(op)) {
+            continue;
+        }
+        let Some(symbol) = relocation::symbol(args) else {
+            continue;
+        };
+        let symbol = symbol.split('@').next().unwrap_or(symbol);
+        // local labels are jumps inside of the function
+        if symbol.starts_with(".L") || symbol.starts_with('L') || res.contains(&symbol) {
+            continue;
+        }
+        res.push(symbol);
+    }
+    res
+}
+
+/// Functions of the file by symbol along with the symbols they call
+pub(super) type Graph<'a> = BTreeMap<&'a str, (&'a Item, Vec<&'a str>)>;
+
+struct Tree<'a, 'b> {
+    graph: &'b Graph<'a>,
+    sizes: &'b BTreeMap<String, u64>,
+    depth: usize,
+    full_name: bool,
+    shown: BTreeSet<&'a str>,
+}
+
+impl<'a> Tree<'a, '_> {
+    fn size(&self, item: &Item) -> String {
+        if self.sizes.is_empty() {
+            format!("{} lines", item.len)
+        } else {
+            format!(
+                "{} bytes",
+                self.sizes.get(&item.hashed).copied().unwrap_or_default()
+            )
+        }
+    }
+
+    fn name(&self, item: &Item) -> String {
+        if self.full_name {
+            item.hashed.clone()
+        } else {
+            item.name.clone()
+        }
+    }
+
+    fn print(&mut self, symbol: &'a str, level: usize, out: &mut dyn Write) -> anyhow::Result<()> {
+        let indent = "    ".repeat(level);
+        let Some((item, callees)) = self.graph.get(symbol) else {
+            let name = demangle::plain(symbol, self.full_name);
+            writeln!(
+                out,
+                "{indent}{} {}",
+                color!(name, symbol),
+                color!("not in this file", comment)
+            )?;
+            return Ok(());
+        };
+        let size = self.size(item);
+        let note = if callees.is_empty() {
+            ""
+        } else if !self.shown.insert(symbol) {
+            " calls are listed above"
+        } else if level == self.depth {
+            " calls more, use a larger --depth to see them"
+        } else {
+            ""
+        };
+        writeln!(
+            out,
+            "{indent}{} {}{}",
+            color!(self.name(item), symbol),
+            color!(size, size),
+            color!(note, comment)
+        )?;
+        if note.is_empty() {
+            for callee in callees {
+                self.print(callee, level + 1, out)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Everything `root` calls directly or through other functions, `root` included
+fn reachable<'a>(graph: &Graph<'a>, root: &'a str) -> BTreeSet<&'a str> {
+    let mut res = BTreeSet::new();
+    let mut todo = vec![root];
+    while let Some(symbol) = todo.pop() {
+        if let Some((_, callees)) = graph.get(symbol) {
+            if res.insert(symbol) {
+                todo.extend(callees);
+            }
+        }
+    }
+    res
+}
+
+/// Print `root` and the functions it calls up to `depth` levels down with their sizes, in
+/// bytes if `sizes` has them and in lines of code otherwise
+pub(super) fn dump(
+    graph: &Graph,
+    root: &str,
+    sizes: &BTreeMap<String, u64>,
+    depth: usize,
+    full_name: bool,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    let all = reachable(graph, root);
+    let (unit, total) = if sizes.is_empty() {
+        (
+            "lines",
+            all.iter().map(|s| graph[s].0.len as u64).sum::<u64>(),
+        )
+    } else {
+        let total = all
+            .iter()
+            .filter_map(|s| sizes.get(&graph[s].0.hashed))
+            .sum::<u64>();
+        ("bytes", total)
+    };
+    let summary = format!(
+        "// {total} {unit} in {} functions with everything it calls",
+        all.len()
+    );
+    writeln!(out, "{}", color!(summary, comment))?;
+    let mut tree = Tree {
+        graph,
+        sizes,
+        depth,
+        full_name,
+        shown: BTreeSet::new(),
+    };
+    tree.print(root, 0, out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::callees;
+    use crate::asm::statements::{Instruction, Statement};
+
+    #[test]
+    fn call_targets() {
+        let instr = |op, args| {
+            Statement::Instruction(Instruction {
+                op,
+                args: Some(args),
+            })
+        };
+        let stmts = [
+            instr("call", "_ZN4core9panicking5panic17h01E"),
+            instr("je", ".LBB3_2"),
+            instr(
+                "call",
+                "qword ptr [rip + _ZN5alloc5alloc6alloc17h02E@GOTPCREL]",
+            ),
+            instr("call", "memcpy@PLT"),
+            instr("call", "rax"),
+            instr("call", "memcpy@PLT"),
+            instr("jmp", "_ZN4cgut1b17h03E"),
+        ];
+        assert_eq!(
+            callees(&stmts),
+            [
+                "_ZN4core9panicking5panic17h01E",
+                "_ZN5alloc5alloc6alloc17h02E",
+                "memcpy",
+                "_ZN4cgut1b17h03E"
+            ]
+        );
+    }
+}
@@ -0,0 +1,184 @@
+//! The code of an `async fn` is in the `poll` of its state machine, `{{closure}}` inside of the
+//! function. State 0 is the first poll, 1 and 2 are for polls after it returned or panicked
+//! and every `.await` in the body gets a state of its own in order starting from 3
+use super::global_asm::sources;
+use super::statements::{Directive, Label, Statement};
+use crate::demangle;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+/// Part of the name that tells a state machine from the function that makes it
+pub const CLOSURE: &str = "::{{closure}}";
+
+/// Lines with `.await` in the body of `async fn name` in `source`, once for every `.await`,
+/// counting from 1
+fn await_lines(source: &str, name: &str) -> Option<Vec<u64>> {
+    let start = source
+        .match_indices(&format!("async fn {name}"))
+        .map(|(pos, pat)| pos + pat.len())
+        .find(|pos| source[*pos..].starts_with(['(', '<']))?;
+    let open = start + source[start..].find('{')?;
+    let mut depth = 0;
+    let mut res = Vec::new();
+    let mut line = source[..open].matches('\n').count() as u64 + 1;
+    for (ix, c) in source[open..].char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+            '\n' => line += 1,
+            '.' if source[open + ix..].starts_with(".await") => res.push(line),
+            _ => {}
+        }
+    }
+    Some(res)
+}
+
+/// Await points of the `async fn` the state machine `name` is for in the sources the file at
+/// `path` was made from: the file and the line of each one
+pub(super) fn await_points(path: &Path, name: &str) -> Option<(PathBuf, Vec<u64>)> {
+    let short = name.strip_suffix(CLOSURE)?.rsplit("::").next()?;
+    sources(path).into_iter().find_map(|file| {
+        let source = std::fs::read_to_string(&file).ok()?;
+        let lines = await_lines(&source, short)?;
+        Some((file, lines))
+    })
+}
+
+fn describe(state: usize, points: &[u64]) -> String {
+    match state {
+        0 => "first poll".to_owned(),
+        1 => "poll after it returned".to_owned(),
+        2 => "poll after it panicked".to_owned(),
+        _ => match points.get(state - 3) {
+            Some(line) => format!("resumed at await point {} on line {line}", state - 2),
+            None => format!("resumed at await point {}", state - 2),
+        },
+    }
+}
+
+/// Targets of the jump table the state machine in `stmts` starts with, in the order of states.
+/// LLVM puts the table after the function so it is looked up in the file at `path`
+pub(super) fn jump_table(path: &Path, stmts: &[Statement]) -> anyhow::Result<Vec<String>> {
+    let Some(table) = stmts.iter().find_map(|stmt| match stmt {
+        Statement::Instruction(instr) => demangle::local_labels(instr.args?)
+            .map(|m| m.as_str())
+            .find(|label| label.contains("JTI")),
+        _ => None,
+    }) else {
+        return Ok(Vec::new());
+    };
+    let start = format!("{table}:");
+    let mut res = Vec::new();
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    for line in lines.by_ref() {
+        if line? == start {
+            break;
+        }
+    }
+    for line in lines {
+        let line = line?;
+        let Some(target) = demangle::local_labels(&line)
+            .map(|m| m.as_str())
+            .find(|label| *label != table)
+        else {
+            break;
+        };
+        res.push(target.to_owned());
+    }
+    Ok(res)
+}
+
+/// Notes for the code of a state machine, each one goes before the statement with that index:
+/// which state each of the `table` targets resumes in and, using `.loc`s pointing at `file`,
+/// where the code for each await point is
+pub(super) fn notes(
+    stmts: &[Statement],
+    sources: &[Statement],
+    file: &Path,
+    points: &[u64],
+    table: &[String],
+) -> Vec<(usize, String)> {
+    let mut res = vec![(
+        0,
+        "\t\t// state machine of an async fn, states are 0 for the first poll, 1 and 2 after \
+         it returned or panicked and 3 and up for the await points"
+            .to_owned(),
+    )];
+    let file = file.canonicalize().unwrap_or_else(|_| file.to_owned());
+    let indices = sources
+        .iter()
+        .filter_map(|stmt| match stmt {
+            Statement::Directive(Directive::File(f)) => Some(f),
+            _ => None,
+        })
+        .filter(|f| {
+            let path = f.path.as_full_path();
+            path.canonicalize()
+                .map_or(*path == file, |path| path == file)
+        })
+        .map(|f| f.index)
+        .collect::<Vec<_>>();
+
+    let mut states = BTreeMap::<&str, Vec<usize>>::new();
+    for (state, label) in table.iter().enumerate() {
+        states.entry(label).or_default().push(state);
+    }
+
+    let mut prev = None;
+    for (ix, stmt) in stmts.iter().enumerate() {
+        match stmt {
+            Statement::Label(Label { id, .. }) => {
+                for state in states.get(id).into_iter().flatten() {
+                    let note =
+                        format!("\t\t// {id} is state {state}: {}", describe(*state, points));
+                    res.push((ix + 1, note));
+                }
+            }
+            Statement::Directive(Directive::Loc(loc)) if loc.line > 0 => {
+                let here = (loc.file, loc.line);
+                if prev == Some(here) {
+                    continue;
+                }
+                prev = Some(here);
+                if !indices.contains(&loc.file) {
+                    continue;
+                }
+                let mut seen = points.iter().enumerate().filter(|(_, l)| **l == loc.line);
+                if let Some((first, _)) = seen.next() {
+                    let more = seen.count();
+                    let note = if more == 0 {
+                        format!("\t\t// await point {}", first + 1)
+                    } else {
+                        format!("\t\t// await points {} to {}", first + 1, first + 1 + more)
+                    };
+                    res.push((ix + 1, note));
+                }
+            }
+            _ => {}
+        }
+    }
+    res.sort_by_key(|(ix, _)| *ix);
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::await_lines;
+
+    #[test]
+    fn await_points() {
+        let source = "async fn other() {}\n\npub async fn work(x: u32) -> u32 {\n    \
+                      let a = other(x).await;\n    if x > 1 {\n        other(a).await;\n    }\n    \
+                      a\n}\nasync fn after() { other().await }\n";
+        assert_eq!(await_lines(source, "work").unwrap(), [4, 6]);
+        assert_eq!(await_lines(source, "other").unwrap(), Vec::<u64>::new());
+        assert!(await_lines(source, "wor").is_none());
+    }
+}
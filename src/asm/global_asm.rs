@@ -0,0 +1,349 @@
+//! Code from `global_asm!` has no symbol of its own. LLVM puts all of it right after the `.file`
+//! directive naming the codegen unit, with two blank lines around each block and three after
+//! the last one. Blocks are named after the module with the `global_asm!` that has the same
+//! first label. Naked functions go there too, as blocks rustc made from their `naked_asm!`.
+use super::{Event, Pos};
+use crate::cache;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// Code from one `global_asm!` or one naked function
+pub(super) struct Block {
+    /// first label in it, blocks are matched to the invocations by it
+    pub label: Option<String>,
+    /// demangled name with and without the hash when the first label is a function
+    pub function: Option<(String, String)>,
+    /// rustc puts the code of naked functions between their label and `.Lfunc_end_{symbol}`
+    pub naked: bool,
+    pub range: Range<Pos>,
+}
+
+/// Blocks found so far
+#[derive(Default)]
+pub(super) struct GlobalAsm {
+    /// still in the part of the file blocks go to
+    active: bool,
+    /// current run of blank lines: its start, the line after it and its length
+    blanks: Option<(Pos, Pos, usize)>,
+    block: Option<Block>,
+    pub found: Vec<Block>,
+}
+
+impl GlobalAsm {
+    pub fn push(&mut self, here: Pos, next: Pos, event: &Event) {
+        match event {
+            Event::UnitFile => {
+                self.active = true;
+                self.blanks = None;
+                self.block = None;
+            }
+            _ if !self.active => {}
+            Event::Blank => match &mut self.blanks {
+                Some((_, end, len)) if end.line == here.line => {
+                    *end = next;
+                    *len += 1;
+                }
+                _ => {
+                    self.end_blanks();
+                    self.blanks = Some((here, next, 1));
+                }
+            },
+            _ => {
+                self.end_blanks();
+                match (&mut self.block, event) {
+                    // anything else right after `.file` means there are no blocks
+                    (None, _) => self.active = false,
+                    (Some(block), Event::EndLabel) => block.naked = block.function.is_some(),
+                    (Some(block @ Block { label: None, .. }), Event::Label(id)) => {
+                        block.label = Some(id.clone());
+                    }
+                    (
+                        Some(block @ Block { label: None, .. }),
+                        Event::Function(id, name, hashed),
+                    ) => {
+                        block.label = Some(id.clone());
+                        block.function = Some((name.clone(), hashed.clone()));
+                    }
+                    (Some(_), _) => {}
+                }
+            }
+        }
+    }
+
+    fn end_blanks(&mut self) {
+        let Some((start, end, len)) = self.blanks.take() else {
+            return;
+        };
+        if len < 2 || !self.active {
+            return;
+        }
+        // naked functions come with two blank lines of their own
+        let naked = self.block.as_ref().is_some_and(|block| block.naked);
+        if let Some(mut block) = self.block.take() {
+            block.range.end = start;
+            self.found.push(block);
+        }
+        if len >= if naked { 5 } else { 3 } {
+            self.active = false;
+        } else {
+            self.block = Some(Block {
+                label: None,
+                function: None,
+                naked: false,
+                range: end..end,
+            });
+        }
+    }
+}
+
+/// `global_asm!` invocations in a source file: module they are in, relative to the file, and
+/// the labels they define
+fn invocations(source: &str) -> Vec<(Vec<String>, Vec<String>)> {
+    let mut res = Vec::new();
+    // inline modules along with the nesting depth their body starts at
+    let mut modules = Vec::<(String, usize)>::new();
+    let mut depth = 0usize;
+    let mut current: Option<(Vec<String>, String)> = None;
+    for line in source.lines() {
+        let code = line.split("//").next().unwrap_or("");
+        let trimmed = code.trim_start().trim_start_matches("pub ");
+        if let Some(name) = trimmed
+            .strip_prefix("mod ")
+            .and_then(|rest| rest.split_once('{'))
+            .map(|(name, _)| name.trim())
+        {
+            modules.push((name.to_owned(), depth + 1));
+        }
+        if let Some(pos) = code.find("global_asm!") {
+            let module = modules.iter().map(|(name, _)| name.clone()).collect();
+            current = Some((module, code[pos..].to_owned()));
+        } else if let Some((_, text)) = &mut current {
+            text.push('\n');
+            text.push_str(code);
+        }
+        if let Some((module, text)) = current.take_if(|(_, text)| text.contains(");")) {
+            res.push((module, labels(&text)));
+        }
+        for c in code.chars() {
+            match c {
+                '{' => depth += 1,
+                '}' => {
+                    depth = depth.saturating_sub(1);
+                    if modules.last().is_some_and(|(_, start)| *start > depth) {
+                        modules.pop();
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    res
+}
+
+/// Labels defined in the string literals of a `global_asm!` invocation
+fn labels(text: &str) -> Vec<String> {
+    text.split('"')
+        .skip(1)
+        .step_by(2)
+        .flat_map(|lit| lit.split("\\n"))
+        .filter_map(|line| line.trim().strip_suffix(':'))
+        .filter(|label| !label.is_empty() && !label.contains(char::is_whitespace))
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Module a source file is for, `root` is the directory of the crate root
+fn file_module(root: &Path, file: &Path) -> Vec<String> {
+    let Ok(rel) = file.strip_prefix(root) else {
+        return Vec::new();
+    };
+    let mut parts = rel
+        .with_extension("")
+        .iter()
+        .map(|part| part.to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    if parts.len() == 1 || parts.last().is_some_and(|part| part == "mod") {
+        parts.pop();
+    }
+    parts
+}
+
+/// Rust sources the file at `path` was made from, the first one is the crate root
+pub(super) fn sources(path: &Path) -> Vec<PathBuf> {
+    let deps = cache::dependencies(path).unwrap_or_default();
+    // dep-info paths are relative to the workspace, the asm file is somewhere inside of it
+    let resolve = |dep: &PathBuf| {
+        path.ancestors()
+            .map(|dir| dir.join(dep))
+            .find(|full| full.exists())
+    };
+    deps.iter()
+        .filter(|dep| dep.extension().is_some_and(|ext| ext == "rs"))
+        .filter_map(resolve)
+        .collect()
+}
+
+/// String literals in the macro invocation `source` starts with along with the lines they are
+/// on, counting from 0. Stops at the closing paren
+fn literals(source: &str) -> Vec<(usize, String)> {
+    let mut res = Vec::new();
+    let mut line = 0;
+    let mut depth = 0;
+    let mut string = None::<String>;
+    let mut chars = source.chars();
+    while let Some(c) = chars.next() {
+        if c == '\n' {
+            line += 1;
+        }
+        match (&mut string, c) {
+            (Some(string), '\\') => {
+                string.push(c);
+                string.extend(chars.next());
+            }
+            (Some(_), '"') => res.extend(string.take().map(|lit| (line, lit))),
+            (Some(string), _) => string.push(c),
+            (None, '"') => string = Some(String::new()),
+            (None, '(') => depth += 1,
+            (None, ')') => {
+                depth -= 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+            (None, _) => {}
+        }
+    }
+    res
+}
+
+/// Code of the naked function `name` as written: the source file and the pieces of its
+/// `naked_asm!` template, one for each line of asm, with the lines they are on
+pub(super) fn naked_template(path: &Path, name: &str) -> Option<(PathBuf, Vec<(u64, String)>)> {
+    let short = name.rsplit("::").next()?;
+    sources(path).into_iter().find_map(|file| {
+        let source = std::fs::read_to_string(&file).ok()?;
+        let pieces = template(&source, short)?;
+        Some((file, pieces))
+    })
+}
+
+/// Pieces of the `naked_asm!` template of `fn name` in `source`, lines count from 1
+fn template(source: &str, name: &str) -> Option<Vec<(u64, String)>> {
+    let start = source
+        .match_indices(&format!("fn {name}"))
+        .map(|(pos, pat)| pos + pat.len())
+        .find(|pos| source[*pos..].starts_with(['(', '<']))?;
+    let asm = start + source[start..].find("naked_asm!")?;
+    let first = source[..asm].matches('\n').count() + 1;
+    let pieces = literals(&source[asm..])
+        .into_iter()
+        .flat_map(|(line, lit)| {
+            lit.split("\\n")
+                .flat_map(|piece| piece.split(';'))
+                .map(|piece| piece.trim().to_owned())
+                .filter(|piece| !piece.is_empty())
+                .map(|piece| ((first + line) as u64, piece))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    Some(pieces)
+}
+
+/// Names for blocks in the file at `path`, one for each of the first `labels`
+pub(super) fn names(path: &Path, labels: &[Option<String>]) -> Vec<String> {
+    let krate = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .map(|stem| stem.split('-').next().unwrap_or_default().to_owned())
+        .filter(|krate| !krate.is_empty());
+    let files = sources(path);
+    let root = files
+        .first()
+        .and_then(|file| file.parent())
+        .map(Path::to_owned);
+    let known = root
+        .map(|root| {
+            files
+                .iter()
+                .filter_map(|file| Some((file, std::fs::read_to_string(file).ok()?)))
+                .filter(|(_, source)| source.contains("global_asm!"))
+                .flat_map(|(file, source)| {
+                    let module = file_module(&root, file);
+                    invocations(&source)
+                        .into_iter()
+                        .map(move |(inner, labels)| {
+                            (
+                                module.iter().chain(&inner).cloned().collect::<Vec<_>>(),
+                                labels,
+                            )
+                        })
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    labels
+        .iter()
+        .map(|label| {
+            let module = label
+                .as_ref()
+                .and_then(|label| known.iter().find(|(_, labels)| labels.contains(label)))
+                .map(|(module, _)| module.clone())
+                .unwrap_or_default();
+            krate
+                .iter()
+                .chain(&module)
+                .map(String::as_str)
+                .chain(["{{global_asm}}"])
+                .collect::<Vec<_>>()
+                .join("::")
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{file_module, invocations, template};
+    use std::path::Path;
+
+    #[test]
+    fn modules_of_invocations() {
+        let source = "core::arch::global_asm!(\"top:\", \"ret\");\n\
+                      pub mod boot {\n    fn f() {}\n    core::arch::global_asm!(\n        \".globl entry\",\n        \"entry:\",\n    );\n}\n\
+                      mod other { global_asm!(\"a:\\n b:\"); }\n";
+        assert_eq!(
+            invocations(source),
+            [
+                (Vec::new(), vec!["top".to_owned()]),
+                (vec!["boot".to_owned()], vec!["entry".to_owned()]),
+                (
+                    vec!["other".to_owned()],
+                    vec!["a".to_owned(), "b".to_owned()]
+                ),
+            ]
+        );
+        let root = Path::new("/w/src");
+        assert!(file_module(root, Path::new("/w/src/lib.rs")).is_empty());
+        assert_eq!(file_module(root, Path::new("/w/src/boot/mod.rs")), ["boot"]);
+        assert_eq!(
+            file_module(root, Path::new("/w/src/boot/x86.rs")),
+            ["boot", "x86"]
+        );
+    }
+
+    #[test]
+    fn naked_template() {
+        let source = "#[unsafe(naked)]\npub extern \"C\" fn add(a: u32) -> u32 {\n    \
+                      core::arch::naked_asm!(\n        \"lea eax, [rdi + {x}]\",\n        \
+                      \"ret; nop\\n int3\",\n        x = const 1,\n    )\n}\n";
+        assert_eq!(
+            template(source, "add").unwrap(),
+            [
+                (4, "lea eax, [rdi + {x}]".to_owned()),
+                (5, "ret".to_owned()),
+                (5, "nop".to_owned()),
+                (5, "int3".to_owned()),
+            ]
+        );
+        assert!(template(source, "ad").is_none());
+    }
+}
@@ -0,0 +1,197 @@
+//! Locating items in a file without keeping all of it in memory: one pass over the lines
+//! records where each function starts and ends, only the selected one is parsed in full
+use super::{events, statements::parse_statement, Event, Item, ItemFinder, Pos, CGU_MARKER};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::Path;
+
+/// Byte ranges of the items in an asm file along with what's needed to print any of them
+pub struct Index {
+    pub items: BTreeMap<Item, Range<usize>>,
+    /// `.file` directives, `.loc` refers to the sources by their index
+    pub files: String,
+    /// codegen units [`merge_codegen_units`](super::merge_codegen_units) marked, by offset
+    pub units: Vec<(usize, String)>,
+}
+
+/// Lines that can't start or end an item or define a source file: instructions, comments
+/// and the most common directives
+fn is_boring(line: &str) -> bool {
+    line.strip_prefix('\t')
+        .is_some_and(|rest| !rest.starts_with('.') || rest.starts_with(".loc\t"))
+}
+
+/// Interesting lines are collected into blocks of about this many bytes and the events for
+/// each block are worked out in parallel
+pub(super) const BLOCK: usize = 1 << 22;
+
+/// Feed events for the lines of `block` to `finder`, each line comes with where it starts
+/// and where the next one does
+fn flush(finder: &mut ItemFinder, block: &mut Vec<(Pos, Pos, String)>) {
+    let events = events(block, |(_, _, line)| {
+        Event::new(&parse_statement(line).ok()?.1)
+    });
+    for (ix, event) in events {
+        let (here, next, _) = block[ix];
+        finder.push(here, next, event);
+    }
+    block.clear();
+}
+
+impl Index {
+    pub fn new(path: &Path) -> anyhow::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut finder = ItemFinder::new();
+        let mut files = String::new();
+        let mut units = Vec::new();
+        let mut block = Vec::new();
+        let mut block_size = 0;
+        let mut here = Pos::default();
+        loop {
+            let mut line = String::new();
+            let len = reader.read_line(&mut line)?;
+            if len == 0 {
+                break;
+            }
+            let next = Pos {
+                line: here.line + 1,
+                byte: here.byte + len,
+            };
+            if !is_boring(&line) {
+                if !line.ends_with('\n') {
+                    line.push('\n');
+                }
+                if line.starts_with("\t.file\t") {
+                    files.push_str(&line);
+                } else if let Some(unit) = line
+                    .strip_prefix("\t.")
+                    .and_then(|b| b.strip_prefix(CGU_MARKER))
+                {
+                    units.push((here.byte, unit.trim_end().to_owned()));
+                }
+                block_size += line.len();
+                block.push((here, next, line));
+                if block_size > BLOCK {
+                    flush(&mut finder, &mut block);
+                    block_size = 0;
+                }
+            }
+            here = next;
+        }
+        flush(&mut finder, &mut block);
+        let items = finder
+            .finish(path)
+            .into_iter()
+            .map(|(item, range)| (item, range.start.byte..range.end.byte))
+            .collect();
+        Ok(Self {
+            items,
+            files,
+            units,
+        })
+    }
+
+    /// Codegen unit the item at `range` comes from
+    pub fn unit(&self, range: &Range<usize>) -> Option<&str> {
+        self.units
+            .iter()
+            .take_while(|(start, _)| *start <= range.start)
+            .last()
+            .map(|(_, name)| name.as_str())
+    }
+}
+
+/// Text of the item at `range` in `path`
+pub fn read_range(path: &Path, range: &Range<usize>) -> anyhow::Result<String> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(range.start as u64))?;
+    let mut buf = vec![0; range.len()];
+    file.read_exact(&mut buf)?;
+    Ok(String::from_utf8(buf)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{read_range, Index};
+    use crate::asm::{find_items, parse_file, section_name, Binding, Linkage};
+    use std::path::Path;
+
+    #[test]
+    fn same_as_whole_file() {
+        let asm = "\t.file\t\"sample\"\n\n\n\t.globl\tentry\nentry:\n\tret\n\n\n\n\t.section\t.text._ZN6sample1f17h0123456789abcdefE,\"ax\",@progbits\n\
+                   \t.globl\t_ZN6sample1f17h0123456789abcdefE\n_ZN6sample1f17h0123456789abcdefE:\n\
+                   \t.cfi_startproc\n\t.file\t1 \"/src\" \"lib.rs\"\n\t.loc\t1 2 0\n\tmov eax, 1\n\tret\n\
+                   .Lfunc_end0:\n\t.cfi_endproc\n\n\t.section\t.text.g,\"ax\",@progbits\n\t.globl\tg\n\
+                   g:\n\t.cfi_startproc\n\txor eax, eax\n\tret\n\t.cfi_endproc\n";
+        let path =
+            std::env::temp_dir().join(format!("cargo-show-asm-index-{}.s", std::process::id()));
+        std::fs::write(&path, asm).unwrap();
+        let index = Index::new(&path).unwrap();
+        let code = index
+            .items
+            .values()
+            .map(|range| read_range(&path, range).unwrap())
+            .collect::<Vec<_>>();
+        std::fs::remove_file(&path).unwrap();
+
+        let file = parse_file(asm).unwrap();
+        let items = find_items(&path, &file);
+        assert!(items
+            .keys()
+            .any(|item| item.name.ends_with("::{{global_asm}}")));
+        assert_eq!(
+            index.items.keys().collect::<Vec<_>>(),
+            items.keys().collect::<Vec<_>>()
+        );
+        for (code, range) in code.iter().zip(items.values()) {
+            let streamed = parse_file(code).unwrap();
+            assert_eq!(
+                format!("{streamed:?}"),
+                format!("{:?}", &file[range.clone()])
+            );
+        }
+        assert_eq!(
+            index.files,
+            "\t.file\t\"sample\"\n\t.file\t1 \"/src\" \"lib.rs\"\n"
+        );
+    }
+
+    #[test]
+    fn linkage() {
+        let asm = "\t.section\t.text.f,\"axG\",@progbits,f,comdat\n\t.weak\tf\nf:\n\tret\n\
+                   \t.cfi_endproc\n\t.section\t.text.g,\"ax\",@progbits\n\t.globl\tg\ng:\n\
+                   \tcall .Lh\n\t.cfi_endproc\n\t.section\t.text,\"xr\",discard,h\n\t.globl\th\n\
+                   h:\n\tret\n\t.cfi_endproc\n";
+        let file = parse_file(asm).unwrap();
+        let linkage = |binding, comdat, section: &str| Linkage {
+            binding,
+            comdat,
+            section: section.to_owned(),
+        };
+        assert_eq!(
+            find_items(Path::new(""), &file)
+                .into_keys()
+                .map(|item| (item.name, item.linkage))
+                .collect::<Vec<_>>(),
+            [
+                ("f".to_owned(), linkage(Binding::Weak, true, ".text")),
+                ("g".to_owned(), linkage(Binding::Global, false, ".text")),
+                ("h".to_owned(), linkage(Binding::Global, true, ".text")),
+            ]
+        );
+        assert_eq!(
+            section_name(
+                ".text.unlikely._ZN1a4cold17h01E,\"ax\",@progbits",
+                "_ZN1a4cold17h01E"
+            ),
+            ".text.unlikely"
+        );
+        assert_eq!(section_name(".iram1,\"ax\",@progbits", "f"), ".iram1");
+        assert_eq!(
+            section_name("__TEXT,__text,regular,pure_instructions", "_f"),
+            "__TEXT,__text"
+        );
+    }
+}
@@ -0,0 +1,509 @@
+//! Profiles shown next to the code: counts from `perf record` or callgrind for every
+//! instruction of the function, in a gutter on the left. Profiles know instructions by their offset from the start
+//! of the function and the assembly doesn't say how long each instruction is, so the offsets
+//! come from disassembling the object file rustc made along with the assembly
+use super::statements::{Instruction, Statement};
+use super::{dump_statements, used_labels, Item, Progress, Sources};
+use crate::{color, demangle, elf, opts::Format};
+use anyhow::Context;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Event counts for every function with samples in it
+#[derive(Debug, Default)]
+pub(super) struct Profile {
+    /// what is counted, the percentages are for the first one
+    events: Vec<String>,
+    /// counts of each event by symbol and offset in it
+    functions: BTreeMap<String, BTreeMap<u64, Vec<u64>>>,
+    /// counts of each event in the whole program
+    totals: Vec<u64>,
+}
+
+impl Profile {
+    fn add(&mut self, symbol: &str, offset: u64, counts: &[u64]) {
+        let at = self
+            .functions
+            .entry(symbol.to_owned())
+            .or_default()
+            .entry(offset)
+            .or_insert_with(|| vec![0; self.events.len()]);
+        for (ix, count) in counts.iter().enumerate() {
+            at[ix] += count;
+            self.totals[ix] += count;
+        }
+    }
+
+    /// Samples from `perf script` output with the `sym` and `symoff` fields
+    fn parse_perf_script(output: &str) -> Self {
+        let mut res = Self {
+            events: vec!["samples".to_owned()],
+            totals: vec![0],
+            ..Self::default()
+        };
+        for line in output.lines() {
+            let Some(last) = line.split_whitespace().last() else {
+                continue;
+            };
+            match last
+                .rsplit_once("+0x")
+                .and_then(|(symbol, offset)| Some((symbol, u64::from_str_radix(offset, 16).ok()?)))
+            {
+                Some((symbol, offset)) => res.add(symbol, offset, &[1]),
+                // samples in code without symbols still count towards the total
+                None => res.totals[0] += 1,
+            }
+        }
+        res
+    }
+
+    /// Samples recorded by `perf record` into `path`, `$PERF` is used instead of `perf` if set
+    pub(super) fn perf(path: &Path) -> anyhow::Result<Self> {
+        let perf = std::env::var("PERF").unwrap_or_else(|_| "perf".into());
+        let output = Command::new(&perf)
+            .arg("script")
+            .arg("-i")
+            .arg(path)
+            .args(["-F", "sym,symoff", "--no-demangle"])
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit())
+            .output()
+            .with_context(|| format!("Failed to run {perf}, is it installed?"))?;
+        anyhow::ensure!(
+            output.status.success(),
+            "'{perf} script' exited with {}",
+            output.status
+        );
+        Ok(Self::parse_perf_script(&String::from_utf8_lossy(
+            &output.stdout,
+        )))
+    }
+
+    /// Costs from a callgrind profile recorded with `--dump-instr=yes`. Only instruction reads
+    /// and cache misses are kept, the other events make the gutter wide without saying much
+    fn parse_callgrind(output: &str) -> anyhow::Result<Self> {
+        let mut events = Vec::new();
+        let mut instr = None;
+        let mut names = BTreeMap::<String, String>::new();
+        let mut function = String::new();
+        // absolute addresses of everything with costs, made relative to the function start below
+        let mut costs = BTreeMap::<String, BTreeMap<u64, Vec<u64>>>::new();
+        let mut totals = None;
+        let mut positions = Vec::<u64>::new();
+        let mut after_call = false;
+        for line in output.lines() {
+            if let Some(list) = line.strip_prefix("positions:") {
+                let list = list.split_whitespace().collect::<Vec<_>>();
+                instr = list.iter().position(|p| *p == "instr");
+                positions = vec![0; list.len()];
+            } else if let Some(list) = line.strip_prefix("events:") {
+                events = list.split_whitespace().map(str::to_owned).collect();
+                if positions.is_empty() {
+                    positions = vec![0];
+                }
+            } else if let Some(list) = line
+                .strip_prefix("totals:")
+                .or_else(|| line.strip_prefix("summary:"))
+            {
+                totals = Some(
+                    list.split_whitespace()
+                        .map(parse_number)
+                        .collect::<Vec<_>>(),
+                );
+            } else if let Some(name) = line
+                .strip_prefix("fn=")
+                .or_else(|| line.strip_prefix("cfn="))
+            {
+                // names are compressed: `(3) name` the first time, `(3)` after that
+                let name = match name.strip_prefix('(').and_then(|n| n.split_once(')')) {
+                    Some((id, "")) => names.get(id).cloned().unwrap_or_default(),
+                    Some((id, name)) => {
+                        let name = name.trim().to_owned();
+                        names.insert(id.to_owned(), name.clone());
+                        name
+                    }
+                    None => name.to_owned(),
+                };
+                if line.starts_with("fn=") {
+                    function = name;
+                }
+            } else if line.starts_with("calls=") {
+                // the next line is the inclusive cost of the call, it's not spent here
+                after_call = true;
+            } else if line.starts_with(|c: char| c.is_ascii_digit() || "+-*".contains(c)) {
+                let Some(instr) = instr else {
+                    anyhow::bail!(
+                        "The profile has no instruction addresses, record it with \
+                         valgrind --tool=callgrind --dump-instr=yes"
+                    );
+                };
+                let mut words = line.split_whitespace();
+                for (position, word) in positions.iter_mut().zip(words.by_ref()) {
+                    *position = match word.as_bytes()[0] {
+                        b'*' => *position,
+                        b'+' => position.wrapping_add(parse_number(&word[1..])),
+                        b'-' => position.wrapping_sub(parse_number(&word[1..])),
+                        _ => parse_number(word),
+                    };
+                }
+                if std::mem::take(&mut after_call) {
+                    continue;
+                }
+                let at = costs
+                    .entry(function.clone())
+                    .or_default()
+                    .entry(positions[instr])
+                    .or_insert_with(|| vec![0; events.len()]);
+                for (ix, count) in words.map(parse_number).enumerate().take(events.len()) {
+                    at[ix] += count;
+                }
+            }
+        }
+
+        let keep = events
+            .iter()
+            .enumerate()
+            .filter(|(_, event)| *event == "Ir" || event.contains('m'))
+            .map(|(ix, _)| ix)
+            .collect::<Vec<_>>();
+        let pick = |counts: &[u64]| {
+            keep.iter()
+                .map(|ix| counts.get(*ix).copied().unwrap_or_default())
+                .collect::<Vec<_>>()
+        };
+        let mut res = Self {
+            events: keep.iter().map(|ix| events[*ix].clone()).collect(),
+            totals: vec![0; keep.len()],
+            ..Self::default()
+        };
+        for (function, counts) in costs {
+            // the entry of a function always runs, nothing that ran is before it
+            let Some(start) = counts.keys().next().copied() else {
+                continue;
+            };
+            for (addr, counts) in counts {
+                res.add(&function, addr - start, &pick(&counts));
+            }
+        }
+        if let Some(totals) = totals {
+            res.totals = pick(&totals);
+        }
+        Ok(res)
+    }
+
+    /// Costs saved by callgrind to `path`
+    pub(super) fn callgrind(path: &Path) -> anyhow::Result<Self> {
+        let output = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse_callgrind(&output)
+            .with_context(|| format!("Failed to load the profile from {}", path.display()))
+    }
+
+    /// Profile asked for with `--perf` or `--callgrind`, if any
+    pub(super) fn load(fmt: &Format) -> anyhow::Result<Option<Self>> {
+        match (&fmt.perf, &fmt.callgrind) {
+            (Some(_), Some(_)) => anyhow::bail!("Pick one of --perf and --callgrind"),
+            (Some(path), None) => Ok(Some(Self::perf(path)?)),
+            (None, Some(path)) => Ok(Some(Self::callgrind(path)?)),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Counts for `symbol`, callgrind names functions after their demangled names by default
+    fn counts(&self, symbol: &str) -> Option<&BTreeMap<u64, Vec<u64>>> {
+        self.functions.get(symbol).or_else(|| {
+            let name = demangle::demangled(symbol)?;
+            self.functions
+                .get(&format!("{name:#}"))
+                .or_else(|| self.functions.get(&name.to_string()))
+        })
+    }
+}
+
+/// Up to `n` of `items` with the most samples or instruction reads, the most first
+pub(super) fn hottest<'a>(profile: &Profile, items: &'a [Item], n: usize) -> Vec<(&'a Item, u64)> {
+    let mut by_name = BTreeMap::<String, u64>::new();
+    for (symbol, counts) in &profile.functions {
+        let total = counts.values().map(|c| c[0]).sum::<u64>();
+        // perf knows functions by their symbols, callgrind by their demangled names
+        let name = demangle::demangled(symbol).map_or_else(|| symbol.clone(), |n| n.to_string());
+        *by_name.entry(name).or_default() += total;
+    }
+    let mut res = items
+        .iter()
+        .filter_map(|item| {
+            let count = by_name
+                .get(&item.hashed)
+                .or_else(|| by_name.get(&item.name))?;
+            Some((item, *count)).filter(|_| *count > 0)
+        })
+        .collect::<Vec<_>>();
+    res.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+    res.truncate(n);
+    res
+}
+
+/// Decimal or `0x` prefixed hex number from a callgrind profile
+fn parse_number(word: &str) -> u64 {
+    match word.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).unwrap_or_default(),
+        None => word.parse().unwrap_or_default(),
+    }
+}
+
+/// Offsets and mnemonics of the instructions of `symbol` in `objdump -d` output
+fn parse_objdump(output: &str, symbol: &str) -> Vec<(u64, String)> {
+    let header = format!("<{symbol}>:");
+    let mut res = Vec::new();
+    let mut base = None;
+    for line in output.lines() {
+        let line = line.trim();
+        let Some(base) = base else {
+            if let Some(addr) = line.strip_suffix(&header) {
+                base = u64::from_str_radix(addr.trim(), 16).ok();
+            }
+            continue;
+        };
+        if line.is_empty() {
+            break;
+        }
+        let Some((addr, instr)) = line.split_once(':') else {
+            continue;
+        };
+        let (Ok(addr), Some(mnemonic)) = (
+            u64::from_str_radix(addr.trim(), 16),
+            instr.split_whitespace().next(),
+        ) else {
+            continue;
+        };
+        res.push((addr.saturating_sub(base), mnemonic.to_owned()));
+    }
+    res
+}
+
+/// Instructions the assembler adds to align code that aren't in the assembly
+fn is_padding(mnemonic: &str) -> bool {
+    mnemonic.starts_with("nop") || ["xchg", "data16", "cs", "int3", "ds"].contains(&mnemonic)
+}
+
+/// Offsets of the instructions of `symbol` in the object at `object`, disassembled with
+/// `$OBJDUMP` or `objdump`
+pub(super) fn disassemble(object: &Path, symbol: &str) -> anyhow::Result<Vec<(u64, String)>> {
+    let output = elf::objdump(object, symbol, &["--no-show-raw-insn"])?;
+    Ok(parse_objdump(&output, symbol))
+}
+
+/// Offset of each instruction in `stmts` given `disassembly` of the same function, padding
+/// the assembler added is skipped
+pub(super) fn offsets(stmts: &[Statement], disassembly: &[(u64, String)]) -> Vec<Option<u64>> {
+    let mut theirs = disassembly.iter().peekable();
+    stmts
+        .iter()
+        .map(|stmt| {
+            let Statement::Instruction(Instruction { op, .. }) = stmt else {
+                return None;
+            };
+            if !is_padding(op) {
+                while theirs.next_if(|(_, m)| is_padding(m)).is_some() {}
+            }
+            theirs.next().map(|(offset, _)| *offset)
+        })
+        .collect()
+}
+
+/// Symbol of the function in `stmts`, the first global label
+pub(super) fn symbol<'a>(stmts: &[Statement<'a>]) -> Option<&'a str> {
+    stmts.iter().find_map(|stmt| match stmt {
+        Statement::Label(label) if !label.local => Some(label.id),
+        _ => None,
+    })
+}
+
+fn gutter(counts: Option<&Vec<u64>>, total: u64, widths: &[usize]) -> String {
+    let mut res = String::new();
+    match counts {
+        Some(counts) => {
+            let share = if total == 0 {
+                0.0
+            } else {
+                counts[0] as f64 * 100.0 / total as f64
+            };
+            res.push_str(&format!("{share:>6.2}%"));
+            for (count, width) in counts.iter().zip(widths) {
+                res.push_str(&format!(" {count:>width$}"));
+            }
+        }
+        None => {
+            res.push_str(&" ".repeat(7));
+            for width in widths {
+                res.push_str(&" ".repeat(width + 1));
+            }
+        }
+    }
+    res.push_str(" | ");
+    res
+}
+
+/// Print the function in `stmts` from `asm_path` with the counts `profile` has for each of its
+/// instructions in a gutter
+pub(super) fn dump(
+    asm_path: &Path,
+    files: &Sources,
+    fmt: &Format,
+    stmts: &[Statement],
+    profile: &Profile,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    let symbol = symbol(stmts).unwrap_or_default();
+    let object = elf::object_near(asm_path)
+        .context("No object file next to the assembly to take instruction offsets from")?;
+    let offsets = offsets(stmts, &disassemble(&object, symbol)?);
+    let empty = BTreeMap::new();
+    let counts = profile.counts(symbol).unwrap_or(&empty);
+    // counts in the function, the percentages are relative to those
+    let mut here = vec![0; profile.events.len()];
+    for values in counts.values() {
+        for (ix, value) in values.iter().enumerate() {
+            here[ix] += value;
+        }
+    }
+    let widths = profile
+        .events
+        .iter()
+        .zip(&here)
+        .map(|(event, total)| event.len().max(total.to_string().len()))
+        .collect::<Vec<_>>();
+
+    let share = match (here.first(), profile.totals.first()) {
+        (Some(here), Some(total)) if *total > 0 => *here as f64 * 100.0 / *total as f64,
+        _ => 0.0,
+    };
+    let summary = format!(
+        "// {} {} in this function, {share:.2}% of the whole program",
+        here.first().copied().unwrap_or_default(),
+        profile.events.first().map_or("samples", String::as_str)
+    );
+    writeln!(out, "{}", color!(summary, comment))?;
+    let mut header = format!("{:>7}", "%");
+    for (event, width) in profile.events.iter().zip(&widths) {
+        header.push_str(&format!(" {event:>width$}"));
+    }
+    writeln!(out, "{}", color!(header, comment))?;
+    if offsets.iter().all(Option::is_none) {
+        let note = format!("// {symbol} is not in {}", object.display());
+        writeln!(out, "{}", color!(note, comment))?;
+    }
+
+    let used = if fmt.keep_labels {
+        Default::default()
+    } else {
+        used_labels(stmts)
+    };
+    let used = |label: &str| used.contains(label);
+    let mut progress = Progress::default();
+    let total = here.first().copied().unwrap_or_default();
+    for (ix, stmt) in stmts.iter().enumerate() {
+        let mut buf = Vec::new();
+        dump_statements(
+            files,
+            fmt,
+            std::slice::from_ref(stmt),
+            &used,
+            &mut progress,
+            &mut buf,
+        )?;
+        let counts = offsets[ix].and_then(|offset| counts.get(&offset));
+        for (n, line) in String::from_utf8_lossy(&buf).lines().enumerate() {
+            let gutter = gutter(counts.filter(|_| n == 0), total, &widths);
+            let gutter = if line.is_empty() {
+                gutter.trim_end()
+            } else {
+                &gutter
+            };
+            writeln!(out, "{}{line}", color!(gutter, size))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{hottest, offsets, parse_objdump, Profile};
+    use crate::asm::statements::{Instruction, Statement};
+    use crate::asm::Item;
+
+    #[test]
+    fn perf_samples() {
+        let profile = Profile::parse_perf_script(
+            "  _ZN4cgut1a17h39ee52d9783e5142E+0x40\n\
+             \t_ZN4cgut1a17h39ee52d9783e5142E+0x40\n\
+             \t_ZN4cgut1a17h39ee52d9783e5142E+0x3\n\
+             [unknown]\n",
+        );
+        assert_eq!(profile.totals, [4]);
+        let counts = &profile.functions["_ZN4cgut1a17h39ee52d9783e5142E"];
+        assert_eq!(counts[&0x40], [2]);
+        assert_eq!(counts[&0x3], [1]);
+    }
+
+    #[test]
+    fn callgrind_costs() {
+        let profile = Profile::parse_callgrind(
+            "version: 1\ncreator: callgrind-3.22.0\npositions: instr line\n\
+             events: Ir Dr Dw I1mr D1mr D1mw ILmr DLmr DLmw\nsummary: 100 0 0 7 3 0 7 1 0\n\n\
+             ob=(1) /tmp/cgut/target/release/cgut\nfl=(1) src/lib.rs\nfn=(1) cgut::a\n\
+             0x109a40 3 1 0 0 1 0 0 1\n+4 * 2 1 0 0 1\ncfn=(2) core::fmt::write\n\
+             calls=1 0x10a000 10\n+3 4 50 20\n+5 * 1\n\nfn=(2)\n0x10a000 10 40\n",
+        )
+        .unwrap();
+        assert_eq!(
+            profile.events,
+            ["Ir", "I1mr", "D1mr", "D1mw", "ILmr", "DLmr", "DLmw"]
+        );
+        assert_eq!(profile.totals, [100, 7, 3, 0, 7, 1, 0]);
+        let counts = profile.counts("_ZN4cgut1a17h39ee52d9783e5142E").unwrap();
+        assert_eq!(counts[&0], [1, 1, 0, 0, 1, 0, 0]);
+        assert_eq!(counts[&4], [2, 0, 1, 0, 0, 0, 0]);
+        // the cost after `calls=` is spent in the callee
+        assert_eq!(counts.len(), 3);
+        assert_eq!(counts[&12], [1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            profile.functions["core::fmt::write"][&0],
+            [40, 0, 0, 0, 0, 0, 0]
+        );
+
+        let item = |name: &str, hashed: &str| Item {
+            name: name.to_owned(),
+            hashed: hashed.to_owned(),
+            index: 0,
+            len: 10,
+            linkage: Default::default(),
+        };
+        let items = [
+            item("cgut::a", "cgut::a::h39ee52d9783e5142"),
+            item("cgut::b", "cgut::b::h0123456789abcdef"),
+        ];
+        let hot = hottest(&profile, &items, 5);
+        assert_eq!(hot.len(), 1);
+        assert_eq!((hot[0].0.name.as_str(), hot[0].1), ("cgut::a", 4));
+    }
+
+    #[test]
+    fn instruction_offsets() {
+        let disassembly = parse_objdump(
+            "Disassembly of section .text.f:\n\n0000000000000010 <f>:\n  10:\ttest   rsi,rsi\n  \
+             13:\tje     21 <f+0x11>\n  15:\tnop    WORD PTR [rax+rax*1+0x0]\n  20:\tret\n\n\
+             0000000000000030 <g>:\n  30:\tret\n",
+            "f",
+        );
+        assert_eq!(disassembly.len(), 4);
+        let instr = |op| Statement::Instruction(Instruction { op, args: None });
+        let stmts = [instr("test"), instr("je"), Statement::Nothing, instr("ret")];
+        assert_eq!(
+            offsets(&stmts, &disassembly),
+            [Some(0), Some(3), None, Some(0x10)]
+        );
+    }
+}
@@ -0,0 +1,162 @@
+//! How an instruction reaches the symbol it refers to and the relocation the assembler emits
+//! for it, worked out from the operand syntax: `@PLT` and `@GOTPCREL` on x86, `:got:` and
+//! `:lo12:` on ELF aarch64, `@PAGE` and `@GOTPAGE` on Mach-O
+use super::statements::Instruction;
+
+/// First symbol in `args` that the linker has to resolve, local branch targets such as
+/// `.LBB0_1` are resolved by the assembler
+pub(super) fn symbol(args: &str) -> Option<&str> {
+    args.split(|c: char| !(c.is_alphanumeric() || "_.$@:".contains(c)))
+        .map(|word| word.trim_start_matches(':'))
+        .map(|word| word.rsplit(':').next().unwrap_or(word))
+        .find(|word| {
+            let first = word.chars().next();
+            first.is_some_and(|c| c.is_alphabetic() || c == '_' || c == '.')
+                && !word.starts_with(".LBB")
+                && !word.starts_with("LBB")
+                && !is_register(word)
+        })
+}
+
+fn is_register(word: &str) -> bool {
+    const X86: [&str; 18] = [
+        "rip", "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rbp", "rsp", "eax", "ebx", "ecx", "edx",
+        "esi", "edi", "ebp", "esp", "ptr",
+    ];
+    let word = word.trim_start_matches('%');
+    X86.contains(&word)
+        || [
+            "byte", "word", "dword", "qword", "xmmword", "ymmword", "zmmword", "offset",
+        ]
+        .contains(&word)
+        || word
+            .strip_prefix('r')
+            .is_some_and(|n| n.trim_end_matches(['d', 'w', 'b']).parse::<u8>().is_ok())
+        || ["x", "w", "v", "q", "d", "s", "h", "b"].iter().any(|p| {
+            word.strip_prefix(p)
+                .is_some_and(|n| n.parse::<u8>().is_ok())
+        })
+        || ["xzr", "wzr", "sp", "lr", "fp"].contains(&word)
+        || ["xmm", "ymm", "zmm"].iter().any(|p| word.starts_with(p))
+}
+
+pub(super) fn is_call(op: &str) -> bool {
+    ["call", "callq", "bl", "blr"].contains(&op)
+}
+
+pub(super) fn is_jump(op: &str) -> bool {
+    op == "b" || op == "jmp" || op == "jmpq" || (op.starts_with('j') && op.len() <= 4)
+}
+
+/// Note on how `instr` gets to the symbol it refers to, `None` if it refers to none
+#[must_use]
+pub(super) fn describe(instr: &Instruction) -> Option<String> {
+    let args = instr.args?;
+    let op = instr.op;
+    let symbol = symbol(args)?;
+    let (how, reloc) = if args.contains("@GOTPCREL") {
+        if is_call(op) || is_jump(op) {
+            ("indirect call through the GOT", "R_X86_64_GOTPCRELX")
+        } else {
+            ("address loaded from the GOT", "R_X86_64_REX_GOTPCRELX")
+        }
+    } else if args.contains("@PLT") {
+        ("call through the PLT", "R_X86_64_PLT32")
+    } else if args.contains("@GOTTPOFF") {
+        (
+            "thread local, offset loaded from the GOT",
+            "R_X86_64_GOTTPOFF",
+        )
+    } else if args.contains("@TLSGD") {
+        ("thread local, general dynamic", "R_X86_64_TLSGD")
+    } else if args.contains("@TLSLD") {
+        ("thread local, local dynamic", "R_X86_64_TLSLD")
+    } else if args.contains("@TPOFF") {
+        ("thread local, local exec", "R_X86_64_TPOFF32")
+    } else if args.contains(":got:") {
+        ("page of the GOT entry", "R_AARCH64_ADR_GOT_PAGE")
+    } else if args.contains(":got_lo12:") {
+        ("address loaded from the GOT", "R_AARCH64_LD64_GOT_LO12_NC")
+    } else if args.contains("@GOTPAGEOFF") {
+        (
+            "address loaded from the GOT",
+            "ARM64_RELOC_GOT_LOAD_PAGEOFF12",
+        )
+    } else if args.contains("@GOTPAGE") {
+        ("page of the GOT entry", "ARM64_RELOC_GOT_LOAD_PAGE21")
+    } else if args.contains("@PAGEOFF") {
+        ("offset in the page", "ARM64_RELOC_PAGEOFF12")
+    } else if args.contains("@PAGE") {
+        ("page of the address", "ARM64_RELOC_PAGE21")
+    } else if args.contains(":lo12:") {
+        if op == "add" {
+            ("offset in the page", "R_AARCH64_ADD_ABS_LO12_NC")
+        } else {
+            ("offset in the page", "R_AARCH64_LDST64_ABS_LO12_NC")
+        }
+    } else if op == "adrp" {
+        ("page of the address", "R_AARCH64_ADR_PREL_PG_HI21")
+    } else if op == "bl" {
+        ("direct call", "R_AARCH64_CALL26")
+    } else if op == "b" {
+        ("direct jump", "R_AARCH64_JUMP26")
+    } else if is_call(op) {
+        ("direct call", "R_X86_64_PLT32")
+    } else if is_jump(op) {
+        ("direct jump", "R_X86_64_PLT32")
+    } else if args.contains("rip") {
+        ("pc-relative", "R_X86_64_PC32")
+    } else if op == "movabs" || op == "movabsq" {
+        ("absolute address", "R_X86_64_64")
+    } else if args.contains("offset") || args.starts_with('$') {
+        ("absolute address", "R_X86_64_32S")
+    } else {
+        return None;
+    };
+    let symbol = symbol.split('@').next().unwrap_or(symbol);
+    Some(format!("{how}, {reloc} against {symbol}"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::describe;
+    use crate::asm::statements::Instruction;
+
+    fn note(op: &str, args: &str) -> Option<String> {
+        describe(&Instruction {
+            op,
+            args: Some(args),
+        })
+    }
+
+    #[test]
+    fn calls_and_data() {
+        assert_eq!(
+            note("call", "qword ptr [rip + _RNv5alloc@GOTPCREL]").unwrap(),
+            "indirect call through the GOT, R_X86_64_GOTPCRELX against _RNv5alloc"
+        );
+        assert_eq!(
+            note("call", "memcpy@PLT").unwrap(),
+            "call through the PLT, R_X86_64_PLT32 against memcpy"
+        );
+        assert_eq!(
+            note("call", "_ZN4core3fmt5write17h01E").unwrap(),
+            "direct call, R_X86_64_PLT32 against _ZN4core3fmt5write17h01E"
+        );
+        assert_eq!(
+            note("lea", "rdx, [rip + .Lanon.1940f37f.3]").unwrap(),
+            "pc-relative, R_X86_64_PC32 against .Lanon.1940f37f.3"
+        );
+        assert_eq!(
+            note("adrp", "x0, :got:_ZN4core3fmt5write17h01E").unwrap(),
+            "page of the GOT entry, R_AARCH64_ADR_GOT_PAGE against _ZN4core3fmt5write17h01E"
+        );
+        assert_eq!(
+            note("ldr", "x8, [x8, :got_lo12:FOO]").unwrap(),
+            "address loaded from the GOT, R_AARCH64_LD64_GOT_LO12_NC against FOO"
+        );
+        assert!(note("je", ".LBB8_1").is_none());
+        assert!(note("mov", "eax, dword ptr [rsp + 20]").is_none());
+        assert!(note("call", "rax").is_none());
+    }
+}
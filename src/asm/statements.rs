@@ -8,7 +8,6 @@ use nom::character::complete::{newline, space1};
 use nom::combinator::{consumed, map, opt, verify};
 use nom::sequence::{delimited, preceded, terminated, tuple};
 use nom::{AsChar, IResult};
-use owo_colors::OwoColorize;
 
 use crate::{color, demangle};
 
@@ -32,8 +31,10 @@ impl<'a> Instruction<'a> {
         alt((Self::parse_regular, Self::parse_sharp))(input)
     }
 
+    /// Comments and markers such as `#APP` on x86, `//APP` on AArch64 and `;APP` on AVR
     fn parse_sharp(input: &'a str) -> IResult<&'a str, Self> {
-        let sharp_tag = tuple((tag("#"), opt(tag("#")), take_while1(|c: char| c != '\n')));
+        let marker = alt((tag("//"), tag("#"), tag(";")));
+        let sharp_tag = tuple((marker, opt(tag("#")), take_while1(|c: char| c != '\n')));
         map(preceded(tag("\t"), consumed(sharp_tag)), |(op, _)| {
             Instruction { op, args: None }
         })(input)
@@ -48,11 +49,128 @@ impl<'a> Instruction<'a> {
     }
 }
 
+/// Split trailing comment LLVM adds with `-Z verbose-asm`, comment marker depends
+/// on the architecture: `#` for x86, `//` for aarch64, `@` for arm and `;` for AVR and MSP430
+pub fn split_comment(input: &str) -> (&str, Option<&str>) {
+    for marker in ["# ", "// ", "@ ", "; "] {
+        if let Some(ix) = input.find(marker) {
+            if ix > 0 && input[..ix].ends_with(char::is_whitespace) {
+                return (input[..ix].trim_end(), Some(&input[ix..]));
+            }
+        }
+    }
+    (input, None)
+}
+
+/// Numbered register such as `x12` with the number up to `max`
+fn numbered(word: &str, prefix: &str, max: u8) -> bool {
+    word.strip_prefix(prefix)
+        .filter(|n| !n.starts_with('0') || *n == "0")
+        .and_then(|n| n.parse::<u8>().ok())
+        .is_some_and(|n| n <= max)
+}
+
+const X86_REGISTERS: &[&str] = &[
+    "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rbp", "rsp", "rip", "eax", "ebx", "ecx", "edx",
+    "esi", "edi", "ebp", "esp", "eip", "ax", "bx", "cx", "dx", "si", "di", "bp", "al", "bl", "cl",
+    "dl", "ah", "bh", "ch", "dh", "sil", "dil", "bpl", "spl", "cs", "ds", "es", "fs", "gs", "ss",
+];
+
+const AARCH64_REGISTERS: &[&str] = &[
+    "sp", "wsp", "xzr", "wzr", "lr", "fp", "nzcv", "fpcr", "fpsr",
+];
+
+/// ABI names, numbered ones are handled separately
+const RISCV_REGISTERS: &[&str] = &["zero", "ra", "sp", "gp", "tp", "fp"];
+
+/// AVR pointer registers and MSP430 special purpose ones, both number the rest `r0`, `r1`, ...
+const EMBEDDED_REGISTERS: &[&str] = &["X", "Y", "Z", "pc", "sp", "sr", "cg"];
+
+/// Register names of x86, AArch64, RISC-V, AVR and MSP430, vector registers can have an
+/// arrangement: `v0.16b`
+fn is_register(word: &str) -> bool {
+    let word = match word.split_once('.') {
+        Some((reg, lanes))
+            if (numbered(reg, "v", 31) || numbered(reg, "z", 31))
+                && lanes.trim_start_matches(|c: char| c.is_ascii_digit()).len() == 1
+                && lanes.ends_with(['b', 'h', 's', 'd', 'q']) =>
+        {
+            reg
+        }
+        _ => word,
+    };
+    let x86 = || {
+        X86_REGISTERS.contains(&word)
+            || (8..=15).any(|n| {
+                ["", "d", "w", "b"]
+                    .iter()
+                    .any(|suffix| word == format!("r{n}{suffix}"))
+            })
+            || ["xmm", "ymm", "zmm"].iter().any(|p| numbered(word, p, 31))
+            || numbered(word, "k", 7)
+    };
+    let aarch64 = || {
+        AARCH64_REGISTERS.contains(&word)
+            || numbered(word, "w", 30)
+            || ["v", "q", "d", "s", "h", "b", "z"]
+                .iter()
+                .any(|p| numbered(word, p, 31))
+            || numbered(word, "p", 15)
+    };
+    let riscv = || {
+        RISCV_REGISTERS.contains(&word)
+            || ["x", "f"].iter().any(|p| numbered(word, p, 31))
+            || numbered(word, "a", 7)
+            || numbered(word, "t", 6)
+            || numbered(word, "s", 11)
+            || numbered(word, "fa", 7)
+            || numbered(word, "ft", 11)
+            || numbered(word, "fs", 11)
+    };
+    let embedded = || EMBEDDED_REGISTERS.contains(&word) || numbered(word, "r", 31);
+    x86() || aarch64() || riscv() || embedded()
+}
+
+/// Demangle symbols and highlight registers in instruction arguments
+fn highlight_args(args: &str, full_name: bool) -> String {
+    let mut res = String::with_capacity(args.len());
+    let mut rest = args;
+    while !rest.is_empty() {
+        let word_len = rest.find(|c| !good_for_label(c)).unwrap_or(rest.len());
+        let len = match word_len {
+            0 => rest.find(good_for_label).unwrap_or(rest.len()),
+            len => len,
+        };
+        let (chunk, tail) = rest.split_at(len);
+        if word_len > 0 && is_register(chunk) {
+            res.push_str(&color!(chunk, register).to_string());
+        } else {
+            res.push_str(&demangle::contents(chunk, full_name));
+        }
+        rest = tail;
+    }
+    res
+}
+
+fn is_comment(input: &str) -> bool {
+    input.starts_with('#')
+        || input.starts_with("//")
+        || input.starts_with("@ ")
+        || input.starts_with(';')
+}
+
 impl std::fmt::Display for Instruction<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", color!(self.op, OwoColorize::bright_blue))?;
+        if is_comment(self.op) {
+            return write!(f, "{}", color!(self.op, comment));
+        }
+        write!(f, "{}", color!(self.op, mnemonic))?;
         if let Some(args) = self.args {
-            write!(f, " {}", demangle::contents(args, f.alternate()))?;
+            let (args, comment) = split_comment(args);
+            write!(f, " {}", highlight_args(args, f.alternate()))?;
+            if let Some(comment) = comment {
+                write!(f, "\t{}", color!(comment, comment))?;
+            }
         }
         Ok(())
     }
@@ -77,6 +195,9 @@ impl std::fmt::Display for Statement<'_> {
                 }
             }
             Statement::Nothing => Ok(()),
+            Statement::Dunno(l) if is_comment(l.trim_start()) => {
+                write!(f, "{}", color!(l, comment))
+            }
             Statement::Dunno(l) => write!(f, "{l}"),
         }
     }
@@ -88,20 +209,14 @@ impl std::fmt::Display for Directive<'_> {
             Directive::File(ff) => ff.fmt(f),
             Directive::Loc(l) => l.fmt(f),
             Directive::Generic(g) => g.fmt(f),
-            Directive::Set(g) => {
-                f.write_str(&format!(".set {}", color!(g, OwoColorize::bright_black)))
-            }
+            Directive::Set(g) => f.write_str(&format!(".set {}", color!(g, directive))),
             Directive::SectionStart(s) => {
                 let dem = demangle::contents(s, f.alternate());
-                f.write_str(&format!(
-                    "{} {}",
-                    color!(".section", OwoColorize::bright_black),
-                    dem
-                ))
+                f.write_str(&format!("{} {}", color!(".section", directive), dem))
             }
             Directive::SubsectionsViaSym => f.write_str(&format!(
-                ".{}",
-                color!("subsections_via_symbols", OwoColorize::bright_black)
+                "\t.{}",
+                color!("subsections_via_symbols", directive)
             )),
         }
     }
@@ -128,10 +243,7 @@ impl std::fmt::Display for GenericDirective<'_> {
         write!(
             f,
             "\t.{}",
-            color!(
-                demangle::contents(self.0, f.alternate()),
-                OwoColorize::bright_black
-            )
+            color!(demangle::contents(self.0, f.alternate()), directive)
         )
     }
 }
@@ -154,10 +266,7 @@ impl std::fmt::Display for Label<'_> {
         write!(
             f,
             "{}:",
-            color!(
-                demangle::contents(self.id, f.alternate()),
-                OwoColorize::bright_black
-            )
+            color!(demangle::contents(self.id, f.alternate()), label)
         )
     }
 }
@@ -173,14 +282,34 @@ impl<'a> Label<'a> {
         // TODO: label can't start with a digit
         map(
             terminated(take_while1(good_for_label), tag(":")),
-            |id: &str| {
-                let local = id.starts_with(".L");
-                Label { id, local }
+            |id: &str| Label {
+                id,
+                local: is_local_label(id),
             },
         )(input)
     }
 }
 
+/// Labels LLVM makes up: `.L` on ELF, `L` and `l` followed by one of the usual prefixes on
+/// Mach-O where every symbol from the source starts with `_` instead
+pub fn is_local_label(id: &str) -> bool {
+    const MACHO: &[&str] = &[
+        "BB",
+        "tmp",
+        "func_end",
+        "CPI",
+        "JTI",
+        "loh",
+        "exception",
+        "anon.",
+        "_",
+    ];
+    id.starts_with(".L")
+        || id
+            .strip_prefix(['L', 'l'])
+            .is_some_and(|rest| MACHO.iter().any(|prefix| rest.starts_with(prefix)))
+}
+
 #[derive(Copy, Clone, Debug, Eq, Default)]
 pub struct Loc<'a> {
     pub file: u64,
@@ -224,10 +353,23 @@ pub enum FilePath<'a> {
 }
 
 impl FilePath<'_> {
+    /// Backslashes in Windows paths come escaped: `"C:\\work\\src"`
     pub fn as_full_path(&self) -> Cow<'_, Path> {
+        fn unescape(path: &str) -> Cow<'_, str> {
+            if path.contains("\\\\") {
+                Cow::Owned(path.replace("\\\\", "\\"))
+            } else {
+                Cow::Borrowed(path)
+            }
+        }
         match self {
-            FilePath::FullPath(path) => Cow::Borrowed(Path::new(path)),
-            FilePath::PathAndFileName { path, name } => Cow::Owned(Path::new(path).join(name)),
+            FilePath::FullPath(path) => match unescape(path) {
+                Cow::Borrowed(path) => Cow::Borrowed(Path::new(path)),
+                Cow::Owned(path) => Cow::Owned(path.into()),
+            },
+            FilePath::PathAndFileName { path, name } => {
+                Cow::Owned(Path::new(&*unescape(path)).join(&*unescape(name)))
+            }
         }
     }
 }
@@ -293,6 +435,141 @@ fn test_parse_label() {
     );
 }
 
+#[test]
+fn test_parse_macho_labels() {
+    for (id, local) in [
+        ("LBB0_2", true),
+        ("Ltmp3", true),
+        ("ltmp0", true),
+        ("lCPI1_0", true),
+        ("l_anon.7a1b.0", true),
+        ("Lfunc_end0", true),
+        ("_main", false),
+        ("__ZN4cfgt3sum17h0E", false),
+        ("Load", false),
+    ] {
+        let line = format!("{id}:");
+        assert_eq!(Label::parse(&line), Ok(("", Label { id, local })), "{id}");
+    }
+    let (_, stmt) = parse_statement("\t.subsections_via_symbols\n").unwrap();
+    assert!(matches!(
+        stmt,
+        Statement::Directive(Directive::SubsectionsViaSym)
+    ));
+}
+
+#[test]
+fn test_parse_seh() {
+    let (_, stmt) = parse_statement(".seh_proc _ZN4cfgt3sum17h0E\n").unwrap();
+    assert!(matches!(
+        stmt,
+        Statement::Directive(Directive::Generic(GenericDirective(
+            "seh_proc _ZN4cfgt3sum17h0E"
+        )))
+    ));
+    let (_, stmt) = parse_statement("\t.seh_endproc\n").unwrap();
+    assert!(stmt.is_end_of_fn());
+}
+
+#[test]
+fn test_parse_commented_label() {
+    let (rest, stmt) =
+        parse_statement("_ZN4cfgt3sum17h0E:           # @_ZN4cfgt3sum17h0E\n").unwrap();
+    assert!(rest.is_empty());
+    assert!(matches!(
+        stmt,
+        Statement::Label(Label {
+            id: "_ZN4cfgt3sum17h0E",
+            local: false
+        })
+    ));
+}
+
+#[test]
+fn test_split_comment() {
+    assert_eq!(
+        split_comment("$4096, %rsp                     # imm = 0x1000"),
+        ("$4096, %rsp", Some("# imm = 0x1000"))
+    );
+    assert_eq!(
+        split_comment("x0, [sp, #16]       // 8-byte Folded Spill"),
+        ("x0, [sp, #16]", Some("// 8-byte Folded Spill"))
+    );
+    assert_eq!(
+        split_comment("r24, 0                          ; 0x0"),
+        ("r24, 0", Some("; 0x0"))
+    );
+    assert_eq!(split_comment("r0, #16"), ("r0, #16", None));
+    assert_eq!(split_comment("foo@PLT"), ("foo@PLT", None));
+}
+
+#[test]
+fn test_registers() {
+    for reg in [
+        "rax", "r10d", "xmm15", "x0", "w30", "sp", "xzr", "v0.16b", "q31", "d8", "p7",
+    ] {
+        assert!(is_register(reg), "{reg}");
+    }
+    for reg in ["zero", "ra", "a7", "t6", "s11", "x31", "fa0", "ft11"] {
+        assert!(is_register(reg), "{reg}");
+    }
+    for reg in ["r24", "r31", "Z", "r15", "sr"] {
+        assert!(is_register(reg), "{reg}");
+    }
+    for word in [
+        "x32", "x01", "r32", ".LBB0_2", "v0.foo", "b32", "rsp2", "a8", "fs12",
+    ] {
+        assert!(!is_register(word), "{word}");
+    }
+    owo_colors::set_override(true);
+    assert_eq!(
+        highlight_args("x0, [sp, #16]", false),
+        "\x1b[35mx0\x1b[39m, [\x1b[35msp\x1b[39m, #16]"
+    );
+    assert_eq!(
+        highlight_args("r25:r24, Z+", false),
+        "\x1b[35mr25\x1b[39m:\x1b[35mr24\x1b[39m, \x1b[35mZ\x1b[39m+"
+    );
+    let args = highlight_args(
+        "qword ptr [rip + _ZN4core3fmt5write17h0123456789abcdefE]",
+        false,
+    );
+    assert!(
+        args.starts_with("qword ptr [\x1b[35mrip\x1b[39m + "),
+        "{args}"
+    );
+}
+
+#[test]
+fn test_parse_aarch64_comment() {
+    let (_, stmt) = parse_statement("\t//APP\n").unwrap();
+    assert!(matches!(
+        stmt,
+        Statement::Instruction(Instruction {
+            op: "//APP",
+            args: None
+        })
+    ));
+    let (_, stmt) = parse_statement("\tb.ne\t.LBB0_2\n").unwrap();
+    assert!(matches!(
+        stmt,
+        Statement::Instruction(Instruction {
+            op: "b.ne",
+            args: Some(".LBB0_2")
+        })
+    ));
+}
+
+#[test]
+fn test_parse_windows_file() {
+    let (_, file) =
+        File::parse("\t.file\t1 \"C:\\\\work\\\\buf-test\" \"src\\\\main.rs\"").unwrap();
+    assert_eq!(
+        file.path.as_full_path(),
+        Path::new(r"C:\work\buf-test").join(r"src\main.rs")
+    );
+}
+
 #[test]
 fn test_parse_loc() {
     assert_eq!(
@@ -403,7 +680,7 @@ pub enum Directive<'a> {
 #[derive(Clone, Debug)]
 pub struct GenericDirective<'a>(pub &'a str);
 
-pub fn parse_statement(input: &str) -> IResult<&str, Statement> {
+pub fn parse_statement(input: &str) -> IResult<&str, Statement<'_>> {
     let label = map(Label::parse, Statement::Label);
 
     let file = map(File::parse, Directive::File);
@@ -417,11 +694,19 @@ pub fn parse_statement(input: &str) -> IResult<&str, Statement> {
     let generic = map(preceded(tag("\t."), take_while1(|c| c != '\n')), |s| {
         Directive::Generic(GenericDirective(s))
     });
+    // LLVM puts `.seh_proc` at the start of the line, other SEH directives are indented
+    let seh = map(
+        preceded(
+            tag("."),
+            verify(take_while1(|c| c != '\n'), |s: &str| s.starts_with("seh_")),
+        ),
+        |s| Directive::Generic(GenericDirective(s)),
+    );
     let set = map(
         preceded(tag(".set"), take_while1(|c| c != '\n')),
         Directive::Set,
     );
-    let ssvs = map(tag(".subsections_via_symbols"), |_| {
+    let ssvs = map(tag("\t.subsections_via_symbols"), |_| {
         Directive::SubsectionsViaSym
     });
 
@@ -434,14 +719,17 @@ pub fn parse_statement(input: &str) -> IResult<&str, Statement> {
     });
 
     let dir = map(
-        alt((file, loc, set, ssvs, section, generic)),
+        alt((file, loc, set, ssvs, section, generic, seh)),
         Statement::Directive,
     );
 
     // use terminated on the subparsers so that if the subparser doesn't consume the whole line, it's discarded
     // we assume that each label/instruction/directive will only take one line
+    // with verbose asm labels can be followed by a comment
+    let label_comment = tuple((space1, verify(take_while1(|c| c != '\n'), is_comment)));
+
     alt((
-        terminated(label, newline),
+        terminated(label, tuple((opt(label_comment), newline))),
         terminated(dir, newline),
         terminated(instr, newline),
         terminated(nothing, newline),
@@ -450,33 +738,22 @@ pub fn parse_statement(input: &str) -> IResult<&str, Statement> {
 }
 
 fn good_for_label(c: char) -> bool {
-    c == '.'
-        || c == '$'
-        || c == '_'
-        || ('a'..='z').contains(&c)
-        || ('A'..='Z').contains(&c)
-        || ('0'..='9').contains(&c)
+    c == '.' || c == '$' || c == '_' || c.is_ascii_alphanumeric()
 }
 impl Statement<'_> {
+    /// `.cfi_endproc` for DWARF unwinding and `.seh_endproc` for Windows SEH
     pub(crate) fn is_end_of_fn(&self) -> bool {
-        #[allow(unused_variables)]
-        if let Statement::Directive(Directive::Generic(GenericDirective("cfi_endproc"))) = self {
-            true
-        } else if let Statement::Label(Label { id, local: true }) = self {
-            #[cfg(windows)]
-            {
-                id.starts_with(".Lfunc_end")
-            }
-            #[cfg(not(windows))]
-            {
-                false
-            }
-        } else {
-            false
-        }
+        matches!(
+            self,
+            Statement::Directive(Directive::Generic(GenericDirective(
+                "cfi_endproc" | "seh_endproc"
+            )))
+        )
     }
 
-    pub(crate) fn is_section_start(&self) -> bool {
-        matches!(self, Statement::Directive(Directive::SectionStart(_)))
+    /// `.Lfunc_end` label, the only end marker for targets without unwinding directives such
+    /// as `i686-pc-windows-msvc`
+    pub(crate) fn is_end_label(&self) -> bool {
+        matches!(self, Statement::Label(Label { id, local: true }) if id.starts_with(".Lfunc_end"))
     }
 }
@@ -0,0 +1,156 @@
+//! Thread local accesses and the TLS model each one uses, worked out from the relocations of the
+//! instructions: local exec and initial exec offset the thread pointer, while the dynamic models
+//! go through `__tls_get_addr` or a TLS descriptor, Mach-O calls the thunk of a TLV descriptor
+use super::relocation;
+use super::statements::{Instruction, Statement};
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Model {
+    LocalExec,
+    InitialExec,
+    LocalDynamic,
+    GeneralDynamic,
+    Descriptor,
+    Darwin,
+}
+
+/// TLS model of the relocation in `args`, offsets in the block of the module `@DTPOFF` finds
+/// belong to a local dynamic access noted before them
+fn model(args: &str) -> Option<Model> {
+    Some(if args.contains("@TLSGD") {
+        Model::GeneralDynamic
+    } else if args.contains("@TLSLD") {
+        Model::LocalDynamic
+    } else if ["@TLSDESC", "@TLSCALL", ":tlsdesc"]
+        .iter()
+        .any(|marker| args.contains(marker))
+    {
+        Model::Descriptor
+    } else if ["@GOTTPOFF", "@GOTNTPOFF", "@INDNTPOFF", ":gottprel"]
+        .iter()
+        .any(|marker| args.contains(marker))
+    {
+        Model::InitialExec
+    } else if args.contains("@DTPOFF") || args.contains(":dtprel") {
+        return None;
+    } else if ["@TPOFF", "@NTPOFF", ":tprel"]
+        .iter()
+        .any(|marker| args.contains(marker))
+    {
+        Model::LocalExec
+    } else if args.contains("@TLVP") {
+        Model::Darwin
+    } else {
+        return None;
+    })
+}
+
+fn describe(model: Model, symbol: &str) -> String {
+    match model {
+        Model::LocalExec => format!(
+            "thread local {symbol}, local exec: a fixed offset from the thread pointer, \
+             the fastest TLS model"
+        ),
+        Model::InitialExec => format!(
+            "thread local {symbol}, initial exec: the offset from the thread pointer is \
+             loaded from the GOT"
+        ),
+        Model::LocalDynamic => "thread locals of this module, local dynamic: __tls_get_addr \
+             finds them, each one is at a fixed offset from there"
+            .into(),
+        Model::GeneralDynamic => format!(
+            "thread local {symbol}, general dynamic: __tls_get_addr looks it up on every \
+             access, the slowest TLS model"
+        ),
+        Model::Descriptor => format!(
+            "thread local {symbol}, TLS descriptor: a call to the resolver in the GOT gives \
+             the offset"
+        ),
+        Model::Darwin => format!(
+            "thread local {symbol}: a call to the thunk of its TLV descriptor gives the address"
+        ),
+    }
+}
+
+/// Notes on the thread local accesses in `stmts` by the index of the instruction each one
+/// starts at, only the first access to each thread local gets one
+pub(super) fn notes(stmts: &[Statement]) -> BTreeMap<usize, String> {
+    let mut res = BTreeMap::new();
+    let mut seen = BTreeSet::new();
+    // `__tls_get_addr` called by a dynamic access that already has a note
+    let mut lookup = false;
+    for (ix, stmt) in stmts.iter().enumerate() {
+        let Statement::Instruction(Instruction {
+            op,
+            args: Some(args),
+        }) = stmt
+        else {
+            continue;
+        };
+        let Some(symbol) = relocation::symbol(args) else {
+            continue;
+        };
+        let symbol = symbol.split('@').next().unwrap_or(symbol);
+        if relocation::is_call(op) && symbol.trim_start_matches('_') == "tls_get_addr" {
+            if !std::mem::take(&mut lookup) {
+                res.insert(
+                    ix,
+                    "__tls_get_addr call, a dynamic thread local lookup".into(),
+                );
+            }
+            continue;
+        }
+        let Some(model) = model(args) else {
+            continue;
+        };
+        lookup = matches!(model, Model::GeneralDynamic | Model::LocalDynamic);
+        if seen.insert((model, symbol)) {
+            res.insert(ix, describe(model, symbol));
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::notes;
+    use crate::asm::statements::{Instruction, Statement};
+
+    #[test]
+    fn tls_models() {
+        let instr = |op, args| {
+            Statement::Instruction(Instruction {
+                op,
+                args: Some(args),
+            })
+        };
+        let stmts = [
+            instr("lea", "rdi, [rip + COUNT@TLSLD]"),
+            instr("call", "__tls_get_addr@PLT"),
+            instr("mov", "ecx, dword ptr [rax + COUNT@DTPOFF]"),
+            instr("mov", "rax, qword ptr [rip + NAMES@GOTTPOFF]"),
+            instr("mov", "rcx, qword ptr fs:[rax]"),
+            instr("adrp", "x0, :tlsdesc:NAMES"),
+            instr("ldr", "x1, [x0, :tlsdesc_lo12:NAMES]"),
+            instr("add", "x8, x8, :tprel_hi12:COUNT"),
+            instr("call", "__tls_get_addr@PLT"),
+            instr("mov", "rax, qword ptr [rip + NAMES@GOTTPOFF]"),
+        ];
+        let notes = notes(&stmts);
+        let models = notes
+            .iter()
+            .map(|(ix, note)| (*ix, note.split(':').next().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            models,
+            [
+                (0, "thread locals of this module, local dynamic"),
+                (3, "thread local NAMES, initial exec"),
+                (5, "thread local NAMES, TLS descriptor"),
+                (7, "thread local COUNT, local exec"),
+                (8, "__tls_get_addr call, a dynamic thread local lookup"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,437 @@
+//! Unwinding directives in readable form: what each `.cfi_*` and `.seh_*` directive says about
+//! the frame and, for functions with landing pads, the call site table of their LSDA in
+//! `.gcc_except_table`. LLVM puts the table after the function, so it's looked up in the file
+use super::statements::{Directive, GenericDirective, Instruction, Label, Statement};
+use crate::demangle;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+/// Where the canonical frame address is: a register and an offset from it
+#[derive(Clone)]
+struct Cfa {
+    register: String,
+    offset: i64,
+}
+
+impl std::fmt::Display for Cfa {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.offset {
+            0 => write!(f, "CFA = {}", self.register),
+            n if n < 0 => write!(f, "CFA = {} - {}", self.register, -n),
+            n => write!(f, "CFA = {} + {n}", self.register),
+        }
+    }
+}
+
+/// Directives read so far, the CFA depends on all of them
+struct Frame {
+    cfa: Cfa,
+    remembered: Vec<Cfa>,
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Self {
+            cfa: Cfa {
+                register: "stack pointer".to_owned(),
+                offset: 0,
+            },
+            remembered: Vec::new(),
+        }
+    }
+}
+
+fn args(rest: &str) -> Vec<&str> {
+    rest.split(',').map(str::trim).collect()
+}
+
+fn offset(arg: Option<&&str>) -> Option<i64> {
+    arg?.parse().ok()
+}
+
+impl Frame {
+    /// What the unwinding directive `directive` says, without the leading `.`
+    fn describe(&mut self, directive: &str) -> Option<String> {
+        let (name, rest) = directive
+            .split_once(char::is_whitespace)
+            .map_or((directive, ""), |(name, rest)| (name, rest.trim()));
+        let args = args(rest);
+        let res = match name {
+            "cfi_startproc" => {
+                *self = Self::default();
+                "unwind info starts, the CFA is the stack pointer before the call".to_owned()
+            }
+            "cfi_endproc" => "unwind info ends".to_owned(),
+            "cfi_personality" => format!(
+                "personality routine {}",
+                args.get(1)?.trim_start_matches("DW.ref.")
+            ),
+            "cfi_lsda" => format!(
+                "landing pads are in the call site table at {}",
+                args.get(1)?
+            ),
+            "cfi_def_cfa_offset" => {
+                self.cfa.offset = offset(args.first())?;
+                self.cfa.to_string()
+            }
+            "cfi_adjust_cfa_offset" => {
+                self.cfa.offset += offset(args.first())?;
+                self.cfa.to_string()
+            }
+            "cfi_def_cfa" => {
+                self.cfa = Cfa {
+                    register: (*args.first()?).to_owned(),
+                    offset: offset(args.get(1))?,
+                };
+                self.cfa.to_string()
+            }
+            "cfi_def_cfa_register" => {
+                (*args.first()?).clone_into(&mut self.cfa.register);
+                self.cfa.to_string()
+            }
+            "cfi_offset" => format!("{} saved at CFA - {}", args.first()?, -offset(args.get(1))?),
+            "cfi_rel_offset" => format!(
+                "{} saved at {} + {}",
+                args.first()?,
+                self.cfa.register,
+                offset(args.get(1))?
+            ),
+            "cfi_restore" => format!("{} has the value of the caller again", args.first()?),
+            "cfi_same_value" => format!("{} is not changed by the function", args.first()?),
+            "cfi_undefined" => format!("{} can't be restored", args.first()?),
+            "cfi_register" => format!("{} saved in {}", args.first()?, args.get(1)?),
+            "cfi_remember_state" => {
+                self.remembered.push(self.cfa.clone());
+                format!("state saved, {}", self.cfa)
+            }
+            "cfi_restore_state" => {
+                self.cfa = self.remembered.pop()?;
+                format!("state restored, {}", self.cfa)
+            }
+            "cfi_escape" => "DWARF expression given as raw bytes".to_owned(),
+            "cfi_negate_ra_state" => "return address signing toggled".to_owned(),
+            "cfi_window_save" => "register window saved".to_owned(),
+            "seh_proc" => "SEH unwind info starts".to_owned(),
+            "seh_endproc" => "SEH unwind info ends".to_owned(),
+            "seh_pushreg" => format!("{} pushed", args.first()?),
+            "seh_stackalloc" => format!("{} bytes of stack allocated", args.first()?),
+            "seh_setframe" => format!(
+                "{} is the frame pointer at stack pointer + {}",
+                args.first()?,
+                args.get(1)?
+            ),
+            "seh_savereg" | "seh_savexmm" => format!(
+                "{} saved at stack pointer + {}",
+                args.first()?,
+                args.get(1)?
+            ),
+            "seh_endprologue" => "prologue ends".to_owned(),
+            "seh_handler" => format!("exception handler {}", args.first()?),
+            _ => return None,
+        };
+        Some(res)
+    }
+}
+
+/// Entry of the call site table: calls between `start` and `end` unwind to `pad`, `None` as
+/// the start or the end is the start or the end of the function
+#[derive(Debug, PartialEq, Eq)]
+struct CallSite {
+    start: Option<String>,
+    end: Option<String>,
+    pad: Option<String>,
+    action: String,
+}
+
+/// Label an offset such as `.Ltmp76-.Lfunc_begin6` is for, `None` for the function bounds
+fn label(expr: &str) -> Option<String> {
+    let label = expr.split('-').next()?.trim();
+    (!label.starts_with(".Lfunc_begin") && !label.starts_with(".Lfunc_end") && label != "0")
+        .then(|| label.to_owned())
+}
+
+/// What the landing pad does for action `action`: `0` is cleanup, the others are 1 based
+/// offsets into `actions` where records of type filter and offset to the next record are
+fn describe_action(action: i64, actions: &[i64], types: &[String]) -> String {
+    if action == 0 {
+        return "cleanup".to_owned();
+    }
+    let mut res = Vec::new();
+    let mut at = action - 1;
+    while let (Some(filter), Some(next)) = (
+        usize::try_from(at).ok().and_then(|at| actions.get(at)),
+        usize::try_from(at + 1).ok().and_then(|at| actions.get(at)),
+    ) {
+        res.push(match *filter {
+            0 => "cleanup".to_owned(),
+            n if n < 0 => "exception specification".to_owned(),
+            n => match usize::try_from(n)
+                .ok()
+                .and_then(|n| types.len().checked_sub(n))
+                .and_then(|ix| types.get(ix))
+            {
+                Some(ty) if ty == "0" => "catch everything".to_owned(),
+                Some(ty) => format!("catch {ty}"),
+                None => format!("catch type {n}"),
+            },
+        });
+        if *next == 0 || res.len() > actions.len() {
+            break;
+        }
+        at += 1 + next;
+    }
+    res.join(", then ")
+}
+
+/// The call site table of the LSDA `lines` have
+fn call_sites(lines: &[String]) -> Vec<CallSite> {
+    let mut values = Vec::new();
+    // values in the call site table, actions and types go after it
+    let mut sites = None;
+    let mut sites_end = None;
+    let mut types_end = None;
+    for line in lines {
+        let line = line.trim();
+        if let Some(label) = line.strip_suffix(':') {
+            if label.starts_with(".Lcst_begin") {
+                sites = Some(values.len());
+            } else if label.starts_with(".Lcst_end") {
+                sites_end = Some(values.len());
+            } else if label.starts_with(".Lttbase") && !label.starts_with(".Lttbaseref") {
+                types_end = Some(values.len());
+            }
+            continue;
+        }
+        let Some((directive, arg)) = line.split_once(char::is_whitespace) else {
+            continue;
+        };
+        if [".byte", ".uleb128", ".sleb128", ".long", ".quad"].contains(&directive) {
+            values.push((directive, arg.trim().to_owned()));
+        }
+    }
+    let (Some(start), Some(end)) = (sites, sites_end) else {
+        return Vec::new();
+    };
+    let number = |value: &str| value.parse::<i64>().unwrap_or_default();
+    let tail = &values[end..types_end.unwrap_or(values.len())];
+    let actions = tail
+        .iter()
+        .take_while(|(directive, _)| *directive == ".byte")
+        .map(|(_, value)| number(value))
+        .collect::<Vec<_>>();
+    let types = tail[actions.len()..]
+        .iter()
+        .map(|(_, value)| demangle::name(value).unwrap_or_else(|| value.clone()))
+        .collect::<Vec<_>>();
+    values[start..end]
+        .chunks_exact(4)
+        .map(|site| CallSite {
+            start: label(&site[0].1),
+            end: label(&site[1].1),
+            pad: label(&site[2].1),
+            action: describe_action(number(&site[3].1), &actions, &types),
+        })
+        .collect()
+}
+
+/// Lines of the LSDA at `label` in the file at `path`
+fn lsda(path: &Path, label: &str) -> anyhow::Result<Vec<String>> {
+    let start = format!("{label}:");
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    for line in lines.by_ref() {
+        if line? == start {
+            break;
+        }
+    }
+    let mut res = Vec::new();
+    for line in lines {
+        let line = line?;
+        if line.is_empty() || line.starts_with("\t.section") {
+            break;
+        }
+        res.push(line);
+    }
+    Ok(res)
+}
+
+/// Instructions in the landing pad starting at `start`, up to a return, a jump or a call that
+/// goes on unwinding
+fn pad_size(stmts: &[Statement], start: usize) -> usize {
+    let mut res = 0;
+    for stmt in &stmts[start..] {
+        let Statement::Instruction(Instruction { op, args }) = stmt else {
+            continue;
+        };
+        res += 1;
+        let resumes = args.is_some_and(|args| {
+            args.contains("_Unwind_Resume") || args.contains("panic_in_cleanup")
+        });
+        if ["ret", "retq", "jmp", "jmpq", "ud2", "b", "brk"].contains(op) || resumes {
+            break;
+        }
+    }
+    res
+}
+
+/// Notes on the unwind info of the function in `stmts` from the file at `path`, each one goes
+/// before the statement with that index
+pub(super) fn notes(path: &Path, stmts: &[Statement]) -> anyhow::Result<Vec<(usize, String)>> {
+    let mut res = Vec::new();
+    let mut frame = Frame::default();
+    let mut lsda_label = None;
+    let mut labels = BTreeMap::new();
+    for (ix, stmt) in stmts.iter().enumerate() {
+        match stmt {
+            Statement::Directive(Directive::Generic(GenericDirective(directive))) => {
+                if let Some(label) = directive.strip_prefix("cfi_lsda") {
+                    lsda_label = args(label).get(1).map(|l| (*l).to_owned());
+                }
+                if let Some(note) = frame.describe(directive) {
+                    res.push((ix + 1, format!("\t\t// {note}")));
+                }
+            }
+            Statement::Label(Label { id, .. }) => {
+                labels.insert(*id, ix + 1);
+            }
+            _ => {}
+        }
+    }
+
+    let sites = match &lsda_label {
+        Some(label) => call_sites(&lsda(path, label)?),
+        None => Vec::new(),
+    };
+    let mut pads = Vec::<&str>::new();
+    for site in &sites {
+        if let Some(pad) = &site.pad {
+            if !pads.contains(&pad.as_str()) {
+                pads.push(pad);
+            }
+        }
+    }
+    let mut table = Vec::new();
+    let mut cleanup = 0;
+    for (n, pad) in pads.iter().enumerate() {
+        let Some(&at) = labels.get(pad) else {
+            continue;
+        };
+        let size = pad_size(stmts, at);
+        cleanup += size;
+        let of = sites
+            .iter()
+            .enumerate()
+            .filter(|(_, site)| site.pad.as_deref() == Some(*pad))
+            .map(|(ix, _)| (ix + 1).to_string())
+            .collect::<Vec<_>>();
+        let of = match of.as_slice() {
+            [site] => format!("call site {site}"),
+            several => format!("call sites {}", several.join(", ")),
+        };
+        let note = format!("\t\t// landing pad {} for {of}, {size} instructions", n + 1);
+        table.push((at, note));
+    }
+    for (ix, site) in sites.iter().enumerate() {
+        let unwind = match &site.pad {
+            Some(pad) => {
+                let n = pads.iter().position(|p| p == pad).unwrap_or_default() + 1;
+                format!("unwinding goes to landing pad {n} for {}", site.action)
+            }
+            None => "unwinding goes on to the caller".to_owned(),
+        };
+        // sites without a start label start with the function
+        let at = site
+            .start
+            .as_deref()
+            .and_then(|label| labels.get(label).copied())
+            .unwrap_or_default();
+        table.push((at, format!("\t\t// call site {} starts, {unwind}", ix + 1)));
+        if let Some(&at) = site.end.as_deref().and_then(|label| labels.get(label)) {
+            table.push((at, format!("\t\t// call site {} ends", ix + 1)));
+        }
+    }
+    if !sites.is_empty() {
+        let summary = format!(
+            "\t\t// {} call sites, {} landing pads with {cleanup} instructions of cleanup code",
+            sites.len(),
+            pads.len()
+        );
+        res.insert(0, (0, summary));
+    }
+    res.extend(table);
+    // call sites ending go before the next one starting
+    res.sort_by_key(|(ix, note)| (*ix, !note.ends_with(" ends")));
+    Ok(res)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{call_sites, CallSite, Frame};
+
+    #[test]
+    fn frame() {
+        let mut frame = Frame::default();
+        let notes = [
+            "cfi_startproc",
+            "cfi_personality 155, DW.ref.rust_eh_personality",
+            "cfi_def_cfa_offset 16",
+            "cfi_offset rbx, -16",
+            "cfi_def_cfa_register rbp",
+            "cfi_remember_state",
+            "cfi_def_cfa rsp, 8",
+            "cfi_restore_state",
+            "p2align 4",
+        ]
+        .map(|d| frame.describe(d));
+        assert_eq!(
+            notes[1..].to_vec(),
+            [
+                Some("personality routine rust_eh_personality".to_owned()),
+                Some("CFA = stack pointer + 16".to_owned()),
+                Some("rbx saved at CFA - 16".to_owned()),
+                Some("CFA = rbp + 16".to_owned()),
+                Some("state saved, CFA = rbp + 16".to_owned()),
+                Some("CFA = rsp + 8".to_owned()),
+                Some("state restored, CFA = rbp + 16".to_owned()),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn lsda() {
+        let lines = "\t.byte\t255\n\t.byte\t155\n\t.uleb128 .Lttbase0-.Lttbaseref0\n.Lttbaseref0:\n\
+                     \t.byte\t1\n\t.uleb128 .Lcst_end0-.Lcst_begin0\n.Lcst_begin0:\n\
+                     \t.uleb128 .Lfunc_begin6-.Lfunc_begin6\n\t.uleb128 .Ltmp76-.Lfunc_begin6\n\
+                     \t.byte\t0\n\t.byte\t0\n\
+                     \t.uleb128 .Ltmp76-.Lfunc_begin6\n\t.uleb128 .Ltmp77-.Ltmp76\n\
+                     \t.uleb128 .Ltmp78-.Lfunc_begin6\n\t.byte\t1\n\
+                     \t.uleb128 .Ltmp77-.Lfunc_begin6\n\t.uleb128 .Lfunc_end6-.Ltmp77\n\
+                     \t.uleb128 .Ltmp79-.Lfunc_begin6\n\t.byte\t0\n\
+                     .Lcst_end0:\n\t.byte\t1\n\t.byte\t0\n\t.p2align\t2, 0x0\n\t.long\t0\n.Lttbase0:\n"
+            .lines()
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+        let site =
+            |start: Option<&str>, end: Option<&str>, pad: Option<&str>, action: &str| CallSite {
+                start: start.map(str::to_owned),
+                end: end.map(str::to_owned),
+                pad: pad.map(str::to_owned),
+                action: action.to_owned(),
+            };
+        assert_eq!(
+            call_sites(&lines),
+            [
+                site(None, Some(".Ltmp76"), None, "cleanup"),
+                site(
+                    Some(".Ltmp76"),
+                    Some(".Ltmp77"),
+                    Some(".Ltmp78"),
+                    "catch everything"
+                ),
+                site(Some(".Ltmp77"), None, Some(".Ltmp79"), "cleanup"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,148 @@
+//! How the code of a crate is split between crates and modules: functions are grouped by
+//! their demangled paths one level below the prefix asked for, biggest groups first.
+//! Methods of trait implementations go with the type they are implemented for
+use crate::{asm::Item, color};
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// Split `path` on `::` outside of generic arguments
+fn split(path: &str) -> Vec<&str> {
+    let mut res = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    let bytes = path.as_bytes();
+    let mut ix = 0;
+    while ix < bytes.len() {
+        match bytes[ix] {
+            b'<' | b'(' | b'[' => depth += 1,
+            b'>' | b')' | b']' => depth -= 1,
+            b':' if depth == 0 && bytes.get(ix + 1) == Some(&b':') => {
+                res.push(&path[start..ix]);
+                start = ix + 2;
+                ix += 1;
+            }
+            _ => {}
+        }
+        ix += 1;
+    }
+    res.push(&path[start..]);
+    res
+}
+
+/// Path segments of a function for grouping, `<T as Trait>::method` is under `T` and the
+/// generic arguments of a type don't make a group of their own
+fn segments(name: &str) -> Vec<String> {
+    let mut parts = split(name);
+    let mut res = Vec::new();
+    if let Some(imp) = parts
+        .first()
+        .and_then(|first| first.strip_prefix('<')?.strip_suffix('>'))
+    {
+        let ty = imp.split_once(" as ").map_or(imp, |(ty, _)| ty);
+        res.extend(segments(ty));
+        parts.remove(0);
+    }
+    res.extend(parts.iter().map(|part| {
+        // `Vec<T>` and `Vec<u8>` are the same type as far as grouping goes
+        part.split_once('<')
+            .filter(|(base, _)| !base.is_empty())
+            .map_or(*part, |(base, _)| base)
+            .to_owned()
+    }));
+    res
+}
+
+/// Everything under a path one level below the prefix
+#[derive(Default)]
+struct Group {
+    size: u64,
+    functions: usize,
+    /// there's a function with exactly this path
+    function: bool,
+}
+
+/// Print the sizes of the groups of `items` under `prefix`, in bytes if `sizes` has them and
+/// in lines of code otherwise
+pub fn report(
+    items: &[Item],
+    sizes: &BTreeMap<String, u64>,
+    prefix: &str,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    let prefix = segments(prefix)
+        .into_iter()
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>();
+    let bytes = !sizes.is_empty();
+    let mut groups = BTreeMap::<String, Group>::new();
+    for item in items {
+        let path = segments(&item.name);
+        if path.len() <= prefix.len() || !path.starts_with(&prefix) {
+            continue;
+        }
+        let size = if bytes {
+            sizes.get(&item.hashed).copied().unwrap_or_default()
+        } else {
+            item.len as u64
+        };
+        let group = groups.entry(path[..=prefix.len()].join("::")).or_default();
+        group.size += size;
+        group.functions += 1;
+        group.function |= path.len() == prefix.len() + 1;
+    }
+    anyhow::ensure!(
+        !groups.is_empty(),
+        crate::exit::Failure::not_found("Nothing matches this path, try a shorter one")
+    );
+
+    let unit = if bytes { "bytes" } else { "lines" };
+    let total = groups.values().map(|g| g.size).sum::<u64>();
+    let mut groups = groups.into_iter().collect::<Vec<_>>();
+    groups.sort_by(|(a_name, a), (b_name, b)| b.size.cmp(&a.size).then(a_name.cmp(b_name)));
+    let heading = format!("{total} {unit} in total");
+    writeln!(out, "{}", color!(heading, comment))?;
+    for (name, group) in groups {
+        let share = if total == 0 {
+            0.0
+        } else {
+            group.size as f64 * 100.0 / total as f64
+        };
+        let count = match (group.function, group.functions) {
+            (true, 1) => "fn".to_owned(),
+            (false, 1) => "1 fn".to_owned(),
+            (_, n) => format!("{n} fns"),
+        };
+        let size = format!("{:>10}", group.size);
+        let count = format!("{count:>8}");
+        writeln!(
+            out,
+            "{} {share:>5.1}% {}  {}",
+            color!(size, size),
+            color!(count, comment),
+            color!(name, symbol)
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{segments, split};
+
+    #[test]
+    fn paths() {
+        assert_eq!(split("a::b<c::d>::e"), ["a", "b<c::d>", "e"]);
+        assert_eq!(
+            segments("<alloc::vec::Vec<T> as core::fmt::Debug>::fmt"),
+            ["alloc", "vec", "Vec", "fmt"]
+        );
+        assert_eq!(
+            segments("core::ptr::drop_in_place<my::Thing>"),
+            ["core", "ptr", "drop_in_place"]
+        );
+        assert_eq!(
+            segments("my::work::{{closure}}"),
+            ["my", "work", "{{closure}}"]
+        );
+    }
+}
@@ -0,0 +1,246 @@
+#![allow(clippy::missing_errors_doc)]
+//! Remembers which file cargo produced for a given set of flags along with the list
+//! of items it contains so repeated invocations don't have to run cargo again
+use crate::asm::Item;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeSet;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::SystemTime;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    /// file produced by rustc: `.s`, `.ll` or `.mir`
+    pub asm_path: PathBuf,
+    /// all the items defined in `asm_path`, populated on the first full scan
+    pub index: Option<Vec<Item>>,
+}
+
+/// Key for cargo invocation, covers everything that can change generated code
+///
+/// Verbosity and color flags only affect what cargo prints so they are not part of the key
+#[must_use]
+pub fn key(cmd: &Command, toolchain: &str, extra: &[String]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    toolchain.hash(&mut hasher);
+    cmd.get_program().hash(&mut hasher);
+    let mut args = cmd.get_args();
+    while let Some(arg) = args.next() {
+        if arg == "--color" {
+            args.next();
+        } else if arg != "-v" {
+            arg.hash(&mut hasher);
+        }
+    }
+    for (key, val) in cmd.get_envs() {
+        key.hash(&mut hasher);
+        val.hash(&mut hasher);
+    }
+    extra.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn entry_path(dir: &Path, key: u64) -> PathBuf {
+    dir.join(format!("{key:016x}.json"))
+}
+
+#[must_use]
+pub fn load(dir: &Path, key: u64) -> Option<Entry> {
+    let payload = std::fs::read_to_string(entry_path(dir, key)).ok()?;
+    serde_json::from_str(&payload).ok()
+}
+
+pub fn store(dir: &Path, key: u64, entry: &Entry) -> anyhow::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(entry_path(dir, key), serde_json::to_string(entry)?)?;
+    Ok(())
+}
+
+/// Source files rustc used to produce `asm_path`, taken from dep-info file next to it
+#[must_use]
+pub fn dependencies(asm_path: &Path) -> Option<Vec<PathBuf>> {
+    let payload = std::fs::read_to_string(asm_path.with_extension("d")).ok()?;
+    let mut res = Vec::new();
+    for line in payload.lines() {
+        if line.starts_with('#') {
+            continue;
+        }
+        if let Some((_, deps)) = line.split_once(": ") {
+            // spaces in file names are escaped with a backslash
+            let deps = deps.replace("\\ ", "\u{0}");
+            res.extend(
+                deps.split_whitespace()
+                    .map(|dep| PathBuf::from(dep.replace('\u{0}', " "))),
+            );
+        }
+    }
+    Some(res)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).ok()?.modified().ok()
+}
+
+/// Check if any of the manifests or rust sources inside of `dir` were modified after `time`
+///
+/// Target directories are skipped, cargo marks them with `CACHEDIR.TAG`
+fn changed_since(dir: &Path, time: SystemTime) -> bool {
+    if dir.join("CACHEDIR.TAG").exists() {
+        return false;
+    }
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return true;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        if file_type.is_dir() {
+            if !entry.file_name().to_string_lossy().starts_with('.') && changed_since(&path, time) {
+                return true;
+            }
+        } else if (path.extension().is_some_and(|ext| ext == "rs")
+            || path
+                .file_name()
+                .is_some_and(|f| f == "Cargo.toml" || f == "Cargo.lock"))
+            && modified(&path).is_some_and(|m| m > time)
+        {
+            return true;
+        }
+    }
+    false
+}
+
+/// Check if generated file is missing or any of the files listed in its dep-info changed
+/// after it was made
+///
+/// Relative paths in dep-info are relative to the workspace `root`
+#[must_use]
+pub fn older_than_sources(asm_path: &Path, root: &Path) -> bool {
+    let Some(generated) = modified(asm_path) else {
+        return true;
+    };
+    let Some(deps) = dependencies(asm_path) else {
+        return true;
+    };
+    !deps
+        .iter()
+        .all(|dep| modified(&root.join(dep)).is_some_and(|m| m <= generated))
+}
+
+/// Generated file is fresh if it exists and none of the sources it was made from changed since
+///
+/// Sources are files listed in dep-info and anything that looks like rust code or manifest in
+/// the workspace `root` to account for changes in path dependencies.
+#[must_use]
+pub fn is_fresh(asm_path: &Path, root: &Path) -> bool {
+    !older_than_sources(asm_path, root)
+        && modified(asm_path).is_some_and(|generated| !changed_since(root, generated))
+}
+
+/// Cache directories that might belong to the project in the current directory: the one in
+/// `CARGO_TARGET_DIR` and `target/cargo-show-asm` in the current directory or its parents.
+/// Found without asking cargo so shell completion stays fast
+#[must_use]
+pub fn nearby_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Some(target) = std::env::var_os("CARGO_TARGET_DIR") {
+        dirs.push(PathBuf::from(target).join("cargo-show-asm"));
+    }
+    if let Ok(cwd) = std::env::current_dir() {
+        if let Some(dir) = cwd
+            .ancestors()
+            .map(|dir| dir.join("target").join("cargo-show-asm"))
+            .find(|dir| dir.is_dir())
+        {
+            dirs.push(dir);
+        }
+    }
+    dirs
+}
+
+/// Names of the items from every entry in `dir` with an index, generated files that are gone
+/// don't count
+#[must_use]
+pub fn cached_names(dir: &Path) -> BTreeSet<String> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return BTreeSet::new();
+    };
+    entries
+        .flatten()
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|entry| {
+            let payload = std::fs::read_to_string(entry.path()).ok()?;
+            serde_json::from_str::<Entry>(&payload).ok()
+        })
+        .filter(|entry| entry.asm_path.exists())
+        .flat_map(|entry| entry.index.unwrap_or_default())
+        .map(|item| item.name)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{cached_names, dependencies, store, Entry};
+    use crate::asm::{Item, Linkage};
+    use std::path::PathBuf;
+
+    #[test]
+    fn parse_dep_info() {
+        let dir = std::env::temp_dir().join(format!("cargo-show-asm-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let asm = dir.join("sample-1234.s");
+        std::fs::write(
+            asm.with_extension("d"),
+            "/t/sample-1234.s: src/lib.rs src/my\\ mod.rs\n\nsrc/lib.rs:\nsrc/my\\ mod.rs:\n\n# env-dep:FOO=bar\n",
+        )
+        .unwrap();
+        let deps = dependencies(&asm).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            deps,
+            [PathBuf::from("src/lib.rs"), PathBuf::from("src/my mod.rs")]
+        );
+    }
+
+    #[test]
+    fn names_from_index() {
+        let dir = std::env::temp_dir().join(format!("cargo-show-asm-names-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let asm_path = dir.join("sample.s");
+        std::fs::write(&asm_path, "").unwrap();
+        let item = |name: &str| Item {
+            name: name.to_owned(),
+            hashed: format!("{name}::h1"),
+            index: 0,
+            len: 1,
+            linkage: Linkage::default(),
+        };
+        let index = Some(vec![
+            item("sample::main"),
+            item("sample::main"),
+            item("sample::f"),
+        ]);
+        store(&dir, 1, &Entry { asm_path, index }).unwrap();
+        let gone = dir.join("gone.s");
+        let index = Some(vec![item("sample::old")]);
+        store(
+            &dir,
+            2,
+            &Entry {
+                asm_path: gone,
+                index,
+            },
+        )
+        .unwrap();
+        let names = cached_names(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            names.into_iter().collect::<Vec<_>>(),
+            ["sample::f", "sample::main"]
+        );
+    }
+}
@@ -0,0 +1,425 @@
+#![allow(clippy::missing_errors_doc)]
+// https://doc.rust-lang.org/cargo/reference/config.html
+use anyhow::Context;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use toml::Value;
+
+/// Parts of cargo configuration that change the generated code
+///
+/// cargo applies those on its own, rustflags go after the flags given to `cargo rustc`. They
+/// are only read to make our own decisions match what cargo is going to do: the cache key,
+/// the notes and the shim for generic functions, which is compiled by rustc directly
+#[derive(Debug, Clone, Default)]
+pub struct CargoConfig {
+    /// `build.target`
+    pub target: Option<String>,
+    /// `CARGO_ENCODED_RUSTFLAGS` or `RUSTFLAGS`, they replace all the rustflags from the config
+    pub env_rustflags: Option<Vec<String>>,
+    /// `target.<triple>.rustflags` and `target.'cfg(..)'.rustflags` by their key
+    pub target_rustflags: BTreeMap<String, Vec<String>>,
+    /// `build.rustflags`, `CARGO_BUILD_RUSTFLAGS` takes priority
+    pub build_rustflags: Option<Vec<String>>,
+    /// `build.rustc-wrapper`, `RUSTC_WRAPPER` takes priority
+    pub rustc_wrapper: Option<String>,
+    /// `build.incremental`, `CARGO_INCREMENTAL` takes priority
+    pub incremental: Option<bool>,
+    /// `profile` table, merged from all the config files
+    pub profiles: toml::value::Table,
+    /// config files that were found, lowest priority first
+    pub files: Vec<PathBuf>,
+    /// `CARGO_TARGET_<TRIPLE>_RUSTFLAGS` by the variable name
+    target_env: BTreeMap<String, String>,
+}
+
+/// Config files cargo reads when invoked from `cwd`, lowest priority first
+fn config_files(cwd: &Path) -> Vec<PathBuf> {
+    let cargo_home = std::env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| {
+            #[allow(deprecated)]
+            std::env::home_dir().map(|home| home.join(".cargo"))
+        });
+
+    let mut dirs = cwd
+        .ancestors()
+        .map(|dir| dir.join(".cargo"))
+        .collect::<Vec<_>>();
+    if let Some(home) = cargo_home {
+        if !dirs.contains(&home) {
+            dirs.push(home);
+        }
+    }
+
+    dirs.iter()
+        .rev()
+        .filter_map(|dir| {
+            ["config.toml", "config"]
+                .iter()
+                .map(|name| dir.join(name))
+                .find(|file| file.is_file())
+        })
+        .collect()
+}
+
+/// Merge `new` into `old` the same way cargo does: tables are merged recursively,
+/// arrays are concatenated and everything else is replaced
+fn merge(old: &mut Value, new: Value) {
+    match (old, new) {
+        (Value::Table(old), Value::Table(new)) => {
+            for (key, val) in new {
+                match old.get_mut(&key) {
+                    Some(old_val) => merge(old_val, val),
+                    None => {
+                        old.insert(key, val);
+                    }
+                }
+            }
+        }
+        (Value::Array(old), Value::Array(new)) => old.extend(new),
+        (old, new) => *old = new,
+    }
+}
+
+/// Flags can be specified either as a single space separated string or as an array of strings
+fn flags(val: &Value) -> Vec<String> {
+    match val {
+        Value::String(s) => s.split_whitespace().map(String::from).collect(),
+        Value::Array(items) => items
+            .iter()
+            .filter_map(|v| v.as_str().map(String::from))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+impl CargoConfig {
+    pub fn load(cwd: &Path) -> anyhow::Result<Self> {
+        let files = config_files(cwd);
+        let mut merged = Value::Table(toml::value::Table::new());
+        for file in &files {
+            let payload = std::fs::read_to_string(file)
+                .with_context(|| format!("Failed to read {}", file.display()))?;
+            let val = payload
+                .parse::<Value>()
+                .with_context(|| format!("Failed to parse {}", file.display()))?;
+            merge(&mut merged, val);
+        }
+        let env = std::env::vars()
+            .filter(|(key, _)| key.starts_with("CARGO_") || key.starts_with("RUST"))
+            .collect();
+        Ok(Self::new(&merged, &env, files))
+    }
+
+    /// Configuration from the `merged` config files and the relevant `env` variables
+    fn new(merged: &Value, env: &BTreeMap<String, String>, files: Vec<PathBuf>) -> Self {
+        let target = match env.get("CARGO_BUILD_TARGET") {
+            Some(target) => Some(target.clone()),
+            None => match merged.get("build").and_then(|b| b.get("target")) {
+                Some(Value::String(target)) => Some(target.clone()),
+                // multi target builds, the first one is as good as any
+                Some(Value::Array(targets)) => {
+                    targets.first().and_then(Value::as_str).map(String::from)
+                }
+                _ => None,
+            },
+        };
+
+        let env_rustflags = if let Some(flags) = env.get("CARGO_ENCODED_RUSTFLAGS") {
+            Some(
+                flags
+                    .split('\x1f')
+                    .filter(|f| !f.is_empty())
+                    .map(String::from)
+                    .collect(),
+            )
+        } else {
+            env.get("RUSTFLAGS")
+                .map(|flags| flags.split_whitespace().map(String::from).collect())
+        };
+
+        let target_rustflags = match merged.get("target") {
+            Some(Value::Table(targets)) => targets
+                .iter()
+                .filter_map(|(key, target)| {
+                    Some((key.clone(), self::flags(target.get("rustflags")?)))
+                })
+                .collect(),
+            _ => BTreeMap::new(),
+        };
+        let target_env = env
+            .iter()
+            .filter(|(key, _)| key.starts_with("CARGO_TARGET_") && key.ends_with("_RUSTFLAGS"))
+            .map(|(key, val)| (key.clone(), val.clone()))
+            .collect();
+
+        let build_rustflags = match env.get("CARGO_BUILD_RUSTFLAGS") {
+            Some(flags) => Some(flags.split_whitespace().map(String::from).collect()),
+            None => merged
+                .get("build")
+                .and_then(|b| b.get("rustflags"))
+                .map(self::flags),
+        };
+
+        let rustc_wrapper = match env.get("RUSTC_WRAPPER") {
+            Some(wrapper) => Some(wrapper.clone()),
+            None => merged
+                .get("build")
+                .and_then(|b| b.get("rustc-wrapper"))
+                .and_then(Value::as_str)
+                .map(String::from),
+        }
+        .filter(|wrapper| !wrapper.is_empty());
+
+        let incremental = match env.get("CARGO_INCREMENTAL") {
+            Some(val) => Some(val != "0"),
+            None => merged
+                .get("build")
+                .and_then(|b| b.get("incremental"))
+                .and_then(Value::as_bool),
+        };
+
+        let profiles = match merged.get("profile") {
+            Some(Value::Table(profiles)) => profiles.clone(),
+            _ => toml::value::Table::new(),
+        };
+
+        Self {
+            target,
+            env_rustflags,
+            target_rustflags,
+            build_rustflags,
+            rustc_wrapper,
+            incremental,
+            profiles,
+            files,
+            target_env,
+        }
+    }
+
+    /// Rustflags cargo passes when building for `target`, the triple or the name of a target
+    /// spec. `CARGO_ENCODED_RUSTFLAGS` or `RUSTFLAGS` replace everything else, then go
+    /// `target.<target>.rustflags` and `build.rustflags`, environment variables for config
+    /// values take priority over config files
+    #[must_use]
+    pub fn rustflags_for(&self, target: &str) -> Vec<String> {
+        if let Some(flags) = &self.env_rustflags {
+            return flags.clone();
+        }
+        let var = format!(
+            "CARGO_TARGET_{}_RUSTFLAGS",
+            target.to_uppercase().replace(['-', '.'], "_")
+        );
+        if let Some(flags) = self.target_env.get(&var) {
+            return flags.split_whitespace().map(String::from).collect();
+        }
+        if let Some(flags) = self.target_rustflags.get(target) {
+            return flags.clone();
+        }
+        self.build_rustflags.clone().unwrap_or_default()
+    }
+
+    /// `target.'cfg(..)'` keys with rustflags that apply unless the environment replaces them.
+    /// Their conditions are not evaluated and the flags are not counted in [`Self::rustflags_for`]
+    pub fn cfg_rustflags(&self) -> impl Iterator<Item = &str> {
+        self.target_rustflags
+            .keys()
+            .filter(|key| key.starts_with("cfg(") && self.env_rustflags.is_none())
+            .map(String::as_str)
+    }
+
+    /// Value of `profile.<profile>.<key>` if set
+    #[must_use]
+    pub fn profile_setting(&self, profile: &str, key: &str) -> Option<&Value> {
+        self.profiles.get(profile)?.get(key)
+    }
+
+    /// Value of `key` in `profile` or the profiles it inherits from, `manifest` is the `profile`
+    /// table from the workspace manifest, config files take priority over it. Gives the name
+    /// of the built-in profile the chain ends at along with the value
+    fn inherited(
+        &self,
+        profile: &str,
+        key: &str,
+        manifest: Option<&Value>,
+    ) -> (String, Option<Value>) {
+        let lookup = |profile: &str, key: &str| {
+            self.profile_setting(profile, key)
+                .or_else(|| manifest?.get(profile)?.get(key))
+                .cloned()
+        };
+        let mut profile = profile.to_owned();
+        // inheritance chains can't have cycles, but don't trust user input too much
+        for _ in 0..16 {
+            if let Some(val) = lookup(&profile, key) {
+                return (profile, Some(val));
+            }
+            match lookup(&profile, "inherits") {
+                Some(Value::String(parent)) => profile = parent,
+                _ => break,
+            }
+        }
+        (profile, None)
+    }
+
+    /// Check if workspace members are compiled incrementally with this `profile`
+    ///
+    /// `manifest` is the `profile` table from the workspace manifest, config files take
+    /// priority over it. Custom profiles inherit settings from their parents.
+    #[must_use]
+    pub fn incremental(&self, profile: &str, manifest: Option<&Value>) -> bool {
+        if let Some(incremental) = self.incremental {
+            return incremental;
+        }
+        match self.inherited(profile, "incremental", manifest) {
+            (_, Some(val)) => val.as_bool().unwrap_or(false),
+            (profile, None) => matches!(profile.as_str(), "dev" | "test"),
+        }
+    }
+
+    /// `opt-level` of this `profile` as rustc takes it, `test` and `bench` get theirs from `dev`
+    /// and `release` unless they set one
+    #[must_use]
+    pub fn opt_level(&self, profile: &str, manifest: Option<&Value>) -> String {
+        let (mut profile, mut val) = self.inherited(profile, "opt-level", manifest);
+        if val.is_none() {
+            let parent = match profile.as_str() {
+                "test" => "dev",
+                "bench" => "release",
+                _ => "",
+            };
+            if !parent.is_empty() {
+                (profile, val) = self.inherited(parent, "opt-level", manifest);
+            }
+        }
+        match val {
+            Some(Value::Integer(level)) => level.to_string(),
+            Some(Value::String(level)) => level,
+            _ if matches!(profile.as_str(), "dev" | "test") => "0".into(),
+            _ => "3".into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{flags, merge, CargoConfig};
+    use std::collections::BTreeMap;
+    use toml::Value;
+
+    fn config(toml: &str, env: &[(&str, &str)]) -> CargoConfig {
+        let env = env
+            .iter()
+            .map(|(key, val)| ((*key).to_owned(), (*val).to_owned()))
+            .collect::<BTreeMap<_, _>>();
+        CargoConfig::new(&toml.parse::<Value>().unwrap(), &env, Vec::new())
+    }
+
+    #[test]
+    fn merge_configs() {
+        let mut old = "[build]\nrustflags = [\"-Cfoo\"]\ntarget = \"a\"\n"
+            .parse::<Value>()
+            .unwrap();
+        let new =
+            "[build]\nrustflags = [\"-Cbar\"]\ntarget = \"b\"\n[profile.release]\nlto = true\n"
+                .parse::<Value>()
+                .unwrap();
+        merge(&mut old, new);
+        assert_eq!(flags(&old["build"]["rustflags"]), ["-Cfoo", "-Cbar"]);
+        assert_eq!(old["build"]["target"].as_str(), Some("b"));
+        assert_eq!(old["profile"]["release"]["lto"].as_bool(), Some(true));
+    }
+
+    #[test]
+    fn incremental_profiles() {
+        let manifest =
+            "[custom]\ninherits = \"dev\"\n[fast]\ninherits = \"release\"\nincremental = true\n"
+                .parse::<Value>()
+                .unwrap();
+        let config = CargoConfig::default();
+        assert!(config.incremental("dev", None));
+        assert!(!config.incremental("release", None));
+        assert!(config.incremental("custom", Some(&manifest)));
+        assert!(config.incremental("fast", Some(&manifest)));
+    }
+
+    #[test]
+    fn opt_levels() {
+        let manifest = "[dev]\nopt-level = 1\n[small]\ninherits = \"release\"\nopt-level = \"z\"\n"
+            .parse::<Value>()
+            .unwrap();
+        let config = CargoConfig::default();
+        assert_eq!(config.opt_level("dev", None), "0");
+        assert_eq!(config.opt_level("bench", None), "3");
+        assert_eq!(config.opt_level("test", Some(&manifest)), "1");
+        assert_eq!(config.opt_level("small", Some(&manifest)), "z");
+    }
+
+    #[test]
+    fn string_flags() {
+        let val = Value::String("-C target-cpu=native  --cfg foo".into());
+        assert_eq!(flags(&val), ["-C", "target-cpu=native", "--cfg", "foo"]);
+    }
+
+    #[test]
+    fn target_rustflags() {
+        let toml = "[build]\nrustflags = \"-Cbuild\"\n\
+            [target.thumbv7em-none-eabihf]\nrustflags = [\"-Ctarget-cpu=cortex-m4\"]\n\
+            [target.my-board]\nrustflags = [\"-Cboard\"]\n\
+            [target.'cfg(target_os = \"none\")']\nrustflags = [\"-Ccfg\"]\n";
+        let config = config(toml, &[]);
+        assert_eq!(
+            config.rustflags_for("thumbv7em-none-eabihf"),
+            ["-Ctarget-cpu=cortex-m4"]
+        );
+        assert_eq!(config.rustflags_for("my-board"), ["-Cboard"]);
+        assert_eq!(
+            config.rustflags_for("x86_64-unknown-linux-gnu"),
+            ["-Cbuild"]
+        );
+        assert_eq!(
+            config.cfg_rustflags().collect::<Vec<_>>(),
+            ["cfg(target_os = \"none\")"]
+        );
+    }
+
+    #[test]
+    fn env_rustflags() {
+        let toml = "[build]\nrustflags = \"-Cbuild\"\n\
+            [target.thumbv7em-none-eabihf]\nrustflags = \"-Ctarget\"\n\
+            [target.'cfg(unix)']\nrustflags = \"-Ccfg\"\n";
+        let thumb = "thumbv7em-none-eabihf";
+        let host = "x86_64-unknown-linux-gnu";
+
+        let encoded = config(
+            toml,
+            &[
+                ("CARGO_ENCODED_RUSTFLAGS", "-C\x1fopt-level=1\x1f"),
+                ("RUSTFLAGS", "-Cunused"),
+            ],
+        );
+        assert_eq!(encoded.rustflags_for(thumb), ["-C", "opt-level=1"]);
+        assert_eq!(encoded.cfg_rustflags().count(), 0);
+
+        let rustflags = config(toml, &[("RUSTFLAGS", "-Cenv  -Cmore")]);
+        assert_eq!(rustflags.rustflags_for(thumb), ["-Cenv", "-Cmore"]);
+        assert_eq!(rustflags.rustflags_for(host), ["-Cenv", "-Cmore"]);
+
+        let target = config(
+            toml,
+            &[
+                (
+                    "CARGO_TARGET_THUMBV7EM_NONE_EABIHF_RUSTFLAGS",
+                    "-Ctarget-env",
+                ),
+                ("CARGO_BUILD_RUSTFLAGS", "-Cbuild-env"),
+            ],
+        );
+        assert_eq!(target.rustflags_for(thumb), ["-Ctarget-env"]);
+        assert_eq!(target.rustflags_for(host), ["-Cbuild-env"]);
+
+        let build = config("", &[("CARGO_BUILD_RUSTFLAGS", "-Cbuild-env")]);
+        assert_eq!(build.rustflags_for(thumb), ["-Cbuild-env"]);
+        assert!(config("", &[]).rustflags_for(host).is_empty());
+    }
+}
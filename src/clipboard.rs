@@ -0,0 +1,104 @@
+#![allow(clippy::missing_errors_doc)]
+//! Output is collected in memory and copied once flushed. Platform tools are tried first,
+//! terminals understand OSC 52 escape sequence which also works over ssh.
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Copy collected output to the system clipboard when flushed
+#[derive(Default)]
+pub struct Clipboard {
+    buf: Vec<u8>,
+}
+
+fn tools() -> &'static [&'static [&'static str]] {
+    if cfg!(target_os = "macos") {
+        &[&["pbcopy"]]
+    } else if cfg!(windows) {
+        &[&["clip.exe"]]
+    } else {
+        &[
+            &["wl-copy"],
+            &["xclip", "-selection", "clipboard"],
+            &["xsel", "--clipboard", "--input"],
+        ]
+    }
+}
+
+fn copy_with(tool: &[&str], buf: &[u8]) -> bool {
+    let Ok(mut child) = Command::new(tool[0])
+        .args(&tool[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+    else {
+        return false;
+    };
+    let written = child
+        .stdin
+        .take()
+        .is_some_and(|mut stdin| stdin.write_all(buf).is_ok());
+    child.wait().is_ok_and(|status| status.success()) && written
+}
+
+fn base64(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut res = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |acc, (ix, b)| acc | u32::from(*b) << (16 - 8 * ix));
+        for ix in 0..4 {
+            if ix <= chunk.len() {
+                res.push(ALPHABET[(n >> (18 - 6 * ix)) as usize & 63] as char);
+            } else {
+                res.push('=');
+            }
+        }
+    }
+    res
+}
+
+impl Clipboard {
+    fn copy(buf: &[u8]) -> std::io::Result<()> {
+        if !tools().iter().any(|tool| copy_with(tool, buf)) {
+            // terminal is the one setting the clipboard, stderr is more likely to be connected
+            // to it than stdout
+            let mut stderr = std::io::stderr().lock();
+            write!(stderr, "\x1b]52;c;{}\x07", base64(buf))?;
+            stderr.flush()?;
+        }
+        let lines = buf.iter().filter(|b| **b == b'\n').count();
+        eprintln!("Copied {lines} lines to the clipboard");
+        Ok(())
+    }
+}
+
+impl Write for Clipboard {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buf.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let buf = std::mem::take(&mut self.buf);
+        Self::copy(&buf)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::base64;
+
+    #[test]
+    fn base64_padding() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+}
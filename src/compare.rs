@@ -0,0 +1,219 @@
+#![allow(clippy::missing_errors_doc)]
+//! Changes between two builds of a binary for `--compare-bins`: functions are matched by their
+//! demangled names without the hash, so a new hash alone doesn't make a function new, and
+//! sizes come from the symbol tables. Code of the matching functions is compared as
+//! `objdump` disassembly with the addresses left out
+use crate::{color, demangle, elf, exit::Failure};
+use anyhow::Context;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+
+/// All the symbols with the same demangled name
+#[derive(Debug, Default)]
+struct Function {
+    size: u64,
+    symbols: Vec<String>,
+}
+
+fn functions(path: &Path) -> anyhow::Result<BTreeMap<String, Function>> {
+    let sizes =
+        elf::symbol_sizes(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    anyhow::ensure!(
+        !sizes.is_empty(),
+        "No functions in {}, only ELF binaries with symbols can be compared",
+        path.display()
+    );
+    let mut res = BTreeMap::<String, Function>::new();
+    for (symbol, size) in sizes {
+        let name = match demangle::demangled(&symbol) {
+            Some(name) => format!("{name:#}"),
+            None => demangle::foreign(&symbol).unwrap_or_else(|| symbol.clone()),
+        };
+        let function = res.entry(name).or_default();
+        function.size += size;
+        function.symbols.push(symbol);
+    }
+    Ok(res)
+}
+
+/// Instructions of `symbol` in `path` without the addresses, those change with every edit
+fn disassemble(path: &Path, symbol: &str, full_name: bool) -> anyhow::Result<Vec<String>> {
+    static ADDRESS: Lazy<Regex> = Lazy::new(|| Regex::new(r"\b[0-9a-f]+ (<[^>]+>)").unwrap());
+    static COMMENT: Lazy<Regex> = Lazy::new(|| Regex::new(r"\s+# [0-9a-f]+$").unwrap());
+    let output = elf::objdump(path, symbol, &["--no-show-raw-insn", "-M", "intel"])?;
+    Ok(output
+        .lines()
+        .filter_map(|line| Some(line.split_once(":\t")?.1))
+        .map(|instr| {
+            let instr = ADDRESS.replace_all(instr, "$1");
+            let instr = COMMENT.replace(&instr, "");
+            demangle::contents(&instr, full_name).into_owned()
+        })
+        .collect())
+}
+
+/// Line diff of `old` and `new`, `None` for lines only in `old`, `Some(false)` for lines only
+/// in `new` and `Some(true)` for lines in both
+fn diff<'a>(old: &'a [String], new: &'a [String]) -> Vec<(Option<bool>, &'a str)> {
+    // length of the longest common subsequence of the tails
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut res = Vec::new();
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            res.push((Some(true), old[i].as_str()));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            res.push((None, old[i].as_str()));
+            i += 1;
+        } else {
+            res.push((Some(false), new[j].as_str()));
+            j += 1;
+        }
+    }
+    res
+}
+
+fn signed(delta: i128) -> String {
+    if delta > 0 {
+        format!("+{delta}")
+    } else {
+        delta.to_string()
+    }
+}
+
+/// Print functions of `old` and `new` that are added, removed or changed in size, biggest
+/// changes first. With a `filter` only matching functions are listed and the code of the
+/// ones in both binaries is compared
+pub fn report(
+    old: &Path,
+    new: &Path,
+    filter: &str,
+    full_name: bool,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    let mut before = functions(old)?;
+    let mut after = functions(new)?;
+    before.retain(|name, _| name.contains(filter));
+    after.retain(|name, _| name.contains(filter));
+    anyhow::ensure!(
+        !before.is_empty() || !after.is_empty(),
+        Failure::not_found("No functions match this name in either binary")
+    );
+
+    let total = |functions: &BTreeMap<String, Function>| functions.values().map(|f| f.size).sum();
+    let (old_total, new_total): (u64, u64) = (total(&before), total(&after));
+    let heading = format!(
+        "{old_total} bytes in {} functions before, {new_total} bytes in {} after, {} bytes",
+        before.len(),
+        after.len(),
+        signed(i128::from(new_total) - i128::from(old_total))
+    );
+    writeln!(out, "{}", color!(heading, comment))?;
+
+    let mut changes = before
+        .keys()
+        .chain(after.keys())
+        .collect::<std::collections::BTreeSet<_>>()
+        .into_iter()
+        .filter_map(|name| {
+            let old = before.get(name).map(|f| f.size);
+            let new = after.get(name).map(|f| f.size);
+            let delta = i128::from(new.unwrap_or(0)) - i128::from(old.unwrap_or(0));
+            let what = match (old, new) {
+                (None, _) => "added",
+                (_, None) => "removed",
+                _ if delta > 0 => "grown",
+                _ if delta < 0 => "shrunk",
+                _ => return None,
+            };
+            Some((name, delta, what))
+        })
+        .collect::<Vec<_>>();
+    changes.sort_by(|(a_name, a, _), (b_name, b, _)| {
+        b.unsigned_abs()
+            .cmp(&a.unsigned_abs())
+            .then(a_name.cmp(b_name))
+    });
+    for (name, delta, what) in &changes {
+        let delta = format!("{:>10}", signed(*delta));
+        let what = format!("{what:>8}");
+        let stub = before
+            .get(*name)
+            .or_else(|| after.get(*name))
+            .and_then(|f| elf::stub_kind(&f.symbols[0]))
+            .map_or(String::new(), |kind| format!("  // {kind}"));
+        writeln!(
+            out,
+            "{} {}  {}{}",
+            color!(delta, size),
+            color!(what, comment),
+            color!(name, symbol),
+            color!(stub, comment)
+        )?;
+    }
+
+    if filter.is_empty() {
+        return Ok(());
+    }
+    for (name, old_fn) in &before {
+        let Some(new_fn) = after.get(name) else {
+            continue;
+        };
+        let old_code = disassemble(old, &old_fn.symbols[0], full_name)?;
+        let new_code = disassemble(new, &new_fn.symbols[0], full_name)?;
+        if old_code == new_code {
+            continue;
+        }
+        writeln!(out)?;
+        let header = format!("// {name}: {} -> {} bytes", old_fn.size, new_fn.size);
+        writeln!(out, "{}", color!(header, header))?;
+        for (same, line) in diff(&old_code, &new_code) {
+            match same {
+                Some(true) => writeln!(out, "  {line}")?,
+                Some(false) => writeln!(out, "{}", color!(format!("+ {line}"), symbol))?,
+                None => writeln!(out, "{}", color!(format!("- {line}"), source))?,
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::diff;
+
+    #[test]
+    fn line_diff() {
+        let lines = |text: &str| text.split(' ').map(str::to_owned).collect::<Vec<_>>();
+        let (old, new) = (
+            lines("push mov add pop ret"),
+            lines("push mov sub pop ret nop"),
+        );
+        let changes = diff(&old, &new)
+            .into_iter()
+            .map(|(same, line)| match same {
+                Some(true) => format!(" {line}"),
+                Some(false) => format!("+{line}"),
+                None => format!("-{line}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            changes,
+            [" push", " mov", "-add", "+sub", " pop", " ret", "+nop"]
+        );
+    }
+}
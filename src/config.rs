@@ -0,0 +1,248 @@
+#![allow(clippy::missing_errors_doc)]
+//! Defaults for command line options from `~/.config/cargo-show-asm/config.toml` and from
+//! `[package.metadata.cargo-show-asm]` or `[workspace.metadata.cargo-show-asm]` in `Cargo.toml`
+//!
+//! ```toml
+//! syntax = "att"
+//! color = false
+//! target-cpu = "native"
+//! keep-labels = true
+//! pager = false
+//! target = "aarch64-unknown-linux-gnu"
+//! features = ["simd"]
+//!
+//! theme = "light"
+//!
+//! [colors]
+//! mnemonic = "bold blue"
+//! comment = "#808080"
+//!
+//! [preset]
+//! small = ["--release", "--simplify"]
+//! ```
+use crate::opts::{Options, Syntax};
+use anyhow::Context;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+/// Values from the config file, anything given on the command line takes priority
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    /// `intel`, `att`, `llvm` or `mir`, modes that only work with assembly ignore it
+    pub syntax: Option<Syntax>,
+    pub color: Option<bool>,
+    pub target_cpu: Option<String>,
+    /// Print interleaved Rust code
+    pub rust: Option<bool>,
+    pub full_name: Option<bool>,
+    /// Keep all the labels and directives instead of stripping unused ones
+    pub keep_labels: Option<bool>,
+    /// Skip assembler directives
+    pub simplify: Option<bool>,
+    /// `dark`, `light` or `high-contrast`
+    pub theme: Option<String>,
+    /// Colors for separate items on top of the theme, see [`crate::theme::Theme`] for names
+    #[serde(default)]
+    pub colors: BTreeMap<String, String>,
+    /// Set to `false` to never use a pager
+    pub pager: Option<bool>,
+    pub target: Option<String>,
+    /// Used unless features are given on the command line
+    pub features: Option<Vec<String>>,
+    /// Named sets of command line arguments, selected with `--preset NAME`
+    ///
+    /// Only the user config can define them: they are expanded before the command line is
+    /// parsed and the project is not known yet
+    #[serde(default)]
+    pub preset: BTreeMap<String, Vec<String>>,
+}
+
+/// `$XDG_CONFIG_HOME/cargo-show-asm/config.toml`, `~/.config` is used if variable is not set
+fn user_config_path() -> Option<PathBuf> {
+    let dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            #[allow(deprecated)]
+            std::env::home_dir().map(|home| home.join(".config"))
+        })?;
+    Some(dir.join("cargo-show-asm").join("config.toml"))
+}
+
+/// Deserialize `cargo-show-asm` table from package or workspace metadata
+fn from_metadata(metadata: &serde_json::Value, what: &str) -> anyhow::Result<Config> {
+    match metadata.get("cargo-show-asm") {
+        Some(table) => serde_json::from_value(table.clone())
+            .with_context(|| format!("Failed to parse {what}.metadata.cargo-show-asm")),
+        None => Ok(Config::default()),
+    }
+}
+
+impl Config {
+    /// Read project config from `manifest` with package settings taking priority over
+    /// workspace ones
+    pub fn load_project(
+        metadata: &cargo_metadata::Metadata,
+        manifest: &std::path::Path,
+    ) -> anyhow::Result<Self> {
+        let package = match metadata
+            .packages
+            .iter()
+            .find(|package| package.manifest_path == manifest)
+        {
+            Some(package) => from_metadata(&package.metadata, "package")?,
+            None => Self::default(),
+        };
+        Ok(package.merge(from_metadata(&metadata.workspace_metadata, "workspace")?))
+    }
+
+    /// Fill whatever is missing from `lower` priority config
+    #[must_use]
+    pub fn merge(self, lower: Self) -> Self {
+        let mut preset = lower.preset;
+        preset.extend(self.preset);
+        let mut colors = lower.colors;
+        colors.extend(self.colors);
+        Self {
+            preset,
+            colors,
+            theme: self.theme.or(lower.theme),
+            syntax: self.syntax.or(lower.syntax),
+            color: self.color.or(lower.color),
+            target_cpu: self.target_cpu.or(lower.target_cpu),
+            rust: self.rust.or(lower.rust),
+            full_name: self.full_name.or(lower.full_name),
+            keep_labels: self.keep_labels.or(lower.keep_labels),
+            simplify: self.simplify.or(lower.simplify),
+            pager: self.pager.or(lower.pager),
+            target: self.target.or(lower.target),
+            features: self.features.or(lower.features),
+        }
+    }
+
+    /// Read user level config, missing file is the same as an empty one
+    pub fn load_user() -> anyhow::Result<Self> {
+        match user_config_path() {
+            Some(path) if path.is_file() => {
+                let payload = std::fs::read_to_string(&path)?;
+                toml::from_str(&payload)
+                    .with_context(|| format!("Failed to parse config file {}", path.display()))
+            }
+            _ => Ok(Self::default()),
+        }
+    }
+
+    /// Insert arguments from presets selected with `--preset` before the rest of the command
+    /// line, `None` if there are no presets to expand
+    pub fn expand_presets(&self, args: &[OsString]) -> anyhow::Result<Option<Vec<OsString>>> {
+        let mut names = Vec::new();
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if arg == "--" {
+                break;
+            } else if arg == "--preset" {
+                if let Some(name) = iter.next() {
+                    names.push(name.to_string_lossy().into_owned());
+                }
+            } else if let Some(name) = arg.to_str().and_then(|a| a.strip_prefix("--preset=")) {
+                names.push(name.to_owned());
+            }
+        }
+        if names.is_empty() {
+            return Ok(None);
+        }
+
+        // cargo passes subcommand name as the first argument
+        let skip = usize::from(args.first().is_some_and(|arg| arg == "asm"));
+        let mut res = args[..skip].to_vec();
+        for name in &names {
+            let Some(preset) = self.preset.get(name) else {
+                let known = self.preset.keys().cloned().collect::<Vec<_>>();
+                if known.is_empty() {
+                    anyhow::bail!("Unknown preset {name:?}, config file defines none");
+                }
+                anyhow::bail!("Unknown preset {name:?}, try one of: {}", known.join(", "));
+            };
+            res.extend(preset.iter().map(OsString::from));
+        }
+        res.extend_from_slice(&args[skip..]);
+        Ok(Some(res))
+    }
+
+    /// Use config values for everything not set on the command line
+    ///
+    /// Flags can only be turned on from the command line, so those are combined
+    pub fn apply(&self, opts: &mut Options) {
+        if opts.syntax.is_none() && !opts.asm_only() {
+            opts.syntax.clone_from(&self.syntax);
+        }
+        if opts.format.color.is_none() {
+            opts.format.color = self.color;
+        }
+        if opts.format.theme.is_none() {
+            opts.format.theme.clone_from(&self.theme);
+        }
+        if opts.target_cpu.is_none() {
+            opts.target_cpu.clone_from(&self.target_cpu);
+        }
+        if opts.target.is_none() {
+            opts.target.clone_from(&self.target);
+        }
+        if opts.cli_features.features.is_empty() {
+            if let Some(features) = &self.features {
+                opts.cli_features.features.clone_from(features);
+            }
+        }
+        opts.format.rust |= self.rust == Some(true);
+        opts.format.full_name |= self.full_name == Some(true);
+        opts.format.keep_labels |= self.keep_labels == Some(true);
+        opts.format.simplify |= self.simplify == Some(true);
+        opts.no_pager |= self.pager == Some(false);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Config;
+    use crate::opts::Syntax;
+
+    #[test]
+    fn parse_config() {
+        let config: Config = toml::from_str(
+            "syntax = \"att\"\ncolor = false\ntarget-cpu = \"native\"\npager = false\n",
+        )
+        .unwrap();
+        assert!(matches!(config.syntax, Some(Syntax::Att)));
+        assert_eq!(config.color, Some(false));
+        assert_eq!(config.target_cpu.as_deref(), Some("native"));
+        assert_eq!(config.pager, Some(false));
+        assert!(toml::from_str::<Config>("sintax = \"att\"").is_err());
+    }
+
+    #[test]
+    fn presets() {
+        let config: Config =
+            toml::from_str("[preset]\nsmall = [\"--release\", \"--simplify\"]").unwrap();
+        let args = ["asm", "--preset", "small", "foo"].map(std::ffi::OsString::from);
+        let expanded = config.expand_presets(&args).unwrap().unwrap();
+        assert_eq!(
+            expanded,
+            ["asm", "--release", "--simplify", "--preset", "small", "foo"]
+                .map(std::ffi::OsString::from)
+        );
+        assert!(config.expand_presets(&args[2..]).unwrap().is_none());
+        assert!(config.expand_presets(&["--preset=speed".into()]).is_err());
+    }
+
+    #[test]
+    fn merge_priority() {
+        let package: Config = toml::from_str("syntax = \"llvm\"").unwrap();
+        let workspace: Config = toml::from_str("syntax = \"mir\"\ncolor = true").unwrap();
+        let merged = package.merge(workspace);
+        assert!(matches!(merged.syntax, Some(Syntax::Llvm)));
+        assert_eq!(merged.color, Some(true));
+    }
+}
@@ -1,17 +1,18 @@
 use crate::color;
 use once_cell::sync::Lazy;
-use owo_colors::OwoColorize;
 use regex::{Regex, Replacer};
 use rustc_demangle::Demangle;
 use std::borrow::Cow;
 
+mod itanium;
+
 #[must_use]
 pub fn name(input: &str) -> Option<String> {
     Some(format!("{:#?}", demangled(input)?))
 }
 
 #[must_use]
-pub fn demangled(input: &str) -> Option<Demangle> {
+pub fn demangled(input: &str) -> Option<Demangle<'_>> {
     let name = if input.starts_with("__") {
         #[allow(clippy::string_slice)]
         rustc_demangle::try_demangle(&input[1..]).ok()?
@@ -21,13 +22,40 @@ pub fn demangled(input: &str) -> Option<Demangle> {
     Some(name)
 }
 
+/// Name for the symbol `input` without colors, rust ones keep the hash with `full_name` and
+/// names that aren't mangled stay as they are
+#[must_use]
+pub fn plain(input: &str, full_name: bool) -> String {
+    match demangled(input) {
+        Some(dem) if full_name => format!("{dem:?}"),
+        Some(dem) => format!("{dem:#?}"),
+        None => foreign(input).unwrap_or_else(|| input.to_owned()),
+    }
+}
+
+/// C++ name for symbols mangled according to the Itanium ABI, both `_Z` and `__Z` on Mach-O
+#[must_use]
+pub fn foreign(input: &str) -> Option<String> {
+    itanium::demangle(
+        input
+            .strip_prefix('_')
+            .filter(|s| s.starts_with("_Z"))
+            .unwrap_or(input),
+    )
+}
+
 static GLOBAL_LABELS: Lazy<Regex> =
     Lazy::new(|| regex::Regex::new(r"_?(_[a-zA-Z0-9_$\.]+)").expect("regexp should be valid"));
 
-static LOCAL_LABELS: Lazy<Regex> =
-    Lazy::new(|| regex::Regex::new(r"(\.L[a-zA-Z0-9_$\.]+)").expect("regexp should be valid"));
+// `.L` on ELF, `L` and `l` on Mach-O, see `is_local_label`
+static LOCAL_LABELS: Lazy<Regex> = Lazy::new(|| {
+    regex::Regex::new(
+        r"(\.L[a-zA-Z0-9_$\.]+|\b[Ll](?:BB|tmp|func_end|CPI|JTI|loh|exception|anon\.|_)[a-zA-Z0-9_$\.]*)",
+    )
+    .expect("regexp should be valid")
+});
 
-pub fn local_labels(input: &str) -> regex::Matches {
+pub fn local_labels(input: &str) -> regex::Matches<'_, '_> {
     LOCAL_LABELS.find_iter(input)
 }
 
@@ -39,10 +67,13 @@ impl Replacer for Demangler {
         if let Ok(dem) = rustc_demangle::try_demangle(&cap[1]) {
             use std::fmt::Write;
             if self.full_name {
-                write!(dst, "{:?}", color!(dem, OwoColorize::green)).unwrap();
+                write!(dst, "{:?}", color!(dem, symbol)).unwrap();
             } else {
-                write!(dst, "{:#?}", color!(dem, OwoColorize::green)).unwrap();
+                write!(dst, "{:#?}", color!(dem, symbol)).unwrap();
             }
+        } else if let Some(name) = itanium::demangle(&cap[1]) {
+            use std::fmt::Write;
+            write!(dst, "{}", color!(name, symbol)).unwrap();
         } else {
             dst.push_str(&cap[0]);
         }
@@ -54,11 +85,19 @@ pub fn contents(input: &str, full_name: bool) -> Cow<'_, str> {
     GLOBAL_LABELS.replace_all(input, Demangler { full_name })
 }
 
+/// Demangled names of the symbols mentioned in `input`, without the hashes
+pub fn symbols(input: &str) -> impl Iterator<Item = String> + '_ {
+    GLOBAL_LABELS.captures_iter(input).filter_map(|cap| {
+        let dem = rustc_demangle::try_demangle(cap.get(1)?.as_str()).ok()?;
+        Some(format!("{dem:#?}"))
+    })
+}
+
 #[cfg(test)]
 mod test {
     use owo_colors::set_override;
 
-    use super::{contents, name};
+    use super::{contents, foreign, name, symbols};
     const MAC: &str =
         "__ZN58_$LT$nom..error..ErrorKind$u20$as$u20$core..fmt..Debug$GT$3fmt17hb98704099c11c31fE";
     const LINUX: &str =
@@ -96,6 +135,24 @@ mod test {
         );
     }
 
+    #[test]
+    fn symbols_in_call() {
+        let names = symbols(CALL_M).collect::<Vec<_>>();
+        assert_eq!(names, ["<nom::error::ErrorKind as core::fmt::Debug>::fmt"]);
+        assert_eq!(symbols("[rip + .LBB0_1]").count(), 0);
+    }
+
+    #[test]
+    fn cpp_demangle_call() {
+        set_override(true);
+        let x = contents("call\t_ZN2ns3fooEPKcRi@PLT", false);
+        assert_eq!(
+            "call\t\u{1b}[32mns::foo(char const*, int&)\u{1b}[39m@PLT",
+            x
+        );
+        assert_eq!(foreign("__ZN3FooD1Ev").as_deref(), Some("Foo::~Foo()"));
+    }
+
     #[test]
     fn mac_demangle_call2() {
         set_override(true);
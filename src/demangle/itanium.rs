@@ -0,0 +1,650 @@
+//! Itanium C++ ABI demangling for the common subset: nested and templated names, constructors
+//! and destructors, operators, builtin and compound types and substitutions. Anything more
+//! exotic such as expressions in template arguments gives up and the name stays mangled.
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+    /// components that later `S_`, `S0_` and so on refer to
+    subs: Vec<String>,
+    /// arguments of the outermost template, what `T_`, `T0_` and so on refer to
+    template_args: Vec<String>,
+    /// the name parsed last ends with template arguments, such functions have a return type
+    templated: bool,
+}
+
+/// Demangle `_Z...` symbol, `None` if it isn't one or uses parts of the ABI not covered here
+pub fn demangle(input: &str) -> Option<String> {
+    let (symbol, clone) = match input.find('.') {
+        Some(ix) => (&input[..ix], Some(&input[ix..])),
+        None => (input, None),
+    };
+    let mut parser = Parser {
+        input: symbol.strip_prefix("_Z")?.as_bytes(),
+        pos: 0,
+        subs: Vec::new(),
+        template_args: Vec::new(),
+        templated: false,
+    };
+    let mut res = parser.special_name()?;
+    if parser.pos != parser.input.len() {
+        return None;
+    }
+    if let Some(clone) = clone {
+        res.push_str(&format!(" [clone {clone}]"));
+    }
+    Some(res)
+}
+
+/// `operator<< <char>` needs a space to be readable
+fn push_args(name: &mut String, args: &str) {
+    if name.ends_with('<') {
+        name.push(' ');
+    }
+    name.push_str(args);
+}
+
+/// `vector` out of `std::vector<int>`, without ABI tags either
+fn base_name(name: &str) -> &str {
+    let name = name.split(['<', '[']).next().unwrap_or(name);
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, prefix: &str) -> bool {
+        let found = self.input[self.pos..].starts_with(prefix.as_bytes());
+        if found {
+            self.pos += prefix.len();
+        }
+        found
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let c = self.peek()?;
+        self.pos += 1;
+        Some(c)
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        let start = self.pos;
+        while self.peek()?.is_ascii_digit() {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.input[start..self.pos])
+            .ok()?
+            .parse()
+            .ok()
+    }
+
+    /// `_` or a base 36 number followed by `_`, shifted by one so `_` is the first one
+    fn seq_id(&mut self) -> Option<usize> {
+        if self.eat("_") {
+            return Some(0);
+        }
+        let mut id = 0;
+        loop {
+            let c = self.next()?;
+            match c {
+                b'_' => return Some(id + 1),
+                b'0'..=b'9' => id = id * 36 + usize::from(c - b'0'),
+                b'A'..=b'Z' => id = id * 36 + usize::from(c - b'A') + 10,
+                _ => return None,
+            }
+        }
+    }
+
+    fn source_name(&mut self) -> Option<String> {
+        let len = self.number()?;
+        let name = self.input.get(self.pos..self.pos + len)?;
+        self.pos += len;
+        let name = std::str::from_utf8(name).ok()?;
+        Some(if name.starts_with("_GLOBAL__N") {
+            "(anonymous namespace)".to_owned()
+        } else {
+            name.to_owned()
+        })
+    }
+
+    /// vtables, typeinfo and thunks, or a regular encoding
+    fn special_name(&mut self) -> Option<String> {
+        for (code, what) in [
+            ("TV", "vtable for "),
+            ("TT", "VTT for "),
+            ("TI", "typeinfo for "),
+            ("TS", "typeinfo name for "),
+        ] {
+            if self.eat(code) {
+                return Some(format!("{what}{}", self.ty()?));
+            }
+        }
+        if self.eat("GV") {
+            return Some(format!("guard variable for {}", self.name()?.0));
+        }
+        if self.eat("Th") {
+            self.offset()?;
+            return Some(format!("non-virtual thunk to {}", self.encoding()?));
+        }
+        if self.eat("Tv") {
+            self.offset()?;
+            self.offset()?;
+            return Some(format!("virtual thunk to {}", self.encoding()?));
+        }
+        self.encoding()
+    }
+
+    /// possibly negative offset in a thunk followed by `_`
+    fn offset(&mut self) -> Option<()> {
+        self.eat("n");
+        self.number()?;
+        self.eat("_").then_some(())
+    }
+
+    fn encoding(&mut self) -> Option<String> {
+        let (name, cv) = self.name()?;
+        if self.pos == self.input.len() || self.peek() == Some(b'E') {
+            return Some(name);
+        }
+        // templated functions come with their return type first
+        let ret = if self.templated {
+            Some(self.ty()?)
+        } else {
+            None
+        };
+        let params = self.params()?;
+        Some(match ret {
+            Some(ret) => format!("{ret} {name}{params}{cv}"),
+            None => format!("{name}{params}{cv}"),
+        })
+    }
+
+    /// parameters of a function up to the end of the input or `E`
+    fn params(&mut self) -> Option<String> {
+        let mut params = Vec::new();
+        while !matches!(self.peek(), None | Some(b'E' | b'.')) {
+            params.push(self.ty()?);
+        }
+        if params == ["void"] {
+            params.clear();
+        }
+        Some(format!("({})", params.join(", ")))
+    }
+
+    /// name along with `const` and reference qualifiers of a method
+    fn name(&mut self) -> Option<(String, String)> {
+        match self.peek()? {
+            b'N' => self.nested_name(),
+            b'Z' => {
+                self.pos += 1;
+                let outer = self.encoding()?;
+                if !self.eat("E") {
+                    return None;
+                }
+                let inner = if self.eat("s") {
+                    "string literal".to_owned()
+                } else {
+                    self.name()?.0
+                };
+                // discriminator to tell apart several entities with the same name
+                if self.eat("_") {
+                    if self.eat("_") {
+                        self.number()?;
+                        self.eat("_");
+                    } else {
+                        self.number()?;
+                    }
+                }
+                Some((format!("{outer}::{inner}"), String::new()))
+            }
+            _ => {
+                let (mut name, fresh) = if self.eat("St") {
+                    (format!("std::{}", self.unqualified_name()?), true)
+                } else if self.peek() == Some(b'S') {
+                    (self.substitution()?, false)
+                } else {
+                    (self.unqualified_name()?, true)
+                };
+                self.templated = self.peek() == Some(b'I');
+                if self.templated {
+                    if fresh {
+                        self.subs.push(name.clone());
+                    }
+                    let args = self.template_args()?;
+                    self.template_args = args.1;
+                    push_args(&mut name, &args.0);
+                    self.templated = true;
+                }
+                Some((name, String::new()))
+            }
+        }
+    }
+
+    fn nested_name(&mut self) -> Option<(String, String)> {
+        self.pos += 1;
+        let mut cv = String::new();
+        if self.eat("r") {
+            cv.push_str(" restrict");
+        }
+        if self.eat("V") {
+            cv.push_str(" volatile");
+        }
+        if self.eat("K") {
+            cv.push_str(" const");
+        }
+        if self.eat("R") {
+            cv.push_str(" &");
+        } else if self.eat("O") {
+            cv.push_str(" &&");
+        }
+        let mut name = String::new();
+        // last plain component, constructors and destructors are named after it
+        let mut last = String::new();
+        // constructors, destructors and conversions have no return type even as templates
+        let mut untyped = false;
+        loop {
+            let c = self.peek()?;
+            if c != b'E' {
+                // only template arguments at the very end make a template
+                self.templated = c == b'I';
+            }
+            match c {
+                b'E' => {
+                    self.pos += 1;
+                    self.templated &= !untyped;
+                    return Some((name, cv));
+                }
+                b'S' if name.is_empty() => {
+                    if self.eat("St") {
+                        name = "std".to_owned();
+                        continue;
+                    }
+                    // already in the table, don't add it again
+                    name = self.substitution()?;
+                    last = base_name(&name).to_owned();
+                    continue;
+                }
+                b'T' if name.is_empty() => {
+                    name = self.template_param()?;
+                    last = base_name(&name).to_owned();
+                }
+                b'I' => {
+                    let (args, list) = self.template_args()?;
+                    self.template_args = list;
+                    push_args(&mut name, &args);
+                    self.templated = true;
+                    if self.peek() != Some(b'E') {
+                        self.subs.push(name.clone());
+                    }
+                    continue;
+                }
+                b'C' | b'D' if matches!(self.input.get(self.pos + 1), Some(b'0'..=b'5')) => {
+                    let tilde = if self.next()? == b'D' { "~" } else { "" };
+                    self.pos += 1;
+                    name = format!("{name}::{tilde}{last}");
+                    untyped = true;
+                }
+                _ => {
+                    let part = self.unqualified_name()?;
+                    last = base_name(&part).to_owned();
+                    untyped = part.starts_with("operator ")
+                        && !["new", "delete"].iter().any(|op| part[9..].starts_with(op));
+                    name = if name.is_empty() {
+                        part
+                    } else {
+                        format!("{name}::{part}")
+                    };
+                }
+            }
+            if self.peek() != Some(b'E') {
+                self.subs.push(name.clone());
+            }
+        }
+    }
+
+    /// source name, operator or ABI tagged name
+    fn unqualified_name(&mut self) -> Option<String> {
+        let mut name = match self.peek()? {
+            b'0'..=b'9' => self.source_name()?,
+            b'L' => {
+                // internal linkage
+                self.pos += 1;
+                self.source_name()?
+            }
+            b'a'..=b'z' => self.operator()?,
+            _ => return None,
+        };
+        // ABI tags such as `[abi:cxx11]`
+        while self.eat("B") {
+            name.push_str(&format!("[abi:{}]", self.source_name()?));
+        }
+        Some(name)
+    }
+
+    fn operator(&mut self) -> Option<String> {
+        const OPERATORS: &[(&str, &str)] = &[
+            ("nw", "new"),
+            ("na", "new[]"),
+            ("dl", "delete"),
+            ("da", "delete[]"),
+            ("ps", "+"),
+            ("ng", "-"),
+            ("ad", "&"),
+            ("de", "*"),
+            ("co", "~"),
+            ("pl", "+"),
+            ("mi", "-"),
+            ("ml", "*"),
+            ("dv", "/"),
+            ("rm", "%"),
+            ("an", "&"),
+            ("or", "|"),
+            ("eo", "^"),
+            ("aS", "="),
+            ("pL", "+="),
+            ("mI", "-="),
+            ("mL", "*="),
+            ("dV", "/="),
+            ("rM", "%="),
+            ("aN", "&="),
+            ("oR", "|="),
+            ("eO", "^="),
+            ("ls", "<<"),
+            ("rs", ">>"),
+            ("lS", "<<="),
+            ("rS", ">>="),
+            ("eq", "=="),
+            ("ne", "!="),
+            ("lt", "<"),
+            ("gt", ">"),
+            ("le", "<="),
+            ("ge", ">="),
+            ("ss", "<=>"),
+            ("nt", "!"),
+            ("aa", "&&"),
+            ("oo", "||"),
+            ("pp", "++"),
+            ("mm", "--"),
+            ("cm", ","),
+            ("pm", "->*"),
+            ("pt", "->"),
+            ("cl", "()"),
+            ("ix", "[]"),
+            ("qu", "?"),
+        ];
+        if self.eat("cv") {
+            return Some(format!("operator {}", self.ty()?));
+        }
+        if self.eat("li") {
+            return Some(format!("operator\"\" {}", self.source_name()?));
+        }
+        let code = self.input.get(self.pos..self.pos + 2)?;
+        let (_, op) = OPERATORS.iter().find(|(c, _)| c.as_bytes() == code)?;
+        self.pos += 2;
+        let space = if op.starts_with(char::is_alphabetic) {
+            " "
+        } else {
+            ""
+        };
+        Some(format!("operator{space}{op}"))
+    }
+
+    fn substitution(&mut self) -> Option<String> {
+        const SPECIAL: &[(&str, &str)] = &[
+            ("Sa", "std::allocator"),
+            ("Sb", "std::basic_string"),
+            (
+                "Ss",
+                "std::basic_string<char, std::char_traits<char>, std::allocator<char> >",
+            ),
+            ("Si", "std::basic_istream<char, std::char_traits<char> >"),
+            ("So", "std::basic_ostream<char, std::char_traits<char> >"),
+            ("Sd", "std::basic_iostream<char, std::char_traits<char> >"),
+        ];
+        for (code, name) in SPECIAL {
+            if self.eat(code) {
+                return Some((*name).to_owned());
+            }
+        }
+        if !self.eat("S") {
+            return None;
+        }
+        let id = self.seq_id()?;
+        self.subs.get(id).cloned()
+    }
+
+    fn template_param(&mut self) -> Option<String> {
+        if !self.eat("T") {
+            return None;
+        }
+        let id = self.seq_id()?;
+        self.template_args.get(id).cloned()
+    }
+
+    /// `<...>` text and the list of arguments for template parameter references
+    fn template_args(&mut self) -> Option<(String, Vec<String>)> {
+        if !self.eat("I") {
+            return None;
+        }
+        let mut args = Vec::new();
+        while !self.eat("E") {
+            args.push(self.template_arg()?);
+        }
+        // empty parameter packs leave no trace
+        let text = args
+            .iter()
+            .filter(|arg| !arg.is_empty())
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(", ");
+        let close = if text.ends_with('>') { " >" } else { ">" };
+        Some((format!("<{text}{close}"), args))
+    }
+
+    fn template_arg(&mut self) -> Option<String> {
+        match self.peek()? {
+            b'L' => {
+                self.pos += 1;
+                if self.peek() == Some(b'_') {
+                    return None;
+                }
+                let ty = self.ty()?;
+                let negative = self.eat("n");
+                let value = self.number()?;
+                if !self.eat("E") {
+                    return None;
+                }
+                let sign = if negative { "-" } else { "" };
+                Some(match ty.as_str() {
+                    "bool" => (if value == 0 { "false" } else { "true" }).to_owned(),
+                    "int" => format!("{sign}{value}"),
+                    "unsigned int" => format!("{value}u"),
+                    "long" => format!("{sign}{value}l"),
+                    "unsigned long" => format!("{value}ul"),
+                    _ => format!("({ty}){sign}{value}"),
+                })
+            }
+            b'J' => {
+                self.pos += 1;
+                let mut args = Vec::new();
+                while !self.eat("E") {
+                    args.push(self.template_arg()?);
+                }
+                Some(args.join(", "))
+            }
+            _ => self.ty(),
+        }
+    }
+
+    /// return type and parameters of `F...E`
+    fn function_type(&mut self) -> Option<(String, String)> {
+        if !self.eat("F") {
+            return None;
+        }
+        self.eat("Y");
+        let ret = self.ty()?;
+        let params = self.params()?;
+        if !self.eat("E") {
+            return None;
+        }
+        Some((ret, params))
+    }
+
+    fn ty(&mut self) -> Option<String> {
+        const BUILTIN: &[(u8, &str)] = &[
+            (b'v', "void"),
+            (b'w', "wchar_t"),
+            (b'b', "bool"),
+            (b'c', "char"),
+            (b'a', "signed char"),
+            (b'h', "unsigned char"),
+            (b's', "short"),
+            (b't', "unsigned short"),
+            (b'i', "int"),
+            (b'j', "unsigned int"),
+            (b'l', "long"),
+            (b'm', "unsigned long"),
+            (b'x', "long long"),
+            (b'y', "unsigned long long"),
+            (b'n', "__int128"),
+            (b'o', "unsigned __int128"),
+            (b'f', "float"),
+            (b'd', "double"),
+            (b'e', "long double"),
+            (b'g', "__float128"),
+            (b'z', "..."),
+        ];
+        let c = self.peek()?;
+        if let Some((_, name)) = BUILTIN.iter().find(|(code, _)| *code == c) {
+            self.pos += 1;
+            return Some((*name).to_owned());
+        }
+        let ty = match c {
+            b'D' => {
+                let name = match self.input.get(self.pos + 1)? {
+                    b'n' => "decltype(nullptr)",
+                    b's' => "char16_t",
+                    b'i' => "char32_t",
+                    b'u' => "char8_t",
+                    b'h' => "half",
+                    _ => return None,
+                };
+                self.pos += 2;
+                return Some(name.to_owned());
+            }
+            b'P' | b'R' | b'O' if self.input.get(self.pos + 1) == Some(&b'F') => {
+                self.pos += 1;
+                let (ret, params) = self.function_type()?;
+                self.subs.push(format!("{ret} {params}"));
+                let ptr = match c {
+                    b'P' => "*",
+                    b'R' => "&",
+                    _ => "&&",
+                };
+                format!("{ret} ({ptr}){params}")
+            }
+            b'P' | b'R' | b'O' | b'K' | b'V' => {
+                self.pos += 1;
+                let inner = self.ty()?;
+                match c {
+                    b'P' => format!("{inner}*"),
+                    // references to references from template parameters collapse
+                    b'R' if inner.ends_with("&&") => inner[..inner.len() - 1].to_owned(),
+                    b'R' | b'O' if inner.ends_with('&') => inner,
+                    b'R' => format!("{inner}&"),
+                    b'O' => format!("{inner}&&"),
+                    b'K' => format!("{inner} const"),
+                    _ => format!("{inner} volatile"),
+                }
+            }
+            b'F' => {
+                let (ret, params) = self.function_type()?;
+                format!("{ret} {params}")
+            }
+            b'A' => {
+                self.pos += 1;
+                let len = self.number()?;
+                if !self.eat("_") {
+                    return None;
+                }
+                format!("{} [{len}]", self.ty()?)
+            }
+            b'T' => {
+                let param = self.template_param()?;
+                if self.peek() == Some(b'I') {
+                    self.subs.push(param.clone());
+                    format!("{param}{}", self.template_args()?.0)
+                } else {
+                    param
+                }
+            }
+            b'S' if !self.input[self.pos..].starts_with(b"St") => {
+                // already in the table or one of the abbreviations, don't add it again
+                let sub = self.substitution()?;
+                if self.peek() != Some(b'I') {
+                    return Some(sub);
+                }
+                format!("{sub}{}", self.template_args()?.0)
+            }
+            b'N' | b'S' | b'0'..=b'9' => {
+                let saved = self.template_args.clone();
+                let (name, _) = self.name()?;
+                self.template_args = saved;
+                name
+            }
+            _ => return None,
+        };
+        self.subs.push(ty.clone());
+        Some(ty)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::demangle;
+
+    #[test]
+    fn common_names() {
+        for (mangled, name) in [
+            ("_Z3foov", "foo()"),
+            ("_Z3fooi", "foo(int)"),
+            ("_ZN2ns3fooEPKcRi", "ns::foo(char const*, int&)"),
+            ("_ZNK3Foo3getEv", "Foo::get() const"),
+            ("_ZN3FooC2Ev", "Foo::Foo()"),
+            ("_ZN3FooD1Ev", "Foo::~Foo()"),
+            ("_ZN3FooplERKS_", "Foo::operator+(Foo const&)"),
+            (
+                "_ZNSt6vectorIiSaIiEE9push_backERKi",
+                "std::vector<int, std::allocator<int> >::push_back(int const&)",
+            ),
+            ("_Z3maxIiET_S0_S0_", "int max<int>(int, int)"),
+            ("_ZN12_GLOBAL__N_14initEv", "(anonymous namespace)::init()"),
+            ("_Z4copyPvS_m", "copy(void*, void*, unsigned long)"),
+            (
+                "_Z5helloRKSs",
+                "hello(std::basic_string<char, std::char_traits<char>, std::allocator<char> > const&)",
+            ),
+            ("_Z3foov.cold", "foo() [clone .cold]"),
+            (
+                "_ZN2ns1AIiE3getERKS1_",
+                "ns::A<int>::get(ns::A<int> const&)",
+            ),
+            ("_ZN5Array3getILi4EEEiv", "int Array::get<4>()"),
+            ("_ZTV3Foo", "vtable for Foo"),
+            ("_ZThn8_N3Foo3getEv", "non-virtual thunk to Foo::get()"),
+        ] {
+            assert_eq!(demangle(mangled).as_deref(), Some(name), "{mangled}");
+        }
+    }
+
+    #[test]
+    fn not_cpp() {
+        assert_eq!(demangle("main"), None);
+        assert_eq!(demangle("_Z"), None);
+        assert_eq!(demangle("_Z3foo"), Some("foo".to_owned()));
+        // expressions in template arguments are not supported
+        assert_eq!(demangle("_Z1fIiEvDTcl1gfp_EE"), None);
+    }
+}
@@ -0,0 +1,65 @@
+#![allow(clippy::missing_errors_doc)]
+//! Doctests are compiled by rustdoc rather than cargo. To get the generated code rustdoc is told
+//! to keep compiled doctests around and to run rustc through the wrapper.
+use crate::exit::Failure;
+use anyhow::Context;
+use std::path::{Path, PathBuf};
+
+/// Directory rustdoc keeps doctests from `package` in
+#[must_use]
+pub fn persist_dir(cache_dir: &Path, package: &str) -> PathBuf {
+    cache_dir.join("doctests").join(package)
+}
+
+/// Find file with `ext` extension generated for doctest `name`
+///
+/// Each doctest gets a directory named after the file and the line it is defined on,
+/// i.e. `src_lib_rs_42_0`. Doctests from the 2024 edition are merged into a single crate.
+pub fn locate(dir: &Path, name: &str, ext: &str) -> anyhow::Result<PathBuf> {
+    let mut doctests = Vec::new();
+    for entry in std::fs::read_dir(dir).context("No doctests were compiled")? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            doctests.push(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+    doctests.sort();
+
+    let selected = match doctests.iter().find(|doctest| *doctest == name) {
+        Some(exact) => vec![exact],
+        None => doctests
+            .iter()
+            .filter(|doctest| doctest.contains(name))
+            .collect(),
+    };
+    if let [doctest] = selected[..] {
+        let dir = dir.join(doctest);
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == ext) {
+                files.push(path);
+            }
+        }
+        // merged doctests are compiled as a library with all the examples and a small runner
+        files.sort_by_key(|path| path.file_stem().is_some_and(|stem| stem == "rust_out"));
+        return files
+            .into_iter()
+            .next()
+            .with_context(|| format!("No .{ext} file was generated for doctest {doctest}"));
+    }
+
+    if doctests.is_empty() {
+        anyhow::bail!(Failure::not_found("No doctests were compiled"));
+    }
+    eprintln!("Compiled doctests:");
+    for doctest in &doctests {
+        eprintln!("\t--doctest {doctest}");
+    }
+    if selected.is_empty() {
+        anyhow::bail!(Failure::not_found(format!("No doctest matching {name:?}")))
+    }
+    anyhow::bail!(Failure::ambiguous(format!(
+        "Multiple doctests match {name:?}"
+    )))
+}
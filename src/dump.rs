@@ -0,0 +1,535 @@
+#![allow(clippy::missing_errors_doc)]
+//! Printing items from the generated files: one function, the whole file, the same function
+//! from several targets or every function to a file of its own, and the list to pick from when
+//! the search doesn't narrow it down to one
+use crate::artifacts::sanitize;
+use crate::asm::{self, Item};
+use crate::exit::Failure;
+use crate::opts::{self, ToDump};
+use crate::pool::{in_order, Task};
+use crate::{color, elf, explain, llvm, mir};
+use anyhow::Context;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Generated file for one of the targets along with everything it defines
+pub struct Generated<'a> {
+    pub package: &'a cargo_metadata::Package,
+    pub focus: opts::Focus,
+    pub asm_path: PathBuf,
+    pub index: Vec<Item>,
+}
+
+/// Print `goal` from `asm_path`, or everything if there's no goal, collect all the items if
+/// `goal` is not found
+pub fn dump_file(
+    opts: &opts::Options,
+    sysroot: &Path,
+    goal: Option<(&str, usize)>,
+    asm_path: &Path,
+    out: &mut dyn Write,
+    items: &mut Vec<Item>,
+) -> anyhow::Result<bool> {
+    match opts.syntax() {
+        opts::Syntax::Intel | opts::Syntax::Att => {
+            let comment = opts.syntax().comment();
+            asm::dump_function(goal, asm_path, sysroot, comment, &opts.format, out, items)
+        }
+        opts::Syntax::Llvm => llvm::dump_function(goal, asm_path, &opts.format, out, items),
+        opts::Syntax::Mir => mir::dump_function(goal, asm_path, &opts.format, out, items),
+    }
+}
+
+/// Passes through the first `left` lines and counts the rest
+struct LineLimit<'a> {
+    inner: &'a mut dyn Write,
+    left: usize,
+    skipped: usize,
+}
+
+impl Write for LineLimit<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut keep = 0;
+        for b in buf {
+            if self.left == 0 {
+                self.skipped += usize::from(*b == b'\n');
+            } else {
+                keep += 1;
+                self.left -= usize::from(*b == b'\n');
+            }
+        }
+        self.inner.write_all(&buf[..keep])?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Same as [`dump_file`], but stops after `--max-lines` lines and says how many are left out
+pub fn dump_limited(
+    opts: &opts::Options,
+    sysroot: &Path,
+    goal: Option<(&str, usize)>,
+    asm_path: &Path,
+    out: &mut dyn Write,
+    items: &mut Vec<Item>,
+) -> anyhow::Result<bool> {
+    let Some(max) = opts.max_lines.filter(|max| *max > 0) else {
+        return dump_file(opts, sysroot, goal, asm_path, out, items);
+    };
+    limit_lines(max, opts.syntax().comment(), out, |out| {
+        dump_file(opts, sysroot, goal, asm_path, out, items)
+    })
+}
+
+/// Let the first `max` lines `dump` writes through to `out` and end with a footer saying how
+/// many are left out, in a comment of the syntax
+fn limit_lines(
+    max: usize,
+    comment: &str,
+    out: &mut dyn Write,
+    dump: impl FnOnce(&mut dyn Write) -> anyhow::Result<bool>,
+) -> anyhow::Result<bool> {
+    let mut limited = LineLimit {
+        inner: out,
+        left: max,
+        skipped: 0,
+    };
+    let seen = dump(&mut limited)?;
+    let skipped = limited.skipped;
+    if skipped > 0 {
+        let footer = format!("{comment} … {skipped} more lines, use --max-lines 0 to see all");
+        writeln!(out, "{}", color!(footer, comment))?;
+    }
+    Ok(seen)
+}
+
+/// Collect all the items defined in `asm_path` without printing any of them
+pub fn list_items(
+    opts: &opts::Options,
+    sysroot: &Path,
+    asm_path: &Path,
+) -> anyhow::Result<Vec<Item>> {
+    let mut items = Vec::new();
+    // there are no items without a name
+    dump_file(
+        opts,
+        sysroot,
+        Some(("", usize::MAX)),
+        asm_path,
+        &mut std::io::sink(),
+        &mut items,
+    )?;
+    Ok(items)
+}
+
+/// Check if `filter` from the command line selects `item`, MIR names items by trimmed paths so
+/// it also takes the full path there
+pub fn selects(opts: &opts::Options, item: &Item, filter: &str) -> bool {
+    match opts.syntax() {
+        opts::Syntax::Mir => mir::matches(&item.name, filter),
+        _ => item.name.contains(filter),
+    }
+}
+
+/// Write every selected function to a file of its own, one subdirectory per target if there
+/// are several, `index.txt` lists which file contains which function
+pub fn dump_to_dir(
+    opts: &opts::Options,
+    sysroot: &Path,
+    dir: &Path,
+    generated: &[Generated],
+) -> anyhow::Result<()> {
+    let filter = match &opts.to_dump {
+        ToDump::Everything => "",
+        ToDump::Function { function, .. } => function.as_deref().unwrap_or(""),
+    };
+    let selected = |item: &Item| selects(opts, item, filter);
+    if !generated.iter().flat_map(|gen| &gen.index).any(selected) {
+        anyhow::bail!(Failure::not_found(
+            "No matching functions, try relaxing your search request"
+        ))
+    }
+
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create output directory {}", dir.display()))?;
+    let mut index = Vec::new();
+    for gen in generated {
+        let subdir = if generated.len() > 1 {
+            let (kind, name) = gen.focus.as_parts();
+            let mut subdir = format!("{}-{kind}", gen.package.name);
+            if let Some(name) = name {
+                subdir.push('-');
+                subdir.push_str(name);
+            }
+            PathBuf::from(sanitize(&subdir))
+        } else {
+            PathBuf::new()
+        };
+
+        let mut taken = BTreeSet::new();
+        let mut files = BTreeMap::new();
+        for item in gen.index.iter().filter(|item| selected(item)) {
+            let base = sanitize(if opts.format.full_name {
+                &item.hashed
+            } else {
+                &item.name
+            });
+            let mut file = format!("{base}.{}", opts.syntax().ext());
+            let mut n = 1;
+            while !taken.insert(file.clone()) {
+                file = format!("{base}_{n}.{}", opts.syntax().ext());
+                n += 1;
+            }
+            files.insert(item.hashed.clone(), subdir.join(file));
+        }
+        if files.is_empty() {
+            continue;
+        }
+        std::fs::create_dir_all(dir.join(&subdir))?;
+
+        let mut open = |item: &Item| -> anyhow::Result<Box<dyn Write>> {
+            let path = &files[&item.hashed];
+            let name = if opts.format.full_name {
+                &item.hashed
+            } else {
+                &item.name
+            };
+            index.push(format!("{}\t{name}\t{}", path.display(), item.len));
+            let path = dir.join(path);
+            let file = File::create(&path)
+                .with_context(|| format!("Failed to create {}", path.display()))?;
+            Ok(Box::new(std::io::BufWriter::new(file)))
+        };
+        match opts.syntax() {
+            opts::Syntax::Intel | opts::Syntax::Att => asm::dump_each(
+                &gen.asm_path,
+                sysroot,
+                &opts.format,
+                &|item| files.contains_key(&item.hashed),
+                &mut open,
+            )?,
+            // these are read line by line and stop once the function is printed
+            opts::Syntax::Llvm | opts::Syntax::Mir => {
+                for item in gen
+                    .index
+                    .iter()
+                    .filter(|item| files.contains_key(&item.hashed))
+                {
+                    let mut out = open(item)?;
+                    let goal = Some((item.name.as_str(), item.index));
+                    dump_file(
+                        opts,
+                        sysroot,
+                        goal,
+                        &gen.asm_path,
+                        &mut out,
+                        &mut Vec::new(),
+                    )?;
+                    out.flush()?;
+                }
+            }
+        }
+    }
+
+    let index_path = dir.join("index.txt");
+    std::fs::write(&index_path, index.join("\n") + "\n")
+        .with_context(|| format!("Failed to write {}", index_path.display()))?;
+    if !opts.porcelain {
+        eprintln!(
+            "Wrote {} functions to {}, see {} for the list",
+            index.len(),
+            dir.display(),
+            index_path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Search for the function in several generated files, each one gets a header with the
+/// package and target it came from
+pub fn dump_many(
+    opts: &opts::Options,
+    sysroot: &Path,
+    root: &Path,
+    generated: &[Generated],
+    sized: bool,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    let header = |gen: &Generated, out: &mut dyn Write| {
+        let title = format!(
+            "{} {} {}",
+            opts.syntax().comment(),
+            gen.package.name,
+            gen.focus.as_cargo_args().collect::<Vec<_>>().join(" ")
+        );
+        writeln!(out, "{}", color!(title, header))
+    };
+
+    // targets are dumped in parallel, each one is printed with its header as it comes once the
+    // ones before it are done
+    let dump = |goal: Option<(&str, usize)>, out: &mut dyn Write| -> anyhow::Result<bool> {
+        let tasks = generated
+            .iter()
+            .filter(|gen| goal.is_none_or(|goal| gen.index.iter().any(|item| item.matches(goal))))
+            .map(|gen| {
+                Box::new(move |out: &mut dyn Write| {
+                    header(gen, out)?;
+                    dump_limited(opts, sysroot, goal, &gen.asm_path, out, &mut Vec::new())
+                }) as Task<'_, anyhow::Result<bool>>
+            })
+            .collect();
+        let mut seen = false;
+        in_order(tasks, out, |found| {
+            seen |= found?;
+            Ok(())
+        })?;
+        Ok(seen)
+    };
+
+    let (filter, nth) = match &opts.to_dump {
+        ToDump::Everything => {
+            dump(None, out)?;
+            out.flush()?;
+            return Ok(());
+        }
+        ToDump::Function { function, nth } => (function.as_deref().unwrap_or(""), *nth),
+    };
+
+    let mut goal = (filter, nth);
+    let mut candidates = generated
+        .iter()
+        .flat_map(|gen| &gen.index)
+        .filter(|item| selects(opts, item, filter));
+    let single_target = match (candidates.next(), candidates.next()) {
+        (Some(item), None) => Some(item.name.clone()),
+        _ => None,
+    };
+    if !generated
+        .iter()
+        .any(|gen| gen.index.iter().any(|item| item.matches(goal)))
+    {
+        if let Some(name) = &single_target {
+            goal = (name, 0);
+        }
+    }
+
+    let copies = generated
+        .iter()
+        .filter_map(|gen| gen.index.iter().find(|item| item.matches(goal)))
+        .collect::<Vec<_>>();
+    if let ([first, _, ..], false) = (copies.as_slice(), opts.porcelain) {
+        let kind = if copies.iter().all(|item| item.linkage.comdat) {
+            Some("in a COMDAT group")
+        } else if copies
+            .iter()
+            .all(|item| item.linkage.binding == asm::Binding::Weak)
+        {
+            Some("a weak symbol")
+        } else {
+            None
+        };
+        if let Some(kind) = kind {
+            eprintln!(
+                "Note: {} is {kind} in each of the {} targets, only one copy is kept when they are linked together",
+                first.name,
+                copies.len()
+            );
+        }
+    }
+
+    if dump(Some(goal), out)? {
+        out.flush()?;
+        return Ok(());
+    }
+
+    let mut found = false;
+    for gen in generated {
+        let items = gen
+            .index
+            .iter()
+            .filter(|item| selects(opts, item, filter))
+            .cloned()
+            .collect::<Vec<_>>();
+        if !items.is_empty() {
+            let mut out = suggestions(filter);
+            if !(found || opts.porcelain && filter.is_empty()) {
+                eprintln!("Try one of those");
+            }
+            found = true;
+            header(gen, &mut out)?;
+            let sizes = match opts.syntax() {
+                opts::Syntax::Intel | opts::Syntax::Att if sized => elf::sizes_near(&gen.asm_path),
+                _ => BTreeMap::new(),
+            };
+            print_names(&mut out, opts, &items, &sizes)?;
+        }
+    }
+    if !found {
+        if filter.is_empty() {
+            anyhow::bail!(Failure::not_found("Selected targets define no functions"))
+        }
+        if let (opts::Syntax::Intel | opts::Syntax::Att | opts::Syntax::Llvm, false) =
+            (opts.syntax(), opts.porcelain)
+        {
+            if let Some(why) = generated
+                .iter()
+                .find_map(|gen| explain::missing(&gen.asm_path, root, filter))
+            {
+                eprintln!("{why}");
+            }
+        }
+        anyhow::bail!(Failure::not_found(
+            "No matching functions, try relaxing your search request"
+        ))
+    }
+    if opts.porcelain && filter.is_empty() {
+        return Ok(());
+    }
+    anyhow::bail!(Failure::ambiguous(
+        "Several functions match, pick one of those"
+    ))
+}
+
+/// Where to print the list of available functions: it is the result when no function is
+/// requested, otherwise stdout is reserved for the function itself
+pub fn suggestions(search: &str) -> Box<dyn Write> {
+    if !search.is_empty() {
+        Box::new(std::io::stderr())
+    } else {
+        Box::new(std::io::stdout())
+    }
+}
+
+pub fn suggest_name(
+    out: &mut dyn Write,
+    search: &str,
+    opts: &opts::Options,
+    items: &[Item],
+    sizes: &BTreeMap<String, u64>,
+) -> anyhow::Result<()> {
+    if items.is_empty() {
+        #[allow(clippy::redundant_else)]
+        if search.is_empty() {
+            anyhow::bail!(Failure::not_found("This target defines no functions"))
+        } else {
+            anyhow::bail!(Failure::not_found(
+                "No matching functions, try relaxing your search request"
+            ))
+        }
+    }
+    if !(opts.porcelain && search.is_empty()) {
+        eprintln!("Try one of those");
+    }
+    print_names(out, opts, items, sizes)?;
+    out.flush()?;
+
+    // the list is what was asked for in porcelain mode rather than a choice to make
+    if opts.porcelain && search.is_empty() {
+        return Ok(());
+    }
+    anyhow::bail!(Failure::ambiguous(
+        "Several functions match, pick one of those"
+    ))
+}
+
+/// `sizes` in bytes are listed after the line counts when known, followed by linkage other than
+/// plain global and the section if it's not the usual one. With `--porcelain` each function
+/// goes on a line of its own as tab separated name, index, lines, bytes and comma separated tags
+fn print_names(
+    out: &mut dyn Write,
+    opts: &opts::Options,
+    items: &[Item],
+    sizes: &BTreeMap<String, u64>,
+) -> std::io::Result<()> {
+    let full = opts.format.full_name;
+    if opts.porcelain {
+        let mut items = items.iter().collect::<Vec<_>>();
+        items.sort_by(|a, b| (&a.name, a.index).cmp(&(&b.name, b.index)));
+        for item in items {
+            let name = if full { &item.hashed } else { &item.name };
+            let bytes = sizes
+                .get(&item.hashed)
+                .map_or(String::new(), u64::to_string);
+            let mut tags = item.linkage.tags();
+            tags.extend(item.linkage.section());
+            let tags = tags.join(",");
+            writeln!(out, "{name}\t{}\t{}\t{bytes}\t{tags}", item.index, item.len)?;
+        }
+        return Ok(());
+    }
+    let names = items.iter().fold(BTreeMap::new(), |mut m, item| {
+        let (lens, bytes, tags) = m
+            .entry(if full { &item.hashed } else { &item.name })
+            .or_insert_with(|| (Vec::new(), Vec::<u64>::new(), BTreeSet::new()));
+        lens.push(item.len);
+        bytes.extend(sizes.get(&item.hashed));
+        tags.extend(item.linkage.tags());
+        tags.extend(item.linkage.section());
+        m
+    });
+
+    for (name, (lens, bytes, tags)) in &names {
+        write!(out, "{:?} {:?}", color!(name, symbol), color!(lens, size))?;
+        if !bytes.is_empty() {
+            write!(out, " {:?} bytes", color!(bytes, size))?;
+        }
+        for tag in tags {
+            write!(out, " {}", color!(tag, comment))?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// What function sizes in bytes stand for on targets where they matter more than anything
+/// else: flash on bare metal and download size on wasm. AVR targets are bare metal too, but
+/// named after the microcontroller: `avr-unknown-gnu-atmega328`
+pub fn byte_sizes(target: Option<&str>) -> Option<&'static str> {
+    let target = target?;
+    if target.starts_with("wasm") {
+        Some("bytes of wasm code")
+    } else if target.contains("-none") || target.starts_with("avr") {
+        Some("bytes of flash")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::limit_lines;
+
+    fn dump(max: usize, text: &str) -> String {
+        owo_colors::set_override(false);
+        let mut out = Vec::new();
+        let seen = limit_lines(max, "#", &mut out, |out| {
+            // in pieces that don't line up with the lines
+            for piece in text.as_bytes().chunks(3) {
+                out.write_all(piece)?;
+            }
+            Ok(true)
+        });
+        assert!(seen.unwrap());
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn max_lines() {
+        let text = "first\nsecond\nthird\nfourth\nfifth\n";
+        assert_eq!(
+            dump(2, text),
+            "first\nsecond\n# … 3 more lines, use --max-lines 0 to see all\n"
+        );
+        assert_eq!(
+            dump(3, text),
+            "first\nsecond\nthird\n# … 2 more lines, use --max-lines 0 to see all\n"
+        );
+        assert_eq!(dump(5, text), text);
+        assert_eq!(dump(9, text), text);
+    }
+}
@@ -0,0 +1,95 @@
+#![allow(clippy::missing_errors_doc)]
+//! Output is collected in memory and opened in an editor once flushed, editors are better
+//! than pagers at searching and folding long functions.
+use std::collections::hash_map::RandomState;
+use std::fs::File;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Write collected output to a temporary file and open it in `$VISUAL` or `$EDITOR`
+pub struct Editor {
+    buf: Vec<u8>,
+    ext: String,
+}
+
+impl Editor {
+    /// `ext` is used for the temporary file so the editor can pick the syntax highlighting
+    #[must_use]
+    pub fn new(ext: &str) -> Self {
+        Self {
+            buf: Vec::new(),
+            ext: ext.to_owned(),
+        }
+    }
+
+    fn command(path: &PathBuf) -> Command {
+        let editor = std::env::var("VISUAL")
+            .or_else(|_| std::env::var("EDITOR"))
+            .ok()
+            .filter(|editor| !editor.trim().is_empty());
+        if cfg!(windows) {
+            let editor = editor.unwrap_or_else(|| "notepad".into());
+            let mut words = editor.split_whitespace();
+            let mut cmd = Command::new(words.next().unwrap_or("notepad"));
+            cmd.args(words).arg(path);
+            cmd
+        } else {
+            let editor = editor.unwrap_or_else(|| "vi".into());
+            // editor can come with arguments, same as with git
+            let mut cmd = Command::new("sh");
+            cmd.arg("-c")
+                .arg(format!("{editor} \"$@\""))
+                .arg(editor)
+                .arg(path);
+            cmd
+        }
+    }
+}
+
+/// Create a file with a name nobody can guess in the temp directory, failing rather than
+/// following a link someone else put there
+fn temp_file(ext: &str) -> std::io::Result<(PathBuf, File)> {
+    let mut options = File::options();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    loop {
+        // keys of `RandomState` are random for each instance
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u32(std::process::id());
+        let name = format!("cargo-asm-{:016x}.{ext}", hasher.finish());
+        let path = std::env::temp_dir().join(name);
+        match options.open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {}
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+impl Write for Editor {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buf.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let (path, mut file) = temp_file(&self.ext)?;
+        file.write_all(&std::mem::take(&mut self.buf))?;
+        drop(file);
+        // editors that detach from the terminal need to be told to wait, `code --wait` and so on
+        let status = Self::command(&path).status();
+        let _ = std::fs::remove_file(&path);
+        let status = status?;
+        if !status.success() {
+            return Err(std::io::Error::other(format!(
+                "Editor exited with {status}"
+            )));
+        }
+        Ok(())
+    }
+}
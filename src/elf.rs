@@ -0,0 +1,397 @@
+#![allow(clippy::missing_errors_doc)]
+//! Function sizes in bytes from the symbol tables of ELF objects, either on their own or inside
+//! of `ar` archives such as rlibs. Assembly alone doesn't say how large the encoded
+//! instructions are, the object files rustc produces next to it do.
+use crate::{demangle, wasm};
+use anyhow::Context;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const STT_FUNC: u8 = 2;
+const SHN_UNDEF: u16 = 0;
+
+/// Sections of PLT stubs, those have no symbols of their own
+const PLT_SECTIONS: [&str; 4] = [".plt", ".plt.sec", ".plt.got", ".iplt"];
+
+struct Reader<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl Reader<'_> {
+    fn bytes<const N: usize>(&self, offset: usize) -> anyhow::Result<[u8; N]> {
+        let bytes = offset
+            .checked_add(N)
+            .and_then(|end| self.data.get(offset..end))
+            .context("Truncated ELF file")?;
+        Ok(bytes.try_into()?)
+    }
+
+    fn u8(&self, offset: usize) -> anyhow::Result<u8> {
+        Ok(self.bytes::<1>(offset)?[0])
+    }
+
+    fn u16(&self, offset: usize) -> anyhow::Result<u16> {
+        let bytes = self.bytes(offset)?;
+        Ok(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, offset: usize) -> anyhow::Result<u32> {
+        let bytes = self.bytes(offset)?;
+        Ok(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    fn u64(&self, offset: usize) -> anyhow::Result<u64> {
+        let bytes = self.bytes(offset)?;
+        Ok(if self.big_endian {
+            u64::from_be_bytes(bytes)
+        } else {
+            u64::from_le_bytes(bytes)
+        })
+    }
+
+    /// Address sized value, `wide` for 64 bit files
+    fn addr(&self, offset: usize, wide: bool) -> anyhow::Result<usize> {
+        let value = if wide {
+            self.u64(offset)?
+        } else {
+            u64::from(self.u32(offset)?)
+        };
+        Ok(usize::try_from(value)?)
+    }
+
+    /// Zero terminated string at `offset`
+    fn str(&self, offset: usize) -> anyhow::Result<&str> {
+        let tail = self.data.get(offset..).context("Truncated ELF file")?;
+        let len = tail.iter().position(|b| *b == 0).unwrap_or(tail.len());
+        Ok(std::str::from_utf8(&tail[..len])?)
+    }
+}
+
+/// A function in the symbol table
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Symbol {
+    pub addr: u64,
+    pub size: u64,
+    /// index of the section with the code
+    pub section: u16,
+    pub name: String,
+}
+
+/// Each function defined in an ELF object, sections of PLT stubs are reported as functions
+/// named after the section
+fn parse_elf(data: &[u8], mut found: impl FnMut(Symbol)) -> anyhow::Result<()> {
+    let wide = match data.get(4) {
+        Some(1) => false,
+        Some(2) => true,
+        _ => anyhow::bail!("Unknown ELF class"),
+    };
+    let elf = Reader {
+        data,
+        big_endian: data.get(5) == Some(&2),
+    };
+    let (shoff, shentsize, shnum, shstrndx) = if wide {
+        let shoff = elf.addr(0x28, true)?;
+        (shoff, elf.u16(0x3a)?, elf.u16(0x3c)?, elf.u16(0x3e)?)
+    } else {
+        let shoff = elf.addr(0x20, false)?;
+        (shoff, elf.u16(0x2e)?, elf.u16(0x30)?, elf.u16(0x32)?)
+    };
+    let section = |ix: usize| shoff + ix * usize::from(shentsize);
+    // offset, size and linked section of a section header
+    let header = |ix: usize| -> anyhow::Result<(usize, usize, usize)> {
+        let sh = section(ix);
+        Ok(if wide {
+            (
+                elf.addr(sh + 24, true)?,
+                elf.addr(sh + 32, true)?,
+                usize::try_from(elf.u32(sh + 40)?)?,
+            )
+        } else {
+            (
+                elf.addr(sh + 16, false)?,
+                elf.addr(sh + 20, false)?,
+                usize::try_from(elf.u32(sh + 24)?)?,
+            )
+        })
+    };
+    for ix in 0..usize::from(shnum) {
+        let kind = elf.u32(section(ix) + 4)?;
+        if kind == SHT_PROGBITS && shstrndx != SHN_UNDEF && shstrndx < shnum {
+            let (names, _, _) = header(usize::from(shstrndx))?;
+            let name = elf.str(names + usize::try_from(elf.u32(section(ix))?)?)?;
+            let (_, size, _) = header(ix)?;
+            if PLT_SECTIONS.contains(&name) && size > 0 {
+                let addr = if wide {
+                    elf.u64(section(ix) + 16)?
+                } else {
+                    u64::from(elf.u32(section(ix) + 12)?)
+                };
+                found(Symbol {
+                    addr,
+                    size: u64::try_from(size)?,
+                    section: u16::try_from(ix)?,
+                    name: name.to_owned(),
+                });
+            }
+        }
+        if kind != SHT_SYMTAB {
+            continue;
+        }
+        let (offset, size, link) = header(ix)?;
+        let (strtab, _, _) = header(link)?;
+        let entsize = if wide { 24 } else { 16 };
+        for sym in (offset..offset + size).step_by(entsize) {
+            let (name, info, shndx, value, size) = if wide {
+                (
+                    elf.u32(sym)?,
+                    elf.u8(sym + 4)?,
+                    elf.u16(sym + 6)?,
+                    elf.u64(sym + 8)?,
+                    elf.u64(sym + 16)?,
+                )
+            } else {
+                let value = u64::from(elf.u32(sym + 4)?);
+                let size = u64::from(elf.u32(sym + 8)?);
+                let (info, shndx) = (elf.u8(sym + 12)?, elf.u16(sym + 14)?);
+                (elf.u32(sym)?, info, shndx, value, size)
+            };
+            if info & 0xf != STT_FUNC || shndx == SHN_UNDEF || size == 0 {
+                continue;
+            }
+            let name = elf.str(strtab + usize::try_from(name)?)?;
+            found(Symbol {
+                addr: value,
+                size,
+                section: shndx,
+                name: name.to_owned(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Go through ELF members of an `ar` archive, other members such as rlib metadata are skipped
+fn parse_archive(data: &[u8], sizes: &mut BTreeMap<String, u64>) -> anyhow::Result<()> {
+    let mut offset = 8;
+    while offset + 60 <= data.len() {
+        let size = std::str::from_utf8(&data[offset + 48..offset + 58])?
+            .trim()
+            .parse::<usize>()
+            .context("Malformed archive member header")?;
+        let start = offset + 60;
+        let member = data.get(start..start + size).context("Truncated archive")?;
+        if member.starts_with(b"\x7fELF") {
+            parse_elf(member, |sym| {
+                sizes.insert(sym.name, sym.size);
+            })?;
+        }
+        // members are aligned to 2 bytes
+        offset = start + size + size % 2;
+    }
+    Ok(())
+}
+
+/// Sizes of all the functions in an ELF file or an archive of them, keyed by symbol name.
+/// Formats other than ELF give nothing
+pub fn symbol_sizes(path: &Path) -> anyhow::Result<BTreeMap<String, u64>> {
+    let data = std::fs::read(path)?;
+    let mut sizes = BTreeMap::new();
+    if data.starts_with(b"!<arch>\n") {
+        parse_archive(&data, &mut sizes)?;
+    } else if data.starts_with(b"\x7fELF") {
+        parse_elf(&data, |sym| {
+            sizes.insert(sym.name, sym.size);
+        })?;
+    }
+    Ok(sizes)
+}
+
+/// Functions in the ELF binary at `path`, by address
+pub fn functions(path: &Path) -> anyhow::Result<Vec<Symbol>> {
+    let data = std::fs::read(path)?;
+    anyhow::ensure!(
+        data.starts_with(b"\x7fELF"),
+        "{} is not an ELF binary",
+        path.display()
+    );
+    let mut res = Vec::new();
+    parse_elf(&data, |sym| res.push(sym))?;
+    res.sort();
+    Ok(res)
+}
+
+/// Disassembly of `symbol` in `path` by `$OBJDUMP` or `objdump`, `args` go before the rest
+pub fn objdump(path: &Path, symbol: &str, args: &[&str]) -> anyhow::Result<String> {
+    let objdump = std::env::var("OBJDUMP").unwrap_or_else(|_| "objdump".into());
+    let output = Command::new(&objdump)
+        .arg("-d")
+        .args(args)
+        .arg(format!("--disassemble={symbol}"))
+        .arg(path)
+        .stdin(Stdio::null())
+        .output()
+        .with_context(|| format!("Failed to run {objdump}, is it installed?"))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stderr = stderr.trim_end();
+        let sep = if stderr.is_empty() { "" } else { "\n" };
+        anyhow::bail!("{objdump} failed with {}{sep}{stderr}", output.status);
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Symbol of the function in the ELF binary at `path` with code at `addr`, along with the
+/// address it starts at
+pub fn function_at(path: &Path, addr: u64) -> anyhow::Result<Option<(String, u64)>> {
+    Ok(functions(path)?
+        .into_iter()
+        .find(|sym| (sym.addr..sym.addr + sym.size).contains(&addr))
+        .map(|sym| (sym.name, sym.addr)))
+}
+
+/// What kind of code the linker or the compiler made for `symbol` when it's a stub that only
+/// passes control on to some other function: PLT entries, long branch veneers and retpoline
+/// thunks. Those have no source, listings label them instead
+#[must_use]
+pub fn stub_kind(symbol: &str) -> Option<&'static str> {
+    const RETPOLINES: [&str; 5] = [
+        "__x86_indirect_thunk_",
+        "__x86_indirect_call_thunk_",
+        "__x86_indirect_jump_thunk_",
+        "__llvm_retpoline_",
+        "__llvm_lvi_thunk_",
+    ];
+    // lld names its veneers `__ARMv7ABSLongThunk_f`, `__AArch64ADRPThunk_f` and so on, GNU ld
+    // uses `__f_veneer`, both add `__long_branch_f` on PowerPC
+    let veneer = symbol.starts_with("__")
+        && (symbol.ends_with("_veneer")
+            || symbol.starts_with("__long_branch_")
+            || ["Thunk_", "_from_thumb", "_from_arm"]
+                .iter()
+                .any(|part| symbol.contains(part)));
+    if PLT_SECTIONS.contains(&symbol) {
+        Some("PLT stubs")
+    } else if symbol.ends_with("@plt") || symbol.starts_with("__plt_") {
+        Some("PLT stub")
+    } else if RETPOLINES.iter().any(|prefix| symbol.starts_with(prefix))
+        || symbol == "__x86_return_thunk"
+    {
+        Some("retpoline thunk")
+    } else if veneer {
+        Some("long branch veneer")
+    } else {
+        None
+    }
+}
+
+/// The library, binary or wasm module rustc produced along with `asm_path`
+#[must_use]
+pub fn object_near(asm_path: &Path) -> Option<PathBuf> {
+    let dir = asm_path.parent()?;
+    let stem = asm_path.file_stem()?.to_string_lossy();
+    let candidates = [
+        format!("lib{stem}.rlib"),
+        format!("lib{stem}.a"),
+        format!("lib{stem}.so"),
+        stem.to_string(),
+        format!("{stem}.elf"),
+        format!("{stem}.wasm"),
+    ];
+    candidates
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// Function sizes from the library, binary or wasm module rustc produced along with
+/// `asm_path`, keyed the same way as [`Item::hashed`](crate::asm::Item::hashed)
+#[must_use]
+pub fn sizes_near(asm_path: &Path) -> BTreeMap<String, u64> {
+    let load = |path: &Path| match path.extension() {
+        Some(ext) if ext == "wasm" => wasm::function_sizes(&std::fs::read(path)?),
+        _ => symbol_sizes(path),
+    };
+    let Some(sizes) = object_near(asm_path).and_then(|path| load(&path).ok()) else {
+        return BTreeMap::new();
+    };
+    sizes
+        .into_iter()
+        .map(|(name, size)| match demangle::demangled(&name) {
+            Some(dem) => (format!("{dem:?}"), size),
+            None => (name, size),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{function_at, parse_archive, parse_elf, stub_kind, symbol_sizes};
+    use std::collections::BTreeMap;
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn sizes_of_this_test() {
+        let exe = std::env::current_exe().unwrap();
+        let sizes = symbol_sizes(&exe).unwrap();
+        assert!(sizes.values().any(|size| *size > 0));
+
+        // the same file wrapped into an archive along with something that isn't ELF
+        let data = std::fs::read(&exe).unwrap();
+        let mut archive = b"!<arch>\n".to_vec();
+        for member in [&b"metadata!"[..], &data] {
+            archive.extend(format!("{:<48}{:<10}`\n", "member/", member.len()).into_bytes());
+            archive.extend(member);
+            if member.len() % 2 == 1 {
+                archive.push(b'\n');
+            }
+        }
+        let mut from_archive = BTreeMap::new();
+        parse_archive(&archive, &mut from_archive).unwrap();
+        assert_eq!(from_archive, sizes);
+
+        let mut first = None;
+        parse_elf(&data, |sym| {
+            if sym.size > 1 && first.is_none() {
+                first = Some((sym.name, sym.addr));
+            }
+        })
+        .unwrap();
+        let (name, start) = first.unwrap();
+        let found = function_at(&exe, start + 1).unwrap().unwrap();
+        assert_eq!(found.1, start, "{name} is not where {} is", found.0);
+    }
+
+    #[test]
+    fn stubs() {
+        assert_eq!(stub_kind(".plt.sec"), Some("PLT stubs"));
+        assert_eq!(stub_kind("memcpy@plt"), Some("PLT stub"));
+        assert_eq!(
+            stub_kind("__x86_indirect_thunk_rax"),
+            Some("retpoline thunk")
+        );
+        assert_eq!(stub_kind("__llvm_retpoline_r11"), Some("retpoline thunk"));
+        assert_eq!(
+            stub_kind("__ARMv7ABSLongThunk_foo"),
+            Some("long branch veneer")
+        );
+        assert_eq!(
+            stub_kind("__AArch64ADRPThunk_foo"),
+            Some("long branch veneer")
+        );
+        assert_eq!(stub_kind("__foo_veneer"), Some("long branch veneer"));
+        assert_eq!(stub_kind("_ZN4cgut3sum17h0123456789abcdefE"), None);
+        assert_eq!(stub_kind("__rust_alloc"), None);
+    }
+}
@@ -0,0 +1,80 @@
+//! Exit codes scripts can rely on
+//!
+//! - `0` - requested output is printed
+//! - `1` - any other error: invalid arguments, unreadable files, etc.
+//! - `2` - function, target, package or doctest is not found
+//! - `3` - there are several matches, they are listed and one needs to be picked
+//! - `4` - build failed or timed out
+//! - `130` - interrupted by a signal
+use std::fmt;
+
+pub const ERROR: i32 = 1;
+pub const NOT_FOUND: i32 = 2;
+pub const AMBIGUOUS: i32 = 3;
+pub const BUILD_FAILED: i32 = 4;
+
+/// Error that should make the process exit with a specific code
+#[derive(Debug)]
+pub struct Failure {
+    pub code: i32,
+    msg: String,
+}
+
+impl Failure {
+    pub fn not_found(msg: impl Into<String>) -> Self {
+        Self {
+            code: NOT_FOUND,
+            msg: msg.into(),
+        }
+    }
+
+    pub fn ambiguous(msg: impl Into<String>) -> Self {
+        Self {
+            code: AMBIGUOUS,
+            msg: msg.into(),
+        }
+    }
+
+    pub fn build_failed(msg: impl Into<String>) -> Self {
+        Self {
+            code: BUILD_FAILED,
+            msg: msg.into(),
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.msg)
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// Exit code for an error that made it all the way to `main`
+#[must_use]
+pub fn code(err: &anyhow::Error) -> i32 {
+    err.downcast_ref::<Failure>()
+        .map_or(ERROR, |failure| failure.code)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{code, Failure, AMBIGUOUS, BUILD_FAILED, ERROR, NOT_FOUND};
+    use anyhow::Context;
+
+    #[test]
+    fn codes() {
+        let failed = |failure: Failure| code(&anyhow::Error::from(failure));
+        assert_eq!(failed(Failure::not_found("no such function")), NOT_FOUND);
+        assert_eq!(failed(Failure::ambiguous("pick one")), AMBIGUOUS);
+        assert_eq!(failed(Failure::build_failed("cargo failed")), BUILD_FAILED);
+        assert_eq!(code(&anyhow::anyhow!("can't read the file")), ERROR);
+
+        // context added on the way up keeps the code
+        let res = Err::<(), _>(Failure::not_found("no such target")).context("while building");
+        let err = res.unwrap_err();
+        assert_eq!(code(&err), NOT_FOUND);
+        assert_eq!(format!("{err:#}"), "while building: no such target");
+    }
+}
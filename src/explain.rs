@@ -0,0 +1,208 @@
+//! Why a function that is there in the sources has no code: an alias in the generated file
+//! says it shares the code of another function, otherwise the definition is found in the files
+//! rustc read and its attributes and signature tell what most likely happened
+use crate::{cache, demangle};
+use std::path::{Path, PathBuf};
+
+/// Definition of a function in the sources
+struct Definition {
+    path: PathBuf,
+    line: usize,
+    /// attributes on the lines right above it
+    attrs: Vec<String>,
+    /// has type parameters or takes `impl Trait` arguments
+    generic: bool,
+    public: bool,
+    asynchronous: bool,
+}
+
+/// Arguments of a function from the `(` after its name up to the matching `)`
+fn arguments(signature: &str) -> &str {
+    let mut depth = 0;
+    for (ix, c) in signature.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 1 => return &signature[..=ix],
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    signature
+}
+
+/// Find `fn name` in `source` from `path`, attributes are the `#[...]` lines right above it
+fn find_definition(path: &Path, source: &str, name: &str) -> Option<Definition> {
+    let lines = source.lines().collect::<Vec<_>>();
+    for (ix, line) in lines.iter().enumerate() {
+        let Some((before, after)) = line.split_once(&format!("fn {name}")) else {
+            continue;
+        };
+        let before = before.trim();
+        let generic = match after.chars().next() {
+            Some('<') => true,
+            // `impl Trait` in the return type is just a type, in the arguments it's generic
+            Some('(') => {
+                let signature = lines[ix..lines.len().min(ix + 20)].join("\n");
+                let start = signature.find(&format!("fn {name}("))? + name.len() + 3;
+                arguments(&signature[start..]).contains("impl ")
+            }
+            _ => continue,
+        };
+        if !before.split_whitespace().all(|word| {
+            word.starts_with("pub")
+                || ["const", "async", "unsafe", "extern", "\"C\""].contains(&word)
+        }) {
+            continue;
+        }
+        let attrs = lines[..ix]
+            .iter()
+            .rev()
+            .map(|line| line.trim())
+            .take_while(|line| line.starts_with("#[") || line.starts_with("///"))
+            .filter(|line| line.starts_with("#["))
+            .map(str::to_owned)
+            .collect();
+        return Some(Definition {
+            path: path.to_owned(),
+            line: ix + 1,
+            attrs,
+            generic,
+            public: before.starts_with("pub"),
+            asynchronous: before.split_whitespace().any(|word| word == "async"),
+        });
+    }
+    None
+}
+
+fn definition(asm_path: &Path, root: &Path, name: &str) -> Option<Definition> {
+    cache::dependencies(asm_path)?
+        .into_iter()
+        .filter(|path| path.extension().is_some_and(|ext| ext == "rs"))
+        .find_map(|path| {
+            let source = std::fs::read_to_string(root.join(&path)).ok()?;
+            find_definition(&path, &source, name)
+        })
+}
+
+/// Function `search` shares the code of when the generated file makes it an alias: `sym = other`
+/// in assembly and `@sym = ... alias ..., ptr @other` in LLVM IR
+fn alias_target(file: &str, search: &str) -> Option<String> {
+    let matches = |symbol: &str| {
+        let name = demangle::plain(symbol, false);
+        name == search || name.ends_with(&format!("::{search}"))
+    };
+    file.lines().find_map(|line| {
+        let (alias, target) = match line.split_once(" = ") {
+            Some((alias, rest)) if alias.starts_with('@') && rest.contains(" alias ") => (
+                alias.trim_start_matches('@'),
+                rest.rsplit('@').next()?.trim_matches('"'),
+            ),
+            Some((alias, target)) => (alias, target),
+            None => {
+                let (alias, target) = line.trim().strip_prefix(".set")?.split_once(',')?;
+                (alias.trim(), target)
+            }
+        };
+        let target = target.trim();
+        let symbol = |s: &str| !s.is_empty() && !s.contains(char::is_whitespace);
+        (symbol(alias) && symbol(target) && matches(alias)).then(|| demangle::plain(target, false))
+    })
+}
+
+/// Explanation for `search` matching nothing in the code generated for `asm_path`, `None` if
+/// there's no function with that name in the sources either
+#[must_use]
+pub fn missing(asm_path: &Path, root: &Path, search: &str) -> Option<String> {
+    let name = search.rsplit("::").next()?;
+    if name.is_empty() || !name.chars().all(|c| c == '_' || c.is_alphanumeric()) {
+        return None;
+    }
+    let file = std::fs::read_to_string(asm_path).unwrap_or_default();
+    if let Some(target) = alias_target(&file, search) {
+        return Some(format!(
+            "Note: `{search}` has no code of its own, it compiles to the same code as `{target}` \
+             and is an alias of it, look at `{target}` instead"
+        ));
+    }
+    let def = definition(asm_path, root, name)?;
+    let location = format!("{}:{}", def.path.display(), def.line);
+    let has = |attr: &str| def.attrs.iter().any(|a| a.starts_with(attr));
+    let generic;
+    let why = if def.generic {
+        let path = if search.contains("::") { search } else { name };
+        generic = format!(
+            "it is generic and rustc only generates code for copies of it used with concrete \
+             types. Pick the types with `--force-codegen \"{path}::<u32>\"` to see one"
+        );
+        &generic
+    } else if def.asynchronous {
+        "it is an `async fn`, its body is in the state machine of the future it returns and that \
+         only gets code where something polls the future. Await it in a `pub` function marked \
+         `#[inline(never)]` and look at that one with `--async`"
+    } else if has("#[cfg(test)]") || has("#[test]") {
+        "it is only compiled for tests, try `--test` with the name of the test target"
+    } else if has("#[inline(always)]") || has("#[inline]") {
+        "it is marked `#[inline]` so it gets inlined into its callers and has no code of its own, \
+         try `#[inline(never)]` instead"
+    } else if def.public {
+        "it was most likely inlined into all of its callers, small functions usually are. \
+         Mark it `#[inline(never)]` to keep a copy"
+    } else {
+        "it is not public so it was either inlined into all of its callers or removed as unused. \
+         Make it `pub` and `#[inline(never)]`, or call it from a test"
+    };
+    Some(format!(
+        "Note: `{name}` is defined at {location} but has no code, {why}"
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{alias_target, find_definition};
+    use std::path::Path;
+
+    #[test]
+    fn definitions() {
+        let source = "/// docs\n#[inline]\npub fn foo(x: u32) -> u32 {\n    x\n}\n\n\
+                      fn bar<T>(x: T) {}\nfn baz(x: impl Copy) {}\n    pub(crate) const fn quux() {}\n\
+                      pub fn ret() -> impl Fn(u32) -> u32 {}\npub fn multi(\n    x: u32,\n    \
+                      f: impl Fn(u32),\n) {}\npub async fn af() {}\n";
+        let find = |name| {
+            find_definition(Path::new("src/lib.rs"), source, name)
+                .map(|def| (def.line, def.attrs, def.generic, def.public))
+        };
+        let asynchronous = |name| {
+            find_definition(Path::new("src/lib.rs"), source, name).map(|def| def.asynchronous)
+        };
+        assert_eq!(
+            find("foo"),
+            Some((3, vec!["#[inline]".to_owned()], false, true))
+        );
+        assert_eq!(find("bar"), Some((7, Vec::new(), true, false)));
+        assert_eq!(find("baz"), Some((8, Vec::new(), true, false)));
+        assert_eq!(find("quux"), Some((9, Vec::new(), false, true)));
+        assert_eq!(find("ret"), Some((10, Vec::new(), false, true)));
+        assert_eq!(find("multi"), Some((11, Vec::new(), true, true)));
+        assert_eq!(asynchronous("af"), Some(true));
+        assert_eq!(asynchronous("foo"), Some(false));
+        assert_eq!(find("ba"), None);
+    }
+
+    #[test]
+    fn aliases() {
+        let asm = "\t.globl\t_ZN6sample4okay17h638d16417171f302E\n\
+                   _ZN6sample4okay17h638d16417171f302E = _ZN6sample4main17ha78b7ed70f6a0c84E\n";
+        assert_eq!(alias_target(asm, "okay").as_deref(), Some("sample::main"));
+        assert_eq!(
+            alias_target(asm, "sample::okay").as_deref(),
+            Some("sample::main")
+        );
+        assert_eq!(alias_target(asm, "kay"), None);
+        let ir = "@_ZN6sample4okay17h638d16417171f302E = unnamed_addr alias void (), \
+                  ptr @_ZN6sample4main17ha78b7ed70f6a0c84E\n";
+        assert_eq!(alias_target(ir, "okay").as_deref(), Some("sample::main"));
+        let set =
+            "\t.set _ZN6sample4okay17h638d16417171f302E, _ZN6sample4main17ha78b7ed70f6a0c84E\n";
+        assert_eq!(alias_target(set, "okay").as_deref(), Some("sample::main"));
+    }
+}
@@ -0,0 +1,109 @@
+#![allow(clippy::missing_errors_doc)]
+//! Where the linker put each function of a binary for `--layout`: functions by address with
+//! their sizes and the padding between them, to check the order PGO, BOLT or a linker script
+//! was supposed to produce
+use crate::elf::{self, Symbol};
+use crate::{color, demangle, exit::Failure};
+use std::io::Write;
+
+/// Print the functions of `symbols` whose names contain `filter` in the order they are in the
+/// binary, with the gap before the next function in the same section when there is one
+pub fn report(
+    symbols: &[Symbol],
+    filter: &str,
+    full_name: bool,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    // aliases share the code, the first name is enough
+    let mut functions = symbols.to_vec();
+    functions.dedup_by(|b, a| a.addr == b.addr && a.section == b.section);
+    let names = functions
+        .iter()
+        .map(|sym| demangle::plain(&sym.name, full_name))
+        .collect::<Vec<_>>();
+
+    let mut shown = 0;
+    let mut code = 0;
+    let mut padding = 0;
+    let mut lines = Vec::new();
+    for (ix, (sym, name)) in functions.iter().zip(&names).enumerate() {
+        let gap = functions
+            .get(ix + 1)
+            .filter(|next| next.section == sym.section)
+            .map_or(0, |next| next.addr.saturating_sub(sym.addr + sym.size));
+        if !name.contains(filter) {
+            continue;
+        }
+        shown += 1;
+        code += sym.size;
+        padding += gap;
+        lines.push((sym, name, gap));
+    }
+    anyhow::ensure!(
+        shown > 0,
+        Failure::not_found("No functions match this name in the binary")
+    );
+
+    let summary = format!(
+        "// {shown} functions, {code} bytes of code and {padding} bytes of gaps after them"
+    );
+    writeln!(out, "{}", color!(summary, comment))?;
+    let mut prev_section = None;
+    for (sym, name, gap) in lines {
+        if prev_section.is_some_and(|prev| prev != sym.section) {
+            writeln!(out)?;
+        }
+        prev_section = Some(sym.section);
+        let addr = format!("{:#010x}", sym.addr);
+        let size = format!("{:>8}", sym.size);
+        let stub = elf::stub_kind(&sym.name).map_or(String::new(), |kind| format!("  // {kind}"));
+        writeln!(
+            out,
+            "{} {}  {}{}",
+            color!(addr, location),
+            color!(size, size),
+            color!(name, symbol),
+            color!(stub, comment)
+        )?;
+        if gap > 0 {
+            let note = format!("{:>19}  gap", format!("+{gap}"));
+            writeln!(out, "{}", color!(note, comment))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::report;
+    use crate::elf::Symbol;
+
+    #[test]
+    fn gaps() {
+        let sym = |addr, size, section, name: &str| Symbol {
+            addr,
+            size,
+            section,
+            name: name.to_owned(),
+        };
+        let symbols = [
+            sym(0x1000, 10, 1, "a"),
+            sym(0x1000, 10, 1, "a_alias"),
+            sym(0x1010, 16, 1, "b"),
+            sym(0x2000, 4, 2, "c"),
+            sym(0x3000, 32, 3, ".plt"),
+        ];
+        let mut out = Vec::new();
+        report(&symbols, "", false, &mut out).unwrap();
+        // other tests may turn on colors
+        let colors = regex::Regex::new("\x1b\\[[0-9;]*m").unwrap();
+        assert_eq!(
+            colors.replace_all(&String::from_utf8(out).unwrap(), ""),
+            "// 4 functions, 62 bytes of code and 6 bytes of gaps after them\n\
+             0x00001000       10  a\n                 +6  gap\n\
+             0x00001010       16  b\n\n\
+             0x00002000        4  c\n\n\
+             0x00003000       32  .plt  // PLT stubs\n"
+        );
+    }
+}
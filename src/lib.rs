@@ -1,13 +1,47 @@
+pub mod artifacts;
 pub mod asm;
+pub mod bloat;
+pub mod cache;
 pub mod cached_lines;
+pub mod cargo_config;
+pub mod clipboard;
+pub mod compare;
+pub mod config;
 pub mod demangle;
+pub mod doctest;
+pub mod dump;
+pub mod editor;
+pub mod elf;
+pub mod exit;
+pub mod explain;
+pub mod layout;
 pub mod llvm;
+pub mod lsp;
 pub mod mir;
 pub mod opts;
+pub mod pager;
+pub mod pool;
+pub mod process;
+pub mod progress;
+pub mod select;
+pub mod serve;
+pub mod session;
+pub mod shim;
+pub mod snippet;
+pub mod statics;
+pub mod theme;
+pub mod tui;
+pub mod wasm;
+pub mod wrapper;
+
+#[cfg(test)]
+mod test_util;
 
 #[macro_export]
 macro_rules! color {
-    ($item:expr, $color:expr) => {
-        owo_colors::OwoColorize::if_supports_color(&$item, owo_colors::Stream::Stdout, $color)
+    ($item:expr, $role:ident) => {
+        owo_colors::OwoColorize::if_supports_color(&$item, owo_colors::Stream::Stdout, |x| {
+            $crate::theme::Painted(x, $crate::theme::get().$role)
+        })
     };
 }
@@ -1,9 +1,13 @@
 #![allow(clippy::missing_errors_doc)]
 // https://llvm.org/docs/LangRef.html
-use owo_colors::OwoColorize;
+use once_cell::sync::Lazy;
 use regex::Regex;
 
+mod cleanup;
+mod module;
+
 pub use crate::asm::Item;
+use crate::asm::Linkage;
 use crate::{
     color,
     demangle::{self, contents},
@@ -12,10 +16,14 @@ use crate::{
 use std::{
     collections::BTreeMap,
     fs::File,
-    io::{BufRead, BufReader},
+    io::{BufRead, BufReader, Write},
     path::Path,
 };
 
+/// Functions a line of LLVM IR refers to, by what can be a mangled name
+static CALLEES: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"@"?(_?_[a-zA-Z0-9_$.]+)"?\("#).expect("regexp should be valid"));
+
 #[derive(Debug)]
 enum State {
     Skipping,
@@ -25,13 +33,73 @@ enum State {
     Define,
 }
 
-/// try to print `goal` from `path`, collect available items otherwise
+/// Names of the functions `line` defines, declares or calls, demangled, as a comment to go
+/// after it. `--raw` leaves the names mangled so the IR stays valid
+fn demangled_comment(line: &str, full_name: bool) -> Option<String> {
+    let code = line.trim_start();
+    let calls = ["define ", "declare "].iter().any(|p| code.starts_with(p))
+        || [" call ", " invoke ", "call ", "invoke "]
+            .iter()
+            .any(|p| code.contains(p));
+    if !calls {
+        return None;
+    }
+    let names = CALLEES
+        .captures_iter(line)
+        .filter_map(|cap| {
+            let symbol = cap.get(1)?.as_str();
+            match demangle::demangled(symbol) {
+                Some(name) if full_name => Some(format!("{name:?}")),
+                Some(name) => Some(format!("{name:#?}")),
+                None => demangle::foreign(symbol),
+            }
+        })
+        .collect::<Vec<_>>();
+    (!names.is_empty()).then(|| format!("; {}", names.join(", ")))
+}
+
+/// Print a line of the function, without the noise `cleanup` removes unless `--raw` is given.
+/// With `--rust` lines get the source location when it changes
+fn write_line(
+    out: &mut dyn Write,
+    line: &str,
+    fmt: &Format,
+    locations: Option<&cleanup::Locations>,
+    last: &mut Option<String>,
+) -> std::io::Result<()> {
+    if fmt.raw {
+        return match demangled_comment(line, fmt.full_name) {
+            Some(comment) => writeln!(out, "{line} {}", color!(comment, comment)),
+            None => writeln!(out, "{line}"),
+        };
+    }
+    if cleanup::is_noise(line) {
+        return Ok(());
+    }
+    let code = cleanup::clean(line);
+    let code = contents(&code, fmt.full_name);
+    let location = locations
+        .zip(cleanup::dbg(line))
+        .and_then(|(locations, id)| locations.describe(id))
+        .filter(|location| last.as_ref() != Some(location));
+    match location {
+        Some(location) => {
+            writeln!(out, "{code} {}", color!(format!("; {location}"), comment))?;
+            *last = Some(location);
+            Ok(())
+        }
+        None => writeln!(out, "{code}"),
+    }
+}
+
+/// try to print `goal` from `path`, collect all available items otherwise
 ///
 ///
 pub fn dump_function(
     goal: Option<(&str, usize)>,
     path: &Path,
     fmt: &Format,
+    out: &mut dyn Write,
     items: &mut Vec<Item>,
 ) -> anyhow::Result<bool> {
     let mut seen = false;
@@ -44,6 +112,9 @@ pub fn dump_function(
     let mut attrs = String::new();
     let mut current_item = None::<Item>;
     let mut names = BTreeMap::new();
+    // source locations for `--rust` and the last one printed
+    let mut locations = None;
+    let mut last = None;
     for (ix, line) in reader.lines().enumerate() {
         let line = line?;
 
@@ -83,7 +154,7 @@ pub fn dump_function(
                     {
                         let hashed = format!("{hashed:?}");
                         let name_entry = names.entry(name.clone()).or_insert(0);
-                        seen = goal.map_or(true, |goal| {
+                        seen = goal.is_none_or(|goal| {
                             (name.as_ref(), *name_entry) == goal || hashed == goal.0
                         });
 
@@ -92,13 +163,25 @@ pub fn dump_function(
                             hashed,
                             index: *name_entry,
                             len: ix,
+                            linkage: Linkage::default(),
                         });
                         *name_entry += 1;
 
+                        if seen && goal.is_some() && fmt.self_contained {
+                            // the name is all that's needed, the rest comes from the whole file
+                            let symbol = regex.captures(&line).and_then(|c| c.get(1));
+                            let symbol = symbol.map_or("", |c| c.as_str());
+                            let ir = std::fs::read_to_string(path)?;
+                            return module::extract(&ir, symbol, out);
+                        }
                         if seen {
-                            println!("{}", color!(name, OwoColorize::cyan));
-                            println!("{}", color!(attrs, OwoColorize::cyan));
-                            println!("{}", contents(&line, fmt.full_name));
+                            if fmt.rust && !fmt.raw {
+                                let ir = std::fs::read_to_string(path)?;
+                                locations = Some(cleanup::Locations::new(&ir));
+                            }
+                            writeln!(out, "{}", color!(name, comment))?;
+                            writeln!(out, "{}", color!(attrs, comment))?;
+                            write_line(out, &line, fmt, None, &mut last)?;
                         }
                     } else {
                         state = State::Skipping;
@@ -109,15 +192,12 @@ pub fn dump_function(
             }
             State::Define => {
                 if seen {
-                    println!("{}", contents(&line, fmt.full_name));
+                    write_line(out, &line, fmt, locations.as_ref(), &mut last)?;
                 }
                 if line == "}" {
                     if let Some(mut cur) = current_item.take() {
                         cur.len = ix - cur.len;
-                        if goal.map_or(true, |goal| goal.0.is_empty() || cur.name.contains(goal.0))
-                        {
-                            items.push(cur);
-                        }
+                        items.push(cur);
                     }
                     if seen {
                         return Ok(true);
@@ -128,5 +208,99 @@ pub fn dump_function(
         }
     }
 
+    // listing shouldn't depend on the order functions end up in the file
+    items.sort();
     Ok(seen)
 }
+
+#[cfg(test)]
+mod test {
+    use super::{demangled_comment, dump_function};
+    use crate::test_util::dump_file;
+
+    const IR: &str = r#"; ModuleID = 'x'
+source_filename = "x"
+
+; x::main
+; Function Attrs: nounwind
+define i32 @_ZN1x4main17h0000000000000000E(i32 %n) unnamed_addr #0 {
+start:
+  %r = call i32 @_ZN1x6helper17h1111111111111111E(i32 %n), !dbg !7
+  %s = call i32 @ext(i32 %r)
+  ret i32 %s
+}
+
+; x::helper
+; Function Attrs: inlinehint
+define internal i32 @_ZN1x6helper17h2222222222222222E(i32 %n) unnamed_addr #1 {
+start:
+  ret i32 %n
+}
+
+; x::helper
+; Function Attrs: inlinehint
+define internal i32 @_ZN1x6helper17h1111111111111111E(i32 %n) unnamed_addr #1 {
+start:
+  ret i32 %n
+}
+
+declare i32 @ext(i32) unnamed_addr #1
+"#;
+
+    #[test]
+    fn items_in_order() {
+        let (items, _) = dump_file(dump_function, IR, ("", usize::MAX), &[]);
+        let items = items
+            .iter()
+            .map(|item| (item.name.as_str(), item.hashed.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            items,
+            [
+                ("x::helper", "x::helper::h1111111111111111"),
+                ("x::helper", "x::helper::h2222222222222222"),
+                ("x::main", "x::main::h0000000000000000"),
+            ]
+        );
+    }
+
+    #[test]
+    fn raw_keeps_mangled_names() {
+        let goal = ("x::main", 0);
+        let (_, code) = dump_file(dump_function, IR, goal, &["--raw"]);
+        let call = code
+            .lines()
+            .find(|line| line.contains("@_ZN1x6helper17h1111111111111111E"))
+            .unwrap();
+        assert!(call.ends_with(", !dbg !7 ; x::helper"), "{call}");
+        let (_, code) = dump_file(dump_function, IR, goal, &["--raw", "--full-name"]);
+        assert!(code.contains("; x::helper::h1111111111111111"));
+        let (_, code) = dump_file(dump_function, IR, goal, &[]);
+        assert!(!code.contains("_ZN1x6helper"));
+        assert!(code.contains("call i32 @x::helper(i32 %n)"));
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(
+            demangled_comment(
+                "define i32 @_ZN1x4main17h0000000000000000E(i32 %n) unnamed_addr #0 {",
+                false
+            )
+            .as_deref(),
+            Some("; x::main")
+        );
+        assert_eq!(
+            demangled_comment("declare i32 @_ZN1x3ext17h0000000000000000E(i32)", true).as_deref(),
+            Some("; x::ext::h0000000000000000")
+        );
+        assert_eq!(
+            demangled_comment("  %s = call i32 @ext(i32 %r)", false),
+            None
+        );
+        assert_eq!(
+            demangled_comment("  store ptr @_ZN1x4main17h0000000000000000E, ptr %p", false),
+            None
+        );
+    }
+}
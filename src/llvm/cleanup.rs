@@ -0,0 +1,168 @@
+//! Most of what rustc puts into LLVM IR is there for the debugger and the optimizer and not
+//! for the reader: debug records, metadata attached to instructions and references to
+//! attribute groups. Those are removed unless `--raw` is given, `--rust` turns `!dbg`
+//! references into a short location instead
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::{borrow::Cow, collections::BTreeMap};
+
+/// `!dbg !12`, `!llvm.loop !7`, `!noundef !{}`, with the comma before them
+static ATTACHMENTS: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r",?\s+![a-z][a-z_.]* !(?:\d+|\{\})").expect("regexp should be valid"));
+/// `#0`, functions and calls refer to attribute groups defined at the end of the file
+static ATTRIBUTE_GROUPS: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\s+#\d+\b").expect("regexp should be valid"));
+static DBG: Lazy<Regex> = Lazy::new(|| Regex::new(r"!dbg !(\d+)").expect("regexp should be valid"));
+static NODE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^!(\d+) = (?:distinct )?!(\w+)\((.*)\)$").expect("regexp should be valid")
+});
+static FIELD: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"\b(line|scope|inlinedAt|file|filename): (?:!(\d+)|(\d+)|"((?:[^"\\]|\\.)*)")"#)
+        .expect("regexp should be valid")
+});
+
+/// Lines that are there only for the debugger: `#dbg_value` records and calls to
+/// `llvm.dbg.*` intrinsics older versions of LLVM use instead
+#[must_use]
+pub fn is_noise(line: &str) -> bool {
+    let line = line.trim_start();
+    line.starts_with("#dbg_")
+        || line.starts_with("call void @llvm.dbg.")
+        || line.starts_with("tail call void @llvm.dbg.")
+}
+
+/// `line` without metadata attachments and attribute group references
+#[must_use]
+pub fn clean(line: &str) -> Cow<'_, str> {
+    match ATTACHMENTS.replace_all(line, "") {
+        Cow::Borrowed(line) => ATTRIBUTE_GROUPS.replace_all(line, ""),
+        Cow::Owned(line) => Cow::Owned(ATTRIBUTE_GROUPS.replace_all(&line, "").into_owned()),
+    }
+}
+
+/// Metadata node `!dbg` on `line` refers to
+#[must_use]
+pub fn dbg(line: &str) -> Option<u32> {
+    DBG.captures(line)?.get(1)?.as_str().parse().ok()
+}
+
+#[derive(Debug, Default)]
+struct Node {
+    line: Option<u32>,
+    scope: Option<u32>,
+    inlined_at: Option<u32>,
+    file: Option<u32>,
+    filename: Option<String>,
+}
+
+/// Debug info metadata from a whole module, enough to find the source line of an instruction
+#[derive(Debug, Default)]
+pub struct Locations(BTreeMap<u32, Node>);
+
+impl Locations {
+    #[must_use]
+    pub fn new(ir: &str) -> Self {
+        let mut nodes = BTreeMap::new();
+        for line in ir.lines().filter(|l| l.starts_with('!')) {
+            let Some(caps) = NODE.captures(line) else {
+                continue;
+            };
+            let Ok(id) = caps[1].parse::<u32>() else {
+                continue;
+            };
+            if !caps[2].starts_with("DI") {
+                continue;
+            }
+            let mut node = Node::default();
+            for field in FIELD.captures_iter(&caps[3]) {
+                let value = field
+                    .get(2)
+                    .or_else(|| field.get(3))
+                    .and_then(|v| v.as_str().parse().ok());
+                match &field[1] {
+                    "line" => node.line = value,
+                    "scope" => node.scope = value,
+                    "inlinedAt" => node.inlined_at = value,
+                    "file" => node.file = value,
+                    _ => node.filename = field.get(4).map(|f| f.as_str().to_owned()),
+                }
+            }
+            nodes.insert(id, node);
+        }
+        Self(nodes)
+    }
+
+    /// `file:line` in the function itself the location `id` is for, code inlined from other
+    /// functions is attributed to the line it was inlined at
+    #[must_use]
+    pub fn describe(&self, mut id: u32) -> Option<String> {
+        let mut seen = 0;
+        while let Some(outer) = self.0.get(&id)?.inlined_at {
+            id = outer;
+            seen += 1;
+            if seen > self.0.len() {
+                return None;
+            }
+        }
+        let location = self.0.get(&id)?;
+        let mut scope = location.scope?;
+        // lexical blocks have files of their own, subprograms always do
+        let file = loop {
+            let node = self.0.get(&scope)?;
+            match (node.file, node.scope) {
+                (Some(file), _) => break file,
+                (None, Some(outer)) if outer != scope => scope = outer,
+                _ => return None,
+            }
+        };
+        let filename = self.0.get(&file)?.filename.as_deref()?;
+        Some(format!("{filename}:{}", location.line?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{clean, dbg, is_noise, Locations};
+
+    #[test]
+    fn cleaning() {
+        assert_eq!(
+            clean("define i32 @f(i32 %x) unnamed_addr #0 personality ptr @p !dbg !143 {"),
+            "define i32 @f(i32 %x) unnamed_addr personality ptr @p {"
+        );
+        assert_eq!(
+            clean("  br i1 %7, label %a, label %b, !dbg !313, !llvm.loop !377"),
+            "  br i1 %7, label %a, label %b"
+        );
+        assert_eq!(
+            clean("  %3 = load i8, ptr %p, align 1, !range !5, !noundef !{}"),
+            "  %3 = load i8, ptr %p, align 1"
+        );
+        assert_eq!(clean("  ret i32 %n"), "  ret i32 %n");
+        assert!(is_noise(
+            "    #dbg_value(ptr %x.0, !152, !DIExpression(), !153)"
+        ));
+        assert!(is_noise(
+            "  call void @llvm.dbg.declare(metadata ptr %x, metadata !12)"
+        ));
+        assert!(!is_noise("  call void @other()"));
+        assert_eq!(dbg("  ret i32 %n, !dbg !42"), Some(42));
+    }
+
+    #[test]
+    fn locations() {
+        let ir = r#"!19 = !DIFile(filename: "src/lib.rs", directory: "/tmp/x")
+!20 = !DIFile(filename: "/rustc/library/core/src/iter.rs", directory: "")
+!143 = distinct !DISubprogram(name: "a", scope: !14, file: !19, line: 2, unit: !69)
+!250 = distinct !DISubprogram(name: "fold", scope: !14, file: !20, line: 270, unit: !69)
+!251 = distinct !DILexicalBlock(scope: !250, file: !20, line: 279, column: 9)
+!258 = !DILocation(line: 3, column: 7, scope: !143)
+!311 = !DILocation(line: 2, column: 5, scope: !143)
+!313 = !DILocation(line: 284, column: 24, scope: !251, inlinedAt: !258)
+"#;
+        let locations = Locations::new(ir);
+        assert_eq!(locations.describe(311).unwrap(), "src/lib.rs:2");
+        assert_eq!(locations.describe(313).unwrap(), "src/lib.rs:3");
+        assert!(locations.describe(1).is_none());
+    }
+}
@@ -0,0 +1,320 @@
+//! Just enough of the LLVM IR syntax to split a module into the things defined at the top
+//! level and to follow the references between them, so a single function can be taken out
+//! along with everything `opt` and `llc` need to accept it on its own
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// Names can be quoted and quoted ones can contain anything
+const NAME: &str = r#"("(?:[^"\\]|\\.)*"|[-a-zA-Z$._0-9]+)"#;
+
+static GLOBALS: Lazy<Regex> =
+    Lazy::new(|| Regex::new(&format!("@{NAME}")).expect("regexp should be valid"));
+static FUNCTIONS: Lazy<Regex> =
+    Lazy::new(|| Regex::new(&format!(r"@{NAME}\(")).expect("regexp should be valid"));
+static TYPES: Lazy<Regex> =
+    Lazy::new(|| Regex::new(&format!("%{NAME}")).expect("regexp should be valid"));
+static COMDATS: Lazy<Regex> =
+    Lazy::new(|| Regex::new(&format!(r"comdat\(\${NAME}\)")).expect("regexp should be valid"));
+static ATTRIBUTES: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"#(\d+)\b").expect("regexp should be valid"));
+static METADATA: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"!(\d+)\b").expect("regexp should be valid"));
+/// Argument names, declarations only have types
+static ARG_NAMES: Lazy<Regex> =
+    Lazy::new(|| Regex::new(&format!(r"\s%{NAME}([,)])")).expect("regexp should be valid"));
+/// Parts of a function definition a declaration can't have
+static DEFINITION_ONLY: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(&format!(
+        r"\s+(?:comdat(?:\(\${NAME}\))?|personality ptr @{NAME}|![a-z_.]+ !\d+)"
+    ))
+    .expect("regexp should be valid")
+});
+static LINKAGE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^(declare)\s+(?:private|internal|available_externally|linkonce|weak|linkonce_odr|weak_odr)\s")
+        .expect("regexp should be valid")
+});
+
+/// Named metadata the verifier wants to see when debug info is there
+const NAMED_METADATA: [&str; 2] = ["!llvm.module.flags", "!llvm.dbg.cu"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// `source_filename`, `target datalayout` and `target triple`
+    Header,
+    Type,
+    Comdat,
+    Global,
+    Declare,
+    Define,
+    Attributes,
+    Metadata,
+}
+
+/// Something defined at the top level along with comments right above it
+#[derive(Debug)]
+struct Entity<'a> {
+    kind: Kind,
+    name: &'a str,
+    text: &'a str,
+    comments: Vec<&'a str>,
+}
+
+fn first<'a>(regex: &Regex, text: &'a str) -> Option<&'a str> {
+    Some(regex.captures(text)?.get(1)?.as_str())
+}
+
+fn parse(ir: &str) -> Vec<Entity<'_>> {
+    let mut res = Vec::new();
+    let mut comments = Vec::new();
+    let mut define = None::<usize>;
+    let mut offset = 0;
+    for line in ir.split_inclusive('\n') {
+        let start = offset;
+        offset += line.len();
+        let line = line.trim_end_matches('\n');
+        if let Some(define_start) = define {
+            if line == "}" {
+                let text = &ir[define_start..start + 1];
+                let name = first(&FUNCTIONS, text).unwrap_or_default();
+                res.push(Entity {
+                    kind: Kind::Define,
+                    name,
+                    text,
+                    comments: std::mem::take(&mut comments),
+                });
+                define = None;
+            }
+            continue;
+        }
+        let kind = if line.starts_with("define ") {
+            define = Some(start);
+            continue;
+        } else if line.starts_with("declare ") {
+            Kind::Declare
+        } else if line.starts_with("source_filename") || line.starts_with("target ") {
+            Kind::Header
+        } else if line.starts_with("attributes #") {
+            Kind::Attributes
+        } else if line.starts_with('!') {
+            Kind::Metadata
+        } else if line.starts_with('@') {
+            Kind::Global
+        } else if line.starts_with('$') {
+            Kind::Comdat
+        } else if line.starts_with('%') && line.contains(" = type ") {
+            Kind::Type
+        } else {
+            if line.starts_with(';') && !line.starts_with("; ModuleID") {
+                comments.push(line);
+            } else {
+                comments.clear();
+            }
+            continue;
+        };
+        let name = match kind {
+            Kind::Declare => first(&FUNCTIONS, line).unwrap_or_default(),
+            Kind::Header => line,
+            Kind::Attributes => first(&ATTRIBUTES, line).unwrap_or_default(),
+            _ => line.split_once(" = ").map_or(line, |(name, _)| name),
+        };
+        // metadata, types and the rest are referred to with the sigil
+        let name = name
+            .strip_prefix(['@', '%', '$'])
+            .filter(|_| !matches!(kind, Kind::Metadata))
+            .unwrap_or(name);
+        res.push(Entity {
+            kind,
+            name,
+            text: line,
+            comments: std::mem::take(&mut comments),
+        });
+    }
+    res
+}
+
+/// Declaration for the function `define` defines, the definition itself isn't needed
+fn declaration(define: &str) -> String {
+    let header = define.lines().next().unwrap_or_default();
+    let header = header.strip_suffix(" {").unwrap_or(header);
+    let header = header.replacen("define ", "declare ", 1);
+    let header = DEFINITION_ONLY.replace_all(&header, "");
+    let header = ARG_NAMES.replace_all(&header, "$2");
+    LINKAGE.replace(&header, "$1 ").into_owned()
+}
+
+/// Print the function with symbol `symbol` from `ir` as a module of its own: with the header,
+/// the types, globals, declarations, attributes and metadata it refers to, in the order they
+/// are in the file. Functions it calls are declared instead of defined
+pub fn extract(ir: &str, symbol: &str, out: &mut dyn Write) -> anyhow::Result<bool> {
+    let entities = parse(ir);
+    let quoted = format!("\"{symbol}\"");
+    let Some(goal) = entities
+        .iter()
+        .position(|e| e.kind == Kind::Define && (e.name == symbol || e.name == quoted))
+    else {
+        return Ok(false);
+    };
+    let mut by_name = BTreeMap::new();
+    for (ix, entity) in entities.iter().enumerate() {
+        let key = match entity.kind {
+            Kind::Header => continue,
+            Kind::Global | Kind::Declare | Kind::Define => ("@", entity.name),
+            Kind::Type => ("%", entity.name),
+            Kind::Comdat => ("$", entity.name),
+            Kind::Attributes => ("#", entity.name),
+            Kind::Metadata => ("!", entity.name.trim_start_matches('!')),
+        };
+        by_name.entry(key).or_insert(ix);
+    }
+
+    let mut texts = BTreeMap::<usize, String>::new();
+    let mut todo = vec![goal];
+    for (ix, entity) in entities.iter().enumerate() {
+        if entity.kind == Kind::Header || NAMED_METADATA.contains(&entity.name) {
+            todo.push(ix);
+        }
+    }
+    while let Some(ix) = todo.pop() {
+        if texts.contains_key(&ix) {
+            continue;
+        }
+        let entity = &entities[ix];
+        let text = if entity.kind == Kind::Define && ix != goal {
+            declaration(entity.text)
+        } else {
+            entity.text.to_owned()
+        };
+        let mut refs = Vec::new();
+        refs.extend(GLOBALS.captures_iter(&text).map(|c| ("@", c.get(1))));
+        refs.extend(TYPES.captures_iter(&text).map(|c| ("%", c.get(1))));
+        refs.extend(COMDATS.captures_iter(&text).map(|c| ("$", c.get(1))));
+        refs.extend(ATTRIBUTES.captures_iter(&text).map(|c| ("#", c.get(1))));
+        refs.extend(METADATA.captures_iter(&text).map(|c| ("!", c.get(1))));
+        for (sigil, name) in refs {
+            if let Some(name) = name {
+                todo.extend(by_name.get(&(sigil, name.as_str())));
+            }
+        }
+        // `comdat` without a name is the one named after the symbol
+        let header = text.lines().next().unwrap_or_default();
+        if header.contains(" comdat") && !header.contains(" comdat(") {
+            todo.extend(by_name.get(&("$", entity.name)));
+        }
+        texts.insert(ix, text);
+    }
+
+    let mut prev = None;
+    for (ix, text) in &texts {
+        let entity = &entities[*ix];
+        let function = matches!(entity.kind, Kind::Define | Kind::Declare);
+        if prev.is_some_and(|prev| prev != entity.kind || function) {
+            writeln!(out)?;
+        }
+        prev = Some(entity.kind);
+        let used = entity.comments.iter().filter(|_| function);
+        for comment in used {
+            writeln!(out, "{comment}")?;
+        }
+        writeln!(out, "{}", text.trim_end())?;
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{declaration, extract};
+
+    const IR: &str = r#"; ModuleID = 'x'
+source_filename = "x"
+target triple = "x86_64-unknown-linux-gnu"
+
+%"Pair" = type { i32, i32 }
+%Unused = type { i8 }
+
+$weak_one = comdat any
+
+@msg = private unnamed_addr constant [2 x i8] c"hi", align 1
+@table = private constant <{ ptr }> <{ ptr @msg }>, align 8
+@other = private constant [1 x i8] c"x", align 1
+
+; x::helper
+; Function Attrs: inlinehint
+define internal i32 @_ZN1x6helper17h0000000000000000E(ptr %p, i32 %n) unnamed_addr #1 comdat personality ptr @rust_eh_personality !dbg !5 {
+start:
+  ret i32 %n
+}
+
+; x::main
+; Function Attrs: nounwind
+define i32 @_ZN1x4main17h0000000000000000E(ptr %pair) unnamed_addr #0 {
+start:
+  %v = load %"Pair", ptr %pair, align 4
+  %r = call i32 @_ZN1x6helper17h0000000000000000E(ptr @table, i32 1), !dbg !7
+  %s = call i32 @ext(i32 %r)
+  ret i32 %s
+}
+
+declare i32 @ext(i32) unnamed_addr #1
+
+declare i32 @rust_eh_personality(...) unnamed_addr #1
+
+attributes #0 = { nounwind }
+attributes #1 = { inlinehint }
+attributes #2 = { cold }
+
+!llvm.module.flags = !{!0}
+
+!0 = !{i32 7, !"PIC Level", i32 2}
+!1 = !{!"unused"}
+!5 = distinct !DISubprogram(name: "helper")
+!7 = !DILocation(line: 3, scope: !8)
+!8 = distinct !DISubprogram(name: "main")
+"#;
+
+    #[test]
+    fn declarations() {
+        let define = "define internal i32 @f(ptr %p, i32 %n) unnamed_addr #1 comdat personality ptr @rust_eh_personality !dbg !5 {\n  ret i32 %n\n}\n";
+        assert_eq!(
+            declaration(define),
+            "declare i32 @f(ptr, i32) unnamed_addr #1"
+        );
+    }
+
+    #[test]
+    fn extracting() {
+        let mut out = Vec::new();
+        assert!(extract(IR, "_ZN1x4main17h0000000000000000E", &mut out).unwrap());
+        let out = String::from_utf8(out).unwrap();
+        for line in [
+            "target triple",
+            "%\"Pair\" = type",
+            "@msg =",
+            "@table =",
+            "; x::main",
+            "declare i32 @_ZN1x6helper17h0000000000000000E(ptr, i32) unnamed_addr #1\n",
+            "declare i32 @ext(i32)",
+            "attributes #0",
+            "attributes #1",
+            "!llvm.module.flags",
+            "!0 =",
+            "!7 =",
+            "!8 =",
+        ] {
+            assert!(out.contains(line), "{line} is missing from {out}");
+        }
+        for line in [
+            "%Unused",
+            "@other",
+            "$weak_one",
+            "attributes #2",
+            "!1 =",
+            "!5 =",
+            "ret i32 %n",
+        ] {
+            assert!(!out.contains(line), "{line} shouldn't be in {out}");
+        }
+        assert!(!extract(IR, "_ZN1x4nope17h0000000000000000E", &mut Vec::new()).unwrap());
+    }
+}
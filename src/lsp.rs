@@ -0,0 +1,344 @@
+#![allow(clippy::missing_errors_doc)]
+//! Editor integration: JSON-RPC on stdin and stdout framed the same way as the language server
+//! protocol so editors can reuse their LSP client. Besides the lifecycle messages there are
+//! three methods:
+//!
+//! - `cargo-show-asm/items` with `{"kind": "asm"}` lists everything in that kind of output
+//! - `cargo-show-asm/show` with either `{"file": "src/lib.rs", "line": 10}` or
+//!   `{"item": "<hashed name>"}` gives the code of a function, `kind`, `rust` and `simplify`
+//!   are optional, `"structured": true` splits the code into lines with source locations
+//! - `cargo-show-asm/rebuild` builds again, saving a file does the same before the next request
+use crate::asm::{self, Item};
+use crate::opts::{Format, Syntax};
+use crate::tui::{label, Backend};
+use anyhow::Context;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+const INVALID_PARAMS: i64 = -32602;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INTERNAL_ERROR: i64 = -32603;
+
+/// Read one message, `None` once the input is over
+fn read_message(input: &mut dyn BufRead) -> anyhow::Result<Option<Value>> {
+    let mut len = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                len = Some(value.trim().parse::<usize>()?);
+            }
+        }
+    }
+    let mut body = vec![0; len.context("Message without Content-Length")?];
+    input.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+fn write_message(out: &mut dyn Write, msg: &Value) -> std::io::Result<()> {
+    let body = msg.to_string();
+    write!(out, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+    out.flush()
+}
+
+/// Path from a `file://` uri or a path relative to the current directory
+fn requested_path(file: &str) -> PathBuf {
+    let path = Path::new(file.strip_prefix("file://").unwrap_or(file));
+    match std::env::current_dir() {
+        Ok(cwd) if path.is_relative() => cwd.join(path),
+        _ => path.to_owned(),
+    }
+}
+
+/// Innermost item with code for `line` of `file`, judging only by lines from the file the item
+/// itself starts in so callers aren't picked for the code they inline
+fn item_at<'a>(locations: &'a asm::Locations, file: &Path, line: u64) -> Option<&'a Item> {
+    locations
+        .iter()
+        .filter_map(|(item, lines)| {
+            let (own, _) = lines.first()?;
+            let (min, max) = lines
+                .iter()
+                .filter(|(path, _)| path == own)
+                .fold((u64::MAX, 0), |(min, max), (_, l)| {
+                    (min.min(*l), max.max(*l))
+                });
+            (own == file && (min..=max).contains(&line)).then_some((item, max - min))
+        })
+        .min_by_key(|(_, span)| *span)
+        .map(|(item, _)| item)
+}
+
+/// Rendered code split into lines, each with the source location of the code on it, the
+/// source lines themselves are dropped since the editor has those
+fn structured_lines(code: &str) -> Vec<Value> {
+    let mut lines = Vec::new();
+    let mut location = None;
+    let mut iter = code.lines();
+    while let Some(line) = iter.next() {
+        if let Some(loc) = asm::location_comment(line) {
+            location = Some(loc);
+            iter.next();
+            continue;
+        }
+        lines.push(match location {
+            Some((file, line_no)) => json!({ "text": line, "file": file, "line": line_no }),
+            None => json!({ "text": line }),
+        });
+    }
+    lines
+}
+
+struct Server<'a> {
+    backend: &'a mut dyn Backend,
+    syntaxes: &'a [Syntax],
+    fmt: Format,
+    /// items for each kind of output, loaded when first requested
+    items: BTreeMap<usize, Vec<Item>>,
+    locations: Option<asm::Locations>,
+    /// a file was saved since the last build
+    dirty: bool,
+    exit: bool,
+}
+
+impl Server<'_> {
+    fn rebuild(&mut self) -> anyhow::Result<()> {
+        self.backend.rebuild()?;
+        self.items.clear();
+        self.locations = None;
+        self.dirty = false;
+        Ok(())
+    }
+
+    fn items(&mut self, kind: usize) -> anyhow::Result<&[Item]> {
+        if self.dirty {
+            self.rebuild()?;
+        }
+        if !self.items.contains_key(&kind) {
+            let items = self.backend.items(self.syntaxes[kind])?;
+            self.items.insert(kind, items);
+        }
+        Ok(&self.items[&kind])
+    }
+
+    fn kind(&self, params: &Value) -> Result<usize, (i64, String)> {
+        match params["kind"].as_str() {
+            None => Ok(0),
+            Some(kind) => self
+                .syntaxes
+                .iter()
+                .position(|s| label(*s) == kind)
+                .ok_or_else(|| (INVALID_PARAMS, format!("Unknown kind {kind:?}"))),
+        }
+    }
+
+    /// Item `params` point to either by name or by a source line
+    fn find(&mut self, kind: usize, params: &Value) -> anyhow::Result<Option<Item>> {
+        if let Some(hashed) = params["item"].as_str() {
+            let items = self.items(kind)?;
+            return Ok(items.iter().find(|i| i.hashed == hashed).cloned());
+        }
+        let (Some(file), Some(line)) = (params["file"].as_str(), params["line"].as_u64()) else {
+            return Ok(None);
+        };
+        if self.dirty {
+            self.rebuild()?;
+        }
+        if self.locations.is_none() {
+            self.locations = Some(self.backend.locations()?);
+        }
+        let Some(found) = self
+            .locations
+            .as_ref()
+            .and_then(|locations| item_at(locations, &requested_path(file), line))
+            .cloned()
+        else {
+            return Ok(None);
+        };
+        // other kinds of output use the same names
+        let items = self.items(kind)?;
+        Ok(items
+            .iter()
+            .find(|i| i.hashed == found.hashed)
+            .or_else(|| {
+                items
+                    .iter()
+                    .find(|i| i.name == found.name && i.index == found.index)
+            })
+            .cloned())
+    }
+
+    fn show(&mut self, params: &Value) -> Result<Value, (i64, String)> {
+        let internal = |err: anyhow::Error| (INTERNAL_ERROR, format!("{err:#}"));
+        let kind = self.kind(params)?;
+        let item = self.find(kind, params).map_err(internal)?;
+        let Some(item) = item else {
+            return Ok(Value::Null);
+        };
+        let structured = params["structured"].as_bool().unwrap_or(false);
+        let mut fmt = self.fmt.clone();
+        // locations come from the source lines
+        fmt.rust = structured || params["rust"].as_bool().unwrap_or(fmt.rust);
+        fmt.simplify = params["simplify"].as_bool().unwrap_or(fmt.simplify);
+        let code = self
+            .backend
+            .render(self.syntaxes[kind], &fmt, &item)
+            .map_err(internal)?;
+        let code = String::from_utf8_lossy(&code);
+        let mut res = json!({
+            "name": item.name,
+            "hashed": item.hashed,
+            "kind": label(self.syntaxes[kind]),
+        });
+        if structured {
+            res["lines"] = Value::Array(structured_lines(&code));
+        } else {
+            res["text"] = Value::String(code.into_owned());
+        }
+        Ok(res)
+    }
+
+    fn request(&mut self, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        let internal = |err: anyhow::Error| (INTERNAL_ERROR, format!("{err:#}"));
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": { "textDocumentSync": { "save": true } },
+                "serverInfo": { "name": "cargo-show-asm", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            "shutdown" => Ok(Value::Null),
+            "cargo-show-asm/items" => {
+                let kind = self.kind(params)?;
+                let items = self.items(kind).map_err(internal)?;
+                serde_json::to_value(items).map_err(|err| internal(err.into()))
+            }
+            "cargo-show-asm/show" => self.show(params),
+            "cargo-show-asm/rebuild" => {
+                self.rebuild().map(|()| Value::Bool(true)).map_err(internal)
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method {method:?}"))),
+        }
+    }
+
+    /// Reply to `msg` if it is a request, notifications get nothing back
+    fn message(&mut self, msg: &Value) -> Option<Value> {
+        let method = msg["method"].as_str().unwrap_or("");
+        let Some(id) = msg.get("id") else {
+            match method {
+                "exit" => self.exit = true,
+                "textDocument/didSave" => self.dirty = true,
+                _ => {}
+            }
+            return None;
+        };
+        let params = msg.get("params").unwrap_or(&Value::Null);
+        Some(match self.request(method, params) {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": code, "message": message },
+            }),
+        })
+    }
+}
+
+/// Answer requests from stdin until the editor sends `exit` or closes it, `syntaxes` are the
+/// kinds of output available without rebuilding
+pub fn run(backend: &mut dyn Backend, syntaxes: &[Syntax], fmt: Format) -> anyhow::Result<()> {
+    let mut input = std::io::stdin().lock();
+    let mut out = std::io::stdout().lock();
+    let mut server = Server {
+        backend,
+        syntaxes,
+        fmt,
+        items: BTreeMap::new(),
+        locations: None,
+        dirty: false,
+        exit: false,
+    };
+    while let Some(msg) = read_message(&mut input)? {
+        if let Some(reply) = server.message(&msg) {
+            write_message(&mut out, &reply)?;
+        }
+        if server.exit {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{read_message, write_message, Server};
+    use crate::opts::Syntax;
+    use crate::test_util::{fmt, Fake};
+    use serde_json::{json, Value};
+
+    #[test]
+    fn framing() {
+        let mut buf = Vec::new();
+        write_message(&mut buf, &json!({ "id": 1 })).unwrap();
+        assert_eq!(buf, b"Content-Length: 8\r\n\r\n{\"id\":1}");
+        let mut input = &buf[..];
+        assert_eq!(read_message(&mut input).unwrap(), Some(json!({ "id": 1 })));
+        assert_eq!(read_message(&mut input).unwrap(), None);
+    }
+
+    #[test]
+    fn requests() {
+        let syntaxes = [Syntax::Intel, Syntax::Llvm];
+        let mut server = Server {
+            backend: &mut Fake,
+            syntaxes: &syntaxes,
+            fmt: fmt(&[]),
+            items: Default::default(),
+            locations: None,
+            dirty: false,
+            exit: false,
+        };
+        let mut call = |method: &str, params: Value| {
+            let msg = json!({ "jsonrpc": "2.0", "id": 7, "method": method, "params": params });
+            server.message(&msg).unwrap()
+        };
+        let show = call(
+            "cargo-show-asm/show",
+            json!({ "file": "/src/lib.rs", "line": 4 }),
+        );
+        assert_eq!(show["result"]["name"], "inner");
+        assert_eq!(show["result"]["text"], "inner:\n\tret");
+        let show = call(
+            "cargo-show-asm/show",
+            json!({ "file": "/src/lib.rs", "line": 8 }),
+        );
+        assert_eq!(show["result"]["name"], "outer");
+        let show = call(
+            "cargo-show-asm/show",
+            json!({ "item": "outer::h1", "kind": "llvm", "structured": true }),
+        );
+        assert_eq!(
+            show["result"]["lines"],
+            json!([{ "text": "outer:" }, { "text": "\tret", "file": "/src/lib.rs", "line": 3 }])
+        );
+        let missing = call(
+            "cargo-show-asm/show",
+            json!({ "file": "/src/lib.rs", "line": 20 }),
+        );
+        assert_eq!(missing["result"], Value::Null);
+        assert_eq!(call("nope", Value::Null)["error"]["code"], -32601);
+        assert_eq!(
+            call("cargo-show-asm/items", json!({ "kind": "mir" }))["error"]["code"],
+            -32602
+        );
+        assert!(server.message(&json!({ "method": "exit" })).is_none());
+        assert!(server.exit);
+    }
+}
@@ -1,14 +1,29 @@
 use anyhow::Context;
-use cargo_metadata::{Artifact, Message, MetadataCommand};
+use cargo_metadata::MetadataCommand;
 use cargo_show_asm::{
     asm::{self, Item},
-    color, llvm, mir,
+    bloat, cache, clipboard, color, compare, config,
+    dump::{
+        byte_sizes, dump_file, dump_limited, dump_many, dump_to_dir, list_items, selects,
+        suggest_name, suggestions, Generated,
+    },
+    editor, elf,
+    exit::{self, Failure},
+    explain, layout, lsp,
     opts::{self, ToDump},
+    pager::{self, FlushOnDrop},
+    process,
+    select::{check_jobs, select_jobs},
+    serve,
+    session::{codegen_flags, sysroot, Session},
+    shim, snippet, statics, theme, tui, wrapper,
 };
+use std::cell::OnceCell;
 use std::collections::BTreeMap;
-use std::io::BufReader;
-use std::path::PathBuf;
-use std::process::Stdio;
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
 
 /// This should be called before calling any cli method or printing any output.
 fn reset_signal_pipe_handler() -> anyhow::Result<()> {
@@ -24,35 +39,58 @@ fn reset_signal_pipe_handler() -> anyhow::Result<()> {
     Ok(())
 }
 
-#[allow(clippy::too_many_lines)]
-fn main() -> anyhow::Result<()> {
-    reset_signal_pipe_handler()?;
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("Error: {err:?}");
+        std::process::exit(exit::code(&err));
+    }
+}
 
-    let opts = opts::options().run();
+fn run() -> anyhow::Result<()> {
+    if let Some(emit) = std::env::var_os(wrapper::EMIT_VAR) {
+        return wrapper::run(&emit);
+    }
+    reset_signal_pipe_handler()?;
+    process::install_interrupt_handler()?;
 
-    let cargo_path = std::env::var("CARGO").unwrap_or_else(|_| "cargo".into());
+    let user_config = config::Config::load_user()?;
+    let args = std::env::args_os().skip(1).collect::<Vec<_>>();
+    let mut opts = match user_config.expand_presets(&args)? {
+        Some(args) => match opts::options().run_inner(bpaf::Args::from(args.as_slice())) {
+            Ok(opts) => opts,
+            Err(bpaf::ParseFailure::Stdout(msg)) => {
+                print!("{msg}");
+                return Ok(());
+            }
+            Err(bpaf::ParseFailure::Stderr(msg)) => anyhow::bail!(msg),
+        },
+        None => opts::options().run(),
+    };
     let rustc_path = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".into());
-
-    let sysroot = {
-        let output = std::process::Command::new(&rustc_path)
-            .arg("--print=sysroot")
-            .stdin(Stdio::null())
-            .stderr(Stdio::inherit())
-            .stdout(Stdio::piped())
-            .output()?;
-        if !output.status.success() {
-            anyhow::bail!(
-                "Failed to get sysroot. '{} --print=sysroot' exited with {}",
-                rustc_path,
-                output.status,
-            );
-        }
-        // `rustc` prints a trailing newline.
-        PathBuf::from(std::str::from_utf8(&output.stdout)?.trim_end())
+    if let Some(path) = opts.file.clone() {
+        return view_file(opts, user_config, &rustc_path, &path);
+    }
+    if let Some((old, new)) = &opts.compare_bins {
+        set_colors(&opts, &user_config)?;
+        let filter = function_filter(&opts);
+        let mut out = open_output(&opts)?;
+        compare::report(old, new, filter, opts.format.full_name, &mut out)?;
+        return Ok(out.flush()?);
+    }
+    let code = match &opts.snippet {
+        Some(path) => Some(
+            std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read snippet {}", path.display()))?,
+        ),
+        None => opts.eval.clone(),
     };
-    if opts.format.verbosity > 0 {
-        eprintln!("Found sysroot: {}", sysroot.display());
+    if let Some(code) = code {
+        opts.manifest_path = snippet::create(&code)?;
+        // small functions in a library get no code of their own otherwise, they are left for
+        // the crates that use them to inline
+        opts.force_codegen = true;
     }
+    let cargo_path = std::env::var("CARGO").unwrap_or_else(|_| "cargo".into());
 
     let metadata = MetadataCommand::new()
         .cargo_path(&cargo_path)
@@ -60,157 +98,378 @@ fn main() -> anyhow::Result<()> {
         .no_deps()
         .exec()?;
 
-    let focus_package = match opts.package {
-        Some(name) => metadata
-            .packages
-            .iter()
-            .find(|p| p.name == name)
-            .with_context(|| format!("Package '{}' is not found", name))?,
-        None if metadata.packages.len() == 1 => &metadata.packages[0],
-        None => {
-            eprintln!(
-                "{:?} refers to multiple packages, you need to specify which one to use",
-                opts.manifest_path
-            );
-            for package in &metadata.packages {
-                eprintln!("\t-p {}", package.name);
-            }
-            anyhow::bail!("Multiple packages found")
-        }
-    };
+    let config = config::Config::load_project(&metadata, &opts.manifest_path)?.merge(user_config);
+    config.apply(&mut opts);
+    let color = set_colors(&opts, &config)?;
 
-    let focus_artifact = match opts.focus {
-        Some(focus) => focus,
-        None => match focus_package.targets.len() {
-            0 => anyhow::bail!("No targets found"),
-            1 => opts::Focus::try_from(&focus_package.targets[0])?,
-            _ => {
-                eprintln!(
-                    "{} defines multiple targets, you need to specify which one to use:",
-                    focus_package.name
-                );
-                for target in &focus_package.targets {
-                    if let Ok(focus) = opts::Focus::try_from(target) {
-                        eprintln!("\t{}", focus.as_cargo_args().collect::<Vec<_>>().join(" "));
-                    }
-                }
-                anyhow::bail!("Multiple targets found")
-            }
-        },
+    let session = Session::new(&opts, &metadata, cargo_path, &rustc_path, color)?;
+    let dependencies = OnceCell::new();
+    let jobs = select_jobs(&opts, &metadata, &dependencies, &session.cargo_path)?;
+    check_jobs(&opts, &jobs)?;
+
+    if opts.print_command {
+        return session.print_commands(&jobs);
+    }
+    if opts.emit_all() {
+        return interactive(&session, &jobs);
+    }
+
+    let mut out = open_output(&opts)?;
+
+    if opts.frame_pointers && !matches!(opts.syntax(), opts::Syntax::Mir) {
+        let comment = opts.syntax().comment();
+        writeln!(
+            out,
+            "{comment} Generated with -C force-frame-pointers=yes: functions set up a frame"
+        )?;
+        writeln!(
+            out,
+            "{comment} pointer in the prologue and address the stack relative to it, optimized"
+        )?;
+        writeln!(
+            out,
+            "{comment} builds usually omit it and use the register for other purposes."
+        )?;
+    }
+
+    if opts.statics || opts.vtable.is_some() {
+        return dump_statics(&session, &jobs, &mut out);
+    }
+
+    anyhow::ensure!(
+        opts.format.addr.is_none() || jobs.len() == 1,
+        "--addr works with a single binary, select one with --bin, --example or --test"
+    );
+    if jobs.len() > 1 || opts.output_dir.is_some() {
+        return dump_jobs(&session, jobs, &mut out);
+    }
+
+    let (package, focus) = &jobs[0];
+    let (mut asm_path, mut index, mut cache_key) = session.produce(package, focus)?;
+    let instance = match &opts.to_dump {
+        ToDump::Function {
+            function: Some(function),
+            ..
+        } if opts.force_codegen && shim::is_instance(function) => Some(function.as_str()),
+        _ => None,
     };
+    if let Some(function) = instance {
+        anyhow::ensure!(
+            !matches!(opts.syntax(), opts::Syntax::Mir),
+            "MIR of generic functions doesn't depend on the types, drop them from the name"
+        );
+        // rustc is called directly here, so the rustflags cargo would add go last the same way
+        let mut flags = codegen_flags(&opts, session.x86);
+        flags.extend(session.rustflags.iter().map(OsString::from));
+        asm_path = shim::build(
+            &asm_path,
+            function,
+            opts.syntax().emit(),
+            session.target.as_deref(),
+            &session.cargo_config.opt_level(
+                opts.compile_mode.profile_name(),
+                session.manifest_profiles.as_ref(),
+            ),
+            package.edition.as_str(),
+            &flags,
+        )?;
+        index = None;
+        cache_key = None;
+    }
+    if opts.format.verbosity > 0 {
+        eprintln!("Asm file: {}", asm_path.display());
+    }
 
-    let mut cargo_child = {
-        use std::ffi::OsStr;
-
-        let mut cmd = std::process::Command::new(&cargo_path);
-
-        // Cargo flags.
-        cmd.arg("rustc")
-            // General.
-            .args([
-                "--message-format=json",
-                "--color",
-                if opts.format.color { "always" } else { "never" },
-            ])
-            .args(std::iter::repeat("-v").take(opts.format.verbosity))
-            // Workspace location.
-            .arg("--manifest-path")
-            .arg(opts.manifest_path)
-            // Artifact selectors.
-            .args(["--package", &focus_package.name])
-            .args(focus_artifact.as_cargo_args())
-            // Compile options.
-            .args(opts.dry.then_some("--dry"))
-            .args(opts.frozen.then_some("--frozen"))
-            .args(opts.locked.then_some("--locked"))
-            .args(opts.offline.then_some("--offline"))
-            .args(opts.target.iter().flat_map(|t| ["--target", t]))
-            .args(
-                opts.target_dir
-                    .iter()
-                    .flat_map(|t| [OsStr::new("--target-dir"), t.as_ref()]),
-            )
-            .args(
-                opts.cli_features
-                    .no_default_features
-                    .then_some("--no-default-features"),
-            )
-            .args(opts.cli_features.all_features.then_some("--all-features"))
-            .args(
-                opts.cli_features
-                    .features
-                    .iter()
-                    .flat_map(|feat| ["--features", feat]),
-            );
-        match opts.compile_mode {
-            opts::CompileMode::Dev => {}
-            opts::CompileMode::Release => {
-                cmd.arg("--release");
-            }
-            opts::CompileMode::Custom(profile) => {
-                cmd.args(["--profile", &profile]);
-            }
-        }
+    if opts.bloat {
+        return report_bloat(&opts, &session.sysroot, &asm_path, index, &mut out);
+    }
+    if opts.layout {
+        return report_layout(&opts, &asm_path, &mut out);
+    }
+    if let Some(section) = &opts.section {
+        return dump_section(&opts, &session.sysroot, &asm_path, index, section, &mut out);
+    }
+    if let Some(n) = opts.hottest {
+        return dump_hottest(&opts, &session.sysroot, &asm_path, index, n, &mut out);
+    }
+    dump_goal(&session, &asm_path, index, cache_key, instance, &mut out)
+}
 
-        // Cargo flags terminator.
-        cmd.arg("--");
-
-        // Rustc flags.
-        // We care about asm.
-        cmd.args(["--emit", opts.syntax.emit()])
-            // So only one file gets created.
-            .arg("-Ccodegen-units=1")
-            // Debug info is needed to map to rust source.
-            .arg("-Cdebuginfo=2")
-            .args(opts.syntax.format().iter().flat_map(|s| ["-C", s]))
-            .args(
-                opts.target_cpu
-                    .iter()
-                    .map(|cpu| format!("-Ctarget-cpu={}", cpu)),
-            );
+/// FUNCTION from the command line, empty if there's none
+fn function_filter(opts: &opts::Options) -> &str {
+    match &opts.to_dump {
+        ToDump::Function {
+            function: Some(function),
+            ..
+        } => function.as_str(),
+        _ => "",
+    }
+}
 
-        cmd.stdin(Stdio::null())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::inherit())
-            .spawn()?
+/// `--tui`, `--serve` and `--lsp`
+fn interactive(
+    session: &Session,
+    jobs: &[(&cargo_metadata::Package, opts::Focus)],
+) -> anyhow::Result<()> {
+    let opts = session.opts;
+    if jobs.len() > 1 {
+        anyhow::bail!(
+            "--tui, --serve and --lsp work with a single target, select one with --lib, --bin, etc."
+        );
+    }
+    let (package, focus) = &jobs[0];
+    let build = || {
+        session
+            .produce(package, focus)
+            .map(|(asm_path, _, _)| asm_path)
     };
+    let mut outputs = Outputs::new(opts, &session.sysroot, &build)?;
+    let syntaxes = outputs
+        .files
+        .iter()
+        .map(|(syntax, _)| *syntax)
+        .collect::<Vec<_>>();
+    if opts.lsp {
+        // stdout is reserved for the protocol and editors want plain text
+        owo_colors::set_override(false);
+        return lsp::run(&mut outputs, &syntaxes, opts.format.clone());
+    }
+    if let Some(addr) = opts.serve {
+        // browser gets plain text
+        owo_colors::set_override(false);
+        return serve::run(&mut outputs, &syntaxes, opts.format.clone(), addr);
+    }
+    let filter = function_filter(opts);
+    owo_colors::set_override(opts.format.color != Some(false));
+    tui::run(&mut outputs, &syntaxes, opts.format.clone(), filter)
+}
 
-    let mut result_artifact = None;
-    let mut success = false;
-    for msg in Message::parse_stream(BufReader::new(cargo_child.stdout.take().unwrap())) {
-        match msg? {
-            Message::CompilerArtifact(artifact) if focus_artifact.matches_artifact(&artifact) => {
-                result_artifact = Some(artifact);
-            }
-            Message::BuildFinished(fin) => {
-                success = fin.success;
-                break;
+/// `--statics` and `--vtable`
+fn dump_statics(
+    session: &Session,
+    jobs: &[(&cargo_metadata::Package, opts::Focus)],
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    let (opts, sysroot) = (session.opts, session.sysroot.as_path());
+    anyhow::ensure!(
+        jobs.len() == 1,
+        "--statics and --vtable work with a single target, select one with --lib, --bin, etc."
+    );
+    let (package, focus) = &jobs[0];
+    let (asm_path, _, _) = session.produce(package, focus)?;
+    if let Some(vtable) = &opts.vtable {
+        let mut list = suggestions(vtable);
+        let methods = statics::dump_vtable(&asm_path, vtable, out, &mut list)?;
+        for method in methods.iter().filter(|_| opts.methods) {
+            writeln!(out)?;
+            let goal = Some((method.as_str(), 0));
+            if !dump_file(opts, sysroot, goal, &asm_path, out, &mut Vec::new())? {
+                let note = format!("// {method} is not in the code of this crate");
+                writeln!(out, "{}", color!(note, comment))?;
             }
-            Message::CompilerMessage(msg) => {
-                eprintln!("{}", msg);
-            }
-            _ => {}
         }
+        return Ok(out.flush()?);
     }
-    if !success {
-        let status = cargo_child.wait()?;
-        eprintln!("Cargo failed with {}", status);
-        std::process::exit(101);
+    let (filter, nth) = match &opts.to_dump {
+        ToDump::Everything => ("", 0),
+        ToDump::Function { function, nth } => (function.as_deref().unwrap_or(""), *nth),
+    };
+    let mut list = suggestions(filter);
+    statics::dump(&asm_path, filter, nth, &opts.format, out, &mut list)
+}
+
+/// Several targets at once or `--output-dir`
+fn dump_jobs(
+    session: &Session,
+    jobs: Vec<(&cargo_metadata::Package, opts::Focus)>,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    let (opts, sysroot) = (session.opts, session.sysroot.as_path());
+    // cargo builds one target at a time, each file is parsed while the next one builds
+    let generated = std::thread::scope(|scope| {
+        let mut parsing = Vec::new();
+        for (package, focus) in jobs {
+            let (asm_path, index, cache_key) = session.produce(package, &focus)?;
+            if opts.format.verbosity > 0 {
+                eprintln!("Asm file: {}", asm_path.display());
+            }
+            let cache_dir = &session.cache_dir;
+            let path = asm_path.clone();
+            let index = scope.spawn(move || -> anyhow::Result<Vec<Item>> {
+                if let Some(index) = index {
+                    return Ok(index);
+                }
+                let index = list_items(opts, sysroot, &path)?;
+                let entry = cache::Entry {
+                    asm_path: path,
+                    index: Some(index.clone()),
+                };
+                if let Some(cache_key) = cache_key {
+                    cache::store(cache_dir, cache_key, &entry)?;
+                }
+                Ok(index)
+            });
+            parsing.push((package, focus, asm_path, index));
+        }
+        parsing
+            .into_iter()
+            .map(|(package, focus, asm_path, index)| {
+                Ok(Generated {
+                    package,
+                    focus,
+                    asm_path,
+                    index: index.join().expect("parsing doesn't panic")?,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()
+    })?;
+    if let Some(dir) = &opts.output_dir {
+        return dump_to_dir(opts, sysroot, dir, &generated);
     }
-    let artifact = result_artifact.context("No artifact found")?;
+    let sized = byte_sizes(session.target.as_deref()).is_some();
+    let root = session.metadata.workspace_root.as_std_path();
+    dump_many(opts, sysroot, root, &generated, sized, out)
+}
 
-    if opts.format.verbosity > 0 {
-        eprintln!("Artifact files: {:?}", artifact.filenames);
+/// `--bloat`
+fn report_bloat(
+    opts: &opts::Options,
+    sysroot: &Path,
+    asm_path: &Path,
+    index: Option<Vec<Item>>,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    let items = match index {
+        Some(index) => index,
+        None => list_items(opts, sysroot, asm_path)?,
+    };
+    let prefix = function_filter(opts);
+    bloat::report(&items, &elf::sizes_near(asm_path), prefix, out)?;
+    Ok(out.flush()?)
+}
+
+/// `--layout`
+fn report_layout(opts: &opts::Options, asm_path: &Path, out: &mut dyn Write) -> anyhow::Result<()> {
+    let binary = elf::object_near(asm_path).context(
+        "No binary next to the assembly, --layout works with --bin, --example or --test",
+    )?;
+    let filter = function_filter(opts);
+    let symbols = elf::functions(&binary)?;
+    layout::report(&symbols, filter, opts.format.full_name, out)?;
+    Ok(out.flush()?)
+}
+
+/// `--section`
+fn dump_section(
+    opts: &opts::Options,
+    sysroot: &Path,
+    asm_path: &Path,
+    index: Option<Vec<Item>>,
+    section: &str,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    let mut items = match index {
+        Some(index) => index,
+        None => list_items(opts, sysroot, asm_path)?,
+    };
+    let filter = function_filter(opts);
+    items.retain(|item| item.linkage.section == *section && selects(opts, item, filter));
+    anyhow::ensure!(
+        !items.is_empty(),
+        Failure::not_found(format!("No functions in section {section}"))
+    );
+    for (ix, item) in items.iter().enumerate() {
+        if ix > 0 {
+            writeln!(out)?;
+        }
+        let goal = Some((item.name.as_str(), item.index));
+        dump_limited(opts, sysroot, goal, asm_path, out, &mut Vec::new())?;
     }
+    Ok(out.flush()?)
+}
 
-    let asm_path = locate_asm_path_via_artifact(&artifact, opts.syntax.ext())?;
-    if opts.format.verbosity > 0 {
-        eprintln!("Asm file: {}", asm_path.display());
+/// `--hottest`
+fn dump_hottest(
+    opts: &opts::Options,
+    sysroot: &Path,
+    asm_path: &Path,
+    index: Option<Vec<Item>>,
+    n: usize,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    let mut items = match index {
+        Some(index) => index,
+        None => list_items(opts, sysroot, asm_path)?,
+    };
+    if let ToDump::Function {
+        function: Some(function),
+        ..
+    } = &opts.to_dump
+    {
+        items.retain(|item| selects(opts, item, function));
+    }
+    let hot = asm::hottest(&opts.format, &items, n)?;
+    anyhow::ensure!(
+        !hot.is_empty(),
+        Failure::not_found("None of the functions of this crate are in the profile")
+    );
+    for (ix, (item, _)) in hot.iter().enumerate() {
+        if ix > 0 {
+            writeln!(out)?;
+        }
+        let goal = Some((item.name.as_str(), item.index));
+        dump_limited(opts, sysroot, goal, asm_path, out, &mut Vec::new())?;
     }
+    Ok(out.flush()?)
+}
 
+/// Print the requested function, or the whole file, from the single target. `instance` is set
+/// when the code comes from a shim for a generic function
+fn dump_goal(
+    session: &Session,
+    asm_path: &Path,
+    mut index: Option<Vec<Item>>,
+    cache_key: Option<u64>,
+    instance: Option<&str>,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    let (opts, sysroot) = (session.opts, session.sysroot.as_path());
+    // the code of an async fn is in the state machine it returns
+    let state_machine;
     let mut target_function = match &opts.to_dump {
         ToDump::Everything => None,
-        ToDump::Function { function, nth } => Some((function.as_deref().unwrap_or(""), *nth)),
+        ToDump::Function { function, nth } => {
+            let function = function.as_deref().unwrap_or("");
+            let function = instance.map_or(function, shim::item_name);
+            if opts.format.async_fn && !function.is_empty() {
+                state_machine = match opts.syntax() {
+                    opts::Syntax::Mir => format!("{function}::{{closure#0}}"),
+                    _ => format!("{function}::{{{{closure}}}}"),
+                };
+                Some((state_machine.as_str(), *nth))
+            } else {
+                Some((function, *nth))
+            }
+        }
+    };
+
+    let by_address;
+    if let Some(addr) = opts.format.addr {
+        by_address = asm::function_at(asm_path, addr)?;
+        target_function = Some((by_address.as_str(), 0));
+    }
+
+    // sizes of the functions and the items they belong to, the latter is needed to pick the
+    // right one out of several functions with the same name
+    let flash = match (opts.syntax(), byte_sizes(session.target.as_deref())) {
+        (opts::Syntax::Intel | opts::Syntax::Att, Some(unit)) => {
+            let items = match &index {
+                Some(index) => index.clone(),
+                None => list_items(opts, sysroot, asm_path)?,
+            };
+            Some((elf::sizes_near(asm_path), items, unit))
+        }
+        _ => None,
     };
 
     // this variable exists to deal with the case where there's only
@@ -220,24 +479,52 @@ fn main() -> anyhow::Result<()> {
     let mut seen;
 
     loop {
-        seen = match opts.syntax {
-            opts::Syntax::Intel | opts::Syntax::Att => asm::dump_function(
-                target_function,
-                &asm_path,
-                &sysroot,
-                &opts.format,
-                &mut existing,
-            ),
-            opts::Syntax::Llvm => {
-                llvm::dump_function(target_function, &asm_path, &opts.format, &mut existing)
+        // index knows about all the items, no need to parse the file if goal isn't there
+        let missing = match (&index, target_function) {
+            (Some(index), Some(goal)) => !index.iter().any(|item| item.matches(goal)),
+            _ => false,
+        };
+        seen = if missing {
+            existing = index.clone().unwrap_or_default();
+            false
+        } else {
+            if let (Some((sizes, items, unit)), Some(goal)) = (&flash, target_function) {
+                if let Some(size) = items
+                    .iter()
+                    .find(|item| item.matches(goal))
+                    .and_then(|item| sizes.get(&item.hashed))
+                {
+                    let note = format!("{} {size} {unit}", opts.syntax().comment());
+                    writeln!(out, "{}", color!(note, comment))?;
+                }
             }
-            opts::Syntax::Mir => {
-                mir::dump_function(target_function, &asm_path, &opts.format, &mut existing)
+            if let (opts::Syntax::Intel | opts::Syntax::Att, Some(goal), false) =
+                (opts.syntax(), target_function, opts.porcelain)
+            {
+                if let Some(cgu) = asm::codegen_unit(asm_path, goal)? {
+                    eprintln!("Note: the code comes from codegen unit {cgu}");
+                }
             }
-        }?;
+            dump_limited(opts, sysroot, target_function, asm_path, out, &mut existing)?
+        };
         if seen {
+            out.flush()?;
             return Ok(());
-        } else if existing.len() == 1 {
+        }
+        if index.is_none() {
+            // nothing was dumped so the whole file was scanned, remember what's in there
+            index = Some(existing.clone());
+            let entry = cache::Entry {
+                asm_path: asm_path.to_path_buf(),
+                index: index.clone(),
+            };
+            if let Some(cache_key) = cache_key {
+                cache::store(&session.cache_dir, cache_key, &entry)?;
+            }
+        }
+        let filter = target_function.map_or("", |goal| goal.0);
+        existing.retain(|item| selects(opts, item, filter));
+        if existing.len() == 1 {
             single_target = existing[0].name.clone();
             target_function = Some((&single_target, 0));
         } else {
@@ -246,111 +533,262 @@ fn main() -> anyhow::Result<()> {
     }
 
     if let (false, ToDump::Function { function, .. }) = (seen, &opts.to_dump) {
-        suggest_name(
-            function.as_deref().unwrap_or(""),
-            opts.format.full_name,
-            &existing,
-        )?;
+        let search = function.as_deref().unwrap_or("");
+        if let (true, false, opts::Syntax::Intel | opts::Syntax::Att | opts::Syntax::Llvm) =
+            (existing.is_empty(), search.is_empty(), opts.syntax())
+        {
+            let root = session.metadata.workspace_root.as_std_path();
+            if let Some(why) = explain::missing(asm_path, root, search).filter(|_| !opts.porcelain)
+            {
+                eprintln!("{why}");
+            }
+        }
+        let sizes = flash.map(|(sizes, _, _)| sizes).unwrap_or_default();
+        suggest_name(&mut suggestions(search), search, opts, &existing, &sizes)?;
     }
 
     Ok(())
 }
 
-fn locate_asm_path_via_artifact(artifact: &Artifact, expect_ext: &str) -> anyhow::Result<PathBuf> {
-    // For lib, test, bench, lib-type example, `filenames` hint the file stem of the asm file.
-    // We could locate asm files precisely.
-    //
-    // `filenames`:
-    // [..]/target/debug/deps/libfoo-01234567.rmeta         # lib by-product
-    // [..]/target/debug/deps/foo-01234567                  # test & bench
-    // [..]/target/debug/deps/example/libfoo-01234567.rmeta # lib-type example by-product
-    // Asm files:
-    // [..]/target/debug/deps/foo-01234567.s
-    // [..]/target/debug/deps/example/foo-01234567.s
-    if let Some(path) = artifact
-        .filenames
-        .iter()
-        .filter(|path| {
-            matches!(
-                path.parent().unwrap().file_name(),
-                Some("deps" | "examples")
-            )
-        })
-        .find_map(|path| {
-            let path = path.with_extension(expect_ext);
-            if path.exists() {
-                return Some(path);
+/// Set up the theme and decide if the output gets colors, gives the decision
+fn set_colors(opts: &opts::Options, config: &config::Config) -> anyhow::Result<bool> {
+    let mut theme = theme::Theme::by_name(opts.format.theme.as_deref().unwrap_or("dark"))?;
+    theme.customize(&config.colors)?;
+    theme::set(theme);
+
+    // a file gets plain text unless colors are explicitly requested
+    let color = !opts.porcelain
+        && !opts.format.quickfix
+        && opts.format.color.unwrap_or_else(|| {
+            opts.output.is_none()
+                && opts.output_dir.is_none()
+                && !opts.clip
+                && !opts.edit
+                && supports_color::on(supports_color::Stream::Stdout).is_some()
+        });
+    owo_colors::set_override(color);
+    Ok(color)
+}
+
+/// Where the output goes: a file, an editor, the clipboard, a pager or stdout
+fn open_output(opts: &opts::Options) -> anyhow::Result<Box<dyn Write>> {
+    Ok(match &opts.output {
+        Some(path) => {
+            let mut path = path.clone();
+            if path.extension().is_none() {
+                path.set_extension(opts.syntax().ext());
             }
-            let path = path.with_file_name(path.file_name()?.strip_prefix("lib")?);
-            if path.exists() {
-                return Some(path);
+            let file = File::create(&path)
+                .with_context(|| format!("Failed to create output file {}", path.display()))?;
+            if opts.format.verbosity > 0 {
+                eprintln!("Writing output to {}", path.display());
             }
-            None
-        })
-    {
-        return Ok(path.into_std_path_buf());
-    }
-
-    // For bin or bin-type example artifacts, `filenames` provide hard-linked paths
-    // without extra-filename.
-    // We scans all possible original artifacts by checking hard links,
-    // in order to retrieve the correct extra-filename, and then locate asm files.
-    //
-    // `filenames`, also `executable`:
-    // [..]/target/debug/foobin                    <+
-    // [..]/target/debug/examples/fooexample        | <+ Hard linked.
-    // Origins:                                     |  |
-    // [..]/target/debug/deps/foobin-01234567      <+  |
-    // [..]/target/debug/examples/fooexample-01234567 <+
-    // Asm files:
-    // [..]/target/debug/deps/foobin-01234567.s
-    // [..]/target/debug/examples/fooexample-01234567.s
-    if let Some(exe_path) = &artifact.executable {
-        let parent = exe_path.parent().unwrap();
-        let deps_dir = if parent.file_name() == Some("examples") {
-            parent.to_owned()
-        } else {
-            exe_path.with_file_name("deps")
-        };
+            Box::new(std::io::BufWriter::new(file))
+        }
+        None if opts.edit => Box::new(FlushOnDrop(editor::Editor::new(opts.syntax().ext()))),
+        None if opts.clip => Box::new(FlushOnDrop(clipboard::Clipboard::default())),
+        None if !opts.no_pager && !opts.porcelain && std::io::stdout().is_terminal() => {
+            match opts.to_dump {
+                ToDump::Everything => Box::new(FlushOnDrop(pager::Pager::streaming())),
+                ToDump::Function { .. } => Box::new(FlushOnDrop(pager::Pager::default())),
+            }
+        }
+        None => Box::new(std::io::stdout().lock()),
+    })
+}
 
-        for entry in deps_dir.read_dir()? {
-            let maybe_origin = entry?.path();
-            if same_file::is_same_file(&exe_path, &maybe_origin)? {
-                let asm_file = maybe_origin.with_extension(expect_ext);
-                if asm_file.exists() {
-                    return Ok(asm_file);
-                }
+/// Show `path` made by something other than this tool, cargo is not involved and the kind of
+/// output comes from the extension
+fn view_file(
+    mut opts: opts::Options,
+    config: config::Config,
+    rustc_path: &str,
+    path: &Path,
+) -> anyhow::Result<()> {
+    let syntax = match path.extension().and_then(OsStr::to_str) {
+        Some("s" | "S" | "asm") => match opts.syntax {
+            Some(opts::Syntax::Att) => opts::Syntax::Att,
+            _ => opts::Syntax::Intel,
+        },
+        Some("ll") => opts::Syntax::Llvm,
+        Some("mir") => opts::Syntax::Mir,
+        _ => anyhow::bail!(
+            "Can't tell what {} is, --file takes .s, .ll and .mir files",
+            path.display()
+        ),
+    };
+    if let Some(given) = &opts.syntax {
+        let asm = |syntax: &opts::Syntax| matches!(syntax, opts::Syntax::Intel | opts::Syntax::Att);
+        anyhow::ensure!(
+            given == &syntax || (asm(given) && asm(&syntax)),
+            "{} is a .{} file, drop --{} or pass a different file",
+            path.display(),
+            syntax.ext(),
+            match given {
+                opts::Syntax::Intel => "intel",
+                opts::Syntax::Att => "att",
+                opts::Syntax::Llvm => "llvm",
+                opts::Syntax::Mir => "mir",
             }
+        );
+    }
+    opts.syntax = Some(syntax);
+    if opts.emit_all() || opts.output_dir.is_some() || opts.print_command {
+        anyhow::bail!(
+            "--file can't be combined with --tui, --serve, --lsp, --output-dir or --print-command"
+        );
+    }
+    anyhow::ensure!(
+        path.exists(),
+        Failure::not_found(format!("{} doesn't exist", path.display()))
+    );
+    config.apply(&mut opts);
+    set_colors(&opts, &config)?;
+    // only needed to find standard library sources for --rust, the file can come from a
+    // machine without rust
+    let sysroot = if opts.format.rust {
+        sysroot(rustc_path, &opts).unwrap_or_default()
+    } else {
+        PathBuf::new()
+    };
+
+    let mut out = open_output(&opts)?;
+    let (filter, nth) = match &opts.to_dump {
+        ToDump::Everything => (None, 0),
+        ToDump::Function { function, nth } => (Some(function.as_deref().unwrap_or("")), *nth),
+    };
+    let asm = matches!(opts.syntax(), opts::Syntax::Intel | opts::Syntax::Att);
+    if opts.statics || opts.vtable.is_some() {
+        anyhow::ensure!(asm, "--statics and --vtable work with assembly only");
+        if let Some(vtable) = &opts.vtable {
+            statics::dump_vtable(path, vtable, &mut out, &mut suggestions(vtable))?;
+            return Ok(out.flush()?);
         }
+        let filter = filter.unwrap_or("");
+        let mut list = suggestions(filter);
+        return statics::dump(path, filter, nth, &opts.format, &mut out, &mut list);
+    }
+    let sizes = if asm {
+        elf::sizes_near(path)
+    } else {
+        BTreeMap::new()
+    };
+    if opts.bloat {
+        let items = list_items(&opts, &sysroot, path)?;
+        bloat::report(&items, &sizes, filter.unwrap_or(""), &mut out)?;
+        return Ok(out.flush()?);
     }
 
-    anyhow::bail!("Cannot locate the path to the asm file");
+    let mut items = Vec::new();
+    let goal = filter.map(|filter| (filter, nth));
+    if dump_limited(&opts, &sysroot, goal, path, &mut out, &mut items)? {
+        return Ok(out.flush()?);
+    }
+    let filter = filter.unwrap_or("");
+    items.retain(|item| selects(&opts, item, filter));
+    if let [item] = items.as_slice() {
+        let goal = Some((item.name.as_str(), 0));
+        if dump_limited(&opts, &sysroot, goal, path, &mut out, &mut Vec::new())? {
+            return Ok(out.flush()?);
+        }
+    }
+    suggest_name(&mut suggestions(filter), filter, &opts, &items, &sizes)
 }
 
-fn suggest_name(search: &str, full: bool, items: &[Item]) -> anyhow::Result<()> {
-    let names = items.iter().fold(BTreeMap::new(), |mut m, item| {
-        m.entry(if full { &item.hashed } else { &item.name })
-            .or_insert_with(Vec::new)
-            .push(item.len);
-        m
-    });
-
-    if names.is_empty() {
-        #[allow(clippy::redundant_else)]
-        if search.is_empty() {
-            anyhow::bail!("This target defines no functions")
-        } else {
-            anyhow::bail!("No matching functions, try relaxing your search request")
+/// Generated files for every kind of output `--tui`, `--serve` and `--lsp` can switch between
+struct Outputs<'a> {
+    opts: &'a opts::Options,
+    sysroot: &'a Path,
+    build: &'a dyn Fn() -> anyhow::Result<PathBuf>,
+    /// the one requested on the command line goes first
+    files: Vec<(opts::Syntax, PathBuf)>,
+}
+
+impl<'a> Outputs<'a> {
+    fn new(
+        opts: &'a opts::Options,
+        sysroot: &'a Path,
+        build: &'a dyn Fn() -> anyhow::Result<PathBuf>,
+    ) -> anyhow::Result<Self> {
+        let mut outputs = Self {
+            opts,
+            sysroot,
+            build,
+            files: Vec::new(),
+        };
+        outputs.files = outputs.locate()?;
+        Ok(outputs)
+    }
+
+    /// Produce the files, the extension is all that's different between them
+    fn locate(&self) -> anyhow::Result<Vec<(opts::Syntax, PathBuf)>> {
+        let asm_path = (self.build)()?;
+        let asm = match self.opts.syntax() {
+            opts::Syntax::Att => opts::Syntax::Att,
+            _ => opts::Syntax::Intel,
+        };
+        let mut files = [asm, opts::Syntax::Llvm, opts::Syntax::Mir]
+            .into_iter()
+            .map(|syntax| (syntax, asm_path.with_extension(syntax.ext())))
+            .filter(|(_, path)| path.exists())
+            .collect::<Vec<_>>();
+        if let Some(ix) = files
+            .iter()
+            .position(|(syntax, _)| syntax == self.opts.syntax())
+        {
+            files.rotate_left(ix);
         }
+        Ok(files)
     }
-    println!("Try one of those");
-    for (name, lens) in &names {
-        println!(
-            "{:?} {:?}",
-            color!(name, owo_colors::OwoColorize::green),
-            color!(lens, owo_colors::OwoColorize::cyan)
-        );
+
+    fn get(
+        &self,
+        syntax: opts::Syntax,
+        fmt: &opts::Format,
+    ) -> anyhow::Result<(opts::Options, &Path)> {
+        let mut opts = self.opts.clone();
+        opts.syntax = Some(syntax);
+        opts.format = fmt.clone();
+        let path = self
+            .files
+            .iter()
+            .find(|(s, _)| *s == syntax)
+            .map(|(_, path)| path.as_path())
+            .with_context(|| format!("No {} file after rebuilding", syntax.ext()))?;
+        Ok((opts, path))
+    }
+}
+
+impl tui::Backend for Outputs<'_> {
+    fn items(&mut self, syntax: opts::Syntax) -> anyhow::Result<Vec<Item>> {
+        let (opts, path) = self.get(syntax, &self.opts.format)?;
+        list_items(&opts, self.sysroot, path)
+    }
+
+    fn render(
+        &mut self,
+        syntax: opts::Syntax,
+        fmt: &opts::Format,
+        item: &Item,
+    ) -> anyhow::Result<Vec<u8>> {
+        let (opts, path) = self.get(syntax, fmt)?;
+        let mut buf = Vec::new();
+        let goal = Some((item.name.as_str(), item.index));
+        dump_file(&opts, self.sysroot, goal, path, &mut buf, &mut Vec::new())?;
+        Ok(buf)
     }
 
-    std::process::exit(1);
+    fn rebuild(&mut self) -> anyhow::Result<()> {
+        self.files = self.locate()?;
+        Ok(())
+    }
+
+    fn locations(&mut self) -> anyhow::Result<asm::Locations> {
+        let (_, path) = self
+            .files
+            .iter()
+            .find(|(syntax, _)| matches!(syntax, opts::Syntax::Intel | opts::Syntax::Att))
+            .context("No assembly file after rebuilding")?;
+        asm::locations(path)
+    }
 }
@@ -1,27 +1,169 @@
-use crate::{color, llvm::Item, opts::Format};
+use crate::{
+    asm::{hyperlink, Linkage},
+    color,
+    llvm::Item,
+    opts::Format,
+};
+use once_cell::sync::Lazy;
 use owo_colors::OwoColorize;
-use regex::Regex;
+use regex::{Captures, Regex};
 use std::{
+    borrow::Cow,
     collections::BTreeMap,
     fs::File,
-    io::{BufRead, BufReader},
-    path::Path,
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
 };
 
+/// Modules in front of a type: `std::ptr::NonNull<u8>`
+static MODULES: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\b(?:[a-z_][a-z0-9_]*::)+([A-Z])").expect("regexp should be valid"));
+/// Where a closure or a coroutine is defined: `{closure@src/lib.rs:2:43: 2:46}`
+static SPANS: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"\{(closure|coroutine|coroutine-closure)@([^{}@: ]+\.rs):(\d+):(\d+): \d+:\d+\}")
+        .expect("regexp should be valid")
+});
+
+/// Shorter references to other items: types without the modules they are in and closures
+/// with where they start, linked to the source. `--full-name` keeps them as they are
+fn shorten<'a>(
+    code: &'a str,
+    path: &Path,
+    fmt: &Format,
+    files: &mut BTreeMap<String, Option<PathBuf>>,
+) -> Cow<'a, str> {
+    if fmt.full_name {
+        return Cow::Borrowed(code);
+    }
+    let code = MODULES.replace_all(code, "$1");
+    let spans = SPANS.replace_all(&code, |caps: &Captures| {
+        let (kind, file, line, col) = (&caps[1], &caps[2], &caps[3], &caps[4]);
+        let text = format!("{file}:{line}:{col}");
+        // spans are relative to the workspace, the MIR file is somewhere inside of it
+        let full = files.entry(file.to_owned()).or_insert_with(|| {
+            path.ancestors()
+                .map(|dir| dir.join(file))
+                .find(|full| full.exists())
+        });
+        let text = match (full, line.parse()) {
+            (Some(full), Ok(line)) => text
+                .if_supports_color(owo_colors::Stream::Stdout, |text| {
+                    hyperlink(fmt, full, line, text)
+                })
+                .to_string(),
+            _ => text,
+        };
+        format!("{{{kind}@{text}}}")
+    });
+    Cow::Owned(spans.into_owned())
+}
+
 #[derive(Debug)]
 enum State {
     Skipping,
     Body,
+    /// after the goal, looking for constants promoted out of it
+    Promoted,
+}
+
+/// What a MIR statement in the state machine of an async fn does with its state: states from
+/// 3 up are the await points in order, the variant for one holds what is live across it
+fn state_note(line: &str) -> Option<String> {
+    let line = line.trim_start();
+    if line.starts_with("discriminant(") {
+        let state = line.rsplit_once(" = ")?.1.strip_suffix(';')?;
+        let point = state
+            .parse::<usize>()
+            .ok()?
+            .checked_sub(2)
+            .filter(|p| *p > 0)?;
+        Some(format!("suspends at await point {point}"))
+    } else if line.starts_with("debug __awaitee => ") {
+        let (_, variant) = line.split_once("as variant#")?;
+        let state = variant.split(')').next()?;
+        let point = state
+            .parse::<usize>()
+            .ok()?
+            .checked_sub(2)
+            .filter(|p| *p > 0)?;
+        Some(format!("awaited at await point {point}"))
+    } else {
+        None
+    }
+}
+
+/// What starts at a line that is not indented
+#[derive(Debug)]
+enum Header<'a> {
+    /// a function, `name(args) -> ret`
+    Fn(&'a str),
+    /// code that computes a constant or a static, `const NAME: Type`
+    Const(&'a str),
+    /// constant promoted out of function `owner`
+    Promoted { owner: &'a str },
+}
+
+fn header(line: &str) -> Option<Header<'_>> {
+    if let Some(name) = line.strip_prefix("fn ").and_then(|l| l.strip_suffix(" {")) {
+        return Some(Header::Fn(name));
+    }
+    let item = line.strip_suffix(" = {")?;
+    if let Some((owner, _)) = item
+        .strip_prefix("const ")
+        .and_then(|name| name.split_once("::promoted["))
+    {
+        Some(Header::Promoted { owner })
+    } else if item.starts_with("const ") || item.starts_with("static ") {
+        Some(Header::Const(item))
+    } else {
+        None
+    }
+}
+
+/// Name of a function without the signature
+fn base_name(name: &str) -> &str {
+    name.split_once('(').map_or(name, |(base, _)| base)
 }
 
-/// try to print `goal` from `path`, collect available items overwise
+/// Path of an item as rustc prints it, without the signature or the type. rustc trims paths
+/// to the shortest unambiguous form: `b(_1: u64) -> u64` is `b` even when it is `my_crate::m::b`
+fn item_path(name: &str) -> &str {
+    let name = name.strip_prefix("const fn ").unwrap_or(name);
+    match name
+        .strip_prefix("const ")
+        .or_else(|| name.strip_prefix("static "))
+    {
+        Some(item) => item.split_once(": ").map_or(item, |(path, _)| path),
+        None => base_name(name),
+    }
+}
+
+/// Check if `filter` from the command line selects the item `name`: either it is a part of the
+/// name or a full path such as `my_crate::m::b` that ends with the trimmed path of the item
+#[must_use]
+pub fn matches(name: &str, filter: &str) -> bool {
+    if name.contains(filter) {
+        return true;
+    }
+    let path = item_path(name);
+    filter
+        .strip_suffix(path)
+        .is_some_and(|rest| rest.ends_with("::"))
+}
+
+/// try to print `goal` from `path`, collect all available items overwise
+///
+/// Functions come with the constants promoted out of them right after, `--promoted` prints
+/// those too. MIR for compile time evaluation is a separate copy marked with a comment, it
+/// is only listed with `--const-eval` along with constants and statics
 ///
 /// # Errors
 /// anyhow handles all the possible issues
 pub fn dump_function(
     goal: Option<(&str, usize)>,
     path: &Path,
-    _fmt: &Format,
+    fmt: &Format,
+    out: &mut dyn Write,
     items: &mut Vec<Item>,
 ) -> anyhow::Result<bool> {
     let mut seen = false;
@@ -29,7 +171,11 @@ pub fn dump_function(
     let mut current_item = None::<Item>;
     let mut names = BTreeMap::new();
     let mut state = State::Skipping;
-    let regex = Regex::new("^fn ([^{]+) \\{$")?;
+    // function the goal is, promoted constants from it are printed after it
+    let mut owner = None::<String>;
+
+    // sources closures refer to, by the path in the MIR
+    let mut files = BTreeMap::new();
 
     let mut block_start = None;
     let mut prefix = Vec::new();
@@ -37,42 +183,79 @@ pub fn dump_function(
     for (ix, line) in reader.lines().enumerate() {
         let line = line?;
         match state {
-            State::Skipping => {
+            State::Skipping | State::Promoted => {
+                let head = header(&line);
+                if let State::Promoted = state {
+                    match head {
+                        Some(Header::Promoted { owner: of }) if owner.as_deref() == Some(of) => {
+                            writeln!(out)?;
+                            writeln!(out, "{line}")?;
+                            state = State::Body;
+                            continue;
+                        }
+                        Some(_) => return Ok(true),
+                        None => continue,
+                    }
+                }
                 if line.starts_with("//") {
                     if block_start.is_none() {
                         block_start = Some(ix);
                         prefix.push(line);
                     }
-                } else if let Some(name) = regex.captures(&line).and_then(|c| c.get(1)) {
-                    state = State::Body;
-
-                    let name = name.as_str().to_owned();
-                    let name_entry = names.entry(name.clone()).or_insert(0);
-                    let hashed = format!("{name}:{name_entry}");
-                    seen = goal.map_or(true, |goal| {
-                        (name.as_ref(), *name_entry) == goal || hashed == goal.0
-                    });
-                    current_item = Some(Item {
-                        index: *name_entry,
-                        len: block_start.take().unwrap_or(ix),
-                        name,
-                        hashed,
-                    });
-                    *name_entry += 1;
-                    prefix.push(line);
-                } else {
-                    prefix.clear();
+                    continue;
+                }
+                let ctfe = prefix.iter().any(|p| p == "// MIR FOR CTFE");
+                let name = match head {
+                    Some(Header::Fn(name)) if ctfe && fmt.const_eval => format!("const fn {name}"),
+                    Some(Header::Fn(name)) if !ctfe => name.to_owned(),
+                    Some(Header::Const(name)) if fmt.const_eval => name.to_owned(),
+                    Some(_) => {
+                        block_start = None;
+                        prefix.clear();
+                        continue;
+                    }
+                    None => {
+                        prefix.clear();
+                        continue;
+                    }
+                };
+                state = State::Body;
+
+                let name_entry = names.entry(name.clone()).or_insert(0);
+                let hashed = format!("{name}:{name_entry}");
+                seen = goal
+                    .is_none_or(|goal| (name.as_ref(), *name_entry) == goal || hashed == goal.0);
+                if seen && fmt.promoted {
+                    if let Some(Header::Fn(name)) = head {
+                        owner = Some(base_name(name).to_owned());
+                    }
                 }
+                current_item = Some(Item {
+                    index: *name_entry,
+                    len: block_start.take().unwrap_or(ix),
+                    linkage: Linkage::default(),
+                    name,
+                    hashed,
+                });
+                *name_entry += 1;
+                prefix.push(line);
             }
             State::Body => {
                 if seen {
                     for p in prefix.drain(..) {
-                        println!("{p}");
+                        writeln!(out, "{p}")?;
                     }
-                    if let Some(ix) = line.rfind("//") {
-                        println!("{}{}", &line[..ix], color!(&line[ix..], OwoColorize::cyan));
+                    let note = fmt.async_fn.then(|| state_note(&line)).flatten();
+                    let shown = match note {
+                        Some(note) => Cow::Owned(format!("{line} // {note}")),
+                        None => Cow::Borrowed(&line),
+                    };
+                    let (code, comment) = shown.split_at(shown.rfind("//").unwrap_or(shown.len()));
+                    let code = shorten(code, path, fmt, &mut files);
+                    if comment.is_empty() {
+                        writeln!(out, "{code}")?;
                     } else {
-                        println!("{line}");
+                        writeln!(out, "{code}{}", color!(comment, comment))?;
                     }
                 }
 
@@ -80,18 +263,146 @@ pub fn dump_function(
                     state = State::Skipping;
                     if let Some(mut cur) = current_item.take() {
                         cur.len = ix - cur.len;
-                        if goal.map_or(true, |goal| goal.0.is_empty() || cur.name.contains(goal.0))
-                        {
-                            items.push(cur);
-                        }
+                        items.push(cur);
                     }
                     if seen {
-                        return Ok(true);
+                        if owner.is_none() {
+                            return Ok(true);
+                        }
+                        state = State::Promoted;
                     }
                 }
             }
         }
     }
 
+    // listing shouldn't depend on the order functions end up in the file
+    items.sort();
     Ok(seen)
 }
+
+#[cfg(test)]
+mod test {
+    use super::{dump_function, matches};
+    use crate::test_util::dump_file;
+
+    const MIR: &str = "// WARNING: This output format is intended for human consumers only
+fn table(_1: usize) -> u8 {
+    let mut _4: &[u8; 3];
+
+    bb0: {
+        _4 = const table::promoted[0];
+        return;
+    }
+}
+
+const table::promoted[0]: &[u8; 3] = {
+    let mut _0: &[u8; 3];
+
+    bb0: {
+        _0 = &_1;
+        return;
+    }
+}
+
+fn twice(_1: u32) -> u32 {
+    bb0: {
+        _0 = Mul(copy _1, const 2_u32);
+        return;
+    }
+}
+
+// MIR FOR CTFE
+fn twice(_1: u32) -> u32 {
+    bb0: {
+        _0 = Mul(move _2, const 2_u32);
+        return;
+    }
+}
+
+const TEN: u32 = {
+    bb0: {
+        _0 = twice(const 5_u32) -> [return: bb1, unwind continue];
+    }
+}
+
+fn each(_1: &[u32]) -> u32 {
+    let mut _2: std::slice::Iter<'_, u32>;
+    scope 1 (inlined Map::<std::slice::Iter<'_, u32>, {closure@src/lib.rs:4:46: 4:49}>::new) {
+    }
+}
+";
+
+    /// Names of the listed items and the text printed for `goal`
+    fn dump(goal: (&str, usize), args: &[&str]) -> (Vec<String>, String) {
+        let (items, code) = dump_file(dump_function, MIR, goal, args);
+        (items.into_iter().map(|item| item.name).collect(), code)
+    }
+
+    #[test]
+    fn items() {
+        let (names, _) = dump(("", usize::MAX), &[]);
+        assert_eq!(
+            names,
+            [
+                "each(_1: &[u32]) -> u32",
+                "table(_1: usize) -> u8",
+                "twice(_1: u32) -> u32",
+            ]
+        );
+        let (names, _) = dump(("", usize::MAX), &["--const-eval"]);
+        assert_eq!(
+            names,
+            [
+                "const TEN: u32",
+                "const fn twice(_1: u32) -> u32",
+                "each(_1: &[u32]) -> u32",
+                "table(_1: usize) -> u8",
+                "twice(_1: u32) -> u32",
+            ]
+        );
+    }
+
+    #[test]
+    fn promoted() {
+        let goal = ("table(_1: usize) -> u8", 0);
+        let (_, code) = dump(goal, &[]);
+        assert!(code.contains("\nfn table(_1: usize) -> u8 {\n"));
+        assert!(!code.contains("promoted[0]: "));
+        let (_, code) = dump(goal, &["--promoted"]);
+        assert!(code.contains("\n\nconst table::promoted[0]: &[u8; 3] = {\n"));
+        assert!(code.ends_with("        _0 = &_1;\n        return;\n    }\n}\n"));
+        assert!(!code.contains("fn twice"));
+    }
+
+    #[test]
+    fn const_eval_copy() {
+        let goal = ("const fn twice(_1: u32) -> u32", 0);
+        let (_, code) = dump(goal, &["--const-eval"]);
+        assert!(code.starts_with("// MIR FOR CTFE\nfn twice(_1: u32) -> u32 {\n"));
+        assert!(code.contains("Mul(move _2, const 2_u32)"));
+    }
+
+    #[test]
+    fn shortened_references() {
+        let goal = ("each(_1: &[u32]) -> u32", 0);
+        let (_, code) = dump(goal, &[]);
+        assert!(code.contains("    let mut _2: Iter<'_, u32>;\n"));
+        assert!(code.contains("(inlined Map::<Iter<'_, u32>, {closure@src/lib.rs:4:46}>::new)"));
+        let (_, code) = dump(goal, &["--full-name"]);
+        assert!(code.contains("    let mut _2: std::slice::Iter<'_, u32>;\n"));
+        assert!(code.contains("{closure@src/lib.rs:4:46: 4:49}"));
+    }
+
+    #[test]
+    fn full_paths() {
+        let b = "b(_1: u64) -> u64";
+        assert!(matches(b, "b"));
+        assert!(matches(b, "mirt::m::b"));
+        assert!(!matches(b, "mirt::m::cb"));
+        assert!(!matches(b, "mirt::m::"));
+        assert!(matches("const TEN: u32", "mirt::TEN"));
+        assert!(matches("const fn twice(_1: u32) -> u32", "mirt::twice"));
+        assert!(!matches("twice(_1: u32) -> u32", "mirt::TEN"));
+    }
+}
@@ -1,6 +1,10 @@
-use bpaf::{construct, long, short, Bpaf, Parser};
+use anyhow::Context;
+use bpaf::{cargo_helper, construct, long, positional, short, Bpaf, OptionParser, Parser};
 use cargo_metadata::Artifact;
-use std::path::PathBuf;
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 fn check_target_dir(path: PathBuf) -> anyhow::Result<PathBuf> {
     if path.is_dir() {
@@ -11,21 +15,167 @@ fn check_target_dir(path: PathBuf) -> anyhow::Result<PathBuf> {
     }
 }
 
+/// Custom target specs are passed as paths to JSON files, both cargo and rustc resolve them
+/// relative to the current directory and cargo names the target directory after the file stem,
+/// pass them around as absolute paths so the spec is found regardless of who is looking
+fn check_target(target: String) -> anyhow::Result<String> {
+    let path = Path::new(&target);
+    if path.extension().is_some_and(|ext| ext == "json") {
+        let path = std::fs::canonicalize(path)
+            .with_context(|| format!("Can't find custom target spec {target:?}"))?;
+        Ok(path
+            .to_str()
+            .with_context(|| format!("Target spec path {path:?} is not valid utf8"))?
+            .to_owned())
+    } else {
+        Ok(target)
+    }
+}
+
+/// Addresses from crash reports are in hex, `0x` or not
+fn parse_addr(addr: String) -> anyhow::Result<u64> {
+    let hex = addr.strip_prefix("0x").unwrap_or(&addr);
+    u64::from_str_radix(hex, 16).with_context(|| format!("Expected a hex address, got {addr:?}"))
+}
+
+fn parse_env(pair: String) -> anyhow::Result<(String, String)> {
+    match pair.split_once('=') {
+        Some(("", _)) => anyhow::bail!("Variable name can't be empty"),
+        Some((key, val)) => Ok((key.to_owned(), val.to_owned())),
+        None => anyhow::bail!("Expected KEY=VALUE, got {pair:?}"),
+    }
+}
+
+/// Command line parser, combinations of options that can't work together are rejected here
+#[must_use]
+pub fn options() -> OptionParser<Options> {
+    let parser = unchecked_options()
+        .guard(
+            |o| !(o.snippet.is_some() && o.eval.is_some()),
+            "--snippet and --eval can't be used together",
+        )
+        .guard(
+            |o| !((o.snippet.is_some() || o.eval.is_some()) && (o.workspace || o.package.is_some())),
+            "--snippet and --eval can't be combined with --workspace or --package",
+        )
+        .guard(
+            |o| !(o.workspace && (o.package.is_some() || !o.focus.is_empty())),
+            "--workspace can't be combined with --package or target selection",
+        )
+        .guard(
+            |o| !o.all_targets || o.focus.is_empty(),
+            "--all-targets can't be combined with target selection",
+        )
+        .guard(
+            |o| !(o.no_build && o.no_cache),
+            "--no-build and --no-cache can't be used together",
+        )
+        .guard(
+            |o| !(o.output.is_some() && o.output_dir.is_some()),
+            "--output and --output-dir can't be used together",
+        )
+        .guard(
+            |o| !(o.clip && o.to_files()),
+            "--clip can't be combined with --output or --output-dir",
+        )
+        .guard(
+            |o| !(o.edit && (o.clip || o.to_files())),
+            "--edit can't be combined with --clip, --output or --output-dir",
+        )
+        .guard(
+            |o| [o.tui, o.serve.is_some(), o.lsp].iter().filter(|on| **on).count() <= 1,
+            "--tui, --serve and --lsp can't be used together",
+        )
+        .guard(
+            |o| {
+                !(o.emit_all()
+                    && (o.clip || o.edit || o.porcelain || o.format.quickfix || o.to_files()))
+            },
+            "--tui, --serve and --lsp can't be combined with --clip, --edit, --porcelain, --quickfix, --output or --output-dir",
+        )
+        .guard(
+            |o| !o.format.quickfix || o.asm(),
+            "--quickfix works with assembly only",
+        )
+        .guard(
+            |o| !(o.statics && o.vtable.is_some()),
+            "--statics and --vtable can't be used together",
+        )
+        .guard(
+            |o| {
+                !(o.statics || o.vtable.is_some())
+                    || (o.asm() && !o.emit_all() && o.output_dir.is_none())
+            },
+            "--statics and --vtable work with assembly only, without --tui, --serve, --lsp or --output-dir",
+        )
+        .guard(
+            |o| !(o.bloat && (o.emit_all() || o.output_dir.is_some())),
+            "--bloat can't be combined with --tui, --serve, --lsp or --output-dir",
+        )
+        .guard(
+            |o| !(o.layout && (o.emit_all() || o.output_dir.is_some() || o.bloat)),
+            "--layout can't be combined with --tui, --serve, --lsp, --output-dir or --bloat",
+        )
+        .guard(
+            |o| o.hottest.is_none() || o.format.perf.is_some() || o.format.callgrind.is_some(),
+            "--hottest needs a profile, pass one with --perf or --callgrind",
+        )
+        .guard(
+            |o| o.hottest.is_none() || (o.asm() && !o.separate_view() && !o.bloat),
+            "--hottest works with assembly only, without --tui, --serve, --lsp, --output-dir or --bloat",
+        )
+        .guard(
+            |o| {
+                o.section.is_none()
+                    || (o.asm() && !o.separate_view() && !o.bloat && o.hottest.is_none())
+            },
+            "--section works with assembly only, without --tui, --serve, --lsp, --output-dir, --bloat or --hottest",
+        )
+        .guard(
+            |o| {
+                o.format.addr.is_none()
+                    || (o.asm()
+                        && !o.separate_view()
+                        && !(o.bloat || o.statics || o.hottest.is_some()))
+            },
+            "--addr works with assembly only, without --tui, --serve, --lsp, --output-dir, --bloat, --statics or --hottest",
+        )
+        .guard(
+            |o| {
+                o.format.addr.is_none()
+                    || !matches!(
+                        o.to_dump,
+                        ToDump::Function {
+                            function: Some(_),
+                            ..
+                        }
+                    )
+            },
+            "--addr picks the function by itself, drop the name",
+        )
+        .guard(
+            |o| !o.format.call_tree || (o.asm() && !o.separate_view()),
+            "--call-tree works with assembly only, without --tui, --serve, --lsp or --output-dir",
+        );
+    cargo_helper("asm", parser)
+        .to_options()
+        .version(env!("CARGO_PKG_VERSION"))
+        .descr("Show the code rustc generates for any function")
+        .header(
+            "\
+Usage:
+  1. Focus on a single assembly producing target:
+     % cargo asm -p isin --lib   # here we are targeting lib in isin crate
+  2. Narrow down a function:
+     % cargo asm -p isin --lib from_ # here \"from_\" is part of the function you are interested intel
+  3. Get the full results:
+     % cargo asm -p isin --lib isin::base36::from_alphanum",
+        )
+}
+
 #[derive(Clone, Debug, Bpaf)]
-#[bpaf(options("asm"), version)]
+#[bpaf(generate(unchecked_options))]
 #[allow(clippy::struct_excessive_bools)]
-#[allow(clippy::doc_markdown)]
-/// Show the code rustc generates for any function
-///
-///
-///
-/// Usage:
-///   1. Focus on a single assembly producing target:
-///      % cargo asm -p isin --lib   # here we are targeting lib in isin crate
-///   2. Narrow down a function:
-///      % cargo asm -p isin --lib from_ # here "from_" is part of the function you are interested intel
-///   3. Get the full results:
-///      % cargo asm -p isin --lib isin::base36::from_alphanum
 pub struct Options {
     // what to compile
     #[bpaf(external, hide_usage)]
@@ -33,8 +183,18 @@ pub struct Options {
     /// Package to use if ambigous
     #[bpaf(long, short, argument("SPEC"))]
     pub package: Option<String>,
-    #[bpaf(external, optional)]
-    pub focus: Option<Focus>,
+    /// Targets to search through, several can be selected at once
+    #[bpaf(external, many)]
+    pub focus: Vec<Focus>,
+    /// Search through every target of every package in the workspace
+    #[bpaf(hide_usage)]
+    pub workspace: bool,
+    /// Search through all the targets of the package: lib, bins, examples, tests and benches
+    #[bpaf(hide_usage)]
+    pub all_targets: bool,
+    /// Build the library as this crate type when it declares several, i.e. cdylib
+    #[bpaf(argument("TYPE"), optional, hide_usage)]
+    pub crate_type: Option<String>,
 
     // how to compile
     /// Use custom target directory for generated artifacts, create if missing
@@ -49,6 +209,55 @@ pub struct Options {
     /// Produce a build plan instead of actually building
     #[bpaf(hide_usage)]
     pub dry: bool,
+    /// Print the build command instead of running it
+    #[bpaf(hide_usage)]
+    pub print_command: bool,
+    /// Add arguments from a preset defined in the config file, can be used multiple times
+    #[bpaf(argument("NAME"), many, hide_usage)]
+    pub preset: Vec<String>,
+    /// Write the result to a file, extension is picked from the output format if missing
+    #[bpaf(short('o'), long("output"), argument("PATH"), optional, hide_usage)]
+    pub output: Option<PathBuf>,
+    /// Copy the result to the clipboard instead of printing it
+    #[bpaf(hide_usage)]
+    pub clip: bool,
+    /// Open the result in `$VISUAL` or `$EDITOR` instead of printing it
+    #[bpaf(hide_usage)]
+    pub edit: bool,
+    /// Browse functions and their code interactively, switching between asm, llvm-ir and MIR
+    #[bpaf(hide_usage)]
+    pub tui: bool,
+    /// Address to serve the web view on, `--serve=ADDR` picks it, port alone means localhost
+    #[bpaf(external(serve), hide_usage)]
+    pub serve: Option<SocketAddr>,
+    /// Answer requests for the code of functions from an editor, JSON-RPC with LSP framing on
+    /// stdin and stdout
+    #[bpaf(hide_usage)]
+    pub lsp: bool,
+    /// Write each function to a separate file in this directory along with an index
+    #[bpaf(argument("DIR"), optional, hide_usage)]
+    pub output_dir: Option<PathBuf>,
+    /// Copy the generated .s, .ll or .mir files to this directory, named after the target and flags
+    #[bpaf(argument("DIR"), optional, hide_usage)]
+    pub keep: Option<PathBuf>,
+    /// Print nothing but the requested output: no progress, notes or colors, stable for scripts
+    #[bpaf(hide_usage)]
+    pub porcelain: bool,
+    /// Don't pipe the output through `$PAGER` when printing to a terminal
+    #[bpaf(hide_usage)]
+    pub no_pager: bool,
+    /// Save everything cargo and rustc print while building to a file
+    #[bpaf(argument("PATH"), optional, hide_usage)]
+    pub build_log: Option<PathBuf>,
+    /// Show a .s, .ll or .mir file made some other way instead of building the crate
+    #[bpaf(argument("PATH"), optional, hide_usage)]
+    pub file: Option<PathBuf>,
+    /// Build the code in this file as a crate of its own instead of the current project
+    #[bpaf(argument("FILE"), optional, hide_usage)]
+    pub snippet: Option<PathBuf>,
+    /// Build this code as a crate of its own, `--eval 'pub fn f(x: u32) -> u32 { x * 3 }'`
+    #[bpaf(argument("CODE"), optional, hide_usage)]
+    pub eval: Option<String>,
     /// Requires Cargo.lock and cache are up to date
     #[bpaf(hide_usage)]
     pub frozen: bool,
@@ -62,32 +271,181 @@ pub struct Options {
     pub cli_features: CliFeatures,
     #[bpaf(external)]
     pub compile_mode: CompileMode,
-    /// Build for the target triple
-    #[bpaf(argument("TRIPLE"))]
+    /// Build for the target triple or a custom target JSON spec
+    #[bpaf(argument("TRIPLE"), parse(check_target), optional)]
     pub target: Option<String>,
 
+    /// Use a custom sysroot for building and for locating standard library sources
+    #[bpaf(argument("PATH"), parse(std::fs::canonicalize), optional, hide_usage)]
+    pub sysroot: Option<PathBuf>,
+
+    /// Always run cargo instead of reusing previously generated files
+    #[bpaf(hide_usage)]
+    pub no_cache: bool,
+
+    /// Never run cargo, show the newest file it made for the target with the requested output
+    #[bpaf(hide_usage)]
+    pub no_build: bool,
+
+    /// Print at most this many lines of each function, 0 prints everything
+    #[bpaf(argument("N"), optional, hide_usage)]
+    pub max_lines: Option<usize>,
+
+    /// Stop the build if it takes longer than this many seconds
+    #[bpaf(argument("SECS"), optional, hide_usage)]
+    pub timeout: Option<u64>,
+
+    /// Ignore rustflags and build target set in cargo config files and environment
+    #[bpaf(hide_usage)]
+    pub ignore_config: bool,
+
+    /// Set environment variable for cargo and rustc, can be used multiple times
+    #[bpaf(argument::<String>("KEY=VALUE"), parse(parse_env), many, hide_usage)]
+    pub env: Vec<(String, String)>,
+
+    /// Build inside of a container, `Some(None)` uses `cross`
+    #[bpaf(external, hide_usage)]
+    pub cross: Option<Option<String>>,
+
+    /// Build standard library from sources, `Some(None)` uses cargo's default set of crates
+    #[bpaf(external, hide_usage)]
+    pub build_std: Option<Option<String>>,
+
     /// Generate code for a specific CPU
     #[bpaf(external)]
     pub target_cpu: Option<String>,
 
+    /// Enable or disable target features, i.e. '+avx2,+fma', can be used multiple times
+    #[bpaf(argument("FEATURES"))]
+    pub target_feature: Vec<String>,
+
+    /// Split the crate into this many codegen units, functions are looked up in all of them
+    #[bpaf(argument("N"), optional, hide_usage)]
+    pub codegen_units: Option<u32>,
+
+    /// Instrument the code with a sanitizer: address, thread or memory, requires nightly
+    #[bpaf(argument("SANITIZER"), optional, hide_usage)]
+    pub sanitizer: Option<Sanitizer>,
+
+    /// Instrument the code for coverage to see the injected counters
+    #[bpaf(hide_usage)]
+    pub coverage: bool,
+
+    /// Keep frame pointers, makes prologues and stack addressing easier to follow
+    #[bpaf(hide_usage)]
+    pub frame_pointers: bool,
+
+    /// Generate code for private and #[inline] functions, generic ones take types: `f::<u32>`
+    #[bpaf(hide_usage)]
+    pub force_codegen: bool,
+
+    /// Include comments LLVM adds to the assembly, requires nightly
+    #[bpaf(hide_usage)]
+    pub asm_comments: bool,
+
+    /// Sizes of crates and modules, biggest first, FUNCTION is the path to look inside of
+    #[bpaf(hide_usage)]
+    pub bloat: bool,
+
+    /// Functions of the binary by address with their sizes and the gaps between them,
+    /// FUNCTION narrows the list down
+    #[bpaf(hide_usage)]
+    pub layout: bool,
+
+    /// Print the N functions with the most samples in the profile from --perf or --callgrind,
+    /// FUNCTION narrows down the choice
+    #[bpaf(argument("N"), optional, hide_usage)]
+    pub hottest: Option<usize>,
+
+    /// Print all the functions placed into this section, `.text.unlikely` or one from
+    /// #[link_section], FUNCTION narrows down the choice
+    #[bpaf(argument("NAME"), optional, hide_usage)]
+    pub section: Option<String>,
+
+    /// List and print statics, string literals and other data instead of functions
+    #[bpaf(hide_usage)]
+    pub statics: bool,
+
+    /// Decode the vtable of a trait object, `--vtable "Type as Trait"`
+    #[bpaf(argument("IMPL"), optional, hide_usage)]
+    pub vtable: Option<String>,
+
+    /// With --vtable, print the code of the methods it points to after it
+    #[bpaf(hide_usage)]
+    pub methods: bool,
+
+    /// Old and new binaries `--compare-bins` reports the changes between
+    #[bpaf(external, hide_usage)]
+    pub compare_bins: Option<(PathBuf, PathBuf)>,
+
     // how to display
     #[bpaf(external)]
     pub format: Format,
-    #[bpaf(external)]
-    pub syntax: Syntax,
+    /// `None` if not given on the command line, see [`Options::syntax`]
+    #[bpaf(external, optional)]
+    pub syntax: Option<Syntax>,
 
     #[bpaf(external)]
     // what to display
     pub to_dump: ToDump,
 }
 
+impl Options {
+    /// Output format, Intel style assembly unless something else is requested
+    #[must_use]
+    pub fn syntax(&self) -> &Syntax {
+        self.syntax.as_ref().unwrap_or(&Syntax::Intel)
+    }
+
+    /// `--tui`, `--serve` and `--lsp` switch between all kinds of output without rebuilding
+    #[must_use]
+    pub fn emit_all(&self) -> bool {
+        self.tui || self.serve.is_some() || self.lsp
+    }
+
+    /// Modes that only make sense for assembly, the syntax from the config doesn't apply to them
+    #[must_use]
+    pub fn asm_only(&self) -> bool {
+        self.format.quickfix
+            || self.statics
+            || self.vtable.is_some()
+            || self.hottest.is_some()
+            || self.section.is_some()
+            || self.format.addr.is_some()
+            || self.format.call_tree
+    }
+
+    fn asm(&self) -> bool {
+        matches!(self.syntax(), Syntax::Intel | Syntax::Att)
+    }
+
+    fn to_files(&self) -> bool {
+        self.output.is_some() || self.output_dir.is_some()
+    }
+
+    /// Output that doesn't go through the usual printing of functions
+    fn separate_view(&self) -> bool {
+        self.emit_all() || self.output_dir.is_some()
+    }
+
+    /// Kinds of files rustc should emit
+    #[must_use]
+    pub fn emit(&self) -> &str {
+        if self.emit_all() {
+            "asm,llvm-ir,mir"
+        } else {
+            self.syntax().emit()
+        }
+    }
+}
+
 #[derive(Debug, Clone, Bpaf)]
 pub enum ToDump {
     /// Dump the whole asm file
     Everything,
     Function {
         /// Dump function with that specific name / filter functions containing this string
-        #[bpaf(positional("FUNCTION"), optional)]
+        #[bpaf(positional("FUNCTION"), complete(complete_function), optional)]
         function: Option<String>,
 
         /// Select specific function when there's several with the same name
@@ -96,6 +454,20 @@ pub enum ToDump {
     },
 }
 
+/// Function names from the files previous runs in this project generated and listed, rebuilding
+/// would be too slow for completion
+#[allow(clippy::ptr_arg)] // signature is dictated by bpaf
+fn complete_function(input: &String) -> Vec<(String, Option<String>)> {
+    let mut names = crate::cache::nearby_dirs()
+        .iter()
+        .flat_map(|dir| crate::cache::cached_names(dir))
+        .filter(|name| name.starts_with(input.as_str()))
+        .collect::<Vec<_>>();
+    names.sort();
+    names.dedup();
+    names.into_iter().map(|name| (name, None)).collect()
+}
+
 fn target_cpu() -> impl Parser<Option<String>> {
     let native = long("native")
         .help("Optimize for the CPU running the compiler")
@@ -106,6 +478,63 @@ fn target_cpu() -> impl Parser<Option<String>> {
     construct!([native, cpu]).optional()
 }
 
+fn cross() -> impl Parser<Option<Option<String>>> {
+    let command = long("cross")
+        .help("Build using a custom cross compilation command instead of cargo")
+        .argument::<String>("CMD")
+        .adjacent()
+        .map(Some);
+    let default = long("cross")
+        .help("Build inside of a container using `cross` instead of cargo")
+        .req_flag(None);
+    construct!([command, default]).optional()
+}
+
+fn build_std() -> impl Parser<Option<Option<String>>> {
+    let crates = long("build-std")
+        .help("Build the standard library crates from sources, requires nightly and --target")
+        .argument::<String>("CRATES")
+        .adjacent()
+        .map(Some);
+    let default = long("build-std")
+        .help("Build the standard library from sources, requires nightly and --target")
+        .req_flag(None);
+    construct!([crates, default]).optional()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sanitizer {
+    Address,
+    Thread,
+    Memory,
+}
+
+impl FromStr for Sanitizer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "address" => Ok(Sanitizer::Address),
+            "thread" => Ok(Sanitizer::Thread),
+            "memory" => Ok(Sanitizer::Memory),
+            _ => Err(format!(
+                "Unknown sanitizer {s:?}, expected one of address, thread or memory"
+            )),
+        }
+    }
+}
+
+impl Sanitizer {
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        match self {
+            Sanitizer::Address => "address",
+            Sanitizer::Thread => "thread",
+            Sanitizer::Memory => "memory",
+        }
+    }
+}
+
 #[derive(Bpaf, Clone, Debug)]
 pub struct CliFeatures {
     /// Do not activate `default` feature
@@ -117,6 +546,9 @@ pub struct CliFeatures {
     /// A feature to activate, can be used multiple times
     #[bpaf(argument("FEATURE"))]
     pub features: Vec<String>,
+
+    /// Do not activate features required by the selected target
+    pub no_required_features: bool,
 }
 
 #[derive(Bpaf, Clone, Debug)]
@@ -133,6 +565,18 @@ pub enum CompileMode {
     ),
 }
 
+impl CompileMode {
+    /// Profile name as cargo sees it
+    #[must_use]
+    pub fn profile_name(&self) -> &str {
+        match self {
+            CompileMode::Release => "release",
+            CompileMode::Dev => "dev",
+            CompileMode::Custom(profile) => profile,
+        }
+    }
+}
+
 fn verbosity() -> impl Parser<usize> {
     short('v')
         .long("verbose")
@@ -165,8 +609,9 @@ pub struct Format {
     /// Print interleaved Rust code
     pub rust: bool,
 
+    /// `None` if neither `--color` nor `--no-color` is given
     #[bpaf(external(color_detection), hide_usage)]
-    pub color: bool,
+    pub color: Option<bool>,
 
     /// Include full demangled name instead of just prefix
     #[bpaf(hide_usage)]
@@ -176,16 +621,84 @@ pub struct Format {
     #[bpaf(hide_usage)]
     pub keep_labels: bool,
 
+    /// Skip assembler directives, keeping only labels and instructions
+    #[bpaf(hide_usage)]
+    pub simplify: bool,
+
+    /// Print calls on the way to a panic as `file:line:col: message` lines instead of the code,
+    /// for quickfix lists in Vim and error parsers of other editors
+    #[bpaf(hide_usage)]
+    pub quickfix: bool,
+
+    /// Show the state machine of an async fn with its states and await points marked
+    #[bpaf(long("async"), hide_usage)]
+    pub async_fn: bool,
+
+    /// Print constants promoted out of the function after its MIR
+    #[bpaf(hide_usage)]
+    pub promoted: bool,
+
+    /// List MIR used for compile time evaluation: of const fns, constants and statics
+    #[bpaf(hide_usage)]
+    pub const_eval: bool,
+
+    /// Print LLVM IR of the function as a module of its own that opt and llc accept
+    #[bpaf(hide_usage)]
+    pub self_contained: bool,
+
+    /// Print LLVM IR as is, with debug records, metadata, attribute groups and mangled names
+    #[bpaf(hide_usage)]
+    pub raw: bool,
+
+    /// Say how each call and data reference reaches its symbol: directly, through the PLT or
+    /// the GOT, along with the relocation the assembler emits for it
+    #[bpaf(hide_usage)]
+    pub relocations: bool,
+
+    /// Decode unwind info of the function: what the CFI directives say about the frame and the
+    /// call sites and landing pads of its exception table
+    #[bpaf(hide_usage)]
+    pub unwind_info: bool,
+
+    /// Show how many samples `perf record` saved to this file hit each instruction
+    #[bpaf(argument("PATH"), optional, hide_usage)]
+    pub perf: Option<PathBuf>,
+
+    /// Show instruction reads and cache misses callgrind saved to this file for each instruction
+    #[bpaf(argument("PATH"), optional, hide_usage)]
+    pub callgrind: Option<PathBuf>,
+
+    /// Show the function of the binary with code at this address and point to the instruction
+    #[bpaf(argument::<String>("ADDR"), parse(parse_addr), optional, hide_usage)]
+    pub addr: Option<u64>,
+
+    /// Print the functions FUNCTION calls, the ones those call and so on, with their sizes
+    #[bpaf(hide_usage)]
+    pub call_tree: bool,
+
+    /// With --call-tree, follow calls this many levels down
+    #[bpaf(argument("N"), optional, hide_usage)]
+    pub depth: Option<usize>,
+
+    /// Color theme: dark, light or high-contrast
+    #[bpaf(argument("THEME"), optional, hide_usage)]
+    pub theme: Option<String>,
+
+    /// Link to sources with this URL instead of `file://{path}`, `{path}` and `{line}` are replaced
+    #[bpaf(argument("TEMPLATE"), optional, hide_usage)]
+    pub link_template: Option<String>,
+
     /// more verbose output, can be specified multiple times
     #[bpaf(external)]
     pub verbosity: usize,
 }
 
-#[derive(Debug, Clone, Bpaf)]
-#[bpaf(fallback(Syntax::Intel))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Bpaf, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Syntax {
     /// Show assembly using Intel style
     #[bpaf(long("intel"), long("asm"))]
+    #[serde(alias = "asm")]
     Intel,
     /// Show assembly using AT&T style
     Att,
@@ -214,6 +727,16 @@ impl Syntax {
         }
     }
 
+    /// Line comment marker in the output
+    #[must_use]
+    pub fn comment(&self) -> &str {
+        match self {
+            Syntax::Intel | Syntax::Att => "#",
+            Syntax::Llvm => ";",
+            Syntax::Mir => "//",
+        }
+    }
+
     #[must_use]
     pub fn ext(&self) -> &str {
         match self {
@@ -224,16 +747,45 @@ impl Syntax {
     }
 }
 
-fn color_detection() -> impl Parser<bool> {
+fn serve() -> impl Parser<Option<SocketAddr>> {
+    let help = "Browse functions in a web browser, --serve=ADDR listens somewhere other than 127.0.0.1:8000";
+    // value must be in the same word, otherwise it's impossible to tell it from FUNCTION
+    let addr = long("serve")
+        .help(help)
+        .argument::<String>("ADDR")
+        .adjacent();
+    let default = long("serve")
+        .help(help)
+        .req_flag(String::from("127.0.0.1:8000"))
+        .hide();
+    // parsed after picking the alternative so a bad address is an error and not a bare flag
+    construct!([addr, default])
+        .parse(|addr| match addr.parse::<u16>() {
+            Ok(port) => Ok(SocketAddr::from(([127, 0, 0, 1], port))),
+            Err(_) => addr.parse::<SocketAddr>(),
+        })
+        .optional()
+}
+
+fn compare_bins() -> impl Parser<Option<(PathBuf, PathBuf)>> {
+    let tag = long("compare-bins")
+        .help("Compare function sizes of two binaries, FUNCTION also shows how its code changed")
+        .req_flag(());
+    let old = positional::<PathBuf>("OLD");
+    let new = positional::<PathBuf>("NEW");
+    construct!(tag, old, new)
+        .map(|((), old, new)| (old, new))
+        .optional()
+}
+
+fn color_detection() -> impl Parser<Option<bool>> {
     let yes = long("color")
         .help("Enable color highlighting")
         .req_flag(true);
     let no = long("no-color")
         .help("Disable color highlighting")
         .req_flag(false);
-    construct!([yes, no]).fallback_with::<_, &str>(|| {
-        Ok(supports_color::on(supports_color::Stream::Stdout).is_some())
-    })
+    construct!([yes, no]).optional()
 }
 
 #[derive(Debug, Clone, Bpaf)]
@@ -248,7 +800,7 @@ pub enum Focus {
     ),
 
     Bench(
-        /// Show results from a benchmark
+        /// Show results from a benchmark, including ones with `harness = false` such as criterion
         #[bpaf(long("bench"), argument("BENCH"))]
         String,
     ),
@@ -264,14 +816,27 @@ pub enum Focus {
         #[bpaf(long("bin"), argument("BIN"))]
         String,
     ),
+
+    /// Show results from the build script
+    BuildScript,
+
+    Doctest(
+        /// Show results from a doctest, requires nightly.
+        /// NAME is a part of the directory rustdoc keeps it in, i.e. src_lib_rs_42_0
+        #[bpaf(long("doctest"), argument("NAME"))]
+        String,
+    ),
 }
 
+/// Crate types cargo builds for a library target, `--lib` covers all of them
+const LIB_KINDS: &[&str] = &["lib", "rlib", "dylib", "cdylib", "staticlib"];
+
 impl TryFrom<&'_ cargo_metadata::Target> for Focus {
     type Error = anyhow::Error;
 
     fn try_from(target: &cargo_metadata::Target) -> Result<Self, Self::Error> {
         match target.kind.first().map(|s| &**s) {
-            Some("lib") => Ok(Focus::Lib),
+            Some(kind) if LIB_KINDS.contains(&kind) => Ok(Focus::Lib),
             Some("test") => Ok(Focus::Test(target.name.clone())),
             Some("bench") => Ok(Focus::Bench(target.name.clone())),
             Some("example") => Ok(Focus::Example(target.name.clone())),
@@ -290,6 +855,9 @@ impl Focus {
             Focus::Bench(name) => ("bench", Some(name)),
             Focus::Example(name) => ("example", Some(name)),
             Focus::Bin(name) => ("bin", Some(name)),
+            Focus::BuildScript => ("build-script", None),
+            // cargo can only build all the doctests at once
+            Focus::Doctest(_) => ("doc", None),
         }
     }
 
@@ -302,7 +870,33 @@ impl Focus {
 
     #[must_use]
     pub fn matches_artifact(&self, artifact: &Artifact) -> bool {
-        let (kind, name) = self.as_parts();
-        artifact.target.kind == [kind] && name.map_or(true, |name| artifact.target.name == *name)
+        let (kind, name) = match self {
+            Focus::Lib => {
+                return artifact
+                    .target
+                    .kind
+                    .iter()
+                    .all(|kind| LIB_KINDS.contains(&kind.as_str()))
+            }
+            Focus::BuildScript => ("custom-build", None),
+            _ => self.as_parts(),
+        };
+        artifact.target.kind == [kind] && name.is_none_or(|name| artifact.target.name == *name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::options;
+
+    #[test]
+    fn conflicts() {
+        let parse = |args: &[&str]| options().run_inner(bpaf::Args::from(args));
+        assert!(parse(&["asm", "--clip", "foo"]).is_ok());
+        assert!(parse(&["asm", "--no-build", "--no-cache"]).is_err());
+        assert!(parse(&["asm", "--tui", "--lsp"]).is_err());
+        assert!(parse(&["asm", "--edit", "-o", "out.s"]).is_err());
+        assert!(parse(&["asm", "--llvm", "--statics"]).is_err());
+        assert!(parse(&["asm", "--addr", "0x10", "foo"]).is_err());
     }
 }
@@ -0,0 +1,147 @@
+#![allow(clippy::missing_errors_doc)]
+//! Output is collected in memory and handed to the pager once everything is rendered, that
+//! way suggestions and errors printed along the way don't end up mixed with the pager screen.
+//! Output too large for that, such as the whole asm file, goes to the pager as it comes.
+use std::io::Write;
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+/// Show collected output with `$PAGER`, or `less` if unset, when flushed
+#[derive(Default)]
+pub struct Pager {
+    buf: Vec<u8>,
+    /// pass the output along right away instead of collecting it
+    streaming: bool,
+    sink: Option<Sink>,
+}
+
+/// Where streamed output goes once the first byte of it is written
+enum Sink {
+    Pager(Child, Option<ChildStdin>),
+    Stdout,
+}
+
+impl Pager {
+    fn command() -> Option<Command> {
+        let pager = std::env::var("PAGER").unwrap_or_else(|_| "less".into());
+        if pager.trim().is_empty() || pager.trim() == "cat" {
+            return None;
+        }
+        let mut cmd = if cfg!(windows) {
+            let mut words = pager.split_whitespace();
+            let mut cmd = Command::new(words.next()?);
+            cmd.args(words);
+            cmd
+        } else {
+            let mut cmd = Command::new("sh");
+            cmd.arg("-c").arg(&pager);
+            cmd
+        };
+        // same as git: quit if everything fits on one screen, keep colors, don't clear the screen
+        if std::env::var_os("LESS").is_none() {
+            cmd.env("LESS", "FRX");
+        }
+        Some(cmd)
+    }
+
+    /// Pager that gets the output as it is written, for output that shouldn't be kept in
+    /// memory
+    #[must_use]
+    pub fn streaming() -> Self {
+        Self {
+            buf: Vec::new(),
+            streaming: true,
+            sink: None,
+        }
+    }
+
+    fn spawn() -> Sink {
+        if let Some(mut cmd) = Self::command() {
+            if let Ok(mut child) = cmd.stdin(Stdio::piped()).spawn() {
+                let stdin = child.stdin.take();
+                return Sink::Pager(child, stdin);
+            }
+        }
+        Sink::Stdout
+    }
+
+    fn show(buf: &[u8]) -> std::io::Result<()> {
+        if let Some(mut cmd) = Self::command() {
+            if let Ok(mut child) = cmd.stdin(Stdio::piped()).spawn() {
+                let mut stdin = child.stdin.take().expect("stdin is piped");
+                // pager quitting early closes the pipe, that's not an error
+                let _ = stdin.write_all(buf);
+                drop(stdin);
+                child.wait()?;
+                return Ok(());
+            }
+        }
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(buf)?;
+        stdout.flush()
+    }
+}
+
+impl Write for Pager {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if !self.streaming {
+            return self.buf.write(buf);
+        }
+        match self.sink.get_or_insert_with(Self::spawn) {
+            Sink::Pager(_, stdin) => {
+                // pager quitting early closes the pipe, the rest of the output is dropped
+                if stdin
+                    .as_mut()
+                    .is_some_and(|pipe| pipe.write_all(buf).is_err())
+                {
+                    *stdin = None;
+                }
+                Ok(buf.len())
+            }
+            Sink::Stdout => std::io::stdout().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.sink {
+            Some(Sink::Pager(_, Some(stdin))) => return stdin.flush().or(Ok(())),
+            Some(Sink::Pager(_, None)) => return Ok(()),
+            Some(Sink::Stdout) => return std::io::stdout().flush(),
+            None => {}
+        }
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let buf = std::mem::take(&mut self.buf);
+        Self::show(&buf)
+    }
+}
+
+/// Flushes the writer it wraps when dropped. Pager, editor and clipboard only act on flush,
+/// early returns and `?` skip the explicit one but output collected so far still ends up
+/// where it was meant to go
+pub struct FlushOnDrop<W: Write>(pub W);
+
+impl<W: Write> Write for FlushOnDrop<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<W: Write> Drop for FlushOnDrop<W> {
+    fn drop(&mut self) {
+        let _ = self.0.flush();
+    }
+}
+
+impl Drop for Sink {
+    fn drop(&mut self) {
+        if let Sink::Pager(child, stdin) = self {
+            drop(stdin.take());
+            let _ = child.wait();
+        }
+    }
+}
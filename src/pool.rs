@@ -0,0 +1,142 @@
+#![allow(clippy::missing_errors_doc)]
+//! Work spread over all the cores with the output handed over in order. Output of the first
+//! unfinished task goes through as it comes, tasks after it only get a few chunks ahead before
+//! they have to wait for their turn, so nothing is held in memory in full.
+use std::io::{BufWriter, Write};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Mutex;
+
+/// Work for [`in_order`], writes its output to the writer it gets
+pub type Task<'a, T> = Box<dyn FnOnce(&mut dyn Write) -> T + Send + 'a>;
+
+/// Chunks a task can get ahead of the output
+const CHUNKS: usize = 16;
+
+/// Size of a chunk
+const CHUNK: usize = 64 * 1024;
+
+enum Msg<T> {
+    Chunk(Vec<u8>),
+    Done(T),
+}
+
+/// Sends everything written to it to [`in_order`]
+struct Pipe<'a, T>(&'a SyncSender<Msg<T>>);
+
+impl<T> Write for Pipe<'_, T> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .send(Msg::Chunk(buf.to_vec()))
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Run `tasks` on as many threads as there are cores and copy their output to `out` in the
+/// order of `tasks`, `done` gets the result of each one once its output is copied
+pub fn in_order<T: Send>(
+    tasks: Vec<Task<'_, T>>,
+    out: &mut dyn Write,
+    mut done: impl FnMut(T) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let threads = std::thread::available_parallelism()
+        .map_or(1, usize::from)
+        .min(tasks.len());
+    let (senders, receivers): (Vec<_>, Vec<Receiver<Msg<T>>>) =
+        tasks.iter().map(|_| mpsc::sync_channel(CHUNKS)).unzip();
+    // tasks are taken in order, the one the output waits for is always running
+    let queue = Mutex::new(tasks.into_iter().zip(senders));
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            let queue = &queue;
+            scope.spawn(move || loop {
+                let Some((task, send)) = queue.lock().expect("tasks don't panic").next() else {
+                    break;
+                };
+                let mut pipe = BufWriter::with_capacity(CHUNK, Pipe(&send));
+                let res = task(&mut pipe);
+                // receiver is gone after an error, there's no one to do the rest for
+                if pipe.flush().is_err() || send.send(Msg::Done(res)).is_err() {
+                    break;
+                }
+            });
+        }
+        let copy = || -> anyhow::Result<()> {
+            for results in receivers {
+                for msg in results {
+                    match msg {
+                        Msg::Chunk(chunk) => out.write_all(&chunk)?,
+                        Msg::Done(res) => done(res)?,
+                    }
+                }
+            }
+            Ok(())
+        };
+        let res = copy();
+        if res.is_err() {
+            // tasks nobody started yet won't start, the running ones stop once they write
+            queue
+                .lock()
+                .expect("tasks don't panic")
+                .by_ref()
+                .for_each(drop);
+        }
+        res
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{in_order, Task};
+    use std::io::Write;
+
+    #[test]
+    fn output_in_order() {
+        let tasks = (0..20usize)
+            .map(|n| {
+                Box::new(move |out: &mut dyn Write| {
+                    // later tasks have less to write and finish first
+                    for line in 0..(20 - n) * 500 {
+                        writeln!(out, "{n} {line}")?;
+                    }
+                    Ok(n)
+                }) as Task<'_, std::io::Result<usize>>
+            })
+            .collect();
+        let mut out = Vec::new();
+        let mut finished = Vec::new();
+        in_order(tasks, &mut out, |res| {
+            finished.push(res?);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(finished, (0..20).collect::<Vec<_>>());
+        let expected = (0..20)
+            .flat_map(|n| (0..(20 - n) * 500).map(move |line| format!("{n} {line}\n")))
+            .collect::<String>();
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+    }
+
+    #[test]
+    fn stops_after_error() {
+        let tasks = (0..100usize)
+            .map(|n| {
+                Box::new(move |out: &mut dyn Write| {
+                    for line in 0..10_000 {
+                        writeln!(out, "{n} {line}")?;
+                    }
+                    Ok(n)
+                }) as Task<'_, std::io::Result<usize>>
+            })
+            .collect();
+        let res = in_order(tasks, &mut std::io::sink(), |res| {
+            anyhow::ensure!(res? < 2, "enough");
+            Ok(())
+        });
+        assert!(res.is_err());
+    }
+}
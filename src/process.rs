@@ -0,0 +1,284 @@
+#![allow(clippy::missing_errors_doc)]
+//! Running cargo. The build gets a process group of its own so Ctrl-C and `--timeout` stop it
+//! along with every rustc it spawned, its output goes through the status line and into the
+//! build log.
+use crate::artifacts::remap_container_paths;
+use crate::exit::Failure;
+use crate::opts;
+use crate::progress::Progress;
+use anyhow::Context;
+use cargo_metadata::{Artifact, Message};
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{BufRead, BufReader, IsTerminal, Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::{
+    mpsc::{self, RecvTimeoutError},
+    Arc, Mutex,
+};
+use std::time::Duration;
+
+/// Process group of the running build, `0` if there's none
+#[cfg(target_family = "unix")]
+static BUILD_GROUP: std::sync::atomic::AtomicI32 = std::sync::atomic::AtomicI32::new(0);
+
+/// Build runs in its own process group so it can be stopped along with all the rustc processes
+/// cargo spawned. Terminal no longer delivers Ctrl-C to it directly so it is forwarded from here.
+pub fn install_interrupt_handler() -> anyhow::Result<()> {
+    #[cfg(target_family = "unix")]
+    {
+        use nix::{libc, sys::signal};
+        extern "C" fn on_interrupt(_: libc::c_int) {
+            let group = BUILD_GROUP.load(std::sync::atomic::Ordering::SeqCst);
+            if group > 0 {
+                stop_group(group);
+            }
+            // Safety: _exit is async-signal-safe
+            unsafe { libc::_exit(130) }
+        }
+        let handler = signal::SigHandler::Handler(on_interrupt);
+        // Safety: handler only calls async-signal-safe functions
+        unsafe {
+            signal::signal(signal::Signal::SIGINT, handler)?;
+            signal::signal(signal::Signal::SIGTERM, handler)?;
+        }
+    }
+    Ok(())
+}
+
+/// Ask process group `group` to stop with SIGTERM so cargo can clean up after itself and only
+/// kill it once cargo doesn't exit in a couple of seconds. Sticks to async-signal-safe calls so
+/// the interrupt handler can use it
+#[cfg(target_family = "unix")]
+fn stop_group(group: nix::libc::pid_t) {
+    use nix::libc;
+    // 40 steps of 50ms
+    const GRACE_STEPS: u32 = 40;
+    let step = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 50_000_000,
+    };
+    // Safety: the calls only take pointers to the locals
+    unsafe {
+        if libc::killpg(group, libc::SIGTERM) != 0 {
+            return;
+        }
+        for _ in 0..GRACE_STEPS {
+            // cargo leads the group, it is left waitable for whoever spawned it
+            let mut info = std::mem::zeroed::<libc::siginfo_t>();
+            #[allow(clippy::cast_sign_loss)]
+            let res = libc::waitid(
+                libc::P_PID,
+                group as libc::id_t,
+                &mut info,
+                libc::WEXITED | libc::WNOHANG | libc::WNOWAIT,
+            );
+            if res != 0 || info.si_pid() == group {
+                break;
+            }
+            libc::nanosleep(&step, std::ptr::null_mut());
+        }
+        // rustc processes cargo left behind
+        libc::killpg(group, libc::SIGKILL);
+    }
+}
+
+/// Stop process `pid` along with everything it spawned
+fn kill_tree(pid: u32) {
+    #[cfg(target_family = "unix")]
+    {
+        #[allow(clippy::cast_possible_wrap)]
+        stop_group(pid as i32);
+    }
+    #[cfg(not(target_family = "unix"))]
+    {
+        let _ = Command::new("taskkill")
+            .args(["/T", "/F", "/PID", &pid.to_string()])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .status();
+    }
+}
+
+/// Quote `arg` so it can be pasted into a POSIX shell as is
+fn shell_quote(arg: &OsStr) -> String {
+    let arg = arg.to_string_lossy();
+    if !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_=+:,./@%".contains(c))
+    {
+        arg.into_owned()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// Command with its environment in a form that can be pasted into a POSIX shell
+pub fn command_line(cmd: &Command) -> String {
+    let envs = cmd.get_envs().map(|(key, val)| {
+        format!(
+            "{}={}",
+            key.to_string_lossy(),
+            shell_quote(val.unwrap_or_default())
+        )
+    });
+    let command =
+        std::iter::once(shell_quote(cmd.get_program())).chain(cmd.get_args().map(shell_quote));
+    envs.chain(command).collect::<Vec<_>>().join(" ")
+}
+
+/// Copy everything from `input` to stderr and to `log`
+fn tee(input: impl Read, log: Option<&Mutex<File>>, progress: &Progress) -> std::io::Result<()> {
+    let mut reader = BufReader::new(input);
+    let mut line = Vec::new();
+    while reader.read_until(b'\n', &mut line)? > 0 {
+        progress.line(&line)?;
+        if let Some(log) = log {
+            log.lock()
+                .map_err(|_| std::io::Error::other("build log is poisoned"))?
+                .write_all(&line)?;
+        }
+        line.clear();
+    }
+    Ok(())
+}
+
+/// Run the build and return the artifact for `focus` target from `package`
+pub fn build_artifact(
+    mut cmd: Command,
+    opts: &opts::Options,
+    metadata: &cargo_metadata::Metadata,
+    package: &cargo_metadata::Package,
+    focus: &opts::Focus,
+) -> anyhow::Result<Artifact> {
+    #[cfg(target_family = "unix")]
+    std::os::unix::process::CommandExt::process_group(&mut cmd, 0);
+    let log = match &opts.build_log {
+        Some(path) => {
+            let mut file = File::create(path)
+                .with_context(|| format!("Can't create build log {}", path.display()))?;
+            writeln!(file, "{}", command_line(&cmd))?;
+            Some(Arc::new(Mutex::new(file)))
+        }
+        None => None,
+    };
+    let progress = Arc::new(Progress::new(
+        !opts.porcelain && std::io::stderr().is_terminal(),
+    ));
+    let mut cargo_child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stderr = cargo_child.stderr.take().expect("stderr is piped");
+    let tee = {
+        let log = log.clone();
+        let progress = progress.clone();
+        std::thread::spawn(move || tee(stderr, log.as_deref(), &progress))
+    };
+    let pid = cargo_child.id();
+    #[cfg(target_family = "unix")]
+    #[allow(clippy::cast_possible_wrap)]
+    BUILD_GROUP.store(pid as i32, std::sync::atomic::Ordering::SeqCst);
+
+    // watchdog stops the build once the time is up unless it is told the build is over
+    let (finished, watchdog) = mpsc::channel::<()>();
+    let watchdog = opts.timeout.map(|secs| {
+        std::thread::spawn(move || {
+            let expired =
+                watchdog.recv_timeout(Duration::from_secs(secs)) == Err(RecvTimeoutError::Timeout);
+            if expired {
+                kill_tree(pid);
+            }
+            expired
+        })
+    });
+
+    let mut result_artifact = None;
+    let mut success = false;
+    for msg in Message::parse_stream(BufReader::new(cargo_child.stdout.take().unwrap())) {
+        match msg? {
+            // with build-std there's a bunch of extra lib artifacts from sysroot crates
+            Message::CompilerArtifact(mut artifact) => {
+                if opts.cross.is_some() {
+                    remap_container_paths(&mut artifact, metadata, opts.target_dir.as_deref());
+                }
+                if artifact.manifest_path == package.manifest_path
+                    && focus.matches_artifact(&artifact)
+                {
+                    result_artifact = Some(artifact);
+                }
+            }
+            Message::BuildFinished(fin) => {
+                success = fin.success;
+                break;
+            }
+            Message::CompilerMessage(msg) => {
+                if !opts.porcelain
+                    || msg.message.level == cargo_metadata::diagnostic::DiagnosticLevel::Error
+                {
+                    progress.message(&msg)?;
+                }
+                if let Some(log) = &log {
+                    if let Ok(mut log) = log.lock() {
+                        writeln!(log, "{msg}")?;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    drop(finished);
+    #[cfg(target_family = "unix")]
+    BUILD_GROUP.store(0, std::sync::atomic::Ordering::SeqCst);
+    let timed_out = watchdog.is_some_and(|w| w.join().unwrap_or(false));
+    if let Ok(Err(err)) = tee.join() {
+        eprintln!("Failed to write build log: {err}");
+    }
+    progress.finish();
+    if !success {
+        let status = cargo_child.wait()?;
+        if timed_out {
+            anyhow::bail!(Failure::build_failed(format!(
+                "Build didn't finish in {} seconds and was stopped",
+                opts.timeout.unwrap_or_default()
+            )));
+        }
+        if let Some(path) = &opts.build_log {
+            eprintln!("Full build log is saved to {}", path.display());
+        }
+        anyhow::bail!(Failure::build_failed(format!("Cargo failed with {status}")));
+    }
+    result_artifact.context("No artifact found")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{command_line, shell_quote};
+    use std::ffi::OsStr;
+    use std::process::Command;
+
+    #[test]
+    fn quoting() {
+        let quote = |arg: &str| shell_quote(OsStr::new(arg));
+        assert_eq!(quote("--target-dir=/tmp/a.b"), "--target-dir=/tmp/a.b");
+        assert_eq!(quote(""), "''");
+        assert_eq!(quote("two words"), "'two words'");
+        assert_eq!(quote("it's"), "'it'\\''s'");
+        assert_eq!(quote("$HOME"), "'$HOME'");
+        assert_eq!(quote("\"x\""), "'\"x\"'");
+    }
+
+    #[test]
+    fn command_lines() {
+        let mut cmd = Command::new("cargo");
+        cmd.args(["rustc", "--", "-C", "llvm-args=a b", ""])
+            .env("RUSTC_WRAPPER", "")
+            .env("FLAGS", "$X 'y'");
+        assert_eq!(
+            command_line(&cmd),
+            "FLAGS='$X '\\''y'\\''' RUSTC_WRAPPER='' cargo rustc -- -C 'llvm-args=a b' ''"
+        );
+    }
+}
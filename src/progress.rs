@@ -0,0 +1,124 @@
+#![allow(clippy::missing_errors_doc)]
+//! Cargo only shows its progress bar when stderr is a terminal, but stderr is piped when
+//! building so progress is reported with a status line of our own.
+use std::io::Write;
+use std::sync::Mutex;
+
+const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+
+#[derive(Default)]
+struct State {
+    current: Option<String>,
+    started: usize,
+    tick: usize,
+}
+
+/// Status line with the crate being compiled, printed below everything else cargo reports
+pub struct Progress {
+    enabled: bool,
+    state: Mutex<State>,
+}
+
+/// Crate name from cargo's `Compiling foo v0.1.0` line
+fn compiling(line: &str) -> Option<&str> {
+    let line = line.trim_start();
+    let rest = line
+        .strip_prefix("Compiling ")
+        .or_else(|| line.strip_prefix("Checking "))?;
+    rest.split_whitespace().next()
+}
+
+/// Remove color escape sequences
+pub(crate) fn strip_ansi(line: &str) -> String {
+    let mut res = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            res.push(c);
+        }
+    }
+    res
+}
+
+impl Progress {
+    #[must_use]
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Print `line` from cargo's stderr, it ends with a newline
+    pub fn line(&self, line: &[u8]) -> std::io::Result<()> {
+        if !self.enabled {
+            return std::io::stderr().write_all(line);
+        }
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let text = strip_ansi(&String::from_utf8_lossy(line));
+        if let Some(name) = compiling(&text) {
+            state.current = Some(name.to_owned());
+            state.started += 1;
+        } else if text.trim_start().starts_with("Finished ") {
+            state.current = None;
+        }
+        let mut stderr = std::io::stderr().lock();
+        write!(stderr, "\r\x1b[K")?;
+        stderr.write_all(line)?;
+        Self::draw(&mut state, &mut stderr)
+    }
+
+    /// Print a message that doesn't come from cargo's stderr
+    pub fn message(&self, msg: &dyn std::fmt::Display) -> std::io::Result<()> {
+        if !self.enabled {
+            eprintln!("{msg}");
+            return Ok(());
+        }
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let mut stderr = std::io::stderr().lock();
+        writeln!(stderr, "\r\x1b[K{msg}")?;
+        Self::draw(&mut state, &mut stderr)
+    }
+
+    fn draw(state: &mut State, out: &mut dyn Write) -> std::io::Result<()> {
+        if let Some(current) = &state.current {
+            state.tick += 1;
+            write!(
+                out,
+                "{} [{}] Building {current}",
+                SPINNER[state.tick % SPINNER.len()],
+                state.started
+            )?;
+        }
+        out.flush()
+    }
+
+    /// Remove the status line once the build is over
+    pub fn finish(&self) {
+        if self.enabled {
+            let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            if state.current.is_some() {
+                eprint!("\r\x1b[K");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compiling, strip_ansi};
+
+    #[test]
+    fn crate_being_compiled() {
+        let line = "\x1b[1m\x1b[92m   Compiling\x1b[0m hashbrown v0.15.5\n";
+        assert_eq!(compiling(&strip_ansi(line)), Some("hashbrown"));
+        assert_eq!(compiling("    Checking a v0.1.0 (/tmp/ws/a)"), Some("a"));
+        assert_eq!(compiling("    Finished `dev` profile"), None);
+    }
+}
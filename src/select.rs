@@ -0,0 +1,440 @@
+#![allow(clippy::missing_errors_doc)]
+//! Packages and targets to build, picked from the command line, the workspace or the
+//! dependencies, and what they need to be built
+use crate::exit::Failure;
+use crate::opts;
+use anyhow::Context;
+use cargo_metadata::{semver::VersionReq, MetadataCommand};
+use std::cell::OnceCell;
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+
+/// Packages and targets to build, more than one when searching through several targets.
+/// `dependencies` gets the metadata of all the packages when the one of interest is a dependency
+pub fn select_jobs<'a>(
+    opts: &opts::Options,
+    metadata: &'a cargo_metadata::Metadata,
+    dependencies: &'a OnceCell<cargo_metadata::Metadata>,
+    cargo_path: &str,
+) -> anyhow::Result<Vec<(&'a cargo_metadata::Package, opts::Focus)>> {
+    let jobs = if opts.workspace {
+        metadata
+            .packages
+            .iter()
+            .flat_map(|package| {
+                package
+                    .targets
+                    .iter()
+                    .filter_map(|target| opts::Focus::try_from(target).ok())
+                    .map(move |focus| (package, focus))
+            })
+            .collect::<Vec<_>>()
+    } else {
+        let focus_package = match &opts.package {
+            Some(spec) => match select_package(&metadata.packages, spec, None)? {
+                Some(package) => package,
+                None => {
+                    // not a workspace member, check the dependencies
+                    let (spec, req) = resolve_rename(metadata, spec);
+                    let metadata = MetadataCommand::new()
+                        .cargo_path(cargo_path)
+                        .manifest_path(&opts.manifest_path)
+                        .exec()?;
+                    let dep_metadata = dependencies.get_or_init(|| metadata);
+                    select_package(&dep_metadata.packages, &spec, req.as_ref())?.ok_or_else(
+                        || Failure::not_found(format!("Package '{}' is not found", spec)),
+                    )?
+                }
+            },
+            None if metadata.packages.len() == 1 => &metadata.packages[0],
+            None => {
+                let choices = metadata
+                    .packages
+                    .iter()
+                    .map(|package| format!("-p {}", package.name))
+                    .collect::<Vec<_>>();
+                match pick(opts, "package", &choices)? {
+                    Some(ix) => &metadata.packages[ix],
+                    None => {
+                        eprintln!(
+                            "{:?} refers to multiple packages, you need to specify which one to use",
+                            opts.manifest_path
+                        );
+                        for choice in &choices {
+                            eprintln!("\t{choice}");
+                        }
+                        anyhow::bail!(Failure::ambiguous("Multiple packages found"))
+                    }
+                }
+            }
+        };
+
+        if opts.all_targets {
+            focus_package
+                .targets
+                .iter()
+                .filter_map(|target| opts::Focus::try_from(target).ok())
+                .map(|focus| (focus_package, focus))
+                .collect()
+        } else if !opts.focus.is_empty() {
+            opts.focus
+                .iter()
+                .map(|focus| Ok((focus_package, resolve_target(focus_package, focus)?)))
+                .collect::<anyhow::Result<_>>()?
+        } else {
+            let targets = focus_package
+                .targets
+                .iter()
+                .filter_map(|target| opts::Focus::try_from(target).ok())
+                .collect::<Vec<_>>();
+            // tests, benches and examples are rarely what one is after if there's lib or bin
+            let main_targets = targets
+                .iter()
+                .filter(|focus| matches!(focus, opts::Focus::Lib | opts::Focus::Bin(_)))
+                .cloned()
+                .collect::<Vec<_>>();
+            let focus_artifact = match (&main_targets[..], &targets[..]) {
+                (_, []) => anyhow::bail!(Failure::not_found("No targets found")),
+                ([focus], _) | ([], [focus]) => {
+                    let focus = focus.clone();
+                    if !opts.porcelain {
+                        eprintln!(
+                            "Note: using {} {}, the only {}target",
+                            focus_package.name,
+                            focus.as_cargo_args().collect::<Vec<_>>().join(" "),
+                            if targets.len() > 1 { "lib or bin " } else { "" },
+                        );
+                    }
+                    focus
+                }
+                _ => {
+                    let choices = targets
+                        .iter()
+                        .map(|focus| focus.as_cargo_args().collect::<Vec<_>>().join(" "))
+                        .collect::<Vec<_>>();
+                    if let Some(ix) = pick(opts, "target", &choices)? {
+                        targets[ix].clone()
+                    } else {
+                        eprintln!(
+                            "{} defines multiple targets, you need to specify which one to use:",
+                            focus_package.name
+                        );
+                        for choice in &choices {
+                            eprintln!("\t{choice}");
+                        }
+                        anyhow::bail!(Failure::ambiguous("Multiple targets found"))
+                    }
+                }
+            };
+            vec![(focus_package, focus_artifact)]
+        }
+    };
+    if jobs.is_empty() {
+        anyhow::bail!(Failure::not_found("No targets found"));
+    }
+    Ok(jobs)
+}
+
+/// Check the crate type of the selected libraries and note the features targets need
+pub fn check_jobs(
+    opts: &opts::Options,
+    jobs: &[(&cargo_metadata::Package, opts::Focus)],
+) -> anyhow::Result<()> {
+    for (package, focus) in jobs {
+        let lib = package
+            .targets
+            .iter()
+            .find(|t| matches!(opts::Focus::try_from(*t), Ok(opts::Focus::Lib)));
+        if let (opts::Focus::Lib, Some(lib)) = (focus, lib) {
+            match &opts.crate_type {
+                Some(crate_type) if !lib.crate_types.contains(crate_type) => {
+                    anyhow::bail!(
+                        "{} lib can be built as {}, not {crate_type}",
+                        package.name,
+                        lib.crate_types.join(", ")
+                    );
+                }
+                None if lib.crate_types.len() > 1 && opts.format.verbosity > 0 => {
+                    eprintln!(
+                        "Note: {} lib is built as {}, use --crate-type to pick one",
+                        package.name,
+                        lib.crate_types.join(", ")
+                    );
+                }
+                _ => {}
+            }
+        }
+        let required = missing_required_features(opts, package, focus);
+        if !required.is_empty() && !opts.porcelain {
+            eprintln!(
+                "Note: enabling features {} required by {} {}, use --no-required-features to disable",
+                required.join(", "),
+                package.name,
+                focus.as_cargo_args().collect::<Vec<_>>().join(" ")
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Find package by `name` or `name@version` spec
+///
+/// Returns `None` if there's no such package, fails if several versions match
+fn select_package<'a>(
+    packages: &'a [cargo_metadata::Package],
+    spec: &str,
+    req: Option<&VersionReq>,
+) -> anyhow::Result<Option<&'a cargo_metadata::Package>> {
+    let (name, version) = match spec.split_once('@') {
+        Some((name, version)) => (name, Some(version)),
+        None => (spec, None),
+    };
+    let found = packages
+        .iter()
+        .filter(|p| p.name == name)
+        .filter(|p| {
+            let full = p.version.to_string();
+            version.is_none_or(|v| full == v || full.starts_with(&format!("{v}.")))
+        })
+        .filter(|p| req.is_none_or(|req| req.matches(&p.version)))
+        .collect::<Vec<_>>();
+    match found[..] {
+        [] => Ok(None),
+        [package] => Ok(Some(package)),
+        _ => {
+            eprintln!("{spec} refers to multiple versions, you need to specify which one to use");
+            for package in found {
+                eprintln!("\t-p {}@{}", package.name, package.version);
+            }
+            anyhow::bail!(Failure::ambiguous("Multiple packages found"))
+        }
+    }
+}
+
+/// Dependencies can be renamed with `new_name = { package = "name" }`, find the package name
+/// and the version requirement for it
+fn resolve_rename(metadata: &cargo_metadata::Metadata, spec: &str) -> (String, Option<VersionReq>) {
+    let (name, version) = match spec.split_once('@') {
+        Some((name, version)) => (name, Some(version)),
+        None => (spec, None),
+    };
+    let renamed = metadata
+        .packages
+        .iter()
+        .flat_map(|p| &p.dependencies)
+        .find(|dep| dep.rename.as_deref() == Some(name));
+    match (renamed, version) {
+        (Some(dep), Some(version)) => (format!("{}@{version}", dep.name), None),
+        (Some(dep), None) => (dep.name.clone(), Some(dep.req.clone())),
+        (None, _) => (spec.to_owned(), None),
+    }
+}
+
+/// Package spec for cargo, dependencies can come in several versions
+pub fn package_spec(
+    metadata: &cargo_metadata::Metadata,
+    package: &cargo_metadata::Package,
+) -> String {
+    if metadata.workspace_members.contains(&package.id) {
+        package.name.clone()
+    } else {
+        format!("{}@{}", package.name, package.version)
+    }
+}
+
+/// Features `focus` target needs that are not enabled already, cargo skips the target otherwise
+pub fn missing_required_features(
+    opts: &opts::Options,
+    package: &cargo_metadata::Package,
+    focus: &opts::Focus,
+) -> Vec<String> {
+    if opts.cli_features.no_required_features || opts.cli_features.all_features {
+        return Vec::new();
+    }
+    package
+        .targets
+        .iter()
+        .find(|target| {
+            opts::Focus::try_from(*target).is_ok_and(|found| found.as_parts() == focus.as_parts())
+        })
+        .map_or_else(Vec::new, |target| {
+            target
+                .required_features
+                .iter()
+                .filter(|feat| !opts.cli_features.features.contains(feat))
+                .cloned()
+                .collect()
+        })
+}
+
+/// Make sure the named target exists, listing the ones that do otherwise
+///
+/// Targets can be given as paths to their sources, i.e. `--test tests/parser.rs`, find
+/// the name cargo uses for them
+fn resolve_target(
+    package: &cargo_metadata::Package,
+    focus: &opts::Focus,
+) -> anyhow::Result<opts::Focus> {
+    let (kind, Some(name)) = focus.as_parts() else {
+        return Ok(focus.clone());
+    };
+    let path = Path::new(name);
+    if path.extension().is_none_or(|ext| ext != "rs") {
+        let same_kind = package
+            .targets
+            .iter()
+            .filter_map(|target| opts::Focus::try_from(target).ok())
+            .filter(|found| found.as_parts().0 == kind)
+            .collect::<Vec<_>>();
+        if same_kind
+            .iter()
+            .any(|found| found.as_parts().1 == Some(name))
+        {
+            return Ok(focus.clone());
+        }
+        if same_kind.is_empty() {
+            anyhow::bail!(Failure::not_found(format!(
+                "{} has no {kind} targets",
+                package.name
+            )));
+        }
+        eprintln!("{} defines these {kind} targets:", package.name);
+        for found in &same_kind {
+            eprintln!("\t{}", found.as_cargo_args().collect::<Vec<_>>().join(" "));
+        }
+        anyhow::bail!(Failure::not_found(format!(
+            "No {kind} target named {name:?}"
+        )));
+    }
+    let package_dir = package.manifest_path.parent().unwrap().as_std_path();
+    let path = std::fs::canonicalize(path)
+        .or_else(|_| std::fs::canonicalize(package_dir.join(path)))
+        .with_context(|| format!("Can't find {kind} source file {name:?}"))?;
+    package
+        .targets
+        .iter()
+        .filter(|target| std::fs::canonicalize(&target.src_path).is_ok_and(|src| src == path))
+        .filter_map(|target| opts::Focus::try_from(target).ok())
+        .find(|found| found.as_parts().0 == kind)
+        .with_context(|| {
+            format!(
+                "{name:?} is not a source of any {kind} target in {}",
+                package.name
+            )
+        })
+}
+
+/// Ask to pick one of the `choices` when running in a terminal
+///
+/// Returns `None` if there's no one to ask or nothing was picked
+fn pick(opts: &opts::Options, what: &str, choices: &[String]) -> anyhow::Result<Option<usize>> {
+    if opts.porcelain || !(std::io::stdin().is_terminal() && std::io::stderr().is_terminal()) {
+        return Ok(None);
+    }
+    eprintln!("Multiple {what}s found:");
+    for (ix, choice) in choices.iter().enumerate() {
+        eprintln!("{:>4}) {choice}", ix + 1);
+    }
+    loop {
+        eprint!("Pick a {what} [1-{}], empty to cancel: ", choices.len());
+        std::io::stderr().flush()?;
+        let mut answer = String::new();
+        if std::io::stdin().read_line(&mut answer)? == 0 || answer.trim().is_empty() {
+            return Ok(None);
+        }
+        match answer.trim().parse::<usize>() {
+            Ok(n) if (1..=choices.len()).contains(&n) => return Ok(Some(n - 1)),
+            _ => eprintln!("{:?} is not one of the choices", answer.trim()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{resolve_rename, resolve_target, select_package};
+    use crate::test_util::{metadata, package};
+    use crate::{exit, opts::Focus};
+    use cargo_metadata::semver::VersionReq;
+
+    #[test]
+    fn package_versions() {
+        let packages = [
+            package("foo", "1.0.0", &[]),
+            package("foo", "2.1.0", &[]),
+            package("bar", "0.3.0", &[]),
+        ];
+        let version = |spec: &str, req: Option<&str>| {
+            let req = req.map(|req| VersionReq::parse(req).unwrap());
+            select_package(&packages, spec, req.as_ref())
+                .unwrap()
+                .map(|p| format!("{}@{}", p.name, p.version))
+        };
+        assert_eq!(version("bar", None).as_deref(), Some("bar@0.3.0"));
+        assert_eq!(version("foo@2", None).as_deref(), Some("foo@2.1.0"));
+        assert_eq!(version("foo@1.0.0", None).as_deref(), Some("foo@1.0.0"));
+        assert_eq!(version("foo", Some("^1")).as_deref(), Some("foo@1.0.0"));
+        assert_eq!(version("foo@3", None), None);
+        assert_eq!(version("baz", None), None);
+
+        let err = select_package(&packages, "foo", None).unwrap_err();
+        assert_eq!(exit::code(&err), exit::AMBIGUOUS);
+    }
+
+    #[test]
+    fn renamed_dependencies() {
+        let metadata = metadata(vec![
+            package("app", "0.1.0", &[("foo", Some("old_foo"), "^1")]),
+            package("foo", "1.0.0", &[]),
+            package("foo", "2.1.0", &[]),
+        ]);
+        let (name, req) = resolve_rename(&metadata, "old_foo");
+        assert_eq!(name, "foo");
+        assert_eq!(req, Some(VersionReq::parse("^1").unwrap()));
+        let found = select_package(&metadata.packages, &name, req.as_ref()).unwrap();
+        assert_eq!(
+            found.map(|p| p.version.to_string()).as_deref(),
+            Some("1.0.0")
+        );
+
+        assert_eq!(
+            resolve_rename(&metadata, "old_foo@2.1.0"),
+            ("foo@2.1.0".to_owned(), None)
+        );
+        assert_eq!(resolve_rename(&metadata, "foo"), ("foo".to_owned(), None));
+    }
+
+    #[test]
+    fn targets_by_path() {
+        let mut package = package("app", "0.1.0", &[]);
+        let mut parser = package.targets[1].clone();
+        parser.kind = vec!["test".to_owned()];
+        parser.name = "parser".to_owned();
+        parser.src_path = format!("{}/src/select.rs", env!("CARGO_MANIFEST_DIR")).into();
+        package.targets.push(parser);
+        let resolve = |focus| resolve_target(&package, &focus);
+        let parts = |focus: Focus| {
+            let (kind, name) = focus.as_parts();
+            format!("{kind} {}", name.unwrap_or_default())
+        };
+
+        let parser = Focus::Test("parser".to_owned());
+        assert_eq!(parts(resolve(parser).unwrap()), "test parser");
+        assert_eq!(
+            parts(resolve(Focus::Test("src/select.rs".to_owned())).unwrap()),
+            "test parser"
+        );
+        assert_eq!(
+            parts(resolve(Focus::Bin("src/main.rs".to_owned())).unwrap()),
+            "bin app"
+        );
+        assert_eq!(parts(resolve(Focus::Lib).unwrap()), "lib ");
+
+        let err = resolve(Focus::Test("lexer".to_owned())).unwrap_err();
+        assert_eq!(exit::code(&err), exit::NOT_FOUND);
+        let err = resolve(Focus::Example("demo".to_owned())).unwrap_err();
+        assert_eq!(err.to_string(), "app has no example targets");
+        // sources of targets of some other kind don't count
+        assert!(resolve(Focus::Test("src/main.rs".to_owned())).is_err());
+        assert!(resolve(Focus::Test("src/missing.rs".to_owned())).is_err());
+    }
+}
@@ -0,0 +1,331 @@
+#![allow(clippy::missing_errors_doc)]
+//! Local web server with a browsable and searchable view of everything in the generated files.
+//! Requests are handled one at a time: there's a single build and a handful of readers, a client
+//! that stops sending in the middle of a request is dropped after a few seconds.
+//!
+//! Browsers let any page send requests to a local server, so a request must name the address
+//! it came to in `Host` and in `Origin` if there is one. A page from elsewhere can't start a
+//! build and can't read anything through a name of its own that resolves to a local address.
+use crate::asm::Item;
+use crate::opts::{Format, Syntax};
+use crate::tui::{label, Backend};
+use anyhow::Context;
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::time::Duration;
+
+const PAGE: &str = include_str!("serve.html");
+
+/// How long a client gets to send the request, others wait for it meanwhile
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest request or header line read, anything longer is refused
+const MAX_LINE: u64 = 8 * 1024;
+
+/// Most headers read, the rest of the request is refused
+const MAX_HEADERS: usize = 64;
+
+/// Decode `%xx` escapes and `+` in a query string component
+fn decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut res = Vec::with_capacity(bytes.len());
+    let mut ix = 0;
+    while ix < bytes.len() {
+        match bytes[ix] {
+            b'+' => res.push(b' '),
+            b'%' => match s
+                .get(ix + 1..ix + 3)
+                .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                Some(b) => {
+                    res.push(b);
+                    ix += 2;
+                }
+                None => res.push(b'%'),
+            },
+            b => res.push(b),
+        }
+        ix += 1;
+    }
+    String::from_utf8_lossy(&res).into_owned()
+}
+
+/// Split `/path?a=1&b=2` into the path and the parameters
+fn parse_target(target: &str) -> (&str, BTreeMap<String, String>) {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let params = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode(key), decode(value))
+        })
+        .collect();
+    (path, params)
+}
+
+/// Read a line of at most [`MAX_LINE`] bytes
+fn read_line(reader: &mut impl BufRead, line: &mut String) -> anyhow::Result<usize> {
+    let len = reader.by_ref().take(MAX_LINE).read_line(line)?;
+    anyhow::ensure!(
+        len < MAX_LINE as usize || line.ends_with('\n'),
+        "Request line is longer than {MAX_LINE} bytes"
+    );
+    Ok(len)
+}
+
+/// `host` and `origin` headers both name `local`, the address the client connected to
+fn same_origin(host: Option<&str>, origin: Option<&str>, local: SocketAddr) -> bool {
+    let expected = |host: &str| {
+        host == local.to_string()
+            || (local.ip().is_loopback() && host == format!("localhost:{}", local.port()))
+    };
+    host.is_some_and(expected)
+        && origin.is_none_or(|origin| origin.strip_prefix("http://").is_some_and(expected))
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn ok(content_type: &'static str, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status: "200 OK",
+            content_type,
+            body: body.into(),
+        }
+    }
+
+    fn error(status: &'static str, msg: impl std::fmt::Display) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: msg.to_string().into_bytes(),
+        }
+    }
+}
+
+struct Server<'a> {
+    backend: &'a mut dyn Backend,
+    syntaxes: &'a [Syntax],
+    fmt: Format,
+    /// items for each kind of output, loaded when first requested
+    items: BTreeMap<usize, Vec<Item>>,
+}
+
+impl Server<'_> {
+    fn items(&mut self, kind: usize) -> anyhow::Result<&[Item]> {
+        if !self.items.contains_key(&kind) {
+            let items = self.backend.items(self.syntaxes[kind])?;
+            self.items.insert(kind, items);
+        }
+        Ok(&self.items[&kind])
+    }
+
+    /// Every route only reads except for `/rebuild`, it takes `POST` so a link or a prefetch
+    /// can't start a build
+    fn respond(&mut self, method: &str, target: &str) -> anyhow::Result<Response> {
+        let (path, params) = parse_target(target);
+        let expected = if path == "/rebuild" { "POST" } else { "GET" };
+        if method != expected {
+            return Ok(Response::error(
+                "405 Method Not Allowed",
+                format!("{path} takes {expected}"),
+            ));
+        }
+        let kind = params
+            .get("kind")
+            .and_then(|kind| self.syntaxes.iter().position(|s| label(*s) == kind))
+            .unwrap_or(0);
+        let flag = |name: &str| params.get(name).is_some_and(|value| value == "1");
+        Ok(match path {
+            "/" => Response::ok("text/html; charset=utf-8", PAGE),
+            "/kinds" => {
+                let kinds = self.syntaxes.iter().map(|s| label(*s)).collect::<Vec<_>>();
+                Response::ok("application/json", serde_json::to_vec(&kinds)?)
+            }
+            "/items" => Response::ok("application/json", serde_json::to_vec(self.items(kind)?)?),
+            "/code" => {
+                let hashed = params.get("item").map_or("", String::as_str);
+                let Some(item) = self
+                    .items(kind)?
+                    .iter()
+                    .find(|i| i.hashed == hashed)
+                    .cloned()
+                else {
+                    return Ok(Response::error("404 Not Found", "No such function"));
+                };
+                let mut fmt = self.fmt.clone();
+                fmt.rust = flag("rust");
+                fmt.simplify = flag("simplify");
+                let code = self.backend.render(self.syntaxes[kind], &fmt, &item)?;
+                Response::ok("text/plain; charset=utf-8", code)
+            }
+            "/rebuild" => {
+                self.backend.rebuild()?;
+                self.items.clear();
+                Response::ok("application/json", "true")
+            }
+            _ => Response::error("404 Not Found", "Not found"),
+        })
+    }
+
+    fn handle(&mut self, stream: TcpStream) -> anyhow::Result<()> {
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let mut reader = BufReader::new(&stream);
+        let mut request = String::new();
+        read_line(&mut reader, &mut request)?;
+        // only `Host` and `Origin` are interesting, but all of them need to be read before
+        // responding
+        let mut headers = BTreeMap::new();
+        let mut header = String::new();
+        while read_line(&mut reader, &mut header)? > 2 {
+            anyhow::ensure!(
+                headers.len() < MAX_HEADERS,
+                "More than {MAX_HEADERS} headers"
+            );
+            if let Some((name, value)) = header.split_once(':') {
+                headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_owned());
+            }
+            header.clear();
+        }
+        let host = headers.get("host").map(String::as_str);
+        let origin = headers.get("origin").map(String::as_str);
+        let mut words = request.split_whitespace();
+        let response = match (words.next(), words.next()) {
+            _ if !same_origin(host, origin, stream.local_addr()?) => {
+                Response::error("403 Forbidden", "Request from another origin")
+            }
+            (Some(method), Some(target)) => self.respond(method, target).unwrap_or_else(|err| {
+                Response::error("500 Internal Server Error", format!("{err:#}"))
+            }),
+            _ => Response::error("400 Bad Request", "Not an HTTP request"),
+        };
+        let mut stream = &stream;
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+            response.status,
+            response.content_type,
+            response.body.len()
+        )?;
+        stream.write_all(&response.body)?;
+        stream.flush()?;
+        Ok(())
+    }
+}
+
+/// Serve the view on `addr` until interrupted, `syntaxes` are the kinds of output available
+/// without rebuilding
+pub fn run(
+    backend: &mut dyn Backend,
+    syntaxes: &[Syntax],
+    fmt: Format,
+    addr: SocketAddr,
+) -> anyhow::Result<()> {
+    let listener =
+        TcpListener::bind(addr).with_context(|| format!("Failed to listen on {addr}"))?;
+    eprintln!(
+        "Serving on http://{}/, press Ctrl-C to stop",
+        listener.local_addr()?
+    );
+    let mut server = Server {
+        backend,
+        syntaxes,
+        fmt,
+        items: BTreeMap::new(),
+    };
+    for stream in listener.incoming() {
+        let res = stream
+            .map_err(anyhow::Error::from)
+            .and_then(|stream| server.handle(stream));
+        if let Err(err) = res {
+            eprintln!("Failed to handle a request: {err:#}");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode, parse_target, read_line, same_origin, Server};
+    use crate::opts::Syntax;
+    use crate::test_util::{fmt, Fake};
+    use std::net::SocketAddr;
+
+    #[test]
+    fn query() {
+        assert_eq!(decode("a%3A%3Ab+c%zz"), "a::b c%zz");
+        let (path, params) = parse_target("/code?kind=llvm&item=%3CT%3E%3A%3Af");
+        assert_eq!(path, "/code");
+        assert_eq!(params["kind"], "llvm");
+        assert_eq!(params["item"], "<T>::f");
+    }
+
+    #[test]
+    fn limits() {
+        let long = "a".repeat(10_000);
+        let mut line = String::new();
+        assert!(read_line(&mut long.as_bytes(), &mut line).is_err());
+        line.clear();
+        assert_eq!(
+            read_line(&mut "GET / HTTP/1.1\r\n".as_bytes(), &mut line).unwrap(),
+            16
+        );
+    }
+
+    #[test]
+    fn origins() {
+        let local: SocketAddr = "127.0.0.1:8000".parse().unwrap();
+        assert!(same_origin(Some("127.0.0.1:8000"), None, local));
+        assert!(same_origin(
+            Some("localhost:8000"),
+            Some("http://localhost:8000"),
+            local
+        ));
+        assert!(!same_origin(None, None, local));
+        assert!(!same_origin(Some("evil.example:8000"), None, local));
+        assert!(!same_origin(
+            Some("127.0.0.1:8000"),
+            Some("http://evil.example"),
+            local
+        ));
+        let lan: SocketAddr = "192.168.1.2:8000".parse().unwrap();
+        assert!(same_origin(Some("192.168.1.2:8000"), None, lan));
+        assert!(!same_origin(Some("localhost:8000"), None, lan));
+    }
+
+    #[test]
+    fn routes() {
+        let syntaxes = [Syntax::Intel, Syntax::Llvm];
+        let mut server = Server {
+            backend: &mut Fake,
+            syntaxes: &syntaxes,
+            fmt: fmt(&[]),
+            items: Default::default(),
+        };
+        let body = |server: &mut Server, target| {
+            let response = server.respond("GET", target).unwrap();
+            (response.status, String::from_utf8(response.body).unwrap())
+        };
+        assert_eq!(body(&mut server, "/kinds").1, r#"["asm","llvm"]"#);
+        assert_eq!(
+            body(&mut server, "/code?kind=llvm&item=ir::h1"),
+            ("200 OK", "ir:\n\tret".to_owned())
+        );
+        assert!(body(&mut server, "/code?item=inner::h1&rust=1")
+            .1
+            .contains("let x = 1;"));
+        assert_eq!(body(&mut server, "/code?item=ir::h1").0, "404 Not Found");
+        assert_eq!(body(&mut server, "/nope").0, "404 Not Found");
+        assert_eq!(body(&mut server, "/rebuild").0, "405 Method Not Allowed");
+        let rebuild = server.respond("POST", "/rebuild").unwrap();
+        assert_eq!(rebuild.status, "200 OK");
+    }
+}
@@ -0,0 +1,770 @@
+#![allow(clippy::missing_errors_doc)]
+//! Toolchain, target and configuration the targets are built with, and the cargo command
+//! building each one of them
+use crate::artifacts::{
+    find_built, invalidate, keep, locate_asm_path_via_artifact, profile_dir, target_name,
+};
+use crate::asm::Item;
+use crate::cargo_config::CargoConfig;
+use crate::opts;
+use crate::process::{build_artifact, command_line};
+use crate::select::{missing_required_features, package_spec};
+use crate::{cache, doctest, wrapper};
+use anyhow::Context;
+use std::collections::BTreeSet;
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Everything the targets are built with, shared by all the kinds of output
+pub struct Session<'a> {
+    pub opts: &'a opts::Options,
+    pub metadata: &'a cargo_metadata::Metadata,
+    pub sysroot: PathBuf,
+    pub cargo_path: String,
+    pub rustc_version: String,
+    pub cargo_config: CargoConfig,
+    /// target cargo is going to build for, if not host
+    pub target: Option<String>,
+    pub x86: bool,
+    pub color: bool,
+    pub incremental: bool,
+    /// `profile` table of the workspace manifest
+    pub manifest_profiles: Option<toml::Value>,
+    pub cache_dir: PathBuf,
+    pub this_exe: PathBuf,
+    /// rustflags from the config files or the environment, cargo passes them after ours
+    pub rustflags: Vec<String>,
+    /// what changes the output without showing up in the command: the rustflags cargo adds on
+    /// its own and `--const-eval` the list of items in MIR depends on
+    pub key_extra: Vec<String>,
+}
+
+impl<'a> Session<'a> {
+    /// Find the toolchain and the target, check the flags make sense for them
+    pub fn new(
+        opts: &'a opts::Options,
+        metadata: &'a cargo_metadata::Metadata,
+        cargo_path: String,
+        rustc_path: &str,
+        color: bool,
+    ) -> anyhow::Result<Self> {
+        let sysroot = sysroot(rustc_path, opts)?;
+        if opts.format.verbosity > 0 {
+            eprintln!("Found sysroot: {}", sysroot.display());
+        }
+
+        let rustc_version = rustc_version(rustc_path)?;
+
+        let cargo_config = CargoConfig::load(&std::env::current_dir()?)?;
+        if opts.format.verbosity > 0 {
+            for file in &cargo_config.files {
+                eprintln!("Found cargo config: {}", file.display());
+            }
+        }
+
+        let target = build_target(
+            opts.target.as_deref(),
+            cargo_config.target.as_deref(),
+            opts.ignore_config,
+            || host_triple(&rustc_version),
+        )?;
+
+        let triple = match &target {
+            Some(target) => target.clone(),
+            None => host_triple(&rustc_version)?,
+        };
+        let x86 = is_x86(&triple);
+        if !x86
+            && matches!(opts.syntax, Some(opts::Syntax::Intel | opts::Syntax::Att))
+            && !opts.porcelain
+        {
+            eprintln!(
+                "Note: --intel and --att only apply to x86 targets, showing the native syntax of {}",
+                target.as_deref().unwrap_or("the host")
+            );
+        }
+
+        // cargo applies the config by itself, rustflags are only needed to tell what it does
+        let rustflags = if opts.ignore_config {
+            Vec::new()
+        } else {
+            cargo_config.rustflags_for(target_name(&triple))
+        };
+        if !opts.ignore_config && !opts.porcelain {
+            for key in cargo_config.cfg_rustflags() {
+                eprintln!(
+                    "Note: rustflags from target.'{key}' in cargo config are not accounted for, cargo still applies them if the target matches"
+                );
+            }
+            if opts.target_cpu.is_some()
+                && rustflags.iter().any(|flag| flag.contains("target-cpu="))
+            {
+                eprintln!(
+                    "Note: cargo passes rustflags from the config last, their target-cpu overrides --target-cpu, use --ignore-config to drop them"
+                );
+            }
+            if opts.codegen_units.is_none()
+                && cargo_config
+                    .profile_setting(opts.compile_mode.profile_name(), "codegen-units")
+                    .is_some()
+            {
+                eprintln!("Note: codegen-units from cargo config is ignored, asm is generated with codegen-units=1, use --codegen-units to change it");
+            }
+        }
+
+        if opts.build_std.is_some() && target.is_none() {
+            anyhow::bail!("--build-std requires an explicit --target");
+        }
+
+        if !opts.target_feature.is_empty() {
+            check_target_features(
+                rustc_path,
+                &sysroot,
+                target.as_deref(),
+                &opts.target_feature,
+            )?;
+        }
+
+        // Incremental compilation can reuse codegen units from the previous session without
+        // producing assembly for them, leaving stale or missing files. Workspace members are
+        // rebuilt with our flags anyway so it's cheap to disable.
+        let manifest_profiles = std::fs::read_to_string(metadata.workspace_root.join("Cargo.toml"))
+            .ok()
+            .and_then(|manifest| manifest.parse::<toml::Value>().ok())
+            .and_then(|manifest| manifest.get("profile").cloned());
+        let incremental =
+            cargo_config.incremental(opts.compile_mode.profile_name(), manifest_profiles.as_ref());
+        if incremental && !opts.porcelain && !opts.no_build {
+            eprintln!(
+                "Note: incremental compilation is disabled to get complete and up to date output"
+            );
+        }
+
+        if let Some(wrapper) = &cargo_config.rustc_wrapper {
+            if opts.format.verbosity > 0 {
+                eprintln!("Not using rustc wrapper {wrapper:?}");
+            }
+        }
+
+        let cache_dir = opts
+            .target_dir
+            .clone()
+            .unwrap_or_else(|| metadata.target_directory.clone().into_std_path_buf())
+            .join("cargo-show-asm");
+        let this_exe = std::env::current_exe()?;
+
+        let mut key_extra = rustflags.clone();
+        if opts.format.const_eval {
+            key_extra.push("--const-eval".to_owned());
+        }
+        Ok(Session {
+            opts,
+            metadata,
+            sysroot,
+            cargo_path,
+            rustc_version,
+            cargo_config,
+            target,
+            x86,
+            color,
+            incremental,
+            manifest_profiles,
+            cache_dir,
+            this_exe,
+            rustflags,
+            key_extra,
+        })
+    }
+
+    pub fn command(
+        &self,
+        focus_package: &cargo_metadata::Package,
+        focus_artifact: &opts::Focus,
+    ) -> Command {
+        let mut cmd = match &self.opts.cross {
+            Some(cross) => std::process::Command::new(cross.as_deref().unwrap_or("cross")),
+            None => std::process::Command::new(&self.cargo_path),
+        };
+
+        let doctest = matches!(focus_artifact, opts::Focus::Doctest(_));
+        let build_script = matches!(focus_artifact, opts::Focus::BuildScript);
+
+        // `cargo rustc` is what passes our flags to the selected target alone, but it only takes
+        // them for one target at a time: several targets need a run each. Flags a wrapper adds
+        // to a single `cargo build` are not a part of the fingerprint, cargo would skip targets
+        // built without them before and leave no file to show.
+
+        // Cargo flags.
+        cmd.arg(if doctest {
+            "test"
+        } else if build_script {
+            // build script runs during check too and the rest doesn't need to be compiled
+            "check"
+        } else {
+            "rustc"
+        })
+        // General.
+        .args((!doctest).then_some("--message-format=json"))
+        .args(["--color", if self.color { "always" } else { "never" }])
+        .args(self.opts.porcelain.then_some("--quiet"))
+        .args(std::iter::repeat_n("-v", self.opts.format.verbosity))
+        // Workspace location.
+        .arg("--manifest-path")
+        .arg(&self.opts.manifest_path)
+        // Artifact selectors.
+        .args(["--package", &package_spec(self.metadata, focus_package)])
+        .args(if build_script {
+            // cargo can't build just the build script, lib is the cheapest thing next to it
+            let has_lib = focus_package
+                .targets
+                .iter()
+                .any(|t| matches!(opts::Focus::try_from(t), Ok(opts::Focus::Lib)));
+            has_lib.then(|| "--lib".to_owned()).into_iter().collect()
+        } else {
+            focus_artifact.as_cargo_args().collect::<Vec<_>>()
+        })
+        .args(
+            self.opts
+                .crate_type
+                .iter()
+                .filter(|_| matches!(focus_artifact, opts::Focus::Lib))
+                .flat_map(|crate_type| ["--crate-type", crate_type]),
+        )
+        // Compile options.
+        .args(self.opts.dry.then_some("--dry"))
+        .args(self.opts.frozen.then_some("--frozen"))
+        .args(self.opts.locked.then_some("--locked"))
+        .args(self.opts.offline.then_some("--offline"))
+        .args(self.target.iter().flat_map(|t| ["--target", t]))
+        .args(self.opts.build_std.as_ref().map(|crates| match crates {
+            Some(crates) => format!("-Zbuild-std={crates}"),
+            None => "-Zbuild-std".to_owned(),
+        }))
+        .args(
+            self.opts
+                .target_dir
+                .iter()
+                .flat_map(|t| [OsStr::new("--target-dir"), t.as_ref()]),
+        )
+        .args(
+            self.opts
+                .cli_features
+                .no_default_features
+                .then_some("--no-default-features"),
+        )
+        .args(
+            self.opts
+                .cli_features
+                .all_features
+                .then_some("--all-features"),
+        )
+        .args(
+            self.opts
+                .cli_features
+                .features
+                .iter()
+                .chain(&missing_required_features(
+                    self.opts,
+                    focus_package,
+                    focus_artifact,
+                ))
+                .flat_map(|feat| ["--features", feat]),
+        );
+        if self.opts.ignore_config {
+            // takes priority over any rustflags specified in configs
+            cmd.env("CARGO_ENCODED_RUSTFLAGS", "");
+        }
+        if self.incremental {
+            cmd.env("CARGO_INCREMENTAL", "0");
+        }
+        if build_script {
+            // build scripts are compiled as dependencies, our flags are passed by the wrapper
+            cmd.env("RUSTC_WRAPPER", &self.this_exe)
+                .env(wrapper::BUILD_SCRIPT_VAR, &focus_package.name)
+                .envs(wrapper::envs(
+                    self.opts.emit(),
+                    &codegen_flags(self.opts, self.x86),
+                ));
+        } else if self.cargo_config.rustc_wrapper.is_some() {
+            // caching wrappers such as sccache don't know about the extra files `--emit`
+            // produces and can either refuse to cache or restore the rlib without them.
+            // Wrapper is not a part of the fingerprint so dependencies are not rebuilt.
+            cmd.env("RUSTC_WRAPPER", "");
+        }
+        match &self.opts.compile_mode {
+            opts::CompileMode::Dev => {}
+            opts::CompileMode::Release => {
+                cmd.arg("--release");
+            }
+            opts::CompileMode::Custom(profile) => {
+                cmd.args(["--profile", profile]);
+            }
+        }
+
+        if doctest {
+            // rustdoc compiles doctests by itself, it gets the same flags and `--emit` is added
+            // by this binary acting as a wrapper for rustc
+            let rustdoc_flags = [
+                OsString::from("-Zunstable-options"),
+                OsString::from("--persist-doctests"),
+                doctest::persist_dir(&self.cache_dir, &focus_package.name).into(),
+                OsString::from("--test-builder-wrapper"),
+                self.this_exe.clone().into(),
+            ];
+            cmd.env(
+                "CARGO_ENCODED_RUSTDOCFLAGS",
+                rustdoc_flags.join(OsStr::new("\x1f")),
+            )
+            .envs(wrapper::envs(
+                self.opts.emit(),
+                &codegen_flags(self.opts, self.x86),
+            ));
+        } else if !build_script {
+            // Cargo flags terminator.
+            cmd.arg("--")
+                // Rustc flags.
+                // We care about asm.
+                .args(["--emit", self.opts.emit()])
+                .args(codegen_flags(self.opts, self.x86));
+        }
+
+        cmd.envs(self.opts.env.iter().map(|(k, v)| (k, v)));
+        cmd
+    }
+
+    pub fn print_commands(
+        &self,
+        jobs: &[(&cargo_metadata::Package, opts::Focus)],
+    ) -> anyhow::Result<()> {
+        if !self.rustflags.is_empty() {
+            println!(
+                "# cargo will also pass rustflags from the config: {}",
+                self.rustflags.join(" ")
+            );
+        }
+        for (package, focus) in jobs {
+            println!("{}", command_line(&self.command(package, focus)));
+        }
+        Ok(())
+    }
+
+    /// Build the target or reuse file generated for it before
+    fn build_or_reuse(
+        &self,
+        focus_package: &cargo_metadata::Package,
+        focus_artifact: &opts::Focus,
+    ) -> anyhow::Result<(PathBuf, Option<Vec<Item>>, Option<u64>)> {
+        let cache_key = cache::key(
+            &self.command(focus_package, focus_artifact),
+            &self.rustc_version,
+            &self.key_extra,
+        );
+        if self.opts.no_build {
+            // a file from a run with the same flags is the best match, whatever its age
+            if let Some(entry) =
+                cache::load(&self.cache_dir, cache_key).filter(|e| e.asm_path.exists())
+            {
+                return Ok((entry.asm_path, entry.index, Some(cache_key)));
+            }
+            let target_dir = self
+                .opts
+                .target_dir
+                .clone()
+                .unwrap_or_else(|| self.metadata.target_directory.clone().into_std_path_buf());
+            let asm_path = find_built(
+                self.opts,
+                &target_dir,
+                self.target.as_deref(),
+                focus_package,
+                focus_artifact,
+            )?;
+            return Ok((asm_path, None, None));
+        }
+        let cached = if self.opts.no_cache {
+            None
+        } else {
+            cache::load(&self.cache_dir, cache_key).filter(|entry| {
+                cache::is_fresh(&entry.asm_path, self.metadata.workspace_root.as_ref())
+            })
+        };
+
+        if let Some(entry) = cached {
+            if self.opts.format.verbosity > 0 {
+                eprintln!("Reusing previously generated file, use --no-cache to rebuild");
+            }
+            return Ok((entry.asm_path, entry.index, Some(cache_key)));
+        }
+
+        if let opts::Focus::Doctest(name) = focus_artifact {
+            let dir = doctest::persist_dir(&self.cache_dir, &focus_package.name);
+            // leftovers from previous runs make it impossible to tell which doctests still exist
+            if dir.exists() {
+                std::fs::remove_dir_all(&dir)?;
+            }
+            // doctests are executed after they are built, keep stdout for the results
+            let status = self
+                .command(focus_package, focus_artifact)
+                .stdin(Stdio::null())
+                .stdout(Stdio::from(std::io::stderr()))
+                .status()?;
+            if !status.success() {
+                eprintln!("Cargo failed with {}", status);
+            }
+            return Ok((
+                doctest::locate(&dir, name, self.opts.syntax().ext())?,
+                None,
+                None,
+            ));
+        }
+
+        let build = || -> anyhow::Result<_> {
+            let artifact = build_artifact(
+                self.command(focus_package, focus_artifact),
+                self.opts,
+                self.metadata,
+                focus_package,
+                focus_artifact,
+            )?;
+            if self.opts.format.verbosity > 0 {
+                eprintln!("Artifact files: {:?}", artifact.filenames);
+            }
+            Ok((
+                artifact.fresh,
+                locate_asm_path_via_artifact(&artifact, self.opts.syntax().ext()),
+            ))
+        };
+        let asm_path = match build()? {
+            // Cargo only looks at its own fingerprints, if generated file went missing or
+            // is older than the sources it was made from - it needs to be made again
+            (true, asm_path)
+                if asm_path.as_ref().map_or(true, |path| {
+                    cache::older_than_sources(path, self.metadata.workspace_root.as_ref())
+                }) =>
+            {
+                if !self.opts.porcelain {
+                    eprintln!(
+                        "Note: generated file is missing or older than the sources, rebuilding"
+                    );
+                }
+                let target_dir =
+                    self.opts.target_dir.clone().unwrap_or_else(|| {
+                        self.metadata.target_directory.clone().into_std_path_buf()
+                    });
+                invalidate(
+                    &profile_dir(
+                        &target_dir,
+                        self.target.as_deref(),
+                        self.opts.compile_mode.profile_name(),
+                    ),
+                    &focus_package.name,
+                    asm_path.as_ref().ok(),
+                )?;
+                build()?.1
+            }
+            (_, asm_path) => asm_path,
+        }?;
+        let entry = cache::Entry {
+            asm_path,
+            index: None,
+        };
+        cache::store(&self.cache_dir, cache_key, &entry)?;
+        Ok((entry.asm_path, entry.index, Some(cache_key)))
+    }
+
+    pub fn produce(
+        &self,
+        focus_package: &cargo_metadata::Package,
+        focus_artifact: &opts::Focus,
+    ) -> anyhow::Result<(PathBuf, Option<Vec<Item>>, Option<u64>)> {
+        let res = self.build_or_reuse(focus_package, focus_artifact)?;
+        if let Some(dir) = &self.opts.keep {
+            let flags = cache::key(
+                &self.command(focus_package, focus_artifact),
+                &self.rustc_version,
+                &self.key_extra,
+            );
+            keep(self.opts, dir, focus_package, focus_artifact, flags, &res.0)?;
+        }
+        Ok(res)
+    }
+}
+
+pub fn sysroot(rustc_path: &str, opts: &opts::Options) -> anyhow::Result<PathBuf> {
+    let output = std::process::Command::new(rustc_path)
+        .arg("--print=sysroot")
+        .args(
+            opts.sysroot
+                .iter()
+                .flat_map(|s| [OsStr::new("--sysroot"), s.as_ref()]),
+        )
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .stdout(Stdio::piped())
+        .output()?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to get sysroot. '{} --print=sysroot' exited with {}",
+            rustc_path,
+            output.status,
+        );
+    }
+    // `rustc` prints a trailing newline.
+    Ok(PathBuf::from(
+        std::str::from_utf8(&output.stdout)?.trim_end(),
+    ))
+}
+
+/// Verbose version info, identifies the toolchain
+fn rustc_version(rustc_path: &str) -> anyhow::Result<String> {
+    let output = std::process::Command::new(rustc_path)
+        .arg("-vV")
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .stdout(Stdio::piped())
+        .output()?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to get rustc version. '{} -vV' exited with {}",
+            rustc_path,
+            output.status,
+        );
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// Target triple rustc generates code for by default
+fn host_triple(rustc_version: &str) -> anyhow::Result<String> {
+    rustc_version
+        .lines()
+        .find_map(|line| line.strip_prefix("host: "))
+        .map(String::from)
+        .context("rustc didn't report the host triple")
+}
+
+/// Target cargo is going to build for, if not host: `--target` or `build.target` from the
+/// cargo config. Cargo reads the config regardless of `--ignore-config`, ignoring its target
+/// takes asking for the `host` one explicitly
+fn build_target(
+    cli: Option<&str>,
+    config: Option<&str>,
+    ignore_config: bool,
+    host: impl FnOnce() -> anyhow::Result<String>,
+) -> anyhow::Result<Option<String>> {
+    Ok(match (cli, config) {
+        (Some(target), _) => Some(target.to_owned()),
+        (None, Some(_)) if ignore_config => Some(host()?),
+        (None, config) => config.map(String::from),
+    })
+}
+
+/// Make sure all the requested target features are known to rustc for the active target
+///
+/// `rustc` itself only warns about unknown features and goes on generating the code
+fn check_target_features(
+    rustc_path: &str,
+    sysroot: &Path,
+    target: Option<&str>,
+    features: &[String],
+) -> anyhow::Result<()> {
+    let output = std::process::Command::new(rustc_path)
+        .arg("--print=target-features")
+        .arg("--sysroot")
+        .arg(sysroot)
+        .args(target.iter().flat_map(|t| ["--target", t]))
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .stdout(Stdio::piped())
+        .output()?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to get target features. '{} --print=target-features' exited with {}",
+            rustc_path,
+            output.status,
+        );
+    }
+    check_feature_list(std::str::from_utf8(&output.stdout)?, features, rustc_path)
+}
+
+/// Check `features` against the `list` `rustc --print=target-features` gives
+fn check_feature_list(list: &str, features: &[String], rustc_path: &str) -> anyhow::Result<()> {
+    let known = list
+        .lines()
+        .filter_map(|line| line.strip_prefix("    ")?.split_whitespace().next())
+        .collect::<BTreeSet<_>>();
+
+    for feature in features.iter().flat_map(|f| f.split(',')) {
+        let name = feature
+            .strip_prefix(['+', '-'])
+            .with_context(|| format!("Target feature {feature:?} must start with '+' or '-'"))?;
+        if !known.contains(name) {
+            anyhow::bail!(
+                "Unknown target feature {:?}, see '{} --print=target-features' for the list of supported ones",
+                name,
+                rustc_path
+            );
+        }
+    }
+    Ok(())
+}
+
+/// `--intel` and `--att` only mean something for x86 targets, custom target specs say which
+/// architecture they are for
+fn is_x86(target: &str) -> bool {
+    let arch = if Path::new(target)
+        .extension()
+        .is_some_and(|ext| ext == "json")
+    {
+        let spec = std::fs::read_to_string(target)
+            .ok()
+            .and_then(|spec| serde_json::from_str::<serde_json::Value>(&spec).ok());
+        match spec.as_ref().and_then(|spec| spec["arch"].as_str()) {
+            Some(arch) => arch.to_owned(),
+            // let rustc complain about it
+            None => return true,
+        }
+    } else {
+        target.split('-').next().unwrap_or(target).to_owned()
+    };
+    arch == "x86_64" || arch == "x86" || ["i386", "i586", "i686"].contains(&arch.as_str())
+}
+
+/// Flags passed to rustc along with `--emit`, assembly syntax flags are only known on `x86`
+pub fn codegen_flags(opts: &opts::Options, x86: bool) -> Vec<OsString> {
+    let mut flags = vec![
+        // So only one file gets created, several get merged after the build
+        format!("-Ccodegen-units={}", opts.codegen_units.unwrap_or(1)).into(),
+        // Debug info is needed to map to rust source.
+        OsString::from("-Cdebuginfo=2"),
+    ];
+    // asm is generated along with the rest for --tui, --serve and --lsp
+    let syntax = match opts.syntax() {
+        opts::Syntax::Llvm | opts::Syntax::Mir if opts.emit_all() => &opts::Syntax::Intel,
+        syntax => syntax,
+    };
+    flags.extend(
+        syntax
+            .format()
+            .filter(|_| x86)
+            .iter()
+            .flat_map(|s| ["-C", s])
+            .map(OsString::from),
+    );
+    if let Some(sysroot) = &opts.sysroot {
+        flags.push("--sysroot".into());
+        flags.push(sysroot.into());
+    }
+    if let Some(cpu) = &opts.target_cpu {
+        flags.push(format!("-Ctarget-cpu={cpu}").into());
+    }
+    if !opts.target_feature.is_empty() {
+        flags.push(format!("-Ctarget-feature={}", opts.target_feature.join(",")).into());
+    }
+    if let Some(san) = opts.sanitizer {
+        flags.push(format!("-Zsanitizer={}", san.as_str()).into());
+    }
+    if opts.coverage {
+        flags.push("-Cinstrument-coverage".into());
+    }
+    if opts.frame_pointers {
+        flags.push("-Cforce-frame-pointers=yes".into());
+    }
+    if opts.force_codegen {
+        // collects all the functions instead of ones reachable from exported ones
+        flags.push("-Clink-dead-code".into());
+    }
+    if opts.asm_comments {
+        flags.push("-Zverbose-asm".into());
+    }
+    flags
+}
+
+#[cfg(test)]
+mod test {
+    use super::{build_target, check_feature_list, is_x86};
+    use crate::artifacts::target_name;
+
+    #[test]
+    fn build_targets() {
+        let host = || Ok("x86_64-unknown-linux-gnu".to_owned());
+        let target =
+            |cli, config, ignore_config| build_target(cli, config, ignore_config, host).unwrap();
+        let thumb = "thumbv7em-none-eabihf";
+        let riscv = "riscv32imac-unknown-none-elf";
+        assert_eq!(
+            target(Some(thumb), Some(riscv), false).as_deref(),
+            Some(thumb)
+        );
+        assert_eq!(target(None, Some(riscv), false).as_deref(), Some(riscv));
+        assert_eq!(target(None, None, false), None);
+        assert_eq!(
+            target(Some(thumb), Some(riscv), true).as_deref(),
+            Some(thumb)
+        );
+        assert_eq!(
+            target(None, Some(riscv), true).as_deref(),
+            Some("x86_64-unknown-linux-gnu")
+        );
+        assert_eq!(target(None, None, true), None);
+    }
+
+    #[test]
+    fn target_specs() {
+        let dir = std::env::temp_dir().join(format!("cargo-show-asm-spec-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let spec = dir.join("my-board.json");
+        std::fs::write(
+            &spec,
+            r#"{ "arch": "arm", "llvm-target": "thumbv7em-none-eabihf" }"#,
+        )
+        .unwrap();
+        let spec = spec.to_str().unwrap();
+        let target = build_target(
+            Some(spec),
+            Some("x86_64-pc-windows-msvc"),
+            false,
+            || unreachable!(),
+        )
+        .unwrap();
+        assert_eq!(target.as_deref(), Some(spec));
+        assert_eq!(target_name(spec), "my-board");
+        assert!(!is_x86(spec));
+        assert!(is_x86("i686-pc-windows-msvc"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn target_features() {
+        let list = "Features supported by rustc for this target:
+    adx                 - Support ADX instructions.
+    avx2                - Enable AVX2 instructions.
+    crt-static          - Enables C Run-time Libraries to be statically linked.
+
+Code-generation features supported by LLVM for this target:
+    fma                 - Enable three-operand fused multiple-add.
+    sse4.2              - Enable SSE 4.2 instructions.
+
+Use +feature to enable a feature, or -feature to disable it.
+For example, rustc -C target-cpu=mycpu -C target-feature=+feature1,-feature2
+";
+        let check = |features: &[&str]| {
+            let features = features.iter().map(|f| (*f).to_owned()).collect::<Vec<_>>();
+            check_feature_list(list, &features, "rustc").map_err(|err| err.to_string())
+        };
+        assert_eq!(check(&[]), Ok(()));
+        assert_eq!(check(&["+avx2,+fma"]), Ok(()));
+        assert_eq!(check(&["+avx2", "-sse4.2", "+crt-static"]), Ok(()));
+        assert_eq!(
+            check(&["+avx2,+avx512"]),
+            Err("Unknown target feature \"avx512\", see 'rustc --print=target-features' for the list of supported ones".to_owned())
+        );
+        assert_eq!(
+            check(&["avx2"]),
+            Err("Target feature \"avx2\" must start with '+' or '-'".to_owned())
+        );
+        assert!(check(&["+avx2,"]).is_err());
+        // words from the text around the list are not features
+        assert!(check(&["+Use"]).is_err());
+        assert!(check(&["+For"]).is_err());
+    }
+}
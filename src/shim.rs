@@ -0,0 +1,111 @@
+#![allow(clippy::missing_errors_doc)]
+//! Generic functions only get code in the crates that use them with concrete types. To see one
+//! a tiny crate referring to `path::to::function::<Types>` is compiled against the metadata of
+//! the crate that defines it and its code is taken from there.
+use anyhow::Context;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const SHIM: &str = "cargo_show_asm_shim";
+
+/// Check if `function` comes with type arguments, those need a shim
+#[must_use]
+pub fn is_instance(function: &str) -> bool {
+    function.contains("::<")
+}
+
+/// Name of the instance of `function` in the listings: type arguments are not a part of it
+#[must_use]
+pub fn item_name(function: &str) -> &str {
+    function
+        .split_once("::<")
+        .map_or(function, |(name, _)| name)
+}
+
+/// Source of the shim crate: an exported function takes the address of `function` so rustc has
+/// to generate the code for it
+fn source(function: &str) -> String {
+    format!("#[no_mangle]\npub extern \"C\" fn {SHIM}() -> usize {{\n    {function} as *const () as usize\n}}\n")
+}
+
+/// Compile the shim for `function` against the crate rustc produced `asm_path` for, `emit`
+/// and `flags` are the same as for the crate itself, `opt_level` and `edition` come from its
+/// profile and package. Gives the path of the generated file
+#[allow(clippy::too_many_arguments)]
+pub fn build(
+    asm_path: &Path,
+    function: &str,
+    emit: &str,
+    target: Option<&str>,
+    opt_level: &str,
+    edition: &str,
+    flags: &[OsString],
+) -> anyhow::Result<PathBuf> {
+    let deps = asm_path
+        .parent()
+        .context("Generated file has no parent directory")?;
+    let stem = asm_path
+        .file_stem()
+        .context("Generated file has no name")?
+        .to_string_lossy();
+    // `{crate}-{hash}`, the same name rustc uses for the metadata
+    let krate = stem.split_once('-').map_or(&*stem, |(name, _)| name);
+    let metadata = ["rmeta", "rlib"]
+        .iter()
+        .map(|ext| deps.join(format!("lib{stem}.{ext}")))
+        .find(|path| path.exists())
+        .with_context(|| format!("No metadata for {krate} next to {}", asm_path.display()))?;
+
+    let dir = deps.with_file_name(SHIM);
+    std::fs::create_dir_all(&dir)?;
+    let src = dir.join(format!("{SHIM}.rs"));
+    std::fs::write(&src, source(function))?;
+    let out = dir
+        .join(SHIM)
+        .with_extension(asm_path.extension().unwrap_or_default());
+
+    let rustc = std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+    let mut cmd = Command::new(rustc);
+    cmd.arg(&src)
+        .args([
+            "--crate-name",
+            SHIM,
+            "--crate-type",
+            "lib",
+            "--edition",
+            edition,
+        ])
+        .args(["--emit", emit])
+        .arg("-o")
+        .arg(&out)
+        .arg("--extern")
+        .arg(format!("{krate}={}", metadata.display()))
+        .arg("-L")
+        .arg(format!("dependency={}", deps.display()))
+        .arg(format!("-Copt-level={opt_level}"))
+        .args(flags);
+    if let Some(target) = target {
+        cmd.args(["--target", target]);
+    }
+    let status = cmd.status().context("Failed to run rustc")?;
+    anyhow::ensure!(
+        status.success(),
+        "Couldn't instantiate {function}, it needs to be public and the type arguments need \
+         full paths"
+    );
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_instance, item_name};
+
+    #[test]
+    fn instance_names() {
+        assert!(is_instance("sample::parse::<u32>"));
+        assert!(!is_instance("sample::parse"));
+        assert_eq!(item_name("sample::parse::<Vec<u8>>"), "sample::parse");
+        assert_eq!(item_name("<T as sample::Tr>::f"), "<T as sample::Tr>::f");
+    }
+}
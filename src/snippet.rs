@@ -0,0 +1,72 @@
+#![allow(clippy::missing_errors_doc)]
+//! Code passed with `--snippet` or `--eval` is built as a crate of its own, made in a directory
+//! only the user can write to. The directory is named after the code so running again reuses
+//! the build
+use anyhow::Context;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+
+/// Name of the crate the code goes to, functions are `snippet::f` and so on
+pub const NAME: &str = "snippet";
+
+/// Manifest of a crate that has `code` as its library, or its binary if `code` has `main`
+pub fn create(code: &str) -> anyhow::Result<PathBuf> {
+    let mut hasher = DefaultHasher::new();
+    code.hash(&mut hasher);
+    let dir = snippets_dir()?.join(format!("snippet-{:x}", hasher.finish()));
+    let src = dir.join("src");
+    std::fs::create_dir_all(&src)?;
+    let file = if has_main(code) { "main.rs" } else { "lib.rs" };
+    let manifest = dir.join("Cargo.toml");
+    write_if_changed(
+        &manifest,
+        &format!(
+            "[package]\nname = \"{NAME}\"\nversion = \"0.0.0\"\nedition = \"2021\"\n\n\
+             # keeps cargo from looking for a workspace above the temp directory\n[workspace]\n"
+        ),
+    )?;
+    write_if_changed(&src.join(file), code)?;
+    Ok(manifest)
+}
+
+/// `$CARGO_TARGET_DIR/cargo-show-asm` or `$XDG_CACHE_HOME/cargo-show-asm`, `~/.cache` is used if
+/// neither is set. A shared temp directory would let others plant a `build.rs` in there
+fn snippets_dir() -> anyhow::Result<PathBuf> {
+    let var = |name| std::env::var_os(name).filter(|dir| !dir.is_empty());
+    let dir = var("CARGO_TARGET_DIR")
+        .or_else(|| var("XDG_CACHE_HOME"))
+        .map(PathBuf::from)
+        .or_else(|| {
+            #[allow(deprecated)]
+            std::env::home_dir().map(|home| home.join(".cache"))
+        })
+        .context("No home directory to build the snippet in, set CARGO_TARGET_DIR")?;
+    Ok(dir.join("cargo-show-asm"))
+}
+
+fn has_main(code: &str) -> bool {
+    code.lines()
+        .any(|line| line.trim_start().starts_with("fn main(") || line.contains(" fn main("))
+}
+
+/// Rewriting a file with the same contents would make cargo rebuild the crate
+fn write_if_changed(path: &std::path::Path, contents: &str) -> anyhow::Result<()> {
+    if std::fs::read_to_string(path).ok().as_deref() != Some(contents) {
+        std::fs::write(path, contents)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::has_main;
+
+    #[test]
+    fn binaries() {
+        assert!(has_main("fn main() {\n    println!(\"hi\");\n}\n"));
+        assert!(has_main("use std::io;\npub fn main() {}\n"));
+        assert!(!has_main("pub fn f(x: u32) -> u32 { x * 3 }"));
+        assert!(!has_main("pub fn main_loop() {}"));
+    }
+}
@@ -0,0 +1,329 @@
+#![allow(clippy::missing_errors_doc)]
+//! Statics, string literals and other data in the assembly: everything `.type` marks as an
+//! `@object` along with the section it goes to and the size `.size` gives it. Only ELF targets
+//! have those directives, Mach-O and COFF assembly has no data objects to find this way.
+use crate::{
+    color,
+    demangle::{self, contents},
+    exit::Failure,
+    opts::Format,
+};
+use std::io::Write;
+use std::path::Path;
+
+mod vtable;
+
+pub use vtable::dump as dump_vtable;
+
+/// Data defined in the assembly
+#[derive(Debug)]
+pub struct Object {
+    /// demangled name, string literals are named by their contents
+    pub name: String,
+    pub symbol: String,
+    pub section: String,
+    pub size: u64,
+    pub global: bool,
+    /// the label and the directives with the initializer
+    lines: Vec<String>,
+}
+
+/// Bytes an assembler string literal such as `"\001ab\n"` stands for, without the quotes
+fn unescape(literal: &str) -> Vec<u8> {
+    let mut res = Vec::new();
+    let mut bytes = literal.bytes().peekable();
+    while let Some(b) = bytes.next() {
+        if b != b'\\' {
+            res.push(b);
+            continue;
+        }
+        match bytes.next() {
+            Some(d @ b'0'..=b'7') => {
+                let mut value = u32::from(d - b'0');
+                for _ in 0..2 {
+                    match bytes.peek() {
+                        Some(d @ b'0'..=b'7') => {
+                            value = value * 8 + u32::from(d - b'0');
+                            bytes.next();
+                        }
+                        _ => break,
+                    }
+                }
+                res.push(value as u8);
+            }
+            Some(b'n') => res.push(b'\n'),
+            Some(b't') => res.push(b'\t'),
+            Some(b'r') => res.push(b'\r'),
+            Some(b'b') => res.push(8),
+            Some(b'f') => res.push(12),
+            Some(other) => res.push(other),
+            None => {}
+        }
+    }
+    res
+}
+
+/// Bytes of the string in a `.ascii` or `.asciz` directive
+fn string_bytes(line: &str) -> Option<Vec<u8>> {
+    let line = line.trim_start();
+    let (zero, rest) = if let Some(rest) = line.strip_prefix(".asciz") {
+        (true, rest)
+    } else {
+        (false, line.strip_prefix(".ascii")?)
+    };
+    let literal = rest.trim().strip_prefix('"')?.strip_suffix('"')?;
+    let mut bytes = unescape(literal);
+    if zero {
+        bytes.push(0);
+    }
+    Some(bytes)
+}
+
+/// Name for data without a symbol of its own: the text if it is a string literal,
+/// `anon.N` otherwise
+fn anonymous(symbol: &str, lines: &[String]) -> String {
+    if let [_, data] = lines {
+        if let Some(text) = string_bytes(data)
+            .and_then(|mut bytes| {
+                // C strings are named without the terminating zero
+                if data.trim_start().starts_with(".asciz") {
+                    bytes.pop();
+                }
+                String::from_utf8(bytes).ok()
+            })
+            .filter(|text| !text.chars().any(char::is_control))
+        {
+            return format!("{text:?}");
+        }
+    }
+    let name = symbol.trim_start_matches(['.', 'L', 'l', '_']);
+    match name.strip_prefix("anon.") {
+        Some(rest) => format!("anon.{}", rest.rsplit('.').next().unwrap_or(rest)),
+        None => name.to_owned(),
+    }
+}
+
+fn is_local(symbol: &str) -> bool {
+    symbol.starts_with(".L") || symbol.starts_with("anon.")
+}
+
+/// Data objects in the assembly `asm`
+#[must_use]
+pub fn objects(asm: &str, full_name: bool) -> Vec<Object> {
+    let mut res = Vec::new();
+    let mut section = String::new();
+    let mut current = None::<Object>;
+    let mut started = false;
+    for line in asm.lines() {
+        let directive = line.trim_start();
+        if let Some(rest) = directive.strip_prefix(".section") {
+            section = rest.trim().split(',').next().unwrap_or_default().to_owned();
+        } else if [".data", ".bss", ".text"].contains(&directive) {
+            section = directive.to_owned();
+        }
+        if let Some(symbol) = directive
+            .strip_prefix(".type")
+            .and_then(|rest| rest.trim().strip_suffix(",@object"))
+        {
+            current = Some(Object {
+                name: String::new(),
+                symbol: symbol.to_owned(),
+                section: String::new(),
+                size: 0,
+                global: false,
+                lines: Vec::new(),
+            });
+            started = false;
+            continue;
+        }
+        let Some(object) = &mut current else {
+            continue;
+        };
+        object.section.clone_from(&section);
+        if let Some(size) = directive
+            .strip_prefix(".size")
+            .and_then(|rest| rest.trim().strip_prefix(object.symbol.as_str()))
+            .and_then(|rest| rest.strip_prefix(','))
+        {
+            let Some(mut object) = current.take() else {
+                continue;
+            };
+            object.size = size.trim().parse().unwrap_or_default();
+            // each object goes to a section of its own, named after it
+            if let Some(kind) = object
+                .section
+                .strip_suffix(object.symbol.as_str())
+                .and_then(|kind| kind.strip_suffix('.'))
+            {
+                object.section = kind.to_owned();
+            }
+            object.name = match demangle::demangled(&object.symbol) {
+                _ if full_name => object.symbol.clone(),
+                Some(name) => format!("{name:#?}"),
+                None if is_local(&object.symbol) => anonymous(&object.symbol, &object.lines),
+                None => object.symbol.clone(),
+            };
+            res.push(object);
+            continue;
+        }
+        let global = [".globl", ".weak"].iter().any(|d| {
+            directive
+                .strip_prefix(d)
+                .is_some_and(|rest| rest.trim() == object.symbol)
+        });
+        object.global |= global;
+        if directive.strip_suffix(':') == Some(object.symbol.as_str()) {
+            started = true;
+        }
+        if started {
+            object.lines.push(line.to_owned());
+        }
+    }
+    res.sort_by(|a, b| (&a.name, &a.symbol).cmp(&(&b.name, &b.symbol)));
+    res
+}
+
+/// At most this many bytes of string data are spelled out in a comment
+const SHOWN_BYTES: usize = 32;
+
+fn print_object(object: &Object, fmt: &Format, out: &mut dyn Write) -> anyhow::Result<()> {
+    let header = format!(
+        "// {}: {} bytes in {}",
+        object.name, object.size, object.section
+    );
+    writeln!(out, "{}", color!(header, comment))?;
+    for line in &object.lines {
+        let code = contents(line, fmt.full_name);
+        match string_bytes(line)
+            .filter(|bytes| bytes.iter().any(|b| !(b.is_ascii_graphic() || *b == b' ')))
+        {
+            Some(bytes) => {
+                let mut hex = bytes
+                    .iter()
+                    .take(SHOWN_BYTES)
+                    .map(|b| format!("{b:02x}"))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                if bytes.len() > SHOWN_BYTES {
+                    hex.push_str(" ...");
+                }
+                writeln!(out, "{code} {}", color!(format!("// {hex}"), comment))?;
+            }
+            None => writeln!(out, "{code}")?,
+        }
+    }
+    Ok(())
+}
+
+fn print_list(objects: &[&Object], out: &mut dyn Write) -> anyhow::Result<()> {
+    for object in objects {
+        // string literals are named by their text in quotes already
+        if object.name.starts_with('"') {
+            write!(out, "{}", color!(object.name, symbol))?;
+        } else {
+            write!(out, "{:?}", color!(object.name, symbol))?;
+        }
+        write!(
+            out,
+            " {} bytes {}",
+            color!(object.size, size),
+            object.section
+        )?;
+        if !object.global {
+            write!(out, " {}", color!("local", comment))?;
+        }
+        writeln!(out)?;
+    }
+    let total = objects.iter().map(|o| o.size).sum::<u64>();
+    writeln!(out, "{} bytes in total", color!(total, size))?;
+    Ok(())
+}
+
+/// Print the data object `filter` picks from the assembly at `path`, list the ones it
+/// matches if there are several, `nth` picks one of several with the same name
+pub fn dump(
+    path: &Path,
+    filter: &str,
+    nth: usize,
+    fmt: &Format,
+    out: &mut dyn Write,
+    list: &mut dyn Write,
+) -> anyhow::Result<()> {
+    let asm = std::fs::read_to_string(path)?;
+    let objects = objects(&asm, fmt.full_name);
+    let matching = objects
+        .iter()
+        .filter(|o| o.name.contains(filter))
+        .collect::<Vec<_>>();
+    let exact = matching
+        .iter()
+        .filter(|o| o.name == filter)
+        .collect::<Vec<_>>();
+    let goal = match (exact.get(nth), matching.as_slice()) {
+        (Some(object), _) => Some(**object),
+        (None, [object]) if nth == 0 && !filter.is_empty() => Some(*object),
+        _ => None,
+    };
+    if let Some(object) = goal {
+        print_object(object, fmt, out)?;
+        return Ok(out.flush()?);
+    }
+    if matching.is_empty() {
+        anyhow::bail!(Failure::not_found(
+            "No matching statics, try relaxing your search request"
+        ))
+    }
+    if !filter.is_empty() {
+        eprintln!("Try one of those");
+    }
+    print_list(&matching, list)?;
+    list.flush()?;
+    if filter.is_empty() {
+        Ok(())
+    } else {
+        anyhow::bail!(Failure::ambiguous(
+            "Several statics match, pick one of those"
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{objects, unescape};
+
+    #[test]
+    fn escapes() {
+        assert_eq!(unescape(r#"\001\000a\n\"\\"#), b"\x01\x00a\n\"\\");
+        assert_eq!(unescape(r"\1344"), b"\x5c4");
+    }
+
+    #[test]
+    fn data_objects() {
+        let asm = "\t.type\tBUF,@object\n\t.section\t.bss.BUF,\"aw\",@nobits\n\t.globl\tBUF\nBUF:\n\
+                   \t.zero\t64\n\t.size\tBUF, 64\n\n\
+                   \t.type\t.Lanon.1940f37f.5,@object\n\t.section\t.rodata..Lanon.1940f37f.5,\"a\",@progbits\n\
+                   .Lanon.1940f37f.5:\n\t.ascii\t\"hello world\"\n\t.size\t.Lanon.1940f37f.5, 11\n\n\
+                   \t.type\t.Lanon.1940f37f.6,@object\n\t.section\t.data.rel.ro..Lanon.1940f37f.6,\"aw\",@progbits\n\
+                   \t.p2align\t3, 0x0\n.Lanon.1940f37f.6:\n\t.quad\tf\n\t.asciz\t\"\\b\\000\"\n\
+                   \t.size\t.Lanon.1940f37f.6, 16\n\n\
+                   \t.type\t_ZN4cgut5TABLE17h97961344192752bdE,@object\n\t.section\t.rodata._ZN4cgut5TABLE17h97961344192752bdE,\"a\",@progbits\n\
+                   \t.globl\t_ZN4cgut5TABLE17h97961344192752bdE\n_ZN4cgut5TABLE17h97961344192752bdE:\n\t.asciz\t\"\\001\\000\"\n\
+                   \t.size\t_ZN4cgut5TABLE17h97961344192752bdE, 4\n";
+        let found = objects(asm, false)
+            .into_iter()
+            .map(|o| (o.name, o.size, o.section, o.global, o.lines.len()))
+            .collect::<Vec<_>>();
+        let row = |name: &str, size, section: &str, global, lines| {
+            (name.to_owned(), size, section.to_owned(), global, lines)
+        };
+        assert_eq!(
+            found,
+            [
+                row("\"hello world\"", 11, ".rodata", false, 2),
+                row("BUF", 64, ".bss", true, 2),
+                row("anon.6", 16, ".data.rel.ro", false, 3),
+                row("cgut::TABLE", 4, ".rodata", true, 2),
+            ]
+        );
+    }
+}
@@ -0,0 +1,316 @@
+//! Vtables are data objects like any other: a pointer to the drop glue, the size and the
+//! alignment of the type followed by pointers to the methods of the trait. Which type and
+//! trait each one is for comes from the names of the functions it points to
+use super::{objects, string_bytes, Object};
+use crate::{color, demangle, exit::Failure};
+use std::io::Write;
+use std::path::Path;
+
+/// Part of a data object as wide as a pointer
+#[derive(Debug, PartialEq, Eq)]
+enum Word {
+    Symbol(String),
+    Value(u64),
+}
+
+/// Something at an offset inside of a data object
+enum Piece {
+    Symbol(String),
+    Bytes(Vec<u8>),
+}
+
+fn number(arg: &str) -> Option<u64> {
+    let arg = arg.trim();
+    match arg.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => arg
+            .parse::<u64>()
+            .ok()
+            .or_else(|| arg.parse::<i64>().ok().map(|v| v as u64)),
+    }
+}
+
+/// Contents of the object split into words along with their width, pointers are as wide as
+/// the directives with symbols in them. Data is assumed to be little endian
+fn words(lines: &[String]) -> (usize, Vec<Word>) {
+    let mut pieces = Vec::new();
+    let mut width = 8;
+    for line in lines {
+        let line = line.trim_start();
+        if let Some(bytes) = string_bytes(line) {
+            pieces.push(Piece::Bytes(bytes));
+            continue;
+        }
+        let Some((directive, args)) = line.split_once(char::is_whitespace) else {
+            continue;
+        };
+        let size = match directive {
+            ".quad" | ".8byte" | ".xword" => 8,
+            ".long" | ".4byte" | ".word" => 4,
+            ".short" | ".value" | ".2byte" | ".hword" => 2,
+            ".byte" => 1,
+            ".zero" | ".space" => {
+                let len = number(args).unwrap_or_default();
+                pieces.push(Piece::Bytes(vec![0; len as usize]));
+                continue;
+            }
+            _ => continue,
+        };
+        for arg in args.split(',') {
+            match number(arg) {
+                Some(value) => pieces.push(Piece::Bytes(value.to_le_bytes()[..size].to_vec())),
+                None => {
+                    width = size;
+                    pieces.push(Piece::Symbol(arg.trim().to_owned()));
+                }
+            }
+        }
+    }
+
+    let mut res = Vec::new();
+    let mut bytes = Vec::new();
+    for piece in pieces {
+        match piece {
+            Piece::Symbol(symbol) => {
+                bytes.clear();
+                res.push(Word::Symbol(symbol));
+            }
+            Piece::Bytes(more) => {
+                bytes.extend(more);
+                while bytes.len() >= width {
+                    let mut word = [0; 8];
+                    word[..width].copy_from_slice(&bytes[..width]);
+                    bytes.drain(..width);
+                    res.push(Word::Value(u64::from_le_bytes(word)));
+                }
+            }
+        }
+    }
+    (width, res)
+}
+
+/// Decoded vtable
+#[derive(Debug)]
+struct Vtable<'a> {
+    /// `Type as Trait`, the trait is `_` if none of the methods say
+    name: String,
+    object: &'a Object,
+    /// bytes in a pointer
+    width: usize,
+    drop: Option<String>,
+    size: u64,
+    align: u64,
+    /// demangled names of the methods, `None` for the ones that can't be called
+    methods: Vec<Option<String>>,
+}
+
+/// `Type as Trait` from `<Type as Trait>::method`
+fn implementation(method: &str) -> Option<&str> {
+    let inner = method.strip_prefix('<')?;
+    let mut depth = 1;
+    for (ix, c) in inner.char_indices() {
+        match c {
+            '<' => depth += 1,
+            '>' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(&inner[..ix]).filter(|imp| imp.contains(" as "));
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn decode(object: &Object) -> Option<Vtable<'_>> {
+    let name = |symbol: &str| demangle::name(symbol).unwrap_or_else(|| symbol.to_owned());
+    let (width, words) = words(&object.lines);
+    let [head, Word::Value(size), Word::Value(align), rest @ ..] = words.as_slice() else {
+        return None;
+    };
+    if !align.is_power_of_two() {
+        return None;
+    }
+    let drop = match head {
+        Word::Symbol(symbol) => {
+            let drop = name(symbol);
+            if !drop.starts_with("core::ptr::drop_in_place<") {
+                return None;
+            }
+            Some(drop)
+        }
+        Word::Value(0) => None,
+        Word::Value(_) => return None,
+    };
+    let methods = rest
+        .iter()
+        .map(|word| match word {
+            Word::Symbol(symbol) => Some(Some(name(symbol))),
+            Word::Value(0) => Some(None),
+            Word::Value(_) => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let name = match methods.iter().flatten().find_map(|m| implementation(m)) {
+        Some(imp) => imp.to_owned(),
+        None => {
+            let ty = drop.as_deref()?.strip_prefix("core::ptr::drop_in_place<")?;
+            format!("{} as _", ty.strip_suffix('>')?)
+        }
+    };
+    Some(Vtable {
+        name,
+        object,
+        width,
+        drop,
+        size: *size,
+        align: *align,
+        methods,
+    })
+}
+
+/// Check if `filter`, such as `Sq as Shape`, picks the vtable `name`: either part of the
+/// name or both the type and the trait with fewer modules in front
+fn matches(name: &str, filter: &str) -> bool {
+    let tail = |full: &str, short: &str| {
+        let (full, short) = (full.trim(), short.trim());
+        full == short || full.ends_with(&format!("::{short}"))
+    };
+    name.contains(filter)
+        || matches!(
+            (name.split_once(" as "), filter.split_once(" as ")),
+            (Some((ty, tr)), Some((fty, ftr))) if tail(ty, fty) && tail(tr, ftr)
+        )
+}
+
+fn print_vtable(vtable: &Vtable, out: &mut dyn Write) -> anyhow::Result<()> {
+    let header = format!(
+        "// vtable of <{}>: {} bytes in {}",
+        vtable.name, vtable.object.size, vtable.object.section
+    );
+    writeln!(out, "{}", color!(header, comment))?;
+    let slot = |ix: usize| color!(format!("{:>4}", ix * vtable.width), size).to_string();
+    let drop = vtable.drop.as_deref().unwrap_or("none, nothing to drop");
+    writeln!(out, "{} drop      {}", slot(0), color!(drop, symbol))?;
+    writeln!(out, "{} size      {}", slot(1), vtable.size)?;
+    writeln!(out, "{} align     {}", slot(2), vtable.align)?;
+    for (ix, method) in vtable.methods.iter().enumerate() {
+        match method {
+            Some(method) => {
+                let short = method.rsplit("::").next().unwrap_or(method);
+                writeln!(
+                    out,
+                    "{} {short:<9} {}",
+                    slot(ix + 3),
+                    color!(method, symbol)
+                )?;
+            }
+            None => writeln!(out, "{} {:<9} null", slot(ix + 3), "-")?,
+        }
+    }
+    Ok(())
+}
+
+/// Print the vtable `filter` picks from the assembly at `path`, list the ones it matches if
+/// there are several. Gives the symbols of the methods with full demangled names for
+/// `--methods` to print
+pub fn dump(
+    path: &Path,
+    filter: &str,
+    out: &mut dyn Write,
+    list: &mut dyn Write,
+) -> anyhow::Result<Vec<String>> {
+    let asm = std::fs::read_to_string(path)?;
+    // names of the symbols are needed to tell types and methods
+    let objects = objects(&asm, true);
+    let vtables = objects.iter().filter_map(decode).collect::<Vec<_>>();
+    let matching = vtables
+        .iter()
+        .filter(|vtable| matches(&vtable.name, filter))
+        .collect::<Vec<_>>();
+    match matching.as_slice() {
+        [] => anyhow::bail!(Failure::not_found(
+            "No matching vtables, try relaxing your search request"
+        )),
+        [vtable] => {
+            print_vtable(vtable, out)?;
+            let symbols = vtable.object.lines.iter().flat_map(|line| {
+                let symbol = line.trim().split_once(char::is_whitespace)?.1.trim();
+                Some(format!("{:?}", demangle::demangled(symbol)?))
+            });
+            Ok(symbols
+                .filter(|name| !name.starts_with("core::ptr::drop_in_place<"))
+                .collect())
+        }
+        several => {
+            eprintln!("Try one of those");
+            for vtable in several {
+                writeln!(
+                    list,
+                    "{} {} bytes",
+                    color!(format!("\"{}\"", vtable.name), symbol),
+                    color!(vtable.object.size, size)
+                )?;
+            }
+            list.flush()?;
+            anyhow::bail!(Failure::ambiguous(
+                "Several vtables match, pick one of those"
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode, implementation, matches, words, Word};
+    use crate::statics::objects;
+
+    #[test]
+    fn decoding() {
+        let lines = [
+            ".L:",
+            "\t.quad\tf",
+            "\t.asciz\t\"\\b\\000\\000\\000\\000\\000\\000\\000\\020\\000\\000\\000\\000\\000\\000\"",
+            "\t.zero\t8",
+        ]
+        .map(str::to_owned);
+        assert_eq!(
+            words(&lines).1,
+            [
+                Word::Symbol("f".to_owned()),
+                Word::Value(8),
+                Word::Value(16),
+                Word::Value(0)
+            ]
+        );
+    }
+
+    #[test]
+    fn vtables() {
+        let asm = "\t.type\t.Lanon.1.6,@object\n\t.section\t.data.rel.ro..Lanon.1.6,\"aw\",@progbits\n\
+                   .Lanon.1.6:\n\t.quad\t_ZN4core3ptr29drop_in_place$LT$cgut..Sq$GT$17hf44eb0d4c50a4114E\n\
+                   \t.asciz\t\"\\b\\000\\000\\000\\000\\000\\000\\000\\b\\000\\000\\000\\000\\000\\000\"\n\
+                   \t.quad\t_ZN40_$LT$cgut..Sq$u20$as$u20$cgut..Shape$GT$4area17h10db396f0050648aE\n\
+                   \t.size\t.Lanon.1.6, 32\n\n\
+                   \t.type\t.Lanon.1.2,@object\n\t.section\t.data.rel.ro..Lanon.1.2,\"aw\",@progbits\n\
+                   .Lanon.1.2:\n\t.quad\t.Lanon.1.1\n\t.asciz\t\"\\n\\000\\000\\000\\000\\000\\000\\000\\025\\000\\000\\000\\\"\\000\\000\"\n\
+                   \t.size\t.Lanon.1.2, 24\n";
+        let objects = objects(asm, true);
+        let found = objects.iter().filter_map(decode).collect::<Vec<_>>();
+        assert_eq!(found.len(), 1);
+        let vtable = &found[0];
+        assert_eq!(vtable.name, "cgut::Sq as cgut::Shape");
+        assert_eq!((vtable.size, vtable.align), (8, 8));
+        assert_eq!(
+            vtable.methods,
+            [Some("<cgut::Sq as cgut::Shape>::area".to_owned())]
+        );
+        assert!(matches(&vtable.name, "Sq as Shape"));
+        assert!(matches(&vtable.name, "cgut::Sq"));
+        assert!(!matches(&vtable.name, "q as Shape"));
+        assert_eq!(
+            implementation("<alloc::vec::Vec<u8> as core::fmt::Debug>::fmt"),
+            Some("alloc::vec::Vec<u8> as core::fmt::Debug")
+        );
+    }
+}
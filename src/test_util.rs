@@ -0,0 +1,150 @@
+//! What tests share: formatting flags from the command line, a file to run a printer on,
+//! a backend for the servers to show and packages to pick targets from
+use crate::asm::{self, Item, Linkage};
+use crate::opts::{format, Format, Syntax};
+use crate::progress::strip_ansi;
+use crate::tui::Backend;
+use bpaf::Parser;
+use cargo_metadata::{Metadata, Package};
+use serde_json::json;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// `dump_function` of one of the printers
+pub type Dump = fn(
+    Option<(&str, usize)>,
+    &Path,
+    &Format,
+    &mut dyn Write,
+    &mut Vec<Item>,
+) -> anyhow::Result<bool>;
+
+/// Formatting options `args` give on the command line
+pub fn fmt(args: &[&str]) -> Format {
+    format()
+        .to_options()
+        .run_inner(bpaf::Args::from(args))
+        .unwrap()
+}
+
+/// Items `dump` finds and the text without colors it prints for `goal` in a file with
+/// `contents`, `args` are the formatting flags
+pub fn dump_file(
+    dump: Dump,
+    contents: &str,
+    goal: (&str, usize),
+    args: &[&str],
+) -> (Vec<Item>, String) {
+    // tests run in parallel, each one needs a file of its own
+    static FILES: AtomicUsize = AtomicUsize::new(0);
+    let path = std::env::temp_dir().join(format!(
+        "cargo-show-asm-test-{}-{}",
+        std::process::id(),
+        FILES.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::write(&path, contents).unwrap();
+    let mut out = Vec::new();
+    let mut items = Vec::new();
+    let res = dump(Some(goal), &path, &fmt(args), &mut out, &mut items);
+    std::fs::remove_file(&path).unwrap();
+    res.unwrap();
+    (items, strip_ansi(&String::from_utf8(out).unwrap()))
+}
+
+/// Item called `name` with the hash `h1`
+pub fn item(name: &str) -> Item {
+    Item {
+        name: name.to_owned(),
+        hashed: format!("{name}::h1"),
+        index: 0,
+        len: 2,
+        linkage: Linkage::default(),
+    }
+}
+
+/// Backend with functions `outer` and `inner` from `/src/lib.rs`, LLVM IR also has `ir`.
+/// Code is the label and a `ret`, with `fmt.rust` the source line goes in between
+pub struct Fake;
+
+impl Backend for Fake {
+    fn items(&mut self, syntax: Syntax) -> anyhow::Result<Vec<Item>> {
+        let mut items = vec![item("outer"), item("inner")];
+        if syntax == Syntax::Llvm {
+            items.push(item("ir"));
+        }
+        Ok(items)
+    }
+
+    fn render(&mut self, _: Syntax, fmt: &Format, item: &Item) -> anyhow::Result<Vec<u8>> {
+        Ok(if fmt.rust {
+            format!(
+                "{}:\n\t\t// /src/lib.rs : 3\n\t\tlet x = 1;\n\tret",
+                item.name
+            )
+        } else {
+            format!("{}:\n\tret", item.name)
+        }
+        .into_bytes())
+    }
+
+    fn rebuild(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn locations(&mut self) -> anyhow::Result<asm::Locations> {
+        let file = PathBuf::from("/src/lib.rs");
+        let other = PathBuf::from("/src/other.rs");
+        Ok(vec![
+            (
+                item("outer"),
+                vec![(file.clone(), 1), (other, 4), (file.clone(), 9)],
+            ),
+            (item("inner"), vec![(file.clone(), 3), (file, 4)]),
+        ])
+    }
+}
+
+/// Package `name` of `version` living in this crate's directory with a lib and a bin target
+/// named after it. `deps` are the names of its dependencies, the names they are renamed to
+/// and version requirements
+pub fn package(name: &str, version: &str, deps: &[(&str, Option<&str>, &str)]) -> Package {
+    let dir = env!("CARGO_MANIFEST_DIR");
+    let deps = deps
+        .iter()
+        .map(|(dep, rename, req)| {
+            json!({
+                "name": dep, "source": null, "req": req, "kind": null, "optional": false,
+                "uses_default_features": true, "features": [], "target": null,
+                "rename": rename, "registry": null, "path": null,
+            })
+        })
+        .collect::<Vec<_>>();
+    serde_json::from_value(json!({
+        "name": name,
+        "version": version,
+        "id": format!("{name} {version}"),
+        "dependencies": deps,
+        "targets": [
+            { "name": name, "kind": ["lib"], "src_path": format!("{dir}/src/lib.rs") },
+            { "name": name, "kind": ["bin"], "src_path": format!("{dir}/src/main.rs") },
+        ],
+        "features": {},
+        "manifest_path": format!("{dir}/Cargo.toml"),
+    }))
+    .unwrap()
+}
+
+/// Workspace with `packages` in it, the first one is the only member
+pub fn metadata(packages: Vec<Package>) -> Metadata {
+    let dir = env!("CARGO_MANIFEST_DIR");
+    serde_json::from_value(json!({
+        "workspace_members": [packages[0].id],
+        "packages": packages,
+        "resolve": null,
+        "workspace_root": dir,
+        "target_directory": format!("{dir}/target"),
+        "version": 1,
+    }))
+    .unwrap()
+}
@@ -0,0 +1,232 @@
+#![allow(clippy::missing_errors_doc)]
+//! Colors used for highlighting, picked with `--theme` or `theme` in the config file and
+//! adjusted with the `[colors]` table of the config
+use anyhow::Context;
+use owo_colors::{AnsiColors, DynColor, DynColors};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::OnceLock;
+
+/// How to highlight one kind of thing
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Paint {
+    color: Option<DynColors>,
+    bold: bool,
+}
+
+impl Paint {
+    const fn ansi(color: AnsiColors) -> Self {
+        Self {
+            color: Some(DynColors::Ansi(color)),
+            bold: false,
+        }
+    }
+
+    const fn bold(color: AnsiColors) -> Self {
+        Self {
+            color: Some(DynColors::Ansi(color)),
+            bold: true,
+        }
+    }
+
+    fn paint(
+        self,
+        f: &mut fmt::Formatter<'_>,
+        inner: impl FnOnce(&mut fmt::Formatter<'_>) -> fmt::Result,
+    ) -> fmt::Result {
+        if self.bold {
+            f.write_str("\x1b[1m")?;
+        }
+        if let Some(color) = self.color {
+            color.fmt_ansi_fg(f)?;
+        }
+        inner(f)?;
+        if self.color.is_some() {
+            f.write_str("\x1b[39m")?;
+        }
+        if self.bold {
+            f.write_str("\x1b[22m")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Paint {
+    type Err = anyhow::Error;
+
+    /// `none`, a color name like `cyan` or `bright black`, or `#rrggbb`, optionally prefixed
+    /// with `bold`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().replace('_', " ");
+        let (bold, color) = match s.strip_prefix("bold") {
+            Some(rest) => (true, rest.trim()),
+            None => (false, s.as_str()),
+        };
+        let color = match color {
+            "" | "none" => None,
+            color => Some(
+                color
+                    .parse::<DynColors>()
+                    .ok()
+                    .with_context(|| format!("{color:?} is not a known color"))?,
+            ),
+        };
+        Ok(Self { color, bold })
+    }
+}
+
+/// Item along with the way to paint it, formatting flags are passed to the item
+pub struct Painted<'a, T: ?Sized>(pub &'a T, pub Paint);
+
+impl<T: fmt::Display + ?Sized> fmt::Display for Painted<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.1.paint(f, |f| fmt::Display::fmt(self.0, f))
+    }
+}
+
+impl<T: fmt::Debug + ?Sized> fmt::Debug for Painted<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.1.paint(f, |f| fmt::Debug::fmt(self.0, f))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Theme {
+    /// Instruction names
+    pub mnemonic: Paint,
+    /// Register names in instruction arguments
+    pub register: Paint,
+    /// Comments in asm, llvm-ir and MIR
+    pub comment: Paint,
+    /// Assembler directives
+    pub directive: Paint,
+    pub label: Paint,
+    /// Demangled symbol names
+    pub symbol: Paint,
+    /// Interleaved rust source
+    pub source: Paint,
+    /// Source file and line for interleaved rust source
+    pub location: Paint,
+    /// Package and target headers when showing several targets
+    pub header: Paint,
+    /// Function sizes in the list of available functions
+    pub size: Paint,
+}
+
+impl Theme {
+    /// Default palette, made for dark backgrounds
+    #[must_use]
+    pub const fn dark() -> Self {
+        Self {
+            mnemonic: Paint::ansi(AnsiColors::BrightBlue),
+            register: Paint::ansi(AnsiColors::Magenta),
+            comment: Paint::ansi(AnsiColors::Yellow),
+            directive: Paint::ansi(AnsiColors::BrightBlack),
+            label: Paint::ansi(AnsiColors::BrightBlack),
+            symbol: Paint::ansi(AnsiColors::Green),
+            source: Paint::ansi(AnsiColors::BrightRed),
+            location: Paint::ansi(AnsiColors::Cyan),
+            header: Paint::ansi(AnsiColors::BrightBlue),
+            size: Paint::ansi(AnsiColors::Cyan),
+        }
+    }
+
+    /// Darker colors that stay readable on light backgrounds
+    #[must_use]
+    pub const fn light() -> Self {
+        Self {
+            mnemonic: Paint::ansi(AnsiColors::Blue),
+            register: Paint::ansi(AnsiColors::Cyan),
+            comment: Paint::ansi(AnsiColors::Magenta),
+            directive: Paint::ansi(AnsiColors::BrightBlack),
+            label: Paint::bold(AnsiColors::Black),
+            symbol: Paint::ansi(AnsiColors::Green),
+            source: Paint::ansi(AnsiColors::Red),
+            location: Paint::ansi(AnsiColors::Blue),
+            header: Paint::bold(AnsiColors::Magenta),
+            size: Paint::ansi(AnsiColors::Blue),
+        }
+    }
+
+    /// Bold bright colors for dark backgrounds
+    #[must_use]
+    pub const fn high_contrast() -> Self {
+        Self {
+            mnemonic: Paint::bold(AnsiColors::BrightCyan),
+            register: Paint::ansi(AnsiColors::BrightWhite),
+            comment: Paint::bold(AnsiColors::BrightYellow),
+            directive: Paint::ansi(AnsiColors::White),
+            label: Paint::bold(AnsiColors::BrightWhite),
+            symbol: Paint::bold(AnsiColors::BrightGreen),
+            source: Paint::bold(AnsiColors::BrightRed),
+            location: Paint::bold(AnsiColors::BrightMagenta),
+            header: Paint::bold(AnsiColors::BrightBlue),
+            size: Paint::ansi(AnsiColors::BrightCyan),
+        }
+    }
+
+    pub fn by_name(name: &str) -> anyhow::Result<Self> {
+        match name {
+            "dark" => Ok(Self::dark()),
+            "light" => Ok(Self::light()),
+            "high-contrast" => Ok(Self::high_contrast()),
+            _ => anyhow::bail!("Unknown theme {name:?}, try one of: dark, light, high-contrast"),
+        }
+    }
+
+    /// Change colors of separate items, keys are the field names
+    pub fn customize(&mut self, colors: &BTreeMap<String, String>) -> anyhow::Result<()> {
+        for (role, value) in colors {
+            let paint = match role.replace('-', "_").as_str() {
+                "mnemonic" => &mut self.mnemonic,
+                "register" => &mut self.register,
+                "comment" => &mut self.comment,
+                "directive" => &mut self.directive,
+                "label" => &mut self.label,
+                "symbol" => &mut self.symbol,
+                "source" => &mut self.source,
+                "location" => &mut self.location,
+                "header" => &mut self.header,
+                "size" => &mut self.size,
+                _ => anyhow::bail!("Unknown color {role:?} in the config"),
+            };
+            *paint = value
+                .parse()
+                .with_context(|| format!("Invalid color for {role}"))?;
+        }
+        Ok(())
+    }
+}
+
+static THEME: OnceLock<Theme> = OnceLock::new();
+
+/// Use `theme` for everything printed from now on, only the first call has any effect
+pub fn set(theme: Theme) {
+    let _ = THEME.set(theme);
+}
+
+/// Current theme, [`Theme::dark`] unless something else is set
+pub fn get() -> &'static Theme {
+    THEME.get_or_init(Theme::dark)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Paint, Painted};
+
+    #[test]
+    fn parse_paint() {
+        let paint = "bold bright_black".parse::<Paint>().unwrap();
+        assert_eq!(
+            format!("{}", Painted("x", paint)),
+            "\x1b[1m\x1b[90mx\x1b[39m\x1b[22m"
+        );
+        let paint = "#ff0000".parse::<Paint>().unwrap();
+        assert_eq!(
+            format!("{}", Painted("x", paint)),
+            "\x1b[38;2;255;0;0mx\x1b[39m"
+        );
+        assert_eq!(format!("{}", Painted("x", "none".parse().unwrap())), "x");
+        assert!("blurple".parse::<Paint>().is_err());
+    }
+}
@@ -0,0 +1,741 @@
+#![allow(clippy::missing_errors_doc)]
+//! Interactive mode: filterable list of functions on the left, code of the selected one on the
+//! right. There's not much to draw so the terminal is driven with escape sequences directly.
+use crate::asm::{self, Item};
+use crate::opts::{Format, Syntax};
+use std::collections::BTreeMap;
+use std::io::Write;
+
+mod nav;
+
+/// Where the items and their code come from, the binary knows about the build and the files.
+/// Also used by [`crate::serve`]
+pub trait Backend {
+    /// All the items defined in the output of `syntax` kind
+    fn items(&mut self, syntax: Syntax) -> anyhow::Result<Vec<Item>>;
+
+    /// Code of `item` from the output of `syntax` kind, formatted according to `fmt`
+    fn render(&mut self, syntax: Syntax, fmt: &Format, item: &Item) -> anyhow::Result<Vec<u8>>;
+
+    /// Build again if anything changed since the last build, items might be different after
+    fn rebuild(&mut self) -> anyhow::Result<()>;
+
+    /// Source lines each of the items in the assembly has code for
+    fn locations(&mut self) -> anyhow::Result<asm::Locations>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Key {
+    Char(char),
+    Ctrl(char),
+    Up,
+    Down,
+    Left,
+    Right,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Enter,
+    Tab,
+    Backspace,
+    Esc,
+}
+
+/// Split raw terminal input into keys, unknown escape sequences are dropped
+fn parse_keys(input: &[u8]) -> Vec<Key> {
+    let mut res = Vec::new();
+    let input = String::from_utf8_lossy(input);
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        let key = match c {
+            '\x1b' => match chars.peek() {
+                Some('[' | 'O') => {
+                    chars.next();
+                    let mut seq = String::new();
+                    for c in chars.by_ref() {
+                        seq.push(c);
+                        if c.is_ascii_alphabetic() || c == '~' {
+                            break;
+                        }
+                    }
+                    match seq.as_str() {
+                        "A" => Key::Up,
+                        "B" => Key::Down,
+                        "C" => Key::Right,
+                        "D" => Key::Left,
+                        "H" | "1~" | "7~" => Key::Home,
+                        "F" | "4~" | "8~" => Key::End,
+                        "5~" => Key::PageUp,
+                        "6~" => Key::PageDown,
+                        "Z" => Key::Tab,
+                        _ => continue,
+                    }
+                }
+                _ => Key::Esc,
+            },
+            '\r' | '\n' => Key::Enter,
+            '\t' => Key::Tab,
+            '\x7f' | '\x08' => Key::Backspace,
+            c @ '\x01'..='\x1a' => Key::Ctrl((c as u8 - 1 + b'a') as char),
+            c if c.is_control() => continue,
+            c => Key::Char(c),
+        };
+        res.push(key);
+    }
+    res
+}
+
+/// Cut `line` to `width` visible characters and expand tabs, escape sequences take no space.
+/// Returns the result along with the number of visible characters in it.
+fn fit(line: &str, width: usize) -> (String, usize) {
+    let mut res = String::with_capacity(line.len());
+    let mut used = 0;
+    let mut escaped = false;
+    let mut rest = line;
+    while let Some(c) = rest.chars().next() {
+        let skip = nav::escape_len(rest);
+        if skip > 0 {
+            escaped = true;
+            res.push_str(&rest[..skip]);
+            rest = &rest[skip..];
+            continue;
+        }
+        if used == width {
+            break;
+        }
+        rest = &rest[c.len_utf8()..];
+        match c {
+            '\t' => {
+                let n = (8 - used % 8).min(width - used);
+                res.extend(std::iter::repeat_n(' ', n));
+                used += n;
+            }
+            c if c.is_control() => {}
+            c => {
+                res.push(c);
+                used += 1;
+            }
+        }
+    }
+    if escaped {
+        // whatever was cut off might have been closing a link or a color
+        res.push_str("\x1b]8;;\x1b\\\x1b[0m");
+    }
+    (res, used)
+}
+
+/// Short name for the kind of output
+pub(crate) fn label(syntax: Syntax) -> &'static str {
+    match syntax {
+        Syntax::Intel | Syntax::Att => "asm",
+        Syntax::Llvm => "llvm",
+        Syntax::Mir => "mir",
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pane {
+    List,
+    Code,
+}
+
+/// Everything that decides what code is shown: kind of output, rust, simplify and the item
+type CodeKey = (usize, bool, bool, String);
+
+/// Search in the code being typed, cursor goes back to `origin` if it is cancelled
+struct Search {
+    query: String,
+    origin: usize,
+}
+
+/// Where to return to after a jump
+struct Mark {
+    hashed: String,
+    filter: String,
+    cursor: usize,
+    top: usize,
+}
+
+struct State<'a> {
+    backend: &'a mut dyn Backend,
+    syntaxes: &'a [Syntax],
+    syntax: usize,
+    fmt: Format,
+    /// items for each kind of output, loaded when first needed
+    items: BTreeMap<usize, Vec<Item>>,
+    filter: String,
+    editing: bool,
+    /// position in the filtered list
+    selected: usize,
+    list_top: usize,
+    /// line of the code pane jumps and searches start from
+    cursor: usize,
+    code_top: usize,
+    focus: Pane,
+    /// rendered code, lines are kept with their colors
+    code: BTreeMap<CodeKey, Vec<String>>,
+    /// collapse code inlined from other files
+    fold: bool,
+    search: Option<Search>,
+    last_search: String,
+    back: Vec<Mark>,
+    message: Option<String>,
+}
+
+impl State<'_> {
+    fn items(&mut self) -> &[Item] {
+        if !self.items.contains_key(&self.syntax) {
+            let items = match self.backend.items(self.syntaxes[self.syntax]) {
+                Ok(items) => items,
+                Err(err) => {
+                    self.message = Some(format!("{err}"));
+                    Vec::new()
+                }
+            };
+            self.items.insert(self.syntax, items);
+        }
+        &self.items[&self.syntax]
+    }
+
+    fn visible(&mut self) -> Vec<Item> {
+        let filter = self.filter.clone();
+        self.items()
+            .iter()
+            .filter(|item| item.name.contains(&filter))
+            .cloned()
+            .collect()
+    }
+
+    fn current(&mut self) -> Option<Item> {
+        let selected = self.selected;
+        self.visible().get(selected).cloned()
+    }
+
+    fn code(&mut self) -> Option<(&Item, &[String])> {
+        let item = self.current()?;
+        let key = (
+            self.syntax,
+            self.fmt.rust,
+            self.fmt.simplify,
+            item.hashed.clone(),
+        );
+        if !self.code.contains_key(&key) {
+            let lines = match self
+                .backend
+                .render(self.syntaxes[self.syntax], &self.fmt, &item)
+            {
+                Ok(payload) => String::from_utf8_lossy(&payload)
+                    .lines()
+                    .map(str::to_owned)
+                    .collect(),
+                Err(err) => vec![format!("Error: {err}")],
+            };
+            self.code.insert(key.clone(), lines);
+        }
+        let item = self.items[&self.syntax]
+            .iter()
+            .find(|i| i.hashed == item.hashed)?;
+        Some((item, &self.code[&key]))
+    }
+
+    fn view(&mut self) -> Option<(Item, nav::View)> {
+        let fold = self.fold;
+        let (item, code) = self.code()?;
+        Some((item.clone(), nav::View::new(code, fold)))
+    }
+
+    /// Code changed, start from the top
+    fn reset_code(&mut self) {
+        self.cursor = 0;
+        self.code_top = 0;
+    }
+
+    /// Select item with `hashed` name, filter is dropped if it is hiding the item
+    fn select(&mut self, hashed: &str) {
+        if !self.visible().iter().any(|item| item.hashed == hashed) {
+            self.filter.clear();
+        }
+        if let Some(ix) = self.visible().iter().position(|item| item.hashed == hashed) {
+            self.selected = ix;
+        }
+    }
+
+    /// Switch to the next kind of output, keeping the same function selected if it's there
+    fn next_syntax(&mut self) {
+        let current = self.current();
+        self.syntax = (self.syntax + 1) % self.syntaxes.len();
+        self.reset_code();
+        self.back.clear();
+        if let Some(current) = current {
+            let visible = self.visible();
+            self.selected = visible
+                .iter()
+                .position(|item| item.hashed == current.hashed)
+                .or_else(|| {
+                    visible
+                        .iter()
+                        .position(|item| item.matches((&current.name, current.index)))
+                })
+                .unwrap_or(0);
+        }
+    }
+
+    fn toggle_fold(&mut self) {
+        // folding works with source locations
+        if !self.fmt.rust {
+            self.fmt.rust = true;
+            self.reset_code();
+        }
+        let code_line = self
+            .view()
+            .and_then(|(_, view)| view.spans.get(self.cursor).map(|span| span.start))
+            .unwrap_or(0);
+        self.fold = !self.fold;
+        self.cursor = self.view().map_or(0, |(_, view)| view.position(code_line));
+    }
+
+    /// Move to the next line matching the last search, starting `skip` lines away from
+    /// `from`
+    fn find(&mut self, from: usize, skip: usize, forward: bool) {
+        let query = self.last_search.clone();
+        let Some((_, view)) = self.view() else {
+            return;
+        };
+        let len = view.text.len();
+        if query.is_empty() || len == 0 {
+            return;
+        }
+        let found = (skip..len + skip)
+            .map(|n| {
+                if forward {
+                    (from + n) % len
+                } else {
+                    (from + len * 2 - n) % len
+                }
+            })
+            .find(|&ix| view.text[ix].contains(&query));
+        match found {
+            Some(ix) => self.cursor = ix,
+            None => self.message = Some(format!("Pattern not found: {query}")),
+        }
+    }
+
+    /// Follow a label or a function referenced on the cursor line
+    fn jump(&mut self) {
+        let Some((item, view)) = self.view() else {
+            return;
+        };
+        let mark = Mark {
+            hashed: item.hashed.clone(),
+            filter: self.filter.clone(),
+            cursor: self.cursor,
+            top: self.code_top,
+        };
+        if let Some(target) = nav::label_target(&view.text, self.cursor) {
+            self.back.push(mark);
+            self.cursor = target;
+            return;
+        }
+        let line = view.text.get(self.cursor).cloned().unwrap_or_default();
+        let callee = nav::callee(&line, self.items(), &item).map(|callee| callee.hashed.clone());
+        match callee {
+            Some(hashed) => {
+                self.back.push(mark);
+                self.select(&hashed);
+                self.reset_code();
+            }
+            None => self.message = Some("Nothing to jump to on this line".to_owned()),
+        }
+    }
+
+    /// Return to where the last jump was made from
+    fn go_back(&mut self) {
+        match self.back.pop() {
+            Some(mark) => {
+                self.filter = mark.filter;
+                self.select(&mark.hashed);
+                self.cursor = mark.cursor;
+                self.code_top = mark.top;
+            }
+            None => self.message = Some("No previous position".to_owned()),
+        }
+    }
+
+    fn handle(&mut self, key: Key, height: usize) -> bool {
+        let rows = height.saturating_sub(2);
+        let page = rows.saturating_sub(1).max(1);
+        if let Some(search) = &mut self.search {
+            let origin = search.origin;
+            match key {
+                Key::Char(c) => search.query.push(c),
+                Key::Backspace => {
+                    search.query.pop();
+                }
+                Key::Enter => {
+                    self.search = None;
+                    return true;
+                }
+                Key::Esc => {
+                    self.search = None;
+                    self.cursor = origin;
+                    return true;
+                }
+                Key::Ctrl('c') => return false,
+                _ => return true,
+            }
+            // incremental: closest match from where the search started
+            self.last_search.clone_from(&search.query);
+            self.cursor = origin;
+            self.find(origin, 0, true);
+            return true;
+        }
+        if self.editing {
+            match key {
+                Key::Char(c) => self.filter.push(c),
+                Key::Backspace => {
+                    self.filter.pop();
+                }
+                Key::Enter => self.editing = false,
+                Key::Esc => {
+                    self.editing = false;
+                    self.filter.clear();
+                }
+                Key::Ctrl('c') => return false,
+                Key::Up | Key::Down | Key::PageUp | Key::PageDown => self.move_list(key, page),
+                _ => {}
+            }
+            self.selected = self.selected.min(self.visible().len().saturating_sub(1));
+            self.reset_code();
+            return true;
+        }
+        match key {
+            Key::Char('q') | Key::Ctrl('c') => return false,
+            Key::Tab | Key::Left | Key::Right => {
+                self.focus = match self.focus {
+                    Pane::List => Pane::Code,
+                    Pane::Code => Pane::List,
+                };
+            }
+            Key::Char('/') if self.focus == Pane::List => self.editing = true,
+            Key::Char('/') => {
+                self.search = Some(Search {
+                    query: String::new(),
+                    origin: self.cursor,
+                });
+            }
+            Key::Char('s') => self.next_syntax(),
+            Key::Char('r') => {
+                self.fmt.rust = !self.fmt.rust;
+                self.reset_code();
+            }
+            Key::Char('d') => {
+                self.fmt.simplify = !self.fmt.simplify;
+                self.reset_code();
+            }
+            Key::Char('f') => self.toggle_fold(),
+            Key::Backspace | Key::Ctrl('o') => self.go_back(),
+            Key::Enter if self.focus == Pane::List => self.focus = Pane::Code,
+            Key::Esc if self.focus == Pane::Code => self.focus = Pane::List,
+            _ if self.focus == Pane::List => {
+                self.move_list(key, page);
+                self.reset_code();
+            }
+            Key::Enter => self.jump(),
+            Key::Char('n') => self.find(self.cursor, 1, true),
+            Key::Char('N') => self.find(self.cursor, 1, false),
+            _ => self.move_code(key, page),
+        }
+        true
+    }
+
+    fn move_list(&mut self, key: Key, page: usize) {
+        let last = self.visible().len().saturating_sub(1);
+        self.selected = match key {
+            Key::Up | Key::Char('k') => self.selected.saturating_sub(1),
+            Key::Down | Key::Char('j') => self.selected + 1,
+            Key::PageUp => self.selected.saturating_sub(page),
+            Key::PageDown | Key::Char(' ') => self.selected + page,
+            Key::Home | Key::Char('g') => 0,
+            Key::End | Key::Char('G') => last,
+            _ => self.selected,
+        }
+        .min(last);
+    }
+
+    fn move_code(&mut self, key: Key, page: usize) {
+        let last = self
+            .view()
+            .map_or(0, |(_, view)| view.lines.len().saturating_sub(1));
+        self.cursor = match key {
+            Key::Up | Key::Char('k') => self.cursor.saturating_sub(1),
+            Key::Down | Key::Char('j') => self.cursor + 1,
+            Key::PageUp => self.cursor.saturating_sub(page),
+            Key::PageDown | Key::Char(' ') => self.cursor + page,
+            Key::Home | Key::Char('g') => 0,
+            Key::End | Key::Char('G') => last,
+            _ => self.cursor,
+        }
+        .min(last);
+    }
+
+    fn draw(&mut self, out: &mut dyn Write, width: usize, height: usize) -> std::io::Result<()> {
+        let rows = height.saturating_sub(2);
+        let visible = self.visible();
+        let longest = visible
+            .iter()
+            .map(|item| item.name.len())
+            .max()
+            .unwrap_or(0);
+        let left = (longest + 7).clamp(20, (width * 2 / 5).max(20)).min(width);
+        let right = width.saturating_sub(left + 1);
+
+        if self.selected < self.list_top {
+            self.list_top = self.selected;
+        } else if self.selected >= self.list_top + rows {
+            self.list_top = self.selected + 1 - rows;
+        }
+        if self.cursor < self.code_top {
+            self.code_top = self.cursor;
+        } else if self.cursor >= self.code_top + rows {
+            self.code_top = self.cursor + 1 - rows;
+        }
+
+        let list_title = if self.editing || !self.filter.is_empty() {
+            format!("/{}{}", self.filter, if self.editing { "_" } else { "" })
+        } else {
+            format!("{} functions", visible.len())
+        };
+        let syntax = self.syntaxes[self.syntax];
+        let focus = self.focus;
+        let (code_title, code) = match self.view() {
+            Some((item, view)) => (format!("{} [{}]", item.name, label(syntax)), view.lines),
+            None => (String::new(), Vec::new()),
+        };
+
+        let mut frame = String::from("\x1b[H");
+        let title = |frame: &mut String, text: &str, width: usize, active: bool| {
+            let (text, used) = fit(text, width);
+            frame.push_str(if active { "\x1b[1;4m" } else { "\x1b[4m" });
+            frame.push_str(&text);
+            frame.extend(std::iter::repeat_n(' ', width - used));
+            frame.push_str("\x1b[0m");
+        };
+        title(&mut frame, &list_title, left, focus == Pane::List);
+        frame.push('│');
+        title(&mut frame, &code_title, right, focus == Pane::Code);
+        frame.push_str("\r\n");
+
+        for row in 0..rows {
+            let ix = self.list_top + row;
+            if let Some(item) = visible.get(ix) {
+                let size = format!(" {}", item.len);
+                let name_width = left.saturating_sub(size.len());
+                let (name, used) = fit(&item.name, name_width);
+                if ix == self.selected {
+                    frame.push_str("\x1b[7m");
+                }
+                frame.push_str(&name);
+                frame.extend(std::iter::repeat_n(' ', name_width - used));
+                frame.push_str(&size[..size.len().min(left)]);
+                frame.push_str("\x1b[0m");
+            } else {
+                frame.extend(std::iter::repeat_n(' ', left));
+            }
+            frame.push('│');
+            let ix = self.code_top + row;
+            if let Some(line) = code.get(ix) {
+                let (line, used) = fit(line, right);
+                if focus == Pane::Code && ix == self.cursor {
+                    frame.push_str("\x1b[7m");
+                    frame.push_str(&line);
+                    frame.push_str("\x1b[7m");
+                    frame.extend(std::iter::repeat_n(' ', right - used));
+                    frame.push_str("\x1b[0m");
+                } else {
+                    frame.push_str(&line);
+                }
+            }
+            frame.push_str("\x1b[K\r\n");
+        }
+
+        let on_off = |flag: bool| if flag { "on" } else { "off" };
+        let status = match (&self.search, &self.message) {
+            (Some(search), _) => format!("/{}_", search.query),
+            (None, Some(msg)) => msg.clone(),
+            (None, None) => format!(
+                "q quit  / {}  tab switch pane  enter jump  backspace back  n/N next match  \
+                 s {}  r rust: {}  d directives: {}  f fold: {}",
+                if focus == Pane::List {
+                    "filter"
+                } else {
+                    "search"
+                },
+                label(syntax),
+                on_off(self.fmt.rust),
+                on_off(!self.fmt.simplify),
+                on_off(self.fold),
+            ),
+        };
+        let (status, used) = fit(&status, width);
+        frame.push_str("\x1b[7m");
+        frame.push_str(&status);
+        frame.extend(std::iter::repeat_n(' ', width - used));
+        frame.push_str("\x1b[0m");
+
+        out.write_all(frame.as_bytes())?;
+        out.flush()
+    }
+}
+
+#[cfg(target_family = "unix")]
+mod terminal {
+    use nix::libc::STDIN_FILENO;
+    use nix::sys::termios::{self, SetArg, SpecialCharacterIndices, Termios};
+    use std::io::{Read, Write};
+
+    /// Raw mode on the alternate screen, everything is restored when dropped
+    pub struct Terminal {
+        saved: Termios,
+    }
+
+    impl Terminal {
+        pub fn enter() -> anyhow::Result<Self> {
+            let saved = termios::tcgetattr(STDIN_FILENO)?;
+            let mut raw = saved.clone();
+            termios::cfmakeraw(&mut raw);
+            // wake up now and then even without any input to notice the terminal resizing
+            raw.control_chars[SpecialCharacterIndices::VMIN as usize] = 0;
+            raw.control_chars[SpecialCharacterIndices::VTIME as usize] = 2;
+            termios::tcsetattr(STDIN_FILENO, SetArg::TCSAFLUSH, &raw)?;
+            print!("\x1b[?1049h\x1b[?25l\x1b[2J");
+            std::io::stdout().flush()?;
+            Ok(Self { saved })
+        }
+
+        /// Width and height in characters
+        pub fn size() -> (usize, usize) {
+            let mut size = nix::libc::winsize {
+                ws_row: 0,
+                ws_col: 0,
+                ws_xpixel: 0,
+                ws_ypixel: 0,
+            };
+            // Safety: TIOCGWINSZ only writes to the winsize struct passed to it
+            let res = unsafe {
+                nix::libc::ioctl(nix::libc::STDOUT_FILENO, nix::libc::TIOCGWINSZ, &mut size)
+            };
+            if res == 0 && size.ws_col > 0 && size.ws_row > 0 {
+                (usize::from(size.ws_col), usize::from(size.ws_row))
+            } else {
+                (80, 24)
+            }
+        }
+
+        /// Whatever was typed since the last call, empty if nothing was
+        pub fn read(&mut self) -> std::io::Result<Vec<u8>> {
+            let mut buf = [0; 64];
+            let n = std::io::stdin().read(&mut buf)?;
+            Ok(buf[..n].to_vec())
+        }
+    }
+
+    impl Drop for Terminal {
+        fn drop(&mut self) {
+            print!("\x1b[?25h\x1b[?1049l");
+            let _ = std::io::stdout().flush();
+            let _ = termios::tcsetattr(STDIN_FILENO, SetArg::TCSAFLUSH, &self.saved);
+        }
+    }
+}
+
+/// Run the interface until the user quits, `syntaxes` are the kinds of output available
+/// without rebuilding, the first one is shown initially, `filter` is the initial search
+#[cfg(target_family = "unix")]
+pub fn run(
+    backend: &mut dyn Backend,
+    syntaxes: &[Syntax],
+    fmt: Format,
+    filter: &str,
+) -> anyhow::Result<()> {
+    use std::io::IsTerminal;
+    if !(std::io::stdin().is_terminal() && std::io::stdout().is_terminal()) {
+        anyhow::bail!("--tui needs a terminal");
+    }
+    let mut state = State {
+        backend,
+        syntaxes,
+        syntax: 0,
+        fmt,
+        items: BTreeMap::new(),
+        filter: filter.to_owned(),
+        editing: false,
+        selected: 0,
+        list_top: 0,
+        cursor: 0,
+        code_top: 0,
+        focus: Pane::List,
+        code: BTreeMap::new(),
+        fold: false,
+        search: None,
+        last_search: String::new(),
+        back: Vec::new(),
+        message: None,
+    };
+
+    let mut terminal = terminal::Terminal::enter()?;
+    let mut drawn = None;
+    loop {
+        let (width, height) = terminal::Terminal::size();
+        if drawn != Some((width, height)) {
+            state.draw(&mut std::io::stdout().lock(), width, height)?;
+            drawn = Some((width, height));
+        }
+        let input = terminal.read()?;
+        if input.is_empty() {
+            continue;
+        }
+        state.message = None;
+        for key in parse_keys(&input) {
+            if !state.handle(key, height) {
+                return Ok(());
+            }
+        }
+        drawn = None;
+    }
+}
+
+#[cfg(not(target_family = "unix"))]
+pub fn run(_: &mut dyn Backend, _: &[Syntax], _: Format, _: &str) -> anyhow::Result<()> {
+    anyhow::bail!("--tui is only supported on unix")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{fit, parse_keys, Key};
+
+    #[test]
+    fn keys() {
+        assert_eq!(
+            parse_keys(b"j\x1b[A\x1b[6~\x1b\r\x03"),
+            [
+                Key::Char('j'),
+                Key::Up,
+                Key::PageDown,
+                Key::Esc,
+                Key::Enter,
+                Key::Ctrl('c')
+            ]
+        );
+    }
+
+    #[test]
+    fn fit_to_width() {
+        assert_eq!(fit("\tmov rax", 12), ("        mov ".to_owned(), 12));
+        assert_eq!(
+            fit("\x1b[94mpush\x1b[39m rbp", 3),
+            ("\x1b[94mpus\x1b]8;;\x1b\\\x1b[0m".to_owned(), 3)
+        );
+        assert_eq!(fit("ret", 10), ("ret".to_owned(), 3));
+    }
+}
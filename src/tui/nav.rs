@@ -0,0 +1,192 @@
+//! Moving around the code: plain text of the rendered lines, jump targets and inlined regions
+use crate::asm::{self, Item};
+use crate::color;
+use std::ops::Range;
+
+/// Length of the escape sequence `s` starts with, `0` if it doesn't start with one
+pub fn escape_len(s: &str) -> usize {
+    let bytes = s.as_bytes();
+    if bytes.first() != Some(&0x1b) {
+        return 0;
+    }
+    match bytes.get(1) {
+        // operating system command such as a hyperlink, terminated with BEL or ESC \
+        Some(b']') => {
+            for i in 2..bytes.len() {
+                if bytes[i] == 0x07 {
+                    return i + 1;
+                }
+                if bytes[i] == 0x1b && bytes.get(i + 1) == Some(&b'\\') {
+                    return i + 2;
+                }
+            }
+            bytes.len()
+        }
+        Some(b'[') => bytes[2..]
+            .iter()
+            .position(u8::is_ascii_alphabetic)
+            .map_or(bytes.len(), |pos| pos + 3),
+        Some(b) if b.is_ascii() => 2,
+        _ => 1,
+    }
+}
+
+/// Text of a rendered line without colors and links
+pub fn plain(line: &str) -> String {
+    let mut res = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(c) = rest.chars().next() {
+        match escape_len(rest) {
+            0 => {
+                res.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+            skip => rest = &rest[skip..],
+        }
+    }
+    res
+}
+
+/// File from the `// path : line` comment `--rust` puts before the source line
+fn location(line: &str) -> Option<&str> {
+    asm::location_comment(line).map(|(file, _)| file)
+}
+
+/// Path of the standard library or dependency sources relative to the crate they belong to
+fn short(file: &str) -> &str {
+    if let Some((_, rest)) = file.split_once("/library/") {
+        rest
+    } else if let Some((_, rest)) = file.split_once("/registry/src/") {
+        rest.split_once('/').map_or(rest, |(_, rest)| rest)
+    } else {
+        file
+    }
+}
+
+/// Lines as they are shown, with the code inlined from other files collapsed when folding
+pub struct View {
+    pub lines: Vec<String>,
+    /// same lines without colors, for searching
+    pub text: Vec<String>,
+    /// lines of the rendered code behind each of the shown lines
+    pub spans: Vec<Range<usize>>,
+}
+
+impl View {
+    /// Inlined code is anything between a location in some other file and the next location
+    /// in the file the function itself comes from
+    pub fn new(code: &[String], fold: bool) -> Self {
+        let text = code.iter().map(|line| plain(line)).collect::<Vec<_>>();
+        let mut view = Self {
+            lines: Vec::new(),
+            text: Vec::new(),
+            spans: Vec::new(),
+        };
+        let own = text.iter().find_map(|line| location(line));
+        let mut ix = 0;
+        while ix < code.len() {
+            match (fold, own, location(&text[ix])) {
+                (true, Some(own), Some(file)) if file != own => {
+                    let end = (ix + 1..code.len())
+                        .find(|&end| location(&text[end]) == Some(own))
+                        .unwrap_or(code.len());
+                    let file = short(file);
+                    let summary = format!("\t\t// … {} lines inlined from {file}", end - ix);
+                    view.lines.push(color!(summary, comment).to_string());
+                    view.text.push(summary);
+                    view.spans.push(ix..end);
+                    ix = end;
+                }
+                _ => {
+                    view.lines.push(code[ix].clone());
+                    view.text.push(text[ix].clone());
+                    view.spans.push(ix..ix + 1);
+                    ix += 1;
+                }
+            }
+        }
+        view
+    }
+
+    /// Position of the shown line with `code_line` behind it
+    pub fn position(&self, code_line: usize) -> usize {
+        self.spans
+            .iter()
+            .position(|span| span.contains(&code_line))
+            .unwrap_or(0)
+    }
+}
+
+/// Line defining a label used on line `from`: `.LBB0_1:` in asm, `bb1:` in llvm-ir and
+/// `bb1: {` in MIR
+pub fn label_target(text: &[String], from: usize) -> Option<usize> {
+    text.get(from)?
+        .split(|c: char| c.is_whitespace() || ",()[]{};".contains(c))
+        .map(|word| word.trim_start_matches('%'))
+        .filter(|word| !word.is_empty() && !word.ends_with(':'))
+        .find_map(|word| {
+            text.iter().position(|line| {
+                line.trim_start()
+                    .strip_prefix(word)
+                    .is_some_and(|rest| rest.starts_with(':'))
+            })
+        })
+}
+
+/// Item other than `current` referenced on `line`, longest name wins so `foo::bar` is picked
+/// over `foo` and an exact hash match wins over everything else
+pub fn callee<'a>(line: &str, items: &'a [Item], current: &Item) -> Option<&'a Item> {
+    items
+        .iter()
+        .filter(|item| item.hashed != current.hashed && line.contains(item.name.as_str()))
+        .max_by_key(|item| (line.contains(item.hashed.as_str()), item.name.len()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{label_target, plain, View};
+
+    fn lines(code: &str) -> Vec<String> {
+        code.lines().map(str::to_owned).collect()
+    }
+
+    #[test]
+    fn plain_text() {
+        let line = "\x1b]8;;file:///src/lib.rs\x1b\\\x1b[94mpush\x1b[39m rbp\x1b]8;;\x1b\\";
+        assert_eq!(plain(line), "push rbp");
+    }
+
+    #[test]
+    fn jump_to_label() {
+        let code = lines("f:\n\tjne .LBB0_2\n\tret\n.LBB0_2:\n\tud2");
+        assert_eq!(label_target(&code, 1), Some(3));
+        assert_eq!(label_target(&code, 2), None);
+        let mir = lines("    bb0: {\n        goto -> bb1;\n    }\n    bb1: {");
+        assert_eq!(label_target(&mir, 1), Some(3));
+        let llvm = lines("start:\n  br i1 %_3, label %bb2, label %bb1\nbb1:\nbb2:");
+        assert_eq!(label_target(&llvm, 1), Some(3));
+        let aarch64 = lines("f:\n\tcbz w0, .LBB0_2\n\tb.ne .LBB0_2\n.LBB0_2:\n\tret");
+        assert_eq!(label_target(&aarch64, 1), Some(3));
+        assert_eq!(label_target(&aarch64, 2), Some(3));
+        let riscv = lines("f:\n\tbeqz a0, .LBB0_2\n\tj .LBB0_2\n.LBB0_2:\n\tret");
+        assert_eq!(label_target(&riscv, 1), Some(3));
+        assert_eq!(label_target(&riscv, 2), Some(3));
+    }
+
+    #[test]
+    fn fold_inlined() {
+        let code = lines(
+            "f:\n\t\t// src/lib.rs : 1\n\tpush rbp\n\t\t// /rustc/abc/library/core/src/ptr.rs : 5\n\
+             \tmov rax, 1\n\tmov rbx, 2\n\t\t// src/lib.rs : 2\n\tret",
+        );
+        let view = View::new(&code, true);
+        assert_eq!(view.text.len(), 6);
+        assert_eq!(
+            view.text[3],
+            "\t\t// … 3 lines inlined from core/src/ptr.rs"
+        );
+        assert_eq!(view.spans[3], 3..6);
+        assert_eq!(view.position(5), 3);
+        assert_eq!(View::new(&code, false).text, code);
+    }
+}
@@ -0,0 +1,182 @@
+#![allow(clippy::missing_errors_doc)]
+//! Function sizes in bytes from linked WebAssembly modules: bodies come from the code section
+//! and names from the `name` custom section, imported functions take the first indices
+use anyhow::Context;
+use std::collections::BTreeMap;
+
+const CUSTOM: u8 = 0;
+const IMPORT: u8 = 2;
+const CODE: u8 = 10;
+const FUNCTION_NAMES: u8 = 1;
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> anyhow::Result<u8> {
+        let b = *self.data.get(self.pos).context("Truncated wasm module")?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn leb(&mut self) -> anyhow::Result<usize> {
+        let mut res = 0usize;
+        for shift in (0..64).step_by(7) {
+            let b = self.byte()?;
+            res |= usize::from(b & 0x7f) << shift;
+            if b & 0x80 == 0 {
+                return Ok(res);
+            }
+        }
+        anyhow::bail!("Malformed wasm module")
+    }
+
+    fn bytes(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+        let end = self.pos.checked_add(len).context("Malformed wasm module")?;
+        let bytes = self
+            .data
+            .get(self.pos..end)
+            .context("Truncated wasm module")?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn name(&mut self) -> anyhow::Result<&'a str> {
+        let len = self.leb()?;
+        Ok(std::str::from_utf8(self.bytes(len)?)?)
+    }
+
+    fn limits(&mut self) -> anyhow::Result<()> {
+        let flags = self.byte()?;
+        self.leb()?;
+        if flags & 1 != 0 {
+            self.leb()?;
+        }
+        Ok(())
+    }
+
+    fn done(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+}
+
+/// Number of imported functions, those come before the defined ones in the index space
+fn imported_functions(section: &mut Reader) -> anyhow::Result<usize> {
+    let mut funcs = 0;
+    for _ in 0..section.leb()? {
+        section.name()?;
+        section.name()?;
+        match section.byte()? {
+            // function with type index
+            0 => {
+                section.leb()?;
+                funcs += 1;
+            }
+            // table with element type
+            1 => {
+                section.byte()?;
+                section.limits()?;
+            }
+            // memory
+            2 => section.limits()?,
+            // global with type and mutability
+            3 => {
+                section.bytes(2)?;
+            }
+            // tag with attribute and type index
+            4 => {
+                section.byte()?;
+                section.leb()?;
+            }
+            kind => anyhow::bail!("Unknown import kind {kind}"),
+        }
+    }
+    Ok(funcs)
+}
+
+/// Names of functions by index from the `name` section payload
+fn function_names<'a>(
+    section: &mut Reader<'a>,
+    names: &mut BTreeMap<usize, &'a str>,
+) -> anyhow::Result<()> {
+    while !section.done() {
+        let id = section.byte()?;
+        let len = section.leb()?;
+        let mut sub = Reader {
+            data: section.bytes(len)?,
+            pos: 0,
+        };
+        if id == FUNCTION_NAMES {
+            for _ in 0..sub.leb()? {
+                let index = sub.leb()?;
+                names.insert(index, sub.name()?);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Sizes of the function bodies in a wasm module, keyed by the names from the `name` section.
+/// Modules without one give nothing
+pub fn function_sizes(data: &[u8]) -> anyhow::Result<BTreeMap<String, u64>> {
+    anyhow::ensure!(data.starts_with(b"\0asm"), "Not a wasm module");
+    let mut module = Reader { data, pos: 8 };
+    let mut imported = 0;
+    let mut bodies = Vec::new();
+    let mut names = BTreeMap::new();
+    while !module.done() {
+        let id = module.byte()?;
+        let len = module.leb()?;
+        let mut section = Reader {
+            data: module.bytes(len)?,
+            pos: 0,
+        };
+        match id {
+            IMPORT => imported = imported_functions(&mut section)?,
+            CODE => {
+                for _ in 0..section.leb()? {
+                    let size = section.leb()?;
+                    section.bytes(size)?;
+                    bodies.push(size as u64);
+                }
+            }
+            CUSTOM if section.name()? == "name" => function_names(&mut section, &mut names)?,
+            _ => {}
+        }
+    }
+    Ok(bodies
+        .into_iter()
+        .enumerate()
+        .filter_map(|(ix, size)| Some(((*names.get(&(imported + ix))?).to_owned(), size)))
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::function_sizes;
+
+    fn section(id: u8, payload: &[u8]) -> Vec<u8> {
+        let mut res = vec![id, u8::try_from(payload.len()).unwrap()];
+        res.extend(payload);
+        res
+    }
+
+    #[test]
+    fn sizes_by_name() {
+        let mut module = b"\0asm\x01\0\0\0".to_vec();
+        // one imported function: "env" "log" of type 0
+        module.extend(section(2, b"\x01\x03env\x03log\x00\x00"));
+        // two bodies, 4 and 2 bytes long
+        module.extend(section(10, b"\x02\x04\x00\x41\x01\x0b\x02\x00\x0b"));
+        let mut names = b"\x04name".to_vec();
+        names.extend(section(1, b"\x03\x00\x03log\x01\x01f\x02\x01g"));
+        module.extend(section(0, &names));
+        let sizes = function_sizes(&module).unwrap();
+        assert_eq!(
+            sizes.into_iter().collect::<Vec<_>>(),
+            [("f".to_owned(), 4), ("g".to_owned(), 2)]
+        );
+    }
+}
@@ -0,0 +1,56 @@
+#![allow(clippy::missing_errors_doc)]
+//! Some of the code is compiled without cargo passing our flags to rustc: doctests are built by
+//! rustdoc and build scripts are dependencies rather than targets. In those cases this binary is
+//! used as a rustc wrapper and adds the flags itself.
+use anyhow::Context;
+use std::ffi::{OsStr, OsString};
+use std::process::Command;
+
+/// When set the binary acts as a rustc wrapper and emits this kind of file in addition to the
+/// usual output
+pub const EMIT_VAR: &str = "CARGO_SHOW_ASM_EMIT";
+
+/// Extra flags for rustc separated with `\x1f`, same as in `CARGO_ENCODED_RUSTFLAGS`
+pub const FLAGS_VAR: &str = "CARGO_SHOW_ASM_FLAGS";
+
+/// Only change the build script of this package, everything else is compiled as is
+pub const BUILD_SCRIPT_VAR: &str = "CARGO_SHOW_ASM_BUILD_SCRIPT";
+
+/// Environment for a build that goes through the wrapper
+#[must_use]
+pub fn envs(emit: &str, flags: &[OsString]) -> [(&'static str, OsString); 2] {
+    [
+        (EMIT_VAR, emit.into()),
+        (FLAGS_VAR, flags.join(OsStr::new("\x1f"))),
+    ]
+}
+
+/// Run rustc with the arguments the wrapper was called with, never returns
+pub fn run(emit: &OsStr) -> anyhow::Result<()> {
+    let mut args = std::env::args_os().skip(1);
+    let rustc = args.next().context("Expected rustc command line")?;
+    let args = args.collect::<Vec<_>>();
+    let mut cmd = Command::new(rustc);
+    cmd.args(&args);
+
+    let selected = match std::env::var_os(BUILD_SCRIPT_VAR) {
+        Some(package) => {
+            std::env::var_os("CARGO_PKG_NAME").as_ref() == Some(&package)
+                && args
+                    .windows(2)
+                    .any(|w| w[0] == "--crate-name" && w[1] == "build_script_build")
+        }
+        None => true,
+    };
+    if selected {
+        let mut emit_arg = OsString::from("--emit=link,");
+        emit_arg.push(emit);
+        cmd.arg(emit_arg);
+        if let Some(flags) = std::env::var_os(FLAGS_VAR) {
+            let flags = flags.to_string_lossy().into_owned();
+            cmd.args(flags.split('\x1f').filter(|flag| !flag.is_empty()));
+        }
+    }
+    let status = cmd.status()?;
+    std::process::exit(status.code().unwrap_or(101));
+}